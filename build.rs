@@ -9,6 +9,28 @@ use std::{
 };
 use walkdir::WalkDir;
 
+// Fixtures from the upstream test suite (in the spec submodule) that we don't pass yet.
+// They're still generated, but ignored, so `cargo test -- --ignored` shows what's left to do
+// and entries can be dropped from here as support lands.
+const KNOWN_FAILURES: &[(&str, &str)] = &[
+    (
+        "upstream_fixtures_equality_mysterious_rock",
+        "Blocked by https://github.com/RockstarLang/rockstar/pull/238",
+    ),
+    (
+        "upstream_fixtures_equality_equalityComparison_rock",
+        "Blocked by https://github.com/RockstarLang/rockstar/pull/238",
+    ),
+    (
+        "upstream_fixtures_equality_nothing_rock",
+        "Blocked by https://github.com/RockstarLang/rockstar/pull/238",
+    ),
+    (
+        "upstream_fixtures_operators_multiplicationOperator_rock",
+        "Blocked by https://github.com/RockstarLang/rockstar/issues/162",
+    ),
+];
+
 fn make_tests() -> Result<(), Error> {
     let out_dir = std::env::var("OUT_DIR")?;
     let destination = std::path::Path::new(&out_dir).join("test.rs");
@@ -23,15 +45,10 @@ fn make_tests() -> Result<(), Error> {
             .replace("tests/", "");
         if name.to_lowercase().ends_with(".rock") {
             let test_name = name.replace(['.', '-', '/'], "_");
-            if test_name == "upstream_fixtures_equality_mysterious_rock"
-                || test_name == "upstream_fixtures_equality_equalityComparison_rock"
-                || test_name == "upstream_fixtures_equality_nothing_rock"
-            {
-                continue; // FIXME: Blocked by https://github.com/RockstarLang/rockstar/pull/238
-            }
-            if test_name == "upstream_fixtures_operators_multiplicationOperator_rock" {
-                continue; // FIXME: Blocked by https://github.com/RockstarLang/rockstar/issues/162
-            }
+            let ignore = match KNOWN_FAILURES.iter().find(|(known, _)| *known == test_name) {
+                Some((_, reason)) => format!("\n    #[ignore = \"{reason}\"]"),
+                None => String::new(),
+            };
 
             let function = if name.contains("failures") {
                 "parse_fail_file_test"
@@ -41,14 +58,15 @@ fn make_tests() -> Result<(), Error> {
             write!(
                 f,
                 "
-    #[test]
+    #[test]{ignore}
     #[allow(non_snake_case)]
     fn {test_name}() {{
         {function}(\"{name}\");
     }}",
                 name = name,
                 test_name = test_name,
-                function = function
+                function = function,
+                ignore = ignore
             )?;
         }
     }
@@ -251,6 +269,392 @@ struct Ast {
     rules: Vec<Rule>,
 }
 
+// Grammar that isn't in the upstream spec, as (existing rule, new rule, pest body).
+// Each new rule is written out just before the existing one and tried as its first alternative.
+const EXTENSIONS: &[(&str, &str, &str)] = &[
+    ("operation", "sleep", r#"^"wait" ~ SPACING+ ~ expression"#),
+    ("operation", "throw", r#"^"throw" ~ SPACING+ ~ expression"#),
+    (
+        "operation",
+        "raw_output",
+        r#"^"write" ~ SPACING+ ~ expression"#,
+    ),
+    // "Say nothing" is left alone, as the reference prints null for it
+    (
+        "operation",
+        "blank_output",
+        r#"(^"say" | ^"shout" | ^"whisper" | ^"scream") ~ SPACING+ ~ ^"a" ~ SPACING+ ~ (^"newline" | ^"blank" ~ SPACING+ ~ ^"line") ~ &(SPACING* ~ (EOL | EOI))"#,
+    ),
+    // Only plain values either side of each "and", so "Say X is 1 and Y is 2" is still logical
+    (
+        "operation",
+        "joined_output",
+        r#"(^"say" | ^"shout" | ^"whisper" | ^"scream") ~ SPACING+ ~ arithmetic ~ (SPACING+ ~ ^"and" ~ SPACING+ ~ arithmetic)+ ~ &(SPACING* ~ (EOL | EOI))"#,
+    ),
+    // Only a number for the amount, as it's fixed when parsed like the count of "up"s
+    (
+        "operation",
+        "increase_by",
+        r#"^"increase" ~ SPACING+ ~ (pronoun | variable) ~ SPACING+ ~ ^"by" ~ SPACING+ ~ number"#,
+    ),
+    (
+        "operation",
+        "decrease_by",
+        r#"^"decrease" ~ SPACING+ ~ (pronoun | variable) ~ SPACING+ ~ ^"by" ~ SPACING+ ~ number"#,
+    ),
+    // Wraps the whole Put/Let/Say, so the modifier applies to the value once it's worked out
+    (
+        "operation",
+        "rounding_modifier",
+        r#"(assignment | output) ~ SPACING* ~ "," ~ SPACING* ~ ^"rounding" ~ SPACING+ ~ rounding_direction"#,
+    ),
+    (
+        "operation",
+        "empty_collection",
+        r#"^"empty" ~ SPACING+ ~ (pronoun | variable)"#,
+    ),
+    (
+        "operation",
+        "flip_sign",
+        r#"^"flip" ~ SPACING+ ~ ^"the" ~ SPACING+ ~ ^"sign" ~ SPACING+ ~ ^"of" ~ SPACING+ ~ variable"#,
+    ),
+    // Values are never shared, so this is an assignment, and nested arrays are copied with it
+    (
+        "assignment",
+        "copy_into",
+        r#"^"copy" ~ SPACING+ ~ expression ~ SPACING+ ~ ^"into" ~ SPACING+ ~ assignable"#,
+    ),
+    (
+        "assignment",
+        "copy_assignment",
+        r#"^"let" ~ SPACING+ ~ assignable ~ SPACING+ ~ ^"be" ~ SPACING+ ~ ^"like" ~ SPACING+ ~ variable"#,
+    ),
+    (
+        "assignment",
+        "rounded_assignment",
+        r#"^"let" ~ SPACING+ ~ assignable ~ SPACING+ ~ ^"be" ~ SPACING+ ~ expression ~ SPACING+ ~ rounded_suffix"#,
+    ),
+    (
+        "simple_expression",
+        "square_root",
+        r#"^"the" ~ SPACING+ ~ ^"square" ~ SPACING+ ~ ^"root" ~ SPACING+ ~ ^"of" ~ SPACING+ ~ simple_expression"#,
+    ),
+    (
+        "simple_expression",
+        "power",
+        "power_base ~ (SPACING+ ~ exponent)+",
+    ),
+    (
+        "operation",
+        "array_enqueue",
+        r#"^"enqueue" ~ SPACING+ ~ expression ~ SPACING+ ~ ^"into" ~ SPACING+ ~ assignable"#,
+    ),
+    (
+        "operation",
+        "array_dequeue",
+        r#"(^"dequeue" | ^"roll") ~ SPACING+ ~ variable ~ (SPACING+ ~ ^"into" ~ SPACING+ ~ variable)?"#,
+    ),
+    (
+        "operation",
+        "array_remove",
+        r#"^"remove" ~ SPACING+ ~ (pronoun | variable) ~ SPACING+ ~ ^"at" ~ SPACING+ ~ expression ~ (SPACING+ ~ ^"into" ~ SPACING+ ~ assignable)?"#,
+    ),
+    (
+        "operation",
+        "array_insert",
+        r#"^"insert" ~ SPACING+ ~ expression ~ SPACING+ ~ ^"into" ~ SPACING+ ~ (pronoun | variable) ~ SPACING+ ~ ^"at" ~ SPACING+ ~ expression"#,
+    ),
+    (
+        "operation",
+        "array_push",
+        r#"(^"rock" | ^"push") ~ SPACING+ ~ assignable ~ (SPACING+ ~ ^"with" ~ SPACING+ ~ expression_list)?"#,
+    ),
+    (
+        "loop",
+        "stays_loop",
+        r#"(while_kw | until_kw) ~ SPACING+ ~ expression ~ SPACING+ ~ ^"stays" ~ SPACING+ ~ (true_kw | false_kw) ~ SPACING* ~ EOL ~ block"#,
+    ),
+    // Not SPACING before the "(", as that would take the defaults for a comment
+    (
+        "function",
+        "function_with_defaults",
+        r#"variable ~ SPACING+ ~ (^"takes" | ^"wants") ~ SPACING+ ~ variable_list ~ (" " | "\t")+ ~ "(" ~ ^"where" ~ SPACING+ ~ parameter_default ~ (SPACING+ ~ ^"and" ~ SPACING+ ~ parameter_default)* ~ ")" ~ SPACING* ~ EOL ~ block"#,
+    ),
+    (
+        "loop",
+        "labelled_loop",
+        r#"variable ~ ":" ~ (" " | "\t")+ ~ loop_kw"#,
+    ),
+    (
+        "operation",
+        "restart",
+        r#"^"restart" ~ SPACING+ ~ variable"#,
+    ),
+    // On loop as it's another command with a block, and the first block ends at the line that
+    // starts with "Recover", like an if's block ends at "Else"
+    (
+        "loop",
+        "try_block",
+        r#"^"try" ~ SPACING* ~ EOL ~ block ~ EOL? ~ SPACING* ~ ^"recover" ~ (SPACING+ ~ ^"with" ~ SPACING+ ~ variable)? ~ SPACING* ~ EOL ~ block"#,
+    ),
+    (
+        "loop",
+        "repeat_loop",
+        r#"^"repeat" ~ SPACING+ ~ expression ~ SPACING+ ~ ^"times" ~ SPACING* ~ EOL ~ block"#,
+    ),
+    (
+        "comparison",
+        "between",
+        r#"arithmetic ~ SPACING+ ~ ^"is" ~ SPACING+ ~ ^"between" ~ SPACING+ ~ arithmetic ~ SPACING+ ~ ^"and" ~ SPACING+ ~ arithmetic"#,
+    ),
+    // "Or" itself stays a boolean, as in the reference, so defaults get their own operator
+    (
+        "or",
+        "coalesce",
+        r#"and ~ SPACING+ ~ ^"or" ~ SPACING+ ~ ^"else" ~ SPACING+ ~ or"#,
+    ),
+    (
+        "comparison",
+        "divisible_by",
+        r#"arithmetic ~ SPACING+ ~ ^"is" ~ SPACING+ ~ ^"divisible" ~ SPACING+ ~ ^"by" ~ SPACING+ ~ arithmetic"#,
+    ),
+    (
+        "comparison",
+        "has_key",
+        r#"arithmetic ~ SPACING+ ~ ^"has" ~ SPACING+ ~ ^"key" ~ SPACING+ ~ arithmetic"#,
+    ),
+    (
+        "comparison",
+        "type_test",
+        r#"arithmetic ~ SPACING+ ~ ^"is" ~ SPACING+ ~ (^"an" | ^"a") ~ SPACING+ ~ type_name ~ !letter"#,
+    ),
+    (
+        "readline",
+        "destructuring_readline",
+        r#"^"listen" ~ SPACING+ ~ ^"to" ~ SPACING+ ~ variable ~ (SPACING+ ~ ^"and" ~ SPACING+ ~ variable)+"#,
+    ),
+    (
+        "readline",
+        "typed_readline",
+        r#"^"listen" ~ SPACING+ ~ ^"to" ~ SPACING+ ~ variable ~ SPACING+ ~ ^"as" ~ SPACING+ ~ ^"a" ~ SPACING+ ~ listen_type"#,
+    ),
+    ("divide", "modulo", r#"SPACING* ~ ^"modulo" ~ SPACING*"#),
+    (
+        "simple_expression",
+        "remainder",
+        r#"^"the" ~ SPACING+ ~ ^"remainder" ~ SPACING+ ~ ^"of" ~ SPACING+ ~ simple_expression ~ SPACING+ ~ ^"over" ~ SPACING+ ~ simple_expression"#,
+    ),
+    (
+        "assignment",
+        "multiple_assignment",
+        r#"^"put" ~ SPACING+ ~ expression ~ SPACING+ ~ ^"into" ~ SPACING+ ~ assignable ~ (SPACING+ ~ ^"and" ~ SPACING+ ~ ^"into" ~ SPACING+ ~ assignable)+"#,
+    ),
+    // On operation rather than assignment, as it'd be left-recursive there
+    (
+        "operation",
+        "guarded_assignment",
+        "assignment ~ SPACING+ ~ assignment_guard ~ SPACING+ ~ expression",
+    ),
+    // The same for any other one-line statement, such as "Say X if Y" or "Break unless Y"
+    (
+        "statement",
+        "guarded_statement",
+        "SPACING* ~ (function_return | break_kw | continue_kw | operation) ~ SPACING+ ~ assignment_guard ~ SPACING+ ~ expression",
+    ),
+    (
+        "simple_expression",
+        "inclusive_slice",
+        r#"variable ~ SPACING+ ~ ^"at" ~ SPACING+ ~ simple_expression ~ SPACING+ ~ ^"through" ~ SPACING+ ~ simple_expression"#,
+    ),
+    (
+        "simple_expression",
+        "builtin_phrase",
+        r#"^"the" ~ SPACING+ ~ builtin_phrase_name ~ SPACING+ ~ ^"of" ~ SPACING+ ~ simple_expression"#,
+    ),
+    // Takes any letters or digits after the prefix, so bad digits are an error rather than the end
+    // of the number
+    (
+        "number",
+        "radix_number",
+        r#""0" ~ (^"x" | ^"b") ~ ASCII_ALPHANUMERIC+"#,
+    ),
+    // ".5" and "5.", which Rust's float parsing already takes. A period after a number is part
+    // of it either way, so one ending a sentence doesn't change the number.
+    (
+        "number",
+        "point_number",
+        r#""-"? ~ ("." ~ ASCII_DIGIT+ | ASCII_DIGIT+ ~ "." ~ !ASCII_DIGIT)"#,
+    ),
+    (
+        "simple_expression",
+        "builtin_pair_phrase",
+        r#"^"the" ~ SPACING+ ~ builtin_pair_name ~ SPACING+ ~ ^"of" ~ SPACING+ ~ simple_expression ~ SPACING+ ~ ^"and" ~ SPACING+ ~ simple_expression"#,
+    ),
+    (
+        "simple_expression",
+        "first_or_last",
+        r#"^"the" ~ SPACING+ ~ (^"first" | ^"last") ~ SPACING+ ~ simple_expression ~ SPACING+ ~ ^"of" ~ SPACING+ ~ simple_expression"#,
+    ),
+    (
+        "simple_expression",
+        "count_of",
+        r#"^"the" ~ SPACING+ ~ ^"count" ~ SPACING+ ~ ^"of" ~ SPACING+ ~ simple_expression ~ SPACING+ ~ ^"in" ~ SPACING+ ~ simple_expression"#,
+    ),
+    (
+        "simple_expression",
+        "bitwise",
+        r#"^"the" ~ SPACING+ ~ ^"bitwise" ~ SPACING+ ~ bitwise_op ~ SPACING+ ~ ^"of" ~ SPACING+ ~ simple_expression ~ SPACING+ ~ ^"and" ~ SPACING+ ~ simple_expression"#,
+    ),
+    (
+        "simple_expression",
+        "shift",
+        r#"^"the" ~ SPACING+ ~ shift_direction ~ SPACING+ ~ ^"shift" ~ SPACING+ ~ ^"of" ~ SPACING+ ~ simple_expression ~ SPACING+ ~ ^"by" ~ SPACING+ ~ simple_expression"#,
+    ),
+    // Needs "number" or a range after "random", so "a random" on its own is still a variable
+    (
+        "simple_expression",
+        "random_number",
+        r#"(^"a" ~ SPACING+)? ~ ^"random" ~ SPACING+ ~ (^"number" ~ !ASCII_ALPHANUMERIC ~ (SPACING+ ~ random_range)? | random_range)"#,
+    ),
+    (
+        "simple_expression",
+        "concatenation",
+        r#"^"the" ~ SPACING+ ~ ^"concatenation" ~ SPACING+ ~ ^"of" ~ SPACING+ ~ expression_list"#,
+    ),
+    (
+        "simple_expression",
+        "number_from",
+        r#"^"the" ~ SPACING+ ~ ^"number" ~ SPACING+ ~ ^"from" ~ SPACING+ ~ simple_expression"#,
+    ),
+    (
+        "simple_expression",
+        "result_of",
+        r#"^"the" ~ SPACING+ ~ ^"result" ~ SPACING+ ~ ^"of" ~ SPACING+ ~ function_call"#,
+    ),
+    (
+        "simple_expression",
+        "opposite",
+        r#"^"the" ~ SPACING+ ~ ^"opposite" ~ SPACING+ ~ ^"of" ~ SPACING+ ~ simple_expression"#,
+    ),
+    (
+        "simple_expression",
+        "text_of",
+        r#"^"the" ~ SPACING+ ~ ^"text" ~ SPACING+ ~ ^"of" ~ SPACING+ ~ simple_expression"#,
+    ),
+    // On function rather than assignment, as the lambda's body is a block
+    (
+        "function",
+        "lambda_assignment",
+        r#"^"let" ~ SPACING+ ~ variable ~ SPACING+ ~ ^"be" ~ SPACING+ ~ lambda"#,
+    ),
+    (
+        "operation",
+        "pad_string",
+        r#"^"pad" ~ SPACING+ ~ simple_expression ~ (SPACING+ ~ ^"on" ~ SPACING+ ~ ^"the" ~ SPACING+ ~ pad_side)? ~ SPACING+ ~ ^"to" ~ SPACING+ ~ simple_expression ~ (SPACING+ ~ ^"with" ~ SPACING+ ~ simple_expression)? ~ SPACING+ ~ ^"into" ~ SPACING+ ~ variable"#,
+    ),
+    // Commas are needed, as "Give back X and Y" is returning one boolean
+    (
+        "function_return",
+        "multiple_return",
+        r#"return_kw ~ SPACING+ ~ arithmetic ~ ("," ~ SPACING* ~ (^"and" ~ SPACING+)? ~ arithmetic)+"#,
+    ),
+    (
+        "operation",
+        "pipeline",
+        r#"simple_expression ~ (SPACING+ ~ ^"then" ~ SPACING+ ~ pipeline_stage)+ ~ SPACING+ ~ ^"into" ~ SPACING+ ~ variable"#,
+    ),
+    (
+        "operation",
+        "clamp",
+        r#"^"clamp" ~ SPACING+ ~ simple_expression ~ SPACING+ ~ ^"between" ~ SPACING+ ~ simple_expression ~ SPACING+ ~ ^"and" ~ SPACING+ ~ simple_expression ~ SPACING+ ~ ^"into" ~ SPACING+ ~ variable"#,
+    ),
+    (
+        "operation",
+        "format_number",
+        r#"^"format" ~ SPACING+ ~ simple_expression ~ SPACING+ ~ ^"to" ~ SPACING+ ~ simple_expression ~ SPACING+ ~ (^"places" | ^"place") ~ SPACING+ ~ ^"into" ~ SPACING+ ~ variable"#,
+    ),
+    (
+        "operation",
+        "map_array",
+        r#"^"map" ~ SPACING+ ~ variable ~ SPACING+ ~ ^"over" ~ SPACING+ ~ simple_expression ~ SPACING+ ~ ^"into" ~ SPACING+ ~ variable"#,
+    ),
+    (
+        "operation",
+        "filter_array",
+        r#"^"filter" ~ SPACING+ ~ simple_expression ~ SPACING+ ~ ^"by" ~ SPACING+ ~ variable ~ SPACING+ ~ ^"into" ~ SPACING+ ~ variable"#,
+    ),
+    (
+        "operation",
+        "reduce_array",
+        r#"^"reduce" ~ SPACING+ ~ simple_expression ~ SPACING+ ~ ^"with" ~ SPACING+ ~ variable ~ SPACING+ ~ ^"into" ~ SPACING+ ~ variable"#,
+    ),
+];
+
+// Helper rules for the extensions, that aren't alternatives of anything upstream
+const EXTRA_RULES: &[(&str, &str)] = &[
+    (
+        "power_base",
+        "function_call | constant | lookup | literal | pronoun",
+    ),
+    ("exponent", r#"^"squared" | ^"cubed""#),
+    ("listen_type", r#"^"number" | ^"string""#),
+    (
+        "type_name",
+        r#"^"number" | ^"string" | ^"array" | ^"boolean""#,
+    ),
+    ("assignment_guard", r#"^"unless" | ^"if""#),
+    ("unless_kw", r#"^"unless""#),
+    (
+        "rounding_direction",
+        r#"(^"up" | ^"down" | ^"to" ~ SPACING+ ~ ^"nearest") ~ !letter"#,
+    ),
+    (
+        "rounded_suffix",
+        r#"^"rounded" ~ (SPACING+ ~ (^"up" | ^"down"))? ~ !letter | ^"truncated" ~ !letter"#,
+    ),
+    (
+        "pipeline_stage",
+        r#"(split | join | cast) ~ (SPACING+ ~ (^"with" | ^"using") ~ SPACING+ ~ simple_expression)?"#,
+    ),
+    (
+        "parameter_default",
+        r#"variable ~ SPACING+ ~ ^"is" ~ SPACING+ ~ simple_expression"#,
+    ),
+    (
+        "builtin_phrase_name",
+        r#"^"absolute" ~ SPACING+ ~ ^"value" | ^"ceiling" | ^"floor" | ^"length" | ^"sum" | ^"product" | ^"index" ~ SPACING+ ~ ^"of" ~ SPACING+ ~ ^"the" ~ SPACING+ ~ (^"biggest" | ^"maximum") | ^"average" | ^"mean" | ^"keys" | ^"values" | ^"code" | ^"character" | ^"digits" | ^"longest" | ^"shortest" | ^"reverse" | ^"sign" | ^"permutations" | ^"factorial""#,
+    ),
+    (
+        "random_range",
+        r#"^"between" ~ SPACING+ ~ simple_expression ~ SPACING+ ~ ^"and" ~ SPACING+ ~ simple_expression"#,
+    ),
+    ("builtin_pair_name", r#"^"gcd" | ^"lcm""#),
+    ("pad_side", r#"^"left" | ^"right""#),
+    ("bitwise_op", r#"^"and" | ^"or" | ^"xor""#),
+    ("shift_direction", r#"^"left" | ^"right""#),
+    (
+        "lambda",
+        r#"(^"an" | ^"a") ~ SPACING+ ~ ^"function" ~ SPACING+ ~ (^"taking" | ^"takes") ~ SPACING+ ~ variable_list ~ SPACING* ~ EOL ~ block"#,
+    ),
+];
+
+// Pest's built-in Unicode rules added to each of the upstream letter rules
+const UNICODE_LETTERS: &[(&str, &[&str])] = &[
+    (
+        "uppercase_letter",
+        &["UPPERCASE_LETTER", "TITLECASE_LETTER"],
+    ),
+    (
+        "lowercase_letter",
+        &[
+            "LOWERCASE_LETTER",
+            "OTHER_LETTER",
+            "MODIFIER_LETTER",
+            "MARK",
+        ],
+    ),
+];
+
 fn make_peg() -> Result<(), Error> {
     if !Path::new("node_modules/.bin/peggy").exists() {
         Command::new("./pnpm")
@@ -283,6 +687,10 @@ Generated from spec/satriani/rockstar.peg, do not edit here!
         &fs::read_to_string("target/rockstar.ast").context("Reading the AST")?,
     )?;
 
+    for (name, body) in EXTRA_RULES {
+        output_peg.write_all(format!("{name} = {{ {body} }}\n\n").as_bytes())?;
+    }
+
     fn write_rule(output_peg: &mut File, rule: &Rule) -> Result<(), Error> {
         let name = &rule.name;
         let printed = rule.expression.print(0);
@@ -533,6 +941,81 @@ Generated from spec/satriani/rockstar.peg, do not edit here!
                 println!("Fail rule 1 {:?}", rule)
             }
         }
+        // "Unless X" is an "If" on the opposite condition, so it's another way to start the same rule
+        if rule.name == "conditional" {
+            fn add_unless(expression: &mut Expression) {
+                match expression {
+                    Expression::Sequence { elements } => {
+                        if let Some(Expression::Literal { value }) = elements.first() {
+                            if value.eq_ignore_ascii_case("if") {
+                                elements[0] = Expression::Choice {
+                                    alternatives: vec![
+                                        Expression::RuleRef {
+                                            name: "unless_kw".to_owned(),
+                                        },
+                                        elements[0].clone(),
+                                    ],
+                                };
+                                return;
+                            }
+                        }
+                        for element in elements {
+                            add_unless(element);
+                        }
+                    }
+                    Expression::Choice { alternatives } => {
+                        for alternative in alternatives {
+                            add_unless(alternative);
+                        }
+                    }
+                    Expression::Action { expression }
+                    | Expression::Labeled { expression }
+                    | Expression::Group { expression } => add_unless(expression),
+                    _ => {}
+                }
+            }
+            add_unless(&mut rule.expression);
+        }
+        // Upstream ends a comment at the first ")", which would leave "note)" of
+        // "(a (nested) note)" behind. Nesting is balanced instead, so an unclosed "(" fails to parse.
+        if rule.name == "comment" {
+            rule.expression = Expression::Sequence {
+                elements: vec![
+                    Expression::Literal {
+                        value: "(".to_owned(),
+                    },
+                    Expression::ZeroOrMore {
+                        expression: Box::new(Expression::Choice {
+                            alternatives: vec![
+                                Expression::RuleRef {
+                                    name: "comment".to_owned(),
+                                },
+                                Expression::Sequence {
+                                    elements: vec![
+                                        Expression::SimpleNot {
+                                            expression: Box::new(Expression::Choice {
+                                                alternatives: vec![
+                                                    Expression::Literal {
+                                                        value: "(".to_owned(),
+                                                    },
+                                                    Expression::Literal {
+                                                        value: ")".to_owned(),
+                                                    },
+                                                ],
+                                            }),
+                                        },
+                                        Expression::Any {},
+                                    ],
+                                },
+                            ],
+                        }),
+                    },
+                    Expression::Literal {
+                        value: ")".to_owned(),
+                    },
+                ],
+            };
+        }
         if rule.name == "variable" {
             output_peg.write_all(r#"// To disallow identifiers like "My back is hurting" (which is illegal because "back" is a keyword)
 // we need to explicitly define all language keywords, and they MUST be matched in descending order of length
@@ -586,6 +1069,34 @@ keyword = @{(kw10 | kw8 | kw7 | kw6 | kw5 | kw5 | kw4 | kw3 | kw2 | kw1) ~ !lett
                 }
             }
         }
+        // Upstream's letter classes only cover Latin scripts. Widening them to the Unicode
+        // categories lets variables be named in any script, while keywords stay ASCII literals.
+        // Caseless letters (and combining marks) only go in lowercase, so they can be simple or
+        // common variables but can't start a proper one.
+        if let Some((_, categories)) = UNICODE_LETTERS.iter().find(|(n, _)| *n == rule.name) {
+            let mut alternatives = vec![rule.expression];
+            for category in *categories {
+                alternatives.push(Expression::RuleRef {
+                    name: category.to_string(),
+                });
+            }
+            rule.expression = Expression::Choice { alternatives };
+        }
+        let mut extensions = vec![];
+        for (target, name, body) in EXTENSIONS {
+            if *target == rule.name {
+                output_peg.write_all(format!("{name} = {{ {body} }}\n\n").as_bytes())?;
+                extensions.push(Expression::RuleRef {
+                    name: name.to_string(),
+                });
+            }
+        }
+        if !extensions.is_empty() {
+            extensions.push(rule.expression);
+            rule.expression = Expression::Choice {
+                alternatives: extensions,
+            };
+        }
         write_rule(&mut output_peg, &rule)?;
     }
     Ok(())
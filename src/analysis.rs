@@ -0,0 +1,1438 @@
+use crate::builtins;
+use crate::common::{Block, Command, CommandLine, Expression, MaidenError, Program};
+use crate::parser;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+
+fn assigned_name(target: &Expression) -> Option<String> {
+    match target {
+        Expression::Variable(name) => Some(name.to_lowercase()),
+        Expression::ArrayRef { name, .. } => assigned_name(name),
+        _ => None,
+    }
+}
+
+// Every variable that gets assigned somewhere in these commands, outside of function bodies
+fn all_assigned(commands: &[CommandLine], assigned: &mut HashSet<String>) {
+    for command in commands {
+        match &command.cmd {
+            Command::Assignment { target, .. } => {
+                assigned.extend(assigned_name(target));
+            }
+            Command::MultipleAssignment { targets, .. } => {
+                assigned.extend(targets.iter().filter_map(assigned_name));
+            }
+            Command::Remove {
+                removed: Some(removed),
+                ..
+            } => {
+                assigned.extend(assigned_name(removed));
+            }
+            Command::Push { target, .. }
+            | Command::Insert { target, .. }
+            | Command::ApplyToArray { target, .. }
+            | Command::Pad { target, .. }
+            | Command::FormatNumber { target, .. }
+            | Command::Clamp { target, .. } => {
+                assigned.extend(assigned_name(target));
+            }
+            Command::Dequeue { target, removed } => {
+                assigned.extend(assigned_name(target));
+                assigned.extend(removed.iter().filter_map(assigned_name));
+            }
+            Command::Listen { targets, .. } => {
+                assigned.extend(targets.iter().map(|target| target.to_lowercase()));
+            }
+            Command::Mutation {
+                target: Some(target),
+                ..
+            } => {
+                assigned.extend(assigned_name(target));
+            }
+            Command::Try {
+                error_var: Some(name),
+                ..
+            } => {
+                assigned.insert(name.to_lowercase());
+            }
+            Command::FunctionDeclaration { .. } => continue,
+            _ => {}
+        }
+        for block in command.cmd.blocks() {
+            all_assigned(&block.commands, assigned);
+        }
+    }
+}
+
+struct UseBeforeAssign {
+    functions: HashSet<String>,
+    globals: HashSet<String>,
+    reported: HashSet<String>,
+    errors: Vec<MaidenError>,
+}
+
+impl UseBeforeAssign {
+    fn read(&mut self, expression: &Expression, assigned: &HashSet<String>, line: usize) {
+        if let Expression::Variable(name) = expression {
+            let lower = name.to_lowercase();
+            if !assigned.contains(&lower)
+                && !self.functions.contains(&lower)
+                && self.reported.insert(lower)
+            {
+                self.errors.push(MaidenError::MissingVariable {
+                    name: name.clone(),
+                    line,
+                });
+            }
+        }
+        for child in expression.children() {
+            self.read(child, assigned, line);
+        }
+    }
+
+    // Function bodies can't know when they'll be called, so assume any global might be set by then
+    fn function(&mut self, args: &[String], rest: &Option<String>, block: &Block) {
+        let mut assigned = self.globals.clone();
+        assigned.extend(args.iter().chain(rest.iter()).map(|arg| arg.to_lowercase()));
+        self.walk(&block.commands, &mut assigned);
+    }
+
+    fn walk(&mut self, commands: &[CommandLine], assigned: &mut HashSet<String>) {
+        for command in commands {
+            let line = command.line;
+            match &command.cmd {
+                Command::Assignment { target, value } => {
+                    self.read(value, assigned, line);
+                    if let Expression::ArrayRef { index, .. } = &**target {
+                        self.read(index, assigned, line);
+                    }
+                    assigned.extend(assigned_name(target));
+                }
+                Command::MultipleAssignment { targets, value } => {
+                    self.read(value, assigned, line);
+                    for target in targets {
+                        if let Expression::ArrayRef { index, .. } = target {
+                            self.read(index, assigned, line);
+                        }
+                        assigned.extend(assigned_name(target));
+                    }
+                }
+                Command::If {
+                    expression,
+                    then,
+                    otherwise,
+                } => {
+                    self.read(expression, assigned, line);
+                    let mut then_assigned = assigned.clone();
+                    if let Some(block) = then {
+                        self.walk(&block.commands, &mut then_assigned);
+                    }
+                    let mut otherwise_assigned = assigned.clone();
+                    if let Some(block) = otherwise {
+                        self.walk(&block.commands, &mut otherwise_assigned);
+                    }
+                    *assigned = then_assigned
+                        .intersection(&otherwise_assigned)
+                        .cloned()
+                        .collect();
+                }
+                // The body can fail before assigning anything, so recover starts from before it,
+                // and both have to assign a variable for it to count afterwards
+                Command::Try {
+                    body,
+                    error_var,
+                    recover,
+                } => {
+                    let mut body_assigned = assigned.clone();
+                    self.walk(&body.commands, &mut body_assigned);
+                    let mut recover_assigned = assigned.clone();
+                    recover_assigned.extend(error_var.iter().map(|name| name.to_lowercase()));
+                    self.walk(&recover.commands, &mut recover_assigned);
+                    *assigned = body_assigned
+                        .intersection(&recover_assigned)
+                        .cloned()
+                        .collect();
+                }
+                // Loop bodies might never run, so nothing they assign counts afterwards
+                Command::While {
+                    expression, block, ..
+                }
+                | Command::Until {
+                    expression, block, ..
+                }
+                | Command::Repeat {
+                    count: expression,
+                    block,
+                    ..
+                } => {
+                    self.read(expression, assigned, line);
+                    self.walk(&block.commands, &mut assigned.clone());
+                }
+                Command::FunctionDeclaration {
+                    args, rest, block, ..
+                } => {
+                    self.function(args, rest, block);
+                }
+                // Always runs, so whatever it assigns is set from here on
+                Command::Include { block, .. } => {
+                    self.walk(&block.commands, assigned);
+                }
+                Command::Increment { target, .. }
+                | Command::Decrement { target, .. }
+                | Command::Floor { target }
+                | Command::Ceil { target }
+                | Command::Round { target }
+                | Command::Negate { target }
+                | Command::Empty { target } => {
+                    self.read(target, assigned, line);
+                }
+                Command::Say { value: expression }
+                | Command::Write { value: expression }
+                | Command::Return {
+                    return_value: expression,
+                }
+                | Command::Sleep {
+                    seconds: expression,
+                }
+                | Command::Throw { value: expression } => {
+                    self.read(expression, assigned, line);
+                }
+                Command::Call { args, .. } => {
+                    for arg in args {
+                        self.read(arg, assigned, line);
+                    }
+                }
+                Command::Push { target, values } => {
+                    for value in values {
+                        self.read(value, assigned, line);
+                    }
+                    assigned.extend(assigned_name(target));
+                }
+                Command::Insert {
+                    value,
+                    target,
+                    index,
+                } => {
+                    self.read(value, assigned, line);
+                    self.read(index, assigned, line);
+                    assigned.extend(assigned_name(target));
+                }
+                Command::Remove {
+                    target,
+                    index,
+                    removed,
+                } => {
+                    self.read(target, assigned, line);
+                    self.read(index, assigned, line);
+                    if let Some(removed) = removed {
+                        assigned.extend(assigned_name(removed));
+                    }
+                }
+                Command::ApplyToArray {
+                    function,
+                    source,
+                    target,
+                    ..
+                } => {
+                    self.read(function, assigned, line);
+                    self.read(source, assigned, line);
+                    assigned.extend(assigned_name(target));
+                }
+                Command::Dequeue { target, removed } => {
+                    self.read(target, assigned, line);
+                    assigned.extend(removed.iter().filter_map(assigned_name));
+                }
+                Command::FormatNumber {
+                    value,
+                    places,
+                    target,
+                } => {
+                    self.read(value, assigned, line);
+                    self.read(places, assigned, line);
+                    assigned.extend(assigned_name(target));
+                }
+                Command::Clamp {
+                    value,
+                    low,
+                    high,
+                    target,
+                } => {
+                    for expression in [value, low, high] {
+                        self.read(expression, assigned, line);
+                    }
+                    assigned.extend(assigned_name(target));
+                }
+                Command::Pad {
+                    value,
+                    width,
+                    fill,
+                    target,
+                    ..
+                } => {
+                    for expression in vec![value, width].into_iter().chain(fill.iter()) {
+                        self.read(expression, assigned, line);
+                    }
+                    assigned.extend(assigned_name(target));
+                }
+                Command::Listen { targets, .. } => {
+                    assigned.extend(targets.iter().map(|target| target.to_lowercase()));
+                }
+                Command::Mutation {
+                    source,
+                    target,
+                    lookup,
+                    modifier,
+                    ..
+                } => {
+                    for expression in source.iter().chain(lookup.iter()).chain(modifier.iter()) {
+                        self.read(expression, assigned, line);
+                    }
+                    if let Some(target) = target {
+                        assigned.extend(assigned_name(target));
+                    }
+                }
+                Command::Continue | Command::Break | Command::Restart { .. } => {}
+            }
+        }
+    }
+}
+
+fn function_names(commands: &[CommandLine], names: &mut HashSet<String>) {
+    for command in commands {
+        if let Command::FunctionDeclaration { name, .. } = &command.cmd {
+            names.insert(name.to_lowercase());
+        }
+        for block in command.cmd.blocks() {
+            function_names(&block.commands, names);
+        }
+    }
+}
+
+// Variables that are read before anything on the way there could have assigned them.
+// Both sides of an `if` have to assign a variable for it to count as assigned afterwards.
+pub fn use_before_assign(program: &Program) -> Vec<MaidenError> {
+    let mut functions: HashSet<String> = program
+        .functions
+        .keys()
+        .map(|name| name.to_lowercase())
+        .collect();
+    function_names(&program.commands, &mut functions);
+    let mut globals = HashSet::new();
+    all_assigned(&program.commands, &mut globals);
+    let mut checker = UseBeforeAssign {
+        functions,
+        globals,
+        reported: HashSet::new(),
+        errors: vec![],
+    };
+    checker.walk(&program.commands, &mut HashSet::new());
+    for function in program.functions.values() {
+        checker.function(&function.args, &function.rest, &function.block);
+    }
+    checker.errors
+}
+
+fn find_shadowed(
+    commands: &[CommandLine],
+    globals: &HashSet<String>,
+    shadowed: &mut Vec<(String, usize)>,
+) {
+    for command in commands {
+        if let Command::FunctionDeclaration { args, .. } = &command.cmd {
+            for arg in args {
+                if globals.contains(&arg.to_lowercase()) {
+                    shadowed.push((arg.clone(), command.line));
+                }
+            }
+        }
+        for block in command.cmd.blocks() {
+            find_shadowed(&block.commands, globals, shadowed);
+        }
+    }
+}
+
+// Function parameters with the same name as a global, which hide that global inside the function.
+// Gives the parameter name and the line of the function declaration.
+pub fn shadowed_parameters(program: &Program) -> Vec<(String, usize)> {
+    let mut globals = HashSet::new();
+    all_assigned(&program.commands, &mut globals);
+    let mut shadowed = vec![];
+    find_shadowed(&program.commands, &globals, &mut shadowed);
+    shadowed
+}
+
+// Whether every path through these commands ends in a Return (or a Throw, which doesn't fall off
+// the end either). Loops might not run at all, so only an `if` with both branches returning counts
+// as returning, apart from Return itself.
+fn always_returns(commands: &[CommandLine]) -> bool {
+    commands.iter().any(|command| match &command.cmd {
+        Command::Return { .. } | Command::Throw { .. } => true,
+        Command::If {
+            then: Some(then),
+            otherwise: Some(otherwise),
+            ..
+        } => always_returns(&then.commands) && always_returns(&otherwise.commands),
+        _ => false,
+    })
+}
+
+fn find_missing_returns(commands: &[CommandLine], missing: &mut Vec<(String, usize)>) {
+    for command in commands {
+        if let Command::FunctionDeclaration { name, block, .. } = &command.cmd {
+            if !always_returns(&block.commands) {
+                missing.push((name.clone(), command.line));
+            }
+        }
+        for block in command.cmd.blocks() {
+            find_missing_returns(&block.commands, missing);
+        }
+    }
+}
+
+// Functions where some path falls off the end without a Return, and so gives back mysterious.
+// Gives the function name and the line of its declaration.
+pub fn missing_returns(program: &Program) -> Vec<(String, usize)> {
+    let mut missing = vec![];
+    find_missing_returns(&program.commands, &mut missing);
+    missing
+}
+
+// Fewest and most arguments each function can be called with
+fn function_arities(commands: &[CommandLine], arities: &mut HashMap<String, (usize, usize)>) {
+    for command in commands {
+        if let Command::FunctionDeclaration {
+            name,
+            args,
+            defaults,
+            rest,
+            ..
+        } = &command.cmd
+        {
+            let most = if rest.is_some() {
+                usize::MAX
+            } else {
+                args.len()
+            };
+            arities.insert(name.clone(), (args.len() - defaults.len(), most));
+        }
+        for block in command.cmd.blocks() {
+            function_arities(&block.commands, arities);
+        }
+    }
+}
+
+fn check_call(
+    arities: &HashMap<String, (usize, usize)>,
+    callable: &HashSet<String>,
+    name: &str,
+    args: usize,
+    line: usize,
+    errors: &mut Vec<MaidenError>,
+) {
+    match arities.get(name) {
+        Some((fewest, _)) if args < *fewest => errors.push(MaidenError::WrongArgCount {
+            expected: *fewest,
+            got: args,
+            line,
+        }),
+        Some((_, most)) if args > *most => errors.push(MaidenError::WrongArgCount {
+            expected: *most,
+            got: args,
+            line,
+        }),
+        Some(_) => {}
+        None if builtins::is_builtin(name) || callable.contains(&name.to_lowercase()) => {}
+        None => errors.push(MaidenError::MissingFunction {
+            name: name.to_string(),
+            line,
+        }),
+    }
+}
+
+fn check_expression_calls(
+    arities: &HashMap<String, (usize, usize)>,
+    callable: &HashSet<String>,
+    expression: &Expression,
+    line: usize,
+    errors: &mut Vec<MaidenError>,
+) {
+    if let Expression::Call(name, args) = expression {
+        check_call(arities, callable, name, args.len(), line, errors);
+    }
+    for child in expression.children() {
+        check_expression_calls(arities, callable, child, line, errors);
+    }
+}
+
+fn check_calls(
+    arities: &HashMap<String, (usize, usize)>,
+    callable: &HashSet<String>,
+    commands: &[CommandLine],
+    errors: &mut Vec<MaidenError>,
+) {
+    for command in commands {
+        if let Command::Call { name, args } = &command.cmd {
+            check_call(arities, callable, name, args.len(), command.line, errors);
+        }
+        for expression in command.cmd.expressions() {
+            check_expression_calls(arities, callable, expression, command.line, errors);
+        }
+        for block in command.cmd.blocks() {
+            check_calls(arities, callable, &block.commands, errors);
+        }
+    }
+}
+
+// Every function's parameters and whatever it assigns, including functions declared inside others
+fn function_variables(commands: &[CommandLine], names: &mut HashSet<String>) {
+    for command in commands {
+        if let Command::FunctionDeclaration {
+            args, rest, block, ..
+        } = &command.cmd
+        {
+            names.extend(args.iter().chain(rest.iter()).map(|arg| arg.to_lowercase()));
+            all_assigned(&block.commands, names);
+        }
+        for block in command.cmd.blocks() {
+            function_variables(&block.commands, names);
+        }
+    }
+}
+
+// Calls to functions that aren't declared anywhere, or with the wrong number of arguments.
+// A variable might hold a function value by the time it's called, so a call to anything that
+// gets assigned (or is a parameter) isn't reported.
+pub fn arity_errors(program: &Program) -> Vec<MaidenError> {
+    arity_errors_with_host_functions(program, &[])
+}
+
+// As arity_errors, but calls to host_functions aren't reported either
+pub fn arity_errors_with_host_functions(
+    program: &Program,
+    host_functions: &[&str],
+) -> Vec<MaidenError> {
+    let mut arities: HashMap<String, (usize, usize)> = program
+        .functions
+        .iter()
+        .map(|(name, function)| {
+            let most = if function.rest.is_some() {
+                usize::MAX
+            } else {
+                function.args.len()
+            };
+            (name.clone(), (function.required_args(), most))
+        })
+        .collect();
+    function_arities(&program.commands, &mut arities);
+    let mut callable: HashSet<String> = host_functions
+        .iter()
+        .map(|name| name.to_lowercase())
+        .collect();
+    all_assigned(&program.commands, &mut callable);
+    function_variables(&program.commands, &mut callable);
+    for function in program.functions.values() {
+        let args = function.args.iter().chain(function.rest.iter());
+        callable.extend(args.map(|arg| arg.to_lowercase()));
+        all_assigned(&function.block.commands, &mut callable);
+        function_variables(&function.block.commands, &mut callable);
+    }
+    let mut errors = vec![];
+    check_calls(&arities, &callable, &program.commands, &mut errors);
+    for function in program.functions.values() {
+        check_calls(&arities, &callable, &function.block.commands, &mut errors);
+    }
+    errors
+}
+
+fn find_unreachable(commands: &[CommandLine], errors: &mut Vec<MaidenError>) {
+    let mut ended = false;
+    for command in commands {
+        if ended {
+            errors.push(MaidenError::UnreachableCode { line: command.line });
+            break;
+        }
+        for block in command.cmd.blocks() {
+            find_unreachable(&block.commands, errors);
+        }
+        ended = matches!(
+            command.cmd,
+            Command::Return { .. }
+                | Command::Break
+                | Command::Continue
+                | Command::Restart { .. }
+                | Command::Throw { .. }
+        );
+    }
+}
+
+// Commands straight after a Return, Break, Continue, Restart or Throw in the same block, which can
+// never run.
+// Only the first such command in each block is reported.
+pub fn unreachable_code(program: &Program) -> Vec<MaidenError> {
+    let mut errors = vec![];
+    find_unreachable(&program.commands, &mut errors);
+    errors
+}
+
+fn function_bodies<'a>(commands: &'a [CommandLine], bodies: &mut HashMap<String, &'a Block>) {
+    for command in commands {
+        if let Command::FunctionDeclaration { name, block, .. } = &command.cmd {
+            bodies.insert(name.clone(), block);
+        }
+        for block in command.cmd.blocks() {
+            function_bodies(&block.commands, bodies);
+        }
+    }
+}
+
+fn expression_calls(expression: &Expression, called: &mut Vec<String>) {
+    if let Expression::Call(name, _) = expression {
+        called.push(name.clone());
+    }
+    for child in expression.children() {
+        expression_calls(child, called);
+    }
+}
+
+// Calls made by running these commands, so not looking inside function declarations. Map,
+// Filter and Reduce call their function too, when it's one of the declared ones rather than a
+// variable holding a function value.
+fn commands_calls(
+    commands: &[CommandLine],
+    declared: &HashMap<String, &Block>,
+    called: &mut Vec<String>,
+) {
+    for command in commands {
+        match &command.cmd {
+            Command::FunctionDeclaration { .. } => continue,
+            Command::Call { name, .. } => called.push(name.clone()),
+            Command::ApplyToArray {
+                function: Expression::Variable(name),
+                ..
+            } if declared.contains_key(name) => called.push(name.clone()),
+            _ => {}
+        }
+        for expression in command.cmd.expressions() {
+            expression_calls(expression, called);
+        }
+        for block in command.cmd.blocks() {
+            commands_calls(&block.commands, declared, called);
+        }
+    }
+}
+
+// Functions that can be called, directly or through other functions, from the top-level code.
+// Any other declared function is dead code. Built-ins aren't included.
+pub fn reachable_functions(program: &Program) -> HashSet<String> {
+    let bodies = all_function_bodies(program);
+
+    let mut reachable = HashSet::new();
+    let mut to_visit = vec![];
+    commands_calls(&program.commands, &bodies, &mut to_visit);
+    while let Some(name) = to_visit.pop() {
+        if let Some(body) = bodies.get(&name) {
+            if reachable.insert(name) {
+                commands_calls(&body.commands, &bodies, &mut to_visit);
+            }
+        }
+    }
+    reachable
+}
+
+fn dot_name(name: &str) -> String {
+    format!("\"{}\"", name.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+// The call graph as Graphviz DOT, with a "main" node for the top-level code. Built-ins are
+// drawn as boxes, and calls to anything that isn't declared or built in are dashed.
+pub fn to_dot(program: &Program) -> String {
+    let declared = all_function_bodies(program);
+    let bodies: BTreeMap<String, &Block> = declared.clone().into_iter().collect();
+    let mut edges = BTreeMap::new();
+    let mut called = vec![];
+    commands_calls(&program.commands, &declared, &mut called);
+    edges.insert("main".to_string(), called);
+    for (name, body) in &bodies {
+        let mut called = vec![];
+        commands_calls(&body.commands, &declared, &mut called);
+        edges.insert(name.clone(), called);
+    }
+
+    let mut dot = "digraph calls {\n".to_string();
+    dot.push_str("    \"main\";\n");
+    for name in bodies.keys() {
+        dot.push_str(&format!("    {};\n", dot_name(name)));
+    }
+    // Everything called that isn't declared, and whether it's a built-in
+    let mut others = BTreeMap::new();
+    for called in edges.values() {
+        for name in called {
+            if !bodies.contains_key(name) {
+                others.insert(name.as_str(), builtins::is_builtin(name));
+            }
+        }
+    }
+    for (name, builtin) in &others {
+        let style = if *builtin {
+            "shape=box"
+        } else {
+            "style=dashed"
+        };
+        dot.push_str(&format!("    {} [{}];\n", dot_name(name), style));
+    }
+    for (caller, called) in &edges {
+        let called: BTreeSet<&String> = called.iter().collect();
+        for name in called {
+            let style = if others.get(name.as_str()) == Some(&false) {
+                " [style=dashed]"
+            } else {
+                ""
+            };
+            dot.push_str(&format!(
+                "    {} -> {}{};\n",
+                dot_name(caller),
+                dot_name(name),
+                style
+            ));
+        }
+    }
+    dot.push_str("}\n");
+    dot
+}
+
+fn find_strings(expression: &Expression, line: usize, found: &mut Vec<(String, usize)>) {
+    if let Expression::String(s) = expression {
+        found.push((s.clone(), line));
+    }
+    for child in expression.children() {
+        find_strings(child, line, found);
+    }
+}
+
+fn collect_strings(commands: &[CommandLine], found: &mut Vec<(String, usize)>) {
+    for command in commands {
+        for expression in command.cmd.expressions() {
+            find_strings(expression, command.line, found);
+        }
+        for block in command.cmd.blocks() {
+            collect_strings(&block.commands, found);
+        }
+    }
+}
+
+// Every string literal in the program, poetic ones included, with the line it's on.
+// They're in program order, so the same string on several lines is listed each time.
+pub fn string_literals(program: &Program) -> Vec<(String, usize)> {
+    let mut found = vec![];
+    collect_strings(&program.commands, &mut found);
+    for function in program.functions.values() {
+        collect_strings(&function.block.commands, &mut found);
+    }
+    found
+}
+
+fn expression_decisions(expression: &Expression) -> usize {
+    let own = match expression {
+        Expression::And(..)
+        | Expression::Or(..)
+        | Expression::Nor(..)
+        | Expression::Coalesce(..) => 1,
+        _ => 0,
+    };
+    own + expression
+        .children()
+        .into_iter()
+        .map(expression_decisions)
+        .sum::<usize>()
+}
+
+// Branches, loops and logical operators in these commands, not counting function declarations,
+// which get their own count
+fn decisions(commands: &[CommandLine]) -> usize {
+    let mut count = 0;
+    for command in commands {
+        match command.cmd {
+            Command::FunctionDeclaration { .. } => continue,
+            Command::If { .. }
+            | Command::While { .. }
+            | Command::Until { .. }
+            | Command::Repeat { .. }
+            | Command::Try { .. } => count += 1,
+            _ => {}
+        }
+        for expression in command.cmd.expressions() {
+            count += expression_decisions(expression);
+        }
+        for block in command.cmd.blocks() {
+            count += decisions(&block.commands);
+        }
+    }
+    count
+}
+
+fn all_function_bodies(program: &Program) -> HashMap<String, &Block> {
+    let mut bodies: HashMap<String, &Block> = program
+        .functions
+        .iter()
+        .map(|(name, function)| (name.clone(), &function.block))
+        .collect();
+    function_bodies(&program.commands, &mut bodies);
+    bodies
+}
+
+// Cyclomatic complexity of each function: one more than the number of decision points in it
+pub fn function_complexity(program: &Program) -> BTreeMap<String, usize> {
+    all_function_bodies(program)
+        .into_iter()
+        .map(|(name, body)| (name, 1 + decisions(&body.commands)))
+        .collect()
+}
+
+// Cyclomatic complexity of the whole program, counting the decision points in every function
+// along with the top-level code
+pub fn complexity(program: &Program) -> usize {
+    let in_functions: usize = all_function_bodies(program)
+        .values()
+        .map(|body| decisions(&body.commands))
+        .sum();
+    1 + decisions(&program.commands) + in_functions
+}
+
+// How many times estimated_cost guesses a loop body runs, or a function is applied over an array
+const LOOP_ESTIMATE: u64 = 10;
+// What estimated_cost charges for a call to a function that's already being called, rather than
+// following the recursion
+const RECURSION_ESTIMATE: u64 = 100;
+
+struct CostEstimate<'a> {
+    bodies: HashMap<String, &'a Block>,
+    calling: Vec<String>,
+}
+
+impl<'a> CostEstimate<'a> {
+    fn call(&mut self, name: &str) -> u64 {
+        let body = match self.bodies.get(name) {
+            Some(body) => *body,
+            // Built-ins, and anything we don't know about
+            None => return 1,
+        };
+        if self.calling.iter().any(|calling| calling == name) {
+            return RECURSION_ESTIMATE;
+        }
+        self.calling.push(name.to_string());
+        let cost = 1 + self.commands(&body.commands);
+        self.calling.pop();
+        cost
+    }
+
+    fn expression(&mut self, expression: &Expression) -> u64 {
+        let own = match expression {
+            Expression::Call(name, _) => self.call(name),
+            _ => 0,
+        };
+        expression.children().into_iter().fold(own, |cost, child| {
+            cost.saturating_add(self.expression(child))
+        })
+    }
+
+    fn commands(&mut self, commands: &[CommandLine]) -> u64 {
+        let mut total: u64 = 0;
+        for command in commands {
+            let mut cost: u64 = 1;
+            for expression in command.cmd.expressions() {
+                cost = cost.saturating_add(self.expression(expression));
+            }
+            cost = cost.saturating_add(match &command.cmd {
+                // Declaring a function doesn't run its body
+                Command::FunctionDeclaration { .. } => 0,
+                Command::If {
+                    then, otherwise, ..
+                } => {
+                    let then = then
+                        .as_ref()
+                        .map_or(0, |block| self.commands(&block.commands));
+                    let otherwise = otherwise
+                        .as_ref()
+                        .map_or(0, |block| self.commands(&block.commands));
+                    then.max(otherwise)
+                }
+                Command::While { block, .. }
+                | Command::Until { block, .. }
+                | Command::Repeat { block, .. } => {
+                    LOOP_ESTIMATE.saturating_mul(self.commands(&block.commands))
+                }
+                Command::Call { name, .. } => self.call(name),
+                Command::ApplyToArray {
+                    function: Expression::Variable(name),
+                    ..
+                } => LOOP_ESTIMATE.saturating_mul(self.call(name)),
+                other => other.blocks().into_iter().fold(0, |cost: u64, block| {
+                    cost.saturating_add(self.commands(&block.commands))
+                }),
+            });
+            total = total.saturating_add(cost);
+        }
+        total
+    }
+}
+
+// A rough guess at how much work running the program takes, for ordering jobs rather than
+// predicting their run time. Each command costs 1, plus whatever it calls. The costlier side of an
+// `if` is counted, loops are assumed to run LOOP_ESTIMATE times, and recursion isn't followed.
+pub fn estimated_cost(program: &Program) -> u64 {
+    let mut estimate = CostEstimate {
+        bodies: all_function_bodies(program),
+        calling: vec![],
+    };
+    estimate.commands(&program.commands)
+}
+
+// Adds the variables read by a loop condition to variables. False if it also reads something that
+// could be changed from elsewhere, like a function call or the pronoun.
+fn condition_variables(expression: &Expression, variables: &mut HashSet<String>) -> bool {
+    match expression {
+        Expression::Variable(name) => {
+            variables.insert(name.to_lowercase());
+        }
+        Expression::Call(..) | Expression::Pronoun => return false,
+        _ => {}
+    }
+    expression
+        .children()
+        .into_iter()
+        .all(|child| condition_variables(child, variables))
+}
+
+fn has_call(expression: &Expression) -> bool {
+    let mut called = vec![];
+    expression_calls(expression, &mut called);
+    !called.is_empty()
+}
+
+// Adds every variable these commands could change to written. False if they might leave the loop
+// or change something we can't name, like through a call or the pronoun.
+fn loop_writes(commands: &[CommandLine], written: &mut HashSet<String>) -> bool {
+    for command in commands {
+        let targets: Vec<&Expression> = match &command.cmd {
+            // A Restart might be for a loop outside this one
+            Command::Break
+            | Command::Restart { .. }
+            | Command::Return { .. }
+            | Command::Throw { .. }
+            | Command::Call { .. }
+            | Command::Include { .. }
+            | Command::ApplyToArray { .. } => return false,
+            // Declaring a function doesn't run its body
+            Command::FunctionDeclaration { .. } => continue,
+            Command::Assignment { target, .. } => vec![target],
+            Command::MultipleAssignment { targets, .. } => targets.iter().collect(),
+            Command::Increment { target, .. }
+            | Command::Decrement { target, .. }
+            | Command::Floor { target }
+            | Command::Ceil { target }
+            | Command::Round { target }
+            | Command::Negate { target }
+            | Command::Empty { target }
+            | Command::Push { target, .. }
+            | Command::Insert { target, .. }
+            | Command::Pad { target, .. }
+            | Command::FormatNumber { target, .. }
+            | Command::Clamp { target, .. } => vec![target],
+            Command::Remove {
+                target, removed, ..
+            }
+            | Command::Dequeue { target, removed } => {
+                std::iter::once(target).chain(removed.iter()).collect()
+            }
+            Command::Mutation { target, lookup, .. } => target
+                .iter()
+                .chain(lookup.iter())
+                .map(|expression| &**expression)
+                .collect(),
+            Command::Listen { targets, .. } => {
+                written.extend(targets.iter().map(|target| target.to_lowercase()));
+                vec![]
+            }
+            Command::Try {
+                error_var: Some(name),
+                ..
+            } => {
+                written.insert(name.to_lowercase());
+                vec![]
+            }
+            _ => vec![],
+        };
+        for target in targets {
+            match assigned_name(target) {
+                Some(name) => {
+                    written.insert(name);
+                }
+                None => return false,
+            }
+        }
+        if command.cmd.expressions().into_iter().any(has_call) {
+            return false;
+        }
+        for block in command.cmd.blocks() {
+            if !loop_writes(&block.commands, written) {
+                return false;
+            }
+        }
+    }
+    true
+}
+
+fn find_infinite_loops(commands: &[CommandLine], lines: &mut Vec<usize>) {
+    for command in commands {
+        if let Command::While {
+            expression, block, ..
+        }
+        | Command::Until {
+            expression, block, ..
+        } = &command.cmd
+        {
+            let mut variables = HashSet::new();
+            let mut written = HashSet::new();
+            if condition_variables(expression, &mut variables)
+                && !variables.is_empty()
+                && loop_writes(&block.commands, &mut written)
+                && variables.is_disjoint(&written)
+            {
+                lines.push(command.line);
+            }
+        }
+        for block in command.cmd.blocks() {
+            find_infinite_loops(&block.commands, lines);
+        }
+    }
+}
+
+// Lines of while and until loops that can't ever stop once started, as nothing in the body can
+// change the variables in the condition. Loops that call functions, use the pronoun or might
+// break or return aren't reported, as we can't be sure about those.
+pub fn likely_infinite_loops(program: &Program) -> Vec<usize> {
+    let mut lines = vec![];
+    find_infinite_loops(&program.commands, &mut lines);
+    for function in program.functions.values() {
+        find_infinite_loops(&function.block.commands, &mut lines);
+    }
+    lines.sort_unstable();
+    lines.dedup();
+    lines
+}
+
+// Everything we can find wrong with a program without running it.
+// A parse failure is the only error reported, as there's nothing to analyse after that.
+pub fn check(source: &str) -> Vec<MaidenError> {
+    check_with_host_functions(source, &[])
+}
+
+// As check, for a host that will register host_functions, which calls can use without
+// declaring them
+pub fn check_with_host_functions(source: &str, host_functions: &[&str]) -> Vec<MaidenError> {
+    match parser::parse(source) {
+        Ok(program) => check_program_with_host_functions(&program, host_functions),
+        Err(err) => vec![err],
+    }
+}
+
+// As check, for a program that's already been parsed
+pub fn check_program(program: &Program) -> Vec<MaidenError> {
+    check_program_with_host_functions(program, &[])
+}
+
+// As check_with_host_functions, for a program that's already been parsed
+pub fn check_program_with_host_functions(
+    program: &Program,
+    host_functions: &[&str],
+) -> Vec<MaidenError> {
+    let mut errors = arity_errors_with_host_functions(program, host_functions);
+    errors.extend(unreachable_code(program));
+    errors.extend(use_before_assign(program));
+    errors
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        check, check_with_host_functions, complexity, estimated_cost, function_complexity,
+        likely_infinite_loops, missing_returns, reachable_functions, shadowed_parameters,
+        string_literals, to_dot, use_before_assign, RECURSION_ESTIMATE,
+    };
+    use crate::common::MaidenError;
+    use crate::parser;
+
+    fn missing_variables(code: &str) -> Vec<(String, usize)> {
+        let program = parser::parse(code).unwrap();
+        use_before_assign(&program)
+            .into_iter()
+            .map(|err| match err {
+                MaidenError::MissingVariable { name, line } => (name, line),
+                other => panic!("{}", other),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn read_before_assignment() {
+        assert_eq!(
+            missing_variables("Say my heart\nPut 1 into my heart\nSay my heart\n"),
+            vec![("my heart".to_string(), 1)]
+        );
+    }
+
+    #[test]
+    fn assigned_in_one_branch() {
+        assert_eq!(
+            missing_variables("If right\nPut 1 into my heart\n\nSay my heart\n"),
+            vec![("my heart".to_string(), 4)]
+        );
+    }
+
+    #[test]
+    fn assigned_in_all_branches() {
+        assert_eq!(
+            missing_variables(
+                "If right
+Put 1 into my heart
+Else
+Put 2 into my heart
+
+Say my heart
+"
+            ),
+            vec![]
+        );
+    }
+
+    #[test]
+    fn function_parameters() {
+        assert_eq!(
+            missing_variables(
+                "Midnight takes your heart
+Give back your heart plus my soul
+
+Put 2 into my soul
+Say Midnight taking 1
+"
+            ),
+            vec![]
+        );
+    }
+
+    #[test]
+    fn shadowing_parameters() {
+        let program = parser::parse(
+            "Put 1 into my heart
+
+Midnight takes my heart & your soul
+Give back my heart plus your soul
+
+Say Midnight taking 2, 3
+",
+        )
+        .unwrap();
+        assert_eq!(
+            shadowed_parameters(&program),
+            vec![("my heart".to_string(), 3)]
+        );
+    }
+
+    #[test]
+    fn returns_in_one_branch() {
+        let program = parser::parse(
+            "Midnight takes your heart
+If your heart is 1
+Give back your heart
+
+
+Say Midnight taking 1
+",
+        )
+        .unwrap();
+        assert_eq!(missing_returns(&program), vec![("Midnight".to_string(), 1)]);
+    }
+
+    #[test]
+    fn returns_in_all_branches() {
+        let program = parser::parse(
+            "Midnight takes your heart
+If your heart is 1
+Give back your heart
+Else
+Give back 0
+
+
+Say Midnight taking 1
+",
+        )
+        .unwrap();
+        assert_eq!(missing_returns(&program), vec![]);
+    }
+
+    #[test]
+    fn check_reports_everything() {
+        let errors: Vec<_> = check(
+            "Midnight takes your heart
+Give back your heart
+Say your heart
+
+Say Midnight taking 1, 2
+Say Noon taking 1
+Say my soul
+",
+        )
+        .iter()
+        .map(|err| err.code())
+        .collect();
+        assert_eq!(
+            errors,
+            vec![
+                "wrong-arg-count",
+                "missing-function",
+                "unreachable-code",
+                "missing-variable"
+            ]
+        );
+    }
+
+    #[test]
+    fn calls_to_function_values() {
+        let code = "Let the doubler be a function taking the number
+Give back the number times 2
+
+Noon takes your fn and your heart
+Give back your fn taking your heart
+
+Say the doubler taking 4
+Say Noon taking the doubler, 10
+";
+        assert_eq!(check(code).len(), 0);
+    }
+
+    #[test]
+    fn calls_to_host_functions() {
+        let errors = check("Say Holler taking 5\n");
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].code(), "missing-function");
+        assert_eq!(
+            check_with_host_functions("Say Holler taking 5\n", &["holler"]).len(),
+            0
+        );
+    }
+
+    #[test]
+    fn check_parse_error() {
+        let errors = check("if 1 is 2");
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].code(), "no-end-of-if");
+    }
+
+    #[test]
+    fn all_string_literals() {
+        let program = parser::parse(
+            "Say \"hello\"
+My heart says rock and roll
+Midnight takes your heart
+If your heart is \"yes\"
+Give back \"no\"
+
+Give back your heart
+
+Put Midnight taking \"maybe\" into my soul
+Cut my soul into my words with \", \"
+",
+        )
+        .unwrap();
+        assert_eq!(
+            string_literals(&program),
+            vec![
+                ("hello".to_string(), 1),
+                ("rock and roll".to_string(), 2),
+                ("yes".to_string(), 4),
+                ("no".to_string(), 5),
+                ("maybe".to_string(), 9),
+                (", ".to_string(), 10),
+            ]
+        );
+    }
+
+    #[test]
+    fn reachable_from_top_level() {
+        let program = parser::parse(
+            "Midnight takes your heart
+Give back Noon taking your heart
+
+Noon takes your soul
+Give back your soul
+
+Dawn takes your mind
+Give back Dusk taking your mind
+
+Dusk takes your mind
+Give back your mind
+
+Say Midnight taking 1
+",
+        )
+        .unwrap();
+        let mut reachable: Vec<String> = reachable_functions(&program).into_iter().collect();
+        reachable.sort();
+        assert_eq!(reachable, vec!["Midnight".to_string(), "Noon".to_string()]);
+    }
+
+    #[test]
+    fn call_graph_dot() {
+        let program = parser::parse(
+            "Midnight takes your heart
+Give back Noon taking your heart
+
+Noon takes your soul
+Say Nowhere taking your soul
+Give back the floor of your soul
+
+Say Midnight taking 1
+Say Midnight taking 2
+",
+        )
+        .unwrap();
+        let dot = to_dot(&program);
+        assert!(dot.starts_with("digraph calls {\n"));
+        for line in [
+            "    \"main\";\n",
+            "    \"Midnight\";\n",
+            "    \"Noon\";\n",
+            "    \"Nowhere\" [style=dashed];\n",
+            "    \"floor\" [shape=box];\n",
+            "    \"main\" -> \"Midnight\";\n",
+            "    \"Midnight\" -> \"Noon\";\n",
+            "    \"Noon\" -> \"Nowhere\" [style=dashed];\n",
+            "    \"Noon\" -> \"floor\";\n",
+        ] {
+            assert!(dot.contains(line), "{} not in {}", line, dot);
+        }
+        // Calling the same function twice is still one edge
+        assert_eq!(dot.matches("-> \"Midnight\"").count(), 1);
+    }
+
+    #[test]
+    fn functions_called_by_map() {
+        let program = parser::parse(
+            "Double takes the number
+Give back the number times 2
+
+Big takes the number
+Give back the number is greater than 2
+
+Unused takes the number
+Give back the number
+
+Rock the numbers with 1, 2, 3
+Map Double over the numbers into the doubled
+Filter the doubled by Big into the winners
+",
+        )
+        .unwrap();
+        let mut reachable: Vec<String> = reachable_functions(&program).into_iter().collect();
+        reachable.sort();
+        assert_eq!(reachable, vec!["Big".to_string(), "Double".to_string()]);
+        let dot = to_dot(&program);
+        for line in [
+            "    \"main\" -> \"Double\";\n",
+            "    \"main\" -> \"Big\";\n",
+        ] {
+            assert!(dot.contains(line), "{} not in {}", line, dot);
+        }
+        assert!(!dot.contains("-> \"Unused\""));
+    }
+
+    #[test]
+    fn branches_and_loops() {
+        let program = parser::parse(
+            "Midnight takes your heart
+If your heart is nothing or your heart is mysterious
+Give back 0
+Else
+Give back your heart
+
+Put 3 into my soul
+While my soul is greater than 0
+Knock my soul down
+If my soul is 1
+Say Midnight taking my soul
+",
+        )
+        .unwrap();
+        assert_eq!(
+            function_complexity(&program)
+                .into_iter()
+                .collect::<Vec<_>>(),
+            vec![("Midnight".to_string(), 3)]
+        );
+        assert_eq!(complexity(&program), 5);
+    }
+
+    #[test]
+    fn infinite_loops() {
+        let program = parser::parse(
+            "Put 0 into my count
+Put 0 into my total
+While my count is less than 10
+Build my total up
+
+Until my count is 10
+Build my count up
+
+While my count is less than 20
+If my total is 5
+Break
+
+Build my total up
+
+",
+        )
+        .unwrap();
+        assert_eq!(likely_infinite_loops(&program), vec![3]);
+    }
+
+    #[test]
+    fn dequeue_in_loops() {
+        let program = parser::parse(
+            "Rock the queue with 1, 2, 3
+Put 0 into my item
+Until my item is 3
+Dequeue the queue into my item
+
+While the queue is greater than 0
+Roll the queue
+
+",
+        )
+        .unwrap();
+        assert_eq!(likely_infinite_loops(&program), vec![]);
+    }
+
+    #[test]
+    fn cost_estimates() {
+        let straight =
+            parser::parse("Put 1 into my heart\nSay my heart\nSay my heart plus 1\n").unwrap();
+        let looping = parser::parse(
+            "Put 1 into my heart\nWhile my heart is less than 100\nBuild my heart up\nSay my heart\n",
+        )
+        .unwrap();
+        let recursive = parser::parse(
+            "Midnight takes your heart
+If your heart is 0
+Give back 0
+
+Give back Midnight taking your heart minus 1
+
+Say Midnight taking 5
+",
+        )
+        .unwrap();
+        assert_eq!(estimated_cost(&straight), 3);
+        assert!(estimated_cost(&looping) > estimated_cost(&straight));
+        // Finite, even though Midnight calls itself
+        assert!(estimated_cost(&recursive) > RECURSION_ESTIMATE);
+    }
+}
@@ -0,0 +1,1341 @@
+use crate::common::*;
+#[cfg(feature = "snapshot")]
+use serde_derive::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+
+// Functions provided by the interpreter, rather than declared in the program.
+// A program's own function with the same name takes precedence over these.
+// "The absolute value of X", "the ceiling of X", "the floor of X", "the length of X",
+// "the sum of X", "the product of X", "the code of X", "the character of X",
+// "the digits of X", "the longest of X", "the shortest of X", "the reverse of X",
+// "the sign of X", "the permutations of X" and "the factorial of X" are parsed into calls to abs,
+// ceil, floor, length, sum, product, code, character, digits, longest, shortest, reverse, sign,
+// permutations and factorial, "the average of X" and "the mean of X" into calls to mean,
+// "the index of the biggest of X" into a call to argmax, "the keys of X" and "the values of X"
+// into calls to keys and values, and "the gcd of X and Y" and "the lcm of X and Y" into calls to
+// gcd and lcm. "The count of A in X" is a call to count with X first, as are "the first N of X"
+// and "the last N of X" to first and last, and "the number from X" is a call to from_digits.
+// "A random number between A and B" is a call to random with both.
+const BUILTINS: &[&str] = &[
+    "abs",
+    "argmax",
+    "ceil",
+    "character",
+    "code",
+    "count",
+    "digits",
+    "factorial",
+    "first",
+    "floor",
+    "from_digits",
+    "gcd",
+    "keys",
+    "last",
+    "lcm",
+    "length",
+    "longest",
+    "mean",
+    "permutations",
+    "powmod",
+    "product",
+    "random",
+    "reverse",
+    "shortest",
+    "sign",
+    "sum",
+    "values",
+];
+
+pub fn is_builtin(name: &str) -> bool {
+    BUILTINS.contains(&name.to_lowercase().as_str())
+}
+
+pub fn call(name: &str, args: &[Expression], line: usize) -> Result<Expression> {
+    return match name.to_lowercase().as_str() {
+        "abs" => check_args(args, 1, line).and_then(|_| unary(name, args, f64::abs, line)),
+        "argmax" => check_args(args, 1, line).and_then(|_| argmax(name, args, line)),
+        "ceil" => check_args(args, 1, line).and_then(|_| unary(name, args, f64::ceil, line)),
+        "character" => check_args(args, 1, line).and_then(|_| character(name, args, line)),
+        "code" => check_args(args, 1, line).and_then(|_| code(name, args, line)),
+        "count" => check_args(args, 2, line).and_then(|_| count(name, args, line)),
+        "digits" => check_args(args, 1, line).and_then(|_| digits(name, args, line)),
+        "factorial" => check_args(args, 1, line).and_then(|_| factorial(name, args, line)),
+        "first" => check_args(args, 2, line).and_then(|_| first_or_last(name, args, true, line)),
+        "floor" => check_args(args, 1, line).and_then(|_| unary(name, args, f64::floor, line)),
+        "from_digits" => check_args(args, 1, line).and_then(|_| from_digits(name, args, line)),
+        "gcd" => check_args(args, 2, line).and_then(|_| gcd_or_lcm(name, args, false, line)),
+        "keys" => check_args(args, 1, line).and_then(|_| keys_or_values(name, args, true, line)),
+        "last" => check_args(args, 2, line).and_then(|_| first_or_last(name, args, false, line)),
+        "lcm" => check_args(args, 2, line).and_then(|_| gcd_or_lcm(name, args, true, line)),
+        "length" => check_args(args, 1, line).and_then(|_| length(name, args, line)),
+        "longest" => check_args(args, 1, line).and_then(|_| by_length(name, args, true, line)),
+        "mean" => check_args(args, 1, line).and_then(|_| mean(name, args, line)),
+        "permutations" => check_args(args, 1, line).and_then(|_| permutations(name, args, line)),
+        "powmod" => check_args(args, 3, line).and_then(|_| powmod(name, args, line)),
+        "product" => {
+            check_args(args, 1, line).and_then(|_| fold(name, args, 1.0, |a, b| a * b, line))
+        }
+        "reverse" => check_args(args, 1, line).and_then(|_| reverse(name, args, line)),
+        "shortest" => check_args(args, 1, line).and_then(|_| by_length(name, args, false, line)),
+        "sign" => check_args(args, 1, line).and_then(|_| sign(name, args, line)),
+        "sum" => check_args(args, 1, line).and_then(|_| fold(name, args, 0.0, |a, b| a + b, line)),
+        "values" => check_args(args, 1, line).and_then(|_| keys_or_values(name, args, false, line)),
+        _ => Err(MaidenError::MissingFunction {
+            name: name.to_string(),
+            line,
+        }),
+    };
+}
+
+// A small generator (splitmix64), so a run can be repeated exactly given the same seed.
+// Not suitable for anything that needs to be unpredictable.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "snapshot", derive(Serialize, Deserialize))]
+pub struct Random(u64);
+
+impl Random {
+    pub fn new(seed: u64) -> Random {
+        Random(seed)
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    // Uniform in [0, 1), from the top 53 bits so every value is exact
+    fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+// Kept apart from call, as it's the only built-in that needs state between calls.
+// With no arguments gives a number from 0 up to (but not including) 1, and with two gives a
+// whole number between them, including both ends.
+pub fn random(generator: &mut Random, args: &[Expression], line: usize) -> Result<Expression> {
+    let name = "random";
+    if args.is_empty() {
+        return Ok(Expression::Floating(generator.next_f64()));
+    }
+    check_args(args, 2, line)?;
+    let low = integer_arg(name, &args[0], line)?;
+    let high = integer_arg(name, &args[1], line)?;
+    if low > high {
+        return Err(MaidenError::BadArgument {
+            name: name.to_string(),
+            description: format!("{} is more than {}", low, high),
+            line,
+        });
+    }
+    let span = (high as i128 - low as i128 + 1) as u128;
+    let offset = (generator.next_u64() as u128 % span) as i128;
+    return Ok(Expression::Floating((low as i128 + offset) as f64));
+}
+
+fn check_args(args: &[Expression], expected: usize, line: usize) -> Result<()> {
+    if args.len() != expected {
+        return Err(MaidenError::WrongArgCount {
+            expected,
+            got: args.len(),
+            line,
+        });
+    }
+    Ok(())
+}
+
+fn integer_arg(name: &str, arg: &Expression, line: usize) -> Result<i64> {
+    if let Expression::Floating(f) = arg {
+        if f.fract() == 0.0 && f.abs() <= i64::MAX as f64 {
+            return Ok(*f as i64);
+        }
+    }
+    return Err(MaidenError::BadArgument {
+        name: name.to_string(),
+        description: format!("expected an integer, got {:?}", arg),
+        line,
+    });
+}
+
+fn unary(name: &str, args: &[Expression], op: fn(f64) -> f64, line: usize) -> Result<Expression> {
+    if let Expression::Floating(f) = args[0] {
+        return Ok(Expression::Floating(op(f)));
+    }
+    return Err(MaidenError::BadArgument {
+        name: name.to_string(),
+        description: format!("expected a number, got {:?}", args[0]),
+        line,
+    });
+}
+
+// -1, 0 or 1. Negative zero is 0, and NaN has no sign, so it's an error rather than some
+// number that would send a branch the wrong way.
+fn sign(name: &str, args: &[Expression], line: usize) -> Result<Expression> {
+    match args[0] {
+        Expression::Floating(f) if f.is_nan() => Err(MaidenError::NotANumber {
+            description: "the sign of NaN".to_string(),
+            line,
+        }),
+        Expression::Floating(f) => Ok(Expression::Floating(if f > 0.0 {
+            1.0
+        } else if f < 0.0 {
+            -1.0
+        } else {
+            0.0
+        })),
+        ref other => Err(MaidenError::BadArgument {
+            name: name.to_string(),
+            description: format!("expected a number, got {:?}", other),
+            line,
+        }),
+    }
+}
+
+// Arrays have the same length that "Say" prints for them, strings count code points
+fn length(name: &str, args: &[Expression], line: usize) -> Result<Expression> {
+    let length = match &args[0] {
+        Expression::String(s) => s.chars().count(),
+        Expression::Array { numeric, .. } => numeric.keys().max().map_or(0, |x| x + 1),
+        other => {
+            return Err(MaidenError::BadArgument {
+                name: name.to_string(),
+                description: format!("expected a string or array, got {:?}", other),
+                line,
+            });
+        }
+    };
+    return Ok(Expression::Floating(length as f64));
+}
+
+// The code point of the first character, so "the code of \"AB\"" is 65
+fn code(name: &str, args: &[Expression], line: usize) -> Result<Expression> {
+    let first = match &args[0] {
+        Expression::String(s) => s.chars().next(),
+        other => {
+            return Err(MaidenError::BadArgument {
+                name: name.to_string(),
+                description: format!("expected a string, got {:?}", other),
+                line,
+            });
+        }
+    };
+    return match first {
+        Some(c) => Ok(Expression::Floating(c as u32 as f64)),
+        None => Err(MaidenError::BadArgument {
+            name: name.to_string(),
+            description: "empty string".to_string(),
+            line,
+        }),
+    };
+}
+
+fn character(name: &str, args: &[Expression], line: usize) -> Result<Expression> {
+    let code = integer_arg(name, &args[0], line)?;
+    return match u32::try_from(code).ok().and_then(char::from_u32) {
+        Some(c) => Ok(Expression::String(c.to_string())),
+        None => Err(MaidenError::BadArgument {
+            name: name.to_string(),
+            description: format!("{} isn't a valid code point", code),
+            line,
+        }),
+    };
+}
+
+// Most significant first, so 0 is [0]. Only for whole numbers from 0 upwards, as there's no one
+// right answer for where a sign or decimal point would go.
+fn digits(name: &str, args: &[Expression], line: usize) -> Result<Expression> {
+    let value = integer_arg(name, &args[0], line)?;
+    if value < 0 {
+        return Err(MaidenError::BadArgument {
+            name: name.to_string(),
+            description: format!("expected a number from 0 upwards, got {}", value),
+            line,
+        });
+    }
+    return Ok(Expression::Array {
+        numeric: value
+            .to_string()
+            .chars()
+            .map(|digit| Box::new(Expression::Floating(f64::from(digit as u8 - b'0'))))
+            .enumerate()
+            .collect(),
+        strings: BTreeMap::new(),
+    });
+}
+
+// A reversed copy. Strings are reversed by code point. Array entries swap ends, so any gaps
+// between indexes are mirrored too, and string keys are left where they are.
+fn reverse(name: &str, args: &[Expression], line: usize) -> Result<Expression> {
+    return match &args[0] {
+        Expression::String(s) => Ok(Expression::String(s.chars().rev().collect())),
+        Expression::Array { numeric, strings } => {
+            let last = numeric.keys().max().copied().unwrap_or(0);
+            Ok(Expression::Array {
+                numeric: numeric
+                    .iter()
+                    .map(|(index, value)| (last - index, value.clone()))
+                    .collect(),
+                strings: strings.clone(),
+            })
+        }
+        other => Err(MaidenError::BadArgument {
+            name: name.to_string(),
+            description: format!("expected a string or an array, got {:?}", other),
+            line,
+        }),
+    };
+}
+
+// Every ordering of an array's entries, as an array of arrays. They come in the order of where
+// each entry was taken from, so [1, 2, 3] gives [1, 2, 3], [1, 3, 2], [2, 1, 3] and so on, and an
+// empty array has just the one, empty, ordering. Any gaps between indexes are closed up, and
+// string keys are left out. There are n! of them, so the runner checks its limits first.
+fn permutations(name: &str, args: &[Expression], line: usize) -> Result<Expression> {
+    let entries: Vec<&Expression> = match &args[0] {
+        Expression::Array { numeric, .. } => numeric.values().map(|entry| &**entry).collect(),
+        other => {
+            return Err(MaidenError::BadArgument {
+                name: name.to_string(),
+                description: format!("expected an array, got {:?}", other),
+                line,
+            });
+        }
+    };
+    let mut order: Vec<usize> = (0..entries.len()).collect();
+    let mut all = BTreeMap::new();
+    loop {
+        let permutation = Expression::Array {
+            numeric: order
+                .iter()
+                .map(|&index| Box::new(entries[index].clone()))
+                .enumerate()
+                .collect(),
+            strings: BTreeMap::new(),
+        };
+        all.insert(all.len(), Box::new(permutation));
+        // The next ordering up: the last index that's smaller than the one after it swaps with
+        // the smallest bigger index after it, and everything after it goes back to ascending
+        let pivot = match (1..order.len()).rev().find(|&i| order[i - 1] < order[i]) {
+            Some(i) => i - 1,
+            None => break,
+        };
+        let next = (pivot + 1..order.len())
+            .rev()
+            .find(|&i| order[i] > order[pivot])
+            .unwrap();
+        order.swap(pivot, next);
+        order[pivot + 1..].reverse();
+    }
+    return Ok(Expression::Array {
+        numeric: all,
+        strings: BTreeMap::new(),
+    });
+}
+
+// Worked out with a loop rather than recursion, so a big one can't run out of stack. Like any
+// other multiplication, it's Infinity once it gets past the biggest number, which happens for
+// anything over 170, so it stops there rather than going round for ever.
+fn factorial(name: &str, args: &[Expression], line: usize) -> Result<Expression> {
+    let value = integer_arg(name, &args[0], line)?;
+    if value < 0 {
+        return Err(MaidenError::BadArgument {
+            name: name.to_string(),
+            description: format!("expected a number from 0 upwards, got {}", value),
+            line,
+        });
+    }
+    let mut result = 1.0;
+    for n in 2..=value {
+        result *= n as f64;
+        if result.is_infinite() {
+            break;
+        }
+    }
+    return Ok(Expression::Floating(result));
+}
+
+// The other way from digits, so every entry has to be a whole number from 0 to 9. No digits at
+// all make 0, as adding no digits to a number leaves it as it was.
+fn from_digits(name: &str, args: &[Expression], line: usize) -> Result<Expression> {
+    let numeric = match &args[0] {
+        Expression::Array { numeric, .. } => numeric,
+        other => {
+            return Err(MaidenError::BadArgument {
+                name: name.to_string(),
+                description: format!("expected an array, got {:?}", other),
+                line,
+            });
+        }
+    };
+    let mut total = 0.0;
+    for value in numeric.values() {
+        match value.as_ref() {
+            Expression::Floating(digit) if (0.0..=9.0).contains(digit) && digit.fract() == 0.0 => {
+                total = total * 10.0 + digit;
+            }
+            other => {
+                return Err(MaidenError::BadArgument {
+                    name: name.to_string(),
+                    description: format!("expected a digit, got {:?}", other),
+                    line,
+                });
+            }
+        }
+    }
+    return Ok(Expression::Floating(total));
+}
+
+// Up to count characters or entries from the start or the end, so asking for more than there
+// are gives all of them. Arrays keep their order and are numbered again from 0, without any
+// string keys.
+fn first_or_last(name: &str, args: &[Expression], first: bool, line: usize) -> Result<Expression> {
+    let count = integer_arg(name, &args[1], line)?;
+    if count < 0 {
+        return Err(MaidenError::BadArgument {
+            name: name.to_string(),
+            description: format!("expected a count from 0 upwards, got {}", count),
+            line,
+        });
+    }
+    let count = count as usize;
+    return match &args[0] {
+        Expression::String(s) => {
+            let length = s.chars().count();
+            let skip = if first {
+                0
+            } else {
+                length.saturating_sub(count)
+            };
+            Ok(Expression::String(
+                s.chars().skip(skip).take(count).collect(),
+            ))
+        }
+        Expression::Array { numeric, .. } => {
+            let skip = if first {
+                0
+            } else {
+                numeric.len().saturating_sub(count)
+            };
+            Ok(Expression::Array {
+                numeric: numeric
+                    .values()
+                    .skip(skip)
+                    .take(count)
+                    .cloned()
+                    .enumerate()
+                    .collect(),
+                strings: BTreeMap::new(),
+            })
+        }
+        other => Err(MaidenError::BadArgument {
+            name: name.to_string(),
+            description: format!("expected a string or an array, got {:?}", other),
+            line,
+        }),
+    };
+}
+
+// Non-overlapping matches in a string, or equal entries in an array's numeric indexes.
+// An empty string is in every string 0 times, rather than once between each character.
+fn count(name: &str, args: &[Expression], line: usize) -> Result<Expression> {
+    let found = match (&args[0], &args[1]) {
+        (Expression::String(haystack), Expression::String(needle)) => {
+            if needle.is_empty() {
+                0
+            } else {
+                haystack.matches(needle.as_str()).count()
+            }
+        }
+        (Expression::String(_), other) => {
+            return Err(MaidenError::BadArgument {
+                name: name.to_string(),
+                description: format!("can only count strings in a string, got {:?}", other),
+                line,
+            });
+        }
+        (Expression::Array { numeric, .. }, needle) => numeric
+            .values()
+            .filter(|value| value.as_ref() == needle)
+            .count(),
+        (other, _) => {
+            return Err(MaidenError::BadArgument {
+                name: name.to_string(),
+                description: format!("expected a string or array, got {:?}", other),
+                line,
+            });
+        }
+    };
+    return Ok(Expression::Floating(found as f64));
+}
+
+// An array is also the map type, so this gives the numeric indexes in order and then the string
+// ones, alphabetically as they're kept sorted rather than in the order they were added
+fn keys_or_values(name: &str, args: &[Expression], keys: bool, line: usize) -> Result<Expression> {
+    let (numeric, strings) = match &args[0] {
+        Expression::Array { numeric, strings } => (numeric, strings),
+        other => {
+            return Err(MaidenError::BadArgument {
+                name: name.to_string(),
+                description: format!("expected an array, got {:?}", other),
+                line,
+            });
+        }
+    };
+    let entries: Vec<Expression> = if keys {
+        numeric
+            .keys()
+            .map(|index| Expression::Floating(*index as f64))
+            .chain(strings.keys().map(|key| Expression::String(key.clone())))
+            .collect()
+    } else {
+        numeric
+            .values()
+            .chain(strings.values())
+            .map(|value| *value.clone())
+            .collect()
+    };
+    return Ok(Expression::Array {
+        numeric: entries.into_iter().map(Box::new).enumerate().collect(),
+        strings: BTreeMap::new(),
+    });
+}
+
+// Over the numeric indexes in order, so an empty array gives back the initial value
+fn fold(
+    name: &str,
+    args: &[Expression],
+    initial: f64,
+    op: fn(f64, f64) -> f64,
+    line: usize,
+) -> Result<Expression> {
+    let numeric = match &args[0] {
+        Expression::Array { numeric, .. } => numeric,
+        other => {
+            return Err(MaidenError::BadArgument {
+                name: name.to_string(),
+                description: format!("expected an array, got {:?}", other),
+                line,
+            });
+        }
+    };
+    let mut result = initial;
+    for (index, value) in numeric {
+        match value.as_ref() {
+            Expression::Floating(f) => result = op(result, *f),
+            other => {
+                return Err(MaidenError::BadArgument {
+                    name: name.to_string(),
+                    description: format!("element {} is not a number, got {:?}", index, other),
+                    line,
+                });
+            }
+        }
+    }
+    return Ok(Expression::Floating(result));
+}
+
+// An empty array has no average, so gives mysterious rather than dividing by zero
+fn mean(name: &str, args: &[Expression], line: usize) -> Result<Expression> {
+    let total = fold(name, args, 0.0, |a, b| a + b, line)?;
+    return Ok(match (&args[0], total) {
+        (Expression::Array { numeric, .. }, Expression::Floating(total)) if !numeric.is_empty() => {
+            Expression::Floating(total / numeric.len() as f64)
+        }
+        _ => Expression::Mysterious,
+    });
+}
+
+// Index of the biggest of the numeric entries, which must be all numbers or all strings. Strings
+// are compared by code point. The first one wins a tie, and an empty array gives mysterious.
+fn argmax(name: &str, args: &[Expression], line: usize) -> Result<Expression> {
+    let numeric = match &args[0] {
+        Expression::Array { numeric, .. } => numeric,
+        other => {
+            return Err(MaidenError::BadArgument {
+                name: name.to_string(),
+                description: format!("expected an array, got {:?}", other),
+                line,
+            });
+        }
+    };
+    let mut best: Option<(usize, &Expression)> = None;
+    for (index, value) in numeric {
+        let bigger = match (best, value.as_ref()) {
+            (None, Expression::Floating(_)) | (None, Expression::String(_)) => true,
+            (Some((_, Expression::Floating(old))), Expression::Floating(new)) => new > old,
+            (Some((_, Expression::String(old))), Expression::String(new)) => new > old,
+            (_, other) => {
+                return Err(MaidenError::BadArgument {
+                    name: name.to_string(),
+                    description: format!("can't compare {:?} with the other entries", other),
+                    line,
+                });
+            }
+        };
+        if bigger {
+            best = Some((*index, value));
+        }
+    }
+    return Ok(match best {
+        Some((index, _)) => Expression::Floating(index as f64),
+        None => Expression::Mysterious,
+    });
+}
+
+// The longest (or shortest) string entry, counting code points, with the first one winning a
+// tie. Like argmax, there's no answer for an empty array, so that's Mysterious.
+fn by_length(name: &str, args: &[Expression], longest: bool, line: usize) -> Result<Expression> {
+    let numeric = match &args[0] {
+        Expression::Array { numeric, .. } => numeric,
+        other => {
+            return Err(MaidenError::BadArgument {
+                name: name.to_string(),
+                description: format!("expected an array, got {:?}", other),
+                line,
+            });
+        }
+    };
+    let mut best: Option<(usize, &String)> = None;
+    for value in numeric.values() {
+        let text = match value.as_ref() {
+            Expression::String(text) => text,
+            other => {
+                return Err(MaidenError::BadArgument {
+                    name: name.to_string(),
+                    description: format!("expected a string, got {:?}", other),
+                    line,
+                });
+            }
+        };
+        let length = text.chars().count();
+        let better = match best {
+            None => true,
+            Some((old, _)) if longest => length > old,
+            Some((old, _)) => length < old,
+        };
+        if better {
+            best = Some((length, text));
+        }
+    }
+    return Ok(match best {
+        Some((_, text)) => Expression::String(text.clone()),
+        None => Expression::Mysterious,
+    });
+}
+
+// Both are never negative, and the gcd of 0 and 0 is 0, as is the lcm of 0 and anything
+fn gcd_or_lcm(name: &str, args: &[Expression], lcm: bool, line: usize) -> Result<Expression> {
+    let first = integer_arg(name, &args[0], line)?.unsigned_abs();
+    let second = integer_arg(name, &args[1], line)?.unsigned_abs();
+    let (mut a, mut b) = (first, second);
+    while b != 0 {
+        let remainder = a % b;
+        a = b;
+        b = remainder;
+    }
+    if !lcm {
+        return Ok(Expression::Floating(a as f64));
+    }
+    if a == 0 {
+        return Ok(Expression::Floating(0.0));
+    }
+    return match (first / a).checked_mul(second) {
+        Some(result) => Ok(Expression::Floating(result as f64)),
+        None => Err(MaidenError::BadArgument {
+            name: name.to_string(),
+            description: "result is too large".to_string(),
+            line,
+        }),
+    };
+}
+
+fn powmod(name: &str, args: &[Expression], line: usize) -> Result<Expression> {
+    let base = integer_arg(name, &args[0], line)?;
+    let exponent = integer_arg(name, &args[1], line)?;
+    let modulus = integer_arg(name, &args[2], line)?;
+    if exponent < 0 {
+        return Err(MaidenError::BadArgument {
+            name: name.to_string(),
+            description: format!("negative exponent {}", exponent),
+            line,
+        });
+    }
+    if modulus <= 0 {
+        return Err(MaidenError::BadArgument {
+            name: name.to_string(),
+            description: format!("non-positive modulus {}", modulus),
+            line,
+        });
+    }
+
+    // Square-and-multiply in u128, so the intermediate products can't overflow
+    let modulus = modulus as u128;
+    let mut base = base.rem_euclid(modulus as i64) as u128;
+    let mut exponent = exponent as u64;
+    let mut result = 1 % modulus;
+    while exponent > 0 {
+        if exponent & 1 == 1 {
+            result = result * base % modulus;
+        }
+        base = base * base % modulus;
+        exponent >>= 1;
+    }
+    return Ok(Expression::Floating(result as f64));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{call, is_builtin, random, Random};
+    use crate::common::{Expression, MaidenError};
+    use std::collections::BTreeMap;
+
+    fn powmod(base: f64, exponent: f64, modulus: f64) -> Expression {
+        let args = [
+            Expression::Floating(base),
+            Expression::Floating(exponent),
+            Expression::Floating(modulus),
+        ];
+        call("powmod", &args, 1).unwrap()
+    }
+
+    #[test]
+    fn powmod_results() {
+        assert_eq!(powmod(4.0, 13.0, 497.0), Expression::Floating(445.0));
+        assert_eq!(powmod(2.0, 10.0, 1000.0), Expression::Floating(24.0));
+        assert_eq!(powmod(3.0, 0.0, 7.0), Expression::Floating(1.0));
+        assert_eq!(powmod(5.0, 3.0, 1.0), Expression::Floating(0.0));
+        assert_eq!(powmod(-2.0, 3.0, 5.0), Expression::Floating(2.0));
+        // Would lose precision as a float long before the modulus is applied
+        assert_eq!(
+            powmod(123456789.0, 1000000.0, 1000000007.0),
+            Expression::Floating(471040903.0)
+        );
+    }
+
+    #[test]
+    fn powmod_bad_arguments() {
+        for args in [
+            [1.5, 2.0, 3.0],
+            [2.0, -1.0, 3.0],
+            [2.0, 2.0, 0.0],
+            [2.0, 2.0, -3.0],
+        ] {
+            let args: Vec<Expression> = args.iter().map(|f| Expression::Floating(*f)).collect();
+            match call("powmod", &args, 4) {
+                Err(MaidenError::BadArgument { name, line, .. }) => {
+                    assert_eq!(name, "powmod");
+                    assert_eq!(line, 4);
+                }
+                other => panic!("{:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn abs_and_length() {
+        assert_eq!(
+            call("abs", &[Expression::Floating(-2.5)], 1).unwrap(),
+            Expression::Floating(2.5)
+        );
+        assert_eq!(
+            call("length", &[Expression::String("naïve".to_string())], 1).unwrap(),
+            Expression::Floating(5.0)
+        );
+        let mut numeric = BTreeMap::new();
+        numeric.insert(2, Box::new(Expression::Null));
+        let array = Expression::Array {
+            numeric,
+            strings: BTreeMap::new(),
+        };
+        assert_eq!(
+            call("length", &[array], 1).unwrap(),
+            Expression::Floating(3.0)
+        );
+        match call("length", &[Expression::Floating(1.0)], 2) {
+            Err(MaidenError::BadArgument { name, line, .. }) => {
+                assert_eq!(name, "length");
+                assert_eq!(line, 2);
+            }
+            other => panic!("{:?}", other),
+        }
+    }
+
+    fn array(values: Vec<Expression>) -> Expression {
+        Expression::Array {
+            numeric: values.into_iter().map(Box::new).enumerate().collect(),
+            strings: BTreeMap::new(),
+        }
+    }
+
+    #[test]
+    fn means() {
+        let numbers = array(vec![
+            Expression::Floating(2.0),
+            Expression::Floating(3.5),
+            Expression::Floating(-4.0),
+            Expression::Floating(10.5),
+        ]);
+        assert_eq!(
+            call("mean", &[numbers], 1).unwrap(),
+            Expression::Floating(3.0)
+        );
+        assert_eq!(
+            call("mean", &[array(vec![])], 1).unwrap(),
+            Expression::Mysterious
+        );
+        let mixed = array(vec![Expression::String("one".to_string())]);
+        match call("mean", &[mixed], 4) {
+            Err(MaidenError::BadArgument { name, line, .. }) => {
+                assert_eq!(name, "mean");
+                assert_eq!(line, 4);
+            }
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn random_numbers() {
+        let mut first = Random::new(42);
+        let mut second = Random::new(42);
+        for _ in 0..100 {
+            let fraction = random(&mut first, &[], 1).unwrap();
+            assert_eq!(fraction, random(&mut second, &[], 1).unwrap());
+            match fraction {
+                Expression::Floating(f) => assert!((0.0..1.0).contains(&f)),
+                other => panic!("Not a number: {:?}", other),
+            }
+            let bounds = [Expression::Floating(-2.0), Expression::Floating(3.0)];
+            let roll = random(&mut first, &bounds, 1).unwrap();
+            assert_eq!(roll, random(&mut second, &bounds, 1).unwrap());
+            match roll {
+                Expression::Floating(f) => {
+                    assert!((-2.0..=3.0).contains(&f));
+                    assert_eq!(f.fract(), 0.0);
+                }
+                other => panic!("Not a number: {:?}", other),
+            }
+        }
+        assert_ne!(
+            random(&mut Random::new(1), &[], 1).unwrap(),
+            random(&mut Random::new(2), &[], 1).unwrap()
+        );
+        let single = [Expression::Floating(7.0), Expression::Floating(7.0)];
+        assert_eq!(
+            random(&mut first, &single, 1).unwrap(),
+            Expression::Floating(7.0)
+        );
+        let backwards = [Expression::Floating(3.0), Expression::Floating(1.0)];
+        match random(&mut first, &backwards, 2) {
+            Err(MaidenError::BadArgument { name, line, .. }) => {
+                assert_eq!(name, "random");
+                assert_eq!(line, 2);
+            }
+            other => panic!("Expected a bad argument, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn digits() {
+        let digits = |values: &[f64]| {
+            array(
+                values
+                    .iter()
+                    .map(|value| Expression::Floating(*value))
+                    .collect(),
+            )
+        };
+        assert_eq!(
+            call("digits", &[Expression::Floating(4096.0)], 1).unwrap(),
+            digits(&[4.0, 0.0, 9.0, 6.0])
+        );
+        assert_eq!(
+            call("digits", &[Expression::Floating(0.0)], 1).unwrap(),
+            digits(&[0.0])
+        );
+        for bad in [-12.0, 1.5] {
+            match call("digits", &[Expression::Floating(bad)], 2) {
+                Err(MaidenError::BadArgument { name, line, .. }) => {
+                    assert_eq!(name, "digits");
+                    assert_eq!(line, 2);
+                }
+                other => panic!("{:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn from_digits() {
+        let number = Expression::Floating(90210.0);
+        let digits = call("digits", std::slice::from_ref(&number), 1).unwrap();
+        assert_eq!(call("from_digits", &[digits], 1).unwrap(), number);
+        assert_eq!(
+            call("from_digits", &[array(vec![])], 1).unwrap(),
+            Expression::Floating(0.0)
+        );
+        for bad in [
+            Expression::Floating(10.0),
+            Expression::Floating(2.5),
+            Expression::String("7".to_string()),
+        ] {
+            match call("from_digits", &[array(vec![bad])], 3) {
+                Err(MaidenError::BadArgument { name, line, .. }) => {
+                    assert_eq!(name, "from_digits");
+                    assert_eq!(line, 3);
+                }
+                other => panic!("{:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn longest_and_shortest() {
+        let words = || {
+            array(vec![
+                Expression::String("rock".to_string()),
+                Expression::String("ballad".to_string()),
+                Expression::String("riff".to_string()),
+                Expression::String("über".to_string()),
+                Expression::String("encore".to_string()),
+            ])
+        };
+        assert_eq!(
+            call("longest", &[words()], 1).unwrap(),
+            Expression::String("ballad".to_string())
+        );
+        assert_eq!(
+            call("shortest", &[words()], 1).unwrap(),
+            Expression::String("rock".to_string())
+        );
+        let clear = array(vec![
+            Expression::String("a".to_string()),
+            Expression::String("abc".to_string()),
+        ]);
+        assert_eq!(
+            call("shortest", &[clear], 1).unwrap(),
+            Expression::String("a".to_string())
+        );
+        assert_eq!(
+            call("longest", &[array(vec![])], 1).unwrap(),
+            Expression::Mysterious
+        );
+        let mixed = array(vec![
+            Expression::String("rock".to_string()),
+            Expression::Floating(7.0),
+        ]);
+        match call("shortest", &[mixed], 4) {
+            Err(MaidenError::BadArgument { name, line, .. }) => {
+                assert_eq!(name, "shortest");
+                assert_eq!(line, 4);
+            }
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn first_and_last() {
+        let numbers = || {
+            array(vec![
+                Expression::Floating(1.0),
+                Expression::Floating(2.0),
+                Expression::Floating(3.0),
+                Expression::Floating(4.0),
+            ])
+        };
+        let two = Expression::Floating(2.0);
+        assert_eq!(
+            call("first", &[numbers(), two.clone()], 1).unwrap(),
+            array(vec![Expression::Floating(1.0), Expression::Floating(2.0)])
+        );
+        assert_eq!(
+            call("last", &[numbers(), two.clone()], 1).unwrap(),
+            array(vec![Expression::Floating(3.0), Expression::Floating(4.0)])
+        );
+        let word = || Expression::String("héllo".to_string());
+        assert_eq!(
+            call("first", &[word(), two.clone()], 1).unwrap(),
+            Expression::String("hé".to_string())
+        );
+        assert_eq!(
+            call("last", &[word(), two], 1).unwrap(),
+            Expression::String("lo".to_string())
+        );
+        let lots = Expression::Floating(10.0);
+        assert_eq!(
+            call("first", &[numbers(), lots.clone()], 1).unwrap(),
+            numbers()
+        );
+        assert_eq!(call("last", &[word(), lots], 1).unwrap(), word());
+        for bad in [Expression::Floating(-1.0), Expression::Floating(1.5)] {
+            match call("first", &[numbers(), bad], 6) {
+                Err(MaidenError::BadArgument { name, line, .. }) => {
+                    assert_eq!(name, "first");
+                    assert_eq!(line, 6);
+                }
+                other => panic!("{:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn reverse() {
+        assert_eq!(
+            call(
+                "reverse",
+                &[Expression::String("naïve café".to_string())],
+                1
+            )
+            .unwrap(),
+            Expression::String("éfac evïan".to_string())
+        );
+        let numbers = array(vec![
+            Expression::Floating(1.0),
+            Expression::Floating(2.0),
+            Expression::Floating(3.0),
+        ]);
+        assert_eq!(
+            call("reverse", &[numbers], 1).unwrap(),
+            array(vec![
+                Expression::Floating(3.0),
+                Expression::Floating(2.0),
+                Expression::Floating(1.0),
+            ])
+        );
+        assert_eq!(call("reverse", &[array(vec![])], 1).unwrap(), array(vec![]));
+        match call("reverse", &[Expression::Floating(12.0)], 2) {
+            Err(MaidenError::BadArgument { name, line, .. }) => {
+                assert_eq!(name, "reverse");
+                assert_eq!(line, 2);
+            }
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn argmax() {
+        let numbers = array(vec![
+            Expression::Floating(2.0),
+            Expression::Floating(9.5),
+            Expression::Floating(-4.0),
+        ]);
+        assert_eq!(
+            call("argmax", &[numbers], 1).unwrap(),
+            Expression::Floating(1.0)
+        );
+        let ties = array(vec![
+            Expression::Floating(1.0),
+            Expression::Floating(7.0),
+            Expression::Floating(3.0),
+            Expression::Floating(7.0),
+        ]);
+        assert_eq!(
+            call("argmax", &[ties], 1).unwrap(),
+            Expression::Floating(1.0)
+        );
+        let words = array(vec![
+            Expression::String("rock".to_string()),
+            Expression::String("paper".to_string()),
+            Expression::String("rocket".to_string()),
+        ]);
+        assert_eq!(
+            call("argmax", &[words], 1).unwrap(),
+            Expression::Floating(2.0)
+        );
+        assert_eq!(
+            call("argmax", &[array(vec![])], 1).unwrap(),
+            Expression::Mysterious
+        );
+        let mixed = array(vec![
+            Expression::Floating(1.0),
+            Expression::String("one".to_string()),
+        ]);
+        match call("argmax", &[mixed], 5) {
+            Err(MaidenError::BadArgument { name, line, .. }) => {
+                assert_eq!(name, "argmax");
+                assert_eq!(line, 5);
+            }
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn keys_and_values() {
+        let mut map = array(vec![Expression::True]);
+        if let Expression::Array { strings, .. } = &mut map {
+            strings.insert("b".to_string(), Box::new(Expression::Floating(2.0)));
+            strings.insert("a".to_string(), Box::new(Expression::Floating(1.0)));
+        }
+        assert_eq!(
+            call("keys", &[map.clone()], 1).unwrap(),
+            array(vec![
+                Expression::Floating(0.0),
+                Expression::String("a".to_string()),
+                Expression::String("b".to_string()),
+            ])
+        );
+        assert_eq!(
+            call("values", &[map], 1).unwrap(),
+            array(vec![
+                Expression::True,
+                Expression::Floating(1.0),
+                Expression::Floating(2.0),
+            ])
+        );
+        assert_eq!(call("keys", &[array(vec![])], 1).unwrap(), array(vec![]));
+        match call("values", &[Expression::String("map".to_string())], 3) {
+            Err(MaidenError::BadArgument { name, line, .. }) => {
+                assert_eq!(name, "values");
+                assert_eq!(line, 3);
+            }
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn sum_and_product() {
+        let numbers = array(vec![
+            Expression::Floating(2.0),
+            Expression::Floating(3.5),
+            Expression::Floating(-4.0),
+        ]);
+        assert_eq!(
+            call("sum", std::slice::from_ref(&numbers), 1).unwrap(),
+            Expression::Floating(1.5)
+        );
+        assert_eq!(
+            call("product", &[numbers], 1).unwrap(),
+            Expression::Floating(-28.0)
+        );
+        assert_eq!(
+            call("sum", &[array(vec![])], 1).unwrap(),
+            Expression::Floating(0.0)
+        );
+        assert_eq!(
+            call("product", &[array(vec![])], 1).unwrap(),
+            Expression::Floating(1.0)
+        );
+        let mixed = array(vec![
+            Expression::Floating(1.0),
+            Expression::String("two".to_string()),
+        ]);
+        match call("sum", &[mixed], 6) {
+            Err(MaidenError::BadArgument { name, line, .. }) => {
+                assert_eq!(name, "sum");
+                assert_eq!(line, 6);
+            }
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn ceil_and_floor() {
+        for (value, ceil, floor) in [(2.5, 3.0, 2.0), (-2.5, -2.0, -3.0), (4.0, 4.0, 4.0)] {
+            assert_eq!(
+                call("ceil", &[Expression::Floating(value)], 1).unwrap(),
+                Expression::Floating(ceil)
+            );
+            assert_eq!(
+                call("floor", &[Expression::Floating(value)], 1).unwrap(),
+                Expression::Floating(floor)
+            );
+        }
+        match call("floor", &[Expression::String("low".to_string())], 3) {
+            Err(MaidenError::BadArgument { name, line, .. }) => {
+                assert_eq!(name, "floor");
+                assert_eq!(line, 3);
+            }
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn character_codes() {
+        assert_eq!(
+            call("code", &[Expression::String("AB".to_string())], 1).unwrap(),
+            Expression::Floating(65.0)
+        );
+        assert_eq!(
+            call("character", &[Expression::Floating(233.0)], 1).unwrap(),
+            Expression::String("é".to_string())
+        );
+        for (name, arg) in [
+            ("code", Expression::String("".to_string())),
+            ("code", Expression::Floating(65.0)),
+            ("character", Expression::Floating(-1.0)),
+            ("character", Expression::Floating(55296.0)),
+            ("character", Expression::Floating(1114112.0)),
+        ] {
+            match call(name, &[arg], 5) {
+                Err(MaidenError::BadArgument {
+                    name: got, line, ..
+                }) => {
+                    assert_eq!(got, name);
+                    assert_eq!(line, 5);
+                }
+                other => panic!("{:?}", other),
+            }
+        }
+    }
+
+    fn pair(name: &str, first: f64, second: f64) -> Result<Expression, MaidenError> {
+        call(
+            name,
+            &[Expression::Floating(first), Expression::Floating(second)],
+            1,
+        )
+    }
+
+    #[test]
+    fn gcd_and_lcm() {
+        assert_eq!(pair("gcd", 12.0, 18.0).unwrap(), Expression::Floating(6.0));
+        assert_eq!(pair("lcm", 12.0, 18.0).unwrap(), Expression::Floating(36.0));
+        assert_eq!(pair("gcd", 9.0, 28.0).unwrap(), Expression::Floating(1.0));
+        assert_eq!(pair("lcm", 9.0, 28.0).unwrap(), Expression::Floating(252.0));
+        assert_eq!(pair("gcd", -4.0, 6.0).unwrap(), Expression::Floating(2.0));
+        assert_eq!(pair("gcd", 0.0, 0.0).unwrap(), Expression::Floating(0.0));
+        assert_eq!(pair("gcd", 0.0, 5.0).unwrap(), Expression::Floating(5.0));
+        assert_eq!(pair("lcm", 0.0, 5.0).unwrap(), Expression::Floating(0.0));
+        match pair("gcd", 2.5, 5.0) {
+            Err(MaidenError::BadArgument { name, .. }) => assert_eq!(name, "gcd"),
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn counts() {
+        let string = |s: &str| Expression::String(s.to_string());
+        let count = |haystack, needle| call("count", &[haystack, needle], 1).unwrap();
+        assert_eq!(
+            count(string("banana"), string("an")),
+            Expression::Floating(2.0)
+        );
+        assert_eq!(
+            count(string("aaaa"), string("aa")),
+            Expression::Floating(2.0)
+        );
+        assert_eq!(
+            count(string("banana"), string("x")),
+            Expression::Floating(0.0)
+        );
+        assert_eq!(
+            count(string("banana"), string("")),
+            Expression::Floating(0.0)
+        );
+        let numbers = array(vec![
+            Expression::Floating(1.0),
+            Expression::Floating(2.0),
+            Expression::Floating(1.0),
+        ]);
+        assert_eq!(
+            count(numbers.clone(), Expression::Floating(1.0)),
+            Expression::Floating(2.0)
+        );
+        assert_eq!(
+            count(numbers, Expression::Floating(3.0)),
+            Expression::Floating(0.0)
+        );
+        match call("count", &[Expression::Floating(1.0), string("a")], 2) {
+            Err(MaidenError::BadArgument { name, line, .. }) => {
+                assert_eq!(name, "count");
+                assert_eq!(line, 2);
+            }
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn factorial() {
+        for (value, expected) in [
+            (0.0, 1.0),
+            (1.0, 1.0),
+            (5.0, 120.0),
+            (20.0, 2432902008176640000.0),
+        ] {
+            assert_eq!(
+                call("factorial", &[Expression::Floating(value)], 1).unwrap(),
+                Expression::Floating(expected)
+            );
+        }
+        assert_eq!(
+            call("factorial", &[Expression::Floating(1e15)], 1).unwrap(),
+            Expression::Floating(f64::INFINITY)
+        );
+        for bad in [Expression::Floating(-1.0), Expression::Floating(2.5)] {
+            match call("factorial", &[bad], 2) {
+                Err(MaidenError::BadArgument { name, line, .. }) => {
+                    assert_eq!(name, "factorial");
+                    assert_eq!(line, 2);
+                }
+                other => panic!("{:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn permutations() {
+        let numbers = |values: &[f64]| {
+            array(
+                values
+                    .iter()
+                    .map(|&value| Expression::Floating(value))
+                    .collect(),
+            )
+        };
+        assert_eq!(
+            call("permutations", &[numbers(&[1.0, 2.0, 3.0])], 1).unwrap(),
+            array(vec![
+                numbers(&[1.0, 2.0, 3.0]),
+                numbers(&[1.0, 3.0, 2.0]),
+                numbers(&[2.0, 1.0, 3.0]),
+                numbers(&[2.0, 3.0, 1.0]),
+                numbers(&[3.0, 1.0, 2.0]),
+                numbers(&[3.0, 2.0, 1.0]),
+            ])
+        );
+        // Equal entries are still different orderings
+        match call("permutations", &[numbers(&[1.0, 1.0])], 1).unwrap() {
+            Expression::Array { numeric, .. } => assert_eq!(numeric.len(), 2),
+            other => panic!("{:?}", other),
+        }
+        assert_eq!(
+            call("permutations", &[array(vec![])], 1).unwrap(),
+            array(vec![array(vec![])])
+        );
+        match call("permutations", &[Expression::String("abc".to_string())], 2) {
+            Err(MaidenError::BadArgument { name, line, .. }) => {
+                assert_eq!(name, "permutations");
+                assert_eq!(line, 2);
+            }
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn sign() {
+        for (value, expected) in [(-2.5, -1.0), (0.0, 0.0), (-0.0, 0.0), (7.0, 1.0)] {
+            assert_eq!(
+                call("sign", &[Expression::Floating(value)], 1).unwrap(),
+                Expression::Floating(expected)
+            );
+        }
+        match call("sign", &[Expression::Floating(f64::NAN)], 2) {
+            Err(MaidenError::NotANumber { line, .. }) => assert_eq!(line, 2),
+            other => panic!("{:?}", other),
+        }
+        match call("sign", &[Expression::String("-1".to_string())], 3) {
+            Err(MaidenError::BadArgument { name, line, .. }) => {
+                assert_eq!(name, "sign");
+                assert_eq!(line, 3);
+            }
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn builtin_names() {
+        assert!(is_builtin("Powmod"));
+        assert!(!is_builtin("frobnicate"));
+    }
+}
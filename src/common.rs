@@ -1,10 +1,13 @@
 use failure::Fail;
+#[cfg(feature = "snapshot")]
+use serde_derive::{Deserialize, Serialize};
 use std::cmp::Ordering;
 use std::collections::{BTreeMap, HashMap};
 
 use crate::peg;
 
 #[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "snapshot", derive(Serialize, Deserialize))]
 pub enum Expression {
     // Single items
     String(String),
@@ -28,10 +31,21 @@ pub enum Expression {
     Mysterious,
     Pronoun,
     Not(Box<Expression>),
+    SquareRoot(Box<Expression>),
+    // Each value as Say prints it, joined with " and "
+    Sentence(Vec<Expression>),
+    // Each value as Say prints it, joined with nothing in between
+    Concatenation(Vec<Expression>),
+    // Each value evaluated in order, into an array numbered from 0
+    List(Vec<Expression>),
+    // The value as Say prints it
+    Text(Box<Expression>),
 
     // needed by loops
     Break,
     Continue,
+    // Ends the current iteration of every loop up to and including the one with this label
+    Restart(String),
 
     // binary operators
     Is(Box<Expression>, Box<Expression>),
@@ -40,6 +54,7 @@ pub enum Expression {
     Subtract(Box<Expression>, Box<Expression>),
     Times(Box<Expression>, Box<Expression>),
     Divide(Box<Expression>, Box<Expression>),
+    Modulo(Box<Expression>, Box<Expression>),
     And(Box<Expression>, Box<Expression>),
     Or(Box<Expression>, Box<Expression>),
     Nor(Box<Expression>, Box<Expression>),
@@ -47,6 +62,95 @@ pub enum Expression {
     GreaterThan(Box<Expression>, Box<Expression>),
     LessThanOrEqual(Box<Expression>, Box<Expression>),
     LessThan(Box<Expression>, Box<Expression>),
+    Power(Box<Expression>, Box<Expression>),
+
+    IsType(Box<Expression>, TypeName),
+    // "Y rounded up", "Y rounded down", "Y rounded" and "Y truncated"
+    Rounded(Box<Expression>, Rounding),
+    Bitwise(BitwiseOp, Box<Expression>, Box<Expression>),
+
+    // value, lower bound, upper bound; both bounds are inclusive
+    Between(Box<Expression>, Box<Expression>, Box<Expression>),
+    // array or string, first index, last index; both indexes are inclusive
+    Slice(Box<Expression>, Box<Expression>, Box<Expression>),
+    // array, key
+    HasKey(Box<Expression>, Box<Expression>),
+    // The first value, unless it's mysterious or null, when it's the second. Unlike Or, that's
+    // the value itself rather than a boolean.
+    Coalesce(Box<Expression>, Box<Expression>),
+
+    // An unnamed function, which becomes a Closure when it's evaluated
+    Lambda {
+        args: Vec<String>,
+        block: Block,
+    },
+    // A function value, with a copy of the variables that were visible when it was made. The
+    // copy is taken by value, so later changes to those variables aren't seen, and anything the
+    // body puts into a captured variable only lasts until that call returns.
+    Closure {
+        args: Vec<String>,
+        block: Block,
+        captured: BTreeMap<String, Expression>,
+    },
+}
+
+impl Expression {
+    // The expressions directly nested inside this one
+    pub fn children(&self) -> Vec<&Expression> {
+        match self {
+            Expression::ArrayRef { name, index } => vec![&**name, &**index],
+            Expression::Array { numeric, strings } => numeric
+                .values()
+                .chain(strings.values())
+                .map(|e| &**e)
+                .collect(),
+            Expression::Call(_, args)
+            | Expression::Sentence(args)
+            | Expression::Concatenation(args)
+            | Expression::List(args) => args.iter().collect(),
+            Expression::Modifier(e)
+            | Expression::Not(e)
+            | Expression::SquareRoot(e)
+            | Expression::Text(e)
+            | Expression::IsType(e, _)
+            | Expression::Rounded(e, _) => vec![&**e],
+            Expression::Is(a, b)
+            | Expression::Aint(a, b)
+            | Expression::Add(a, b)
+            | Expression::Subtract(a, b)
+            | Expression::Times(a, b)
+            | Expression::Divide(a, b)
+            | Expression::Modulo(a, b)
+            | Expression::And(a, b)
+            | Expression::Or(a, b)
+            | Expression::Nor(a, b)
+            | Expression::GreaterThanOrEqual(a, b)
+            | Expression::GreaterThan(a, b)
+            | Expression::LessThanOrEqual(a, b)
+            | Expression::LessThan(a, b)
+            | Expression::Power(a, b)
+            | Expression::Bitwise(_, a, b)
+            | Expression::HasKey(a, b)
+            | Expression::Coalesce(a, b) => vec![&**a, &**b],
+            Expression::Between(value, lower, upper) => vec![&**value, &**lower, &**upper],
+            Expression::Slice(value, start, end) => vec![&**value, &**start, &**end],
+            Expression::String(_)
+            | Expression::Floating(_)
+            | Expression::Variable(_)
+            | Expression::Object(_)
+            | Expression::True
+            | Expression::False
+            | Expression::Nothing
+            | Expression::Null
+            | Expression::Mysterious
+            | Expression::Pronoun
+            | Expression::Break
+            | Expression::Continue
+            | Expression::Restart(_)
+            | Expression::Lambda { .. }
+            | Expression::Closure { .. } => vec![],
+        }
+    }
 }
 
 impl PartialOrd for Expression {
@@ -72,6 +176,7 @@ impl PartialOrd for Expression {
 }
 
 #[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "snapshot", derive(Serialize, Deserialize))]
 pub enum SymbolType {
     Is,
     Up,
@@ -88,6 +193,7 @@ pub enum SymbolType {
     Times,
     Aint,
     Divide,
+    Modulo,
     Empty,
     Join,
     Cast,
@@ -103,24 +209,112 @@ pub struct Token {
     pub symbol: SymbolType,
 }
 
+// What "Listen to X as a ..." coerces the input line into
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "snapshot", derive(Serialize, Deserialize))]
+pub enum ListenType {
+    String,
+    Number,
+}
+
+// "The bitwise and/or/xor of X and Y", and "the left/right shift of X by Y"
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "snapshot", derive(Serialize, Deserialize))]
+pub enum BitwiseOp {
+    And,
+    Or,
+    Xor,
+    ShiftLeft,
+    ShiftRight,
+}
+
+impl BitwiseOp {
+    // As written in the program, for errors
+    pub fn name(self) -> &'static str {
+        match self {
+            BitwiseOp::And => "bitwise and",
+            BitwiseOp::Or => "bitwise or",
+            BitwiseOp::Xor => "bitwise xor",
+            BitwiseOp::ShiftLeft => "left shift",
+            BitwiseOp::ShiftRight => "right shift",
+        }
+    }
+}
+
+// What "X is a ..." checks the value of X against
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "snapshot", derive(Serialize, Deserialize))]
+pub enum TypeName {
+    Number,
+    String,
+    Array,
+    Boolean,
+}
+
+// Which whole number a number is rounded to. Nearest goes away from zero on a half, as Turn round
+// does, and Truncate goes towards zero.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "snapshot", derive(Serialize, Deserialize))]
+pub enum Rounding {
+    Up,
+    Down,
+    Nearest,
+    Truncate,
+}
+
+impl Rounding {
+    pub fn apply(self, value: f64) -> f64 {
+        match self {
+            Rounding::Up => value.ceil(),
+            Rounding::Down => value.floor(),
+            Rounding::Nearest => value.round(),
+            Rounding::Truncate => value.trunc(),
+        }
+    }
+}
+
+// What a function value is used for over an array's entries
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "snapshot", derive(Serialize, Deserialize))]
+pub enum ArrayOperation {
+    Map,
+    Filter,
+    Reduce,
+}
+
 #[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "snapshot", derive(Serialize, Deserialize))]
 pub struct Block {
     pub commands: Vec<CommandLine>,
 }
 
 #[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(feature = "snapshot", derive(Serialize, Deserialize))]
 pub enum Command {
     Assignment {
         target: Box<Expression>,
         value: Box<Expression>,
     },
+    // The value is evaluated once, then put into each target in order
+    MultipleAssignment {
+        targets: Vec<Expression>,
+        value: Box<Expression>,
+    },
+    // Loops can be labelled, so a Restart inside them can say which one to go round again
     Until {
         expression: Expression,
         block: Block,
+        label: Option<String>,
     },
     While {
         expression: Expression,
         block: Block,
+        label: Option<String>,
+    },
+    Repeat {
+        count: Expression,
+        block: Block,
+        label: Option<String>,
     },
     If {
         expression: Expression,
@@ -137,15 +331,31 @@ pub enum Command {
     },
     Continue,
     Break,
+    // A continue for the enclosing loop with this (lowercased) label
+    Restart {
+        label: String,
+    },
     Say {
         value: Expression,
     },
+    // The exact characters of a string, without any conversion or newline
+    Write {
+        value: Expression,
+    },
+    // With one target, that gets the whole line. With more, as in "Listen to X and Y", the line
+    // is split into words, one for each: any extra words are dropped, and targets without one
+    // are mysterious.
     Listen {
-        target: Option<String>,
+        targets: Vec<String>,
+        as_type: ListenType,
     },
     FunctionDeclaration {
         name: String,
         args: Vec<String>,
+        // Values for the last defaults.len() args, when a call leaves them out
+        defaults: Vec<Expression>,
+        // Gets an array of any arguments after args
+        rest: Option<String>,
         block: Block,
     },
     Return {
@@ -164,6 +374,42 @@ pub enum Command {
     Round {
         target: Expression,
     },
+    Negate {
+        target: Expression,
+    },
+    // Removes every entry, numeric and string-keyed, leaving an empty array
+    Empty {
+        target: Expression,
+    },
+    Sleep {
+        seconds: Expression,
+    },
+    // Fails with a UserError with value's printed form as the message, which Try can catch
+    Throw {
+        value: Expression,
+    },
+    Push {
+        target: Expression,
+        values: Vec<Expression>,
+    },
+    // "Dequeue X into Y" or "Roll X into Y", taking the first entry out of the array X
+    Dequeue {
+        target: Expression,
+        removed: Option<Expression>,
+    },
+    // Takes the entry at index out, moving everything after it down one, and puts it into
+    // removed if that's given
+    Remove {
+        target: Expression,
+        index: Expression,
+        removed: Option<Expression>,
+    },
+    // Puts value at index, moving that entry and everything after it up one
+    Insert {
+        value: Expression,
+        target: Expression,
+        index: Expression,
+    },
     Mutation {
         mutator: SymbolType,
         source: Option<Box<Expression>>,
@@ -171,15 +417,188 @@ pub enum Command {
         lookup: Option<Box<Expression>>,
         modifier: Option<Box<Expression>>,
     },
+    // "Map F over X into Y", "Filter X by F into Y" and "Reduce X with F into Y"
+    ApplyToArray {
+        operation: ArrayOperation,
+        function: Expression,
+        source: Expression,
+        target: Expression,
+    },
+    // Pads value out to width characters with fill (a space if not given), on the left unless
+    // right is set
+    Pad {
+        value: Expression,
+        width: Expression,
+        fill: Option<Expression>,
+        right: bool,
+        target: Expression,
+    },
+    // value, or the nearest of low and high if it's outside them
+    Clamp {
+        value: Expression,
+        low: Expression,
+        high: Expression,
+        target: Expression,
+    },
+    // A number as a string with exactly places digits after the decimal point
+    FormatNumber {
+        value: Expression,
+        places: Expression,
+        target: Expression,
+    },
+    // Runs recover instead of failing if anything in body fails, with the error's message in
+    // error_var. Running out of a limit can't be recovered from.
+    Try {
+        body: Block,
+        error_var: Option<String>,
+        recover: Block,
+    },
+    // Another file's commands, which keep that file's own line numbers
+    Include {
+        file: String,
+        block: Block,
+    },
+}
+
+impl Command {
+    // The blocks directly nested inside this command
+    pub fn blocks(&self) -> Vec<&Block> {
+        match self {
+            Command::Until { block, .. }
+            | Command::While { block, .. }
+            | Command::Repeat { block, .. }
+            | Command::FunctionDeclaration { block, .. }
+            | Command::Include { block, .. } => vec![block],
+            Command::If {
+                then, otherwise, ..
+            } => then.iter().chain(otherwise.iter()).collect(),
+            Command::Try { body, recover, .. } => vec![body, recover],
+            _ => vec![],
+        }
+    }
+
+    // The expressions directly used by this command, not counting those in nested blocks
+    pub fn expressions(&self) -> Vec<&Expression> {
+        match self {
+            Command::Assignment { target, value } => vec![&**target, &**value],
+            Command::MultipleAssignment { targets, value } => {
+                targets.iter().chain(std::iter::once(&**value)).collect()
+            }
+            Command::Until { expression, .. }
+            | Command::While { expression, .. }
+            | Command::If { expression, .. }
+            | Command::Repeat {
+                count: expression, ..
+            }
+            | Command::Increment {
+                target: expression, ..
+            }
+            | Command::Decrement {
+                target: expression, ..
+            }
+            | Command::Say { value: expression }
+            | Command::Write { value: expression }
+            | Command::Return {
+                return_value: expression,
+            }
+            | Command::Floor { target: expression }
+            | Command::Ceil { target: expression }
+            | Command::Round { target: expression }
+            | Command::Negate { target: expression }
+            | Command::Empty { target: expression }
+            | Command::Sleep {
+                seconds: expression,
+            }
+            | Command::Throw { value: expression } => vec![expression],
+            Command::Call { args, .. } => args.iter().collect(),
+            Command::FunctionDeclaration { defaults, .. } => defaults.iter().collect(),
+            Command::Push { target, values } => {
+                std::iter::once(target).chain(values.iter()).collect()
+            }
+            Command::Dequeue { target, removed } => {
+                std::iter::once(target).chain(removed.iter()).collect()
+            }
+            Command::Insert {
+                value,
+                target,
+                index,
+            } => vec![value, target, index],
+            Command::Remove {
+                target,
+                index,
+                removed,
+            } => vec![target, index]
+                .into_iter()
+                .chain(removed.iter())
+                .collect(),
+            Command::ApplyToArray {
+                function,
+                source,
+                target,
+                ..
+            } => vec![function, source, target],
+            Command::FormatNumber {
+                value,
+                places,
+                target,
+            } => vec![value, places, target],
+            Command::Clamp {
+                value,
+                low,
+                high,
+                target,
+            } => vec![value, low, high, target],
+            Command::Pad {
+                value,
+                width,
+                fill,
+                target,
+                ..
+            } => std::iter::once(value)
+                .chain(std::iter::once(width))
+                .chain(fill.iter())
+                .chain(std::iter::once(target))
+                .collect(),
+            Command::Mutation {
+                source,
+                target,
+                lookup,
+                modifier,
+                ..
+            } => source
+                .iter()
+                .chain(target.iter())
+                .chain(lookup.iter())
+                .chain(modifier.iter())
+                .map(|expression| &**expression)
+                .collect(),
+            Command::Continue
+            | Command::Restart { .. }
+            | Command::Break
+            | Command::Listen { .. }
+            | Command::Include { .. }
+            | Command::Try { .. } => vec![],
+        }
+    }
 }
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct Function {
     pub args: Vec<String>,
+    pub defaults: Vec<Expression>,
+    pub rest: Option<String>,
     pub block: Block,
 }
 
+impl Function {
+    // Arguments a call has to give, as the rest have defaults
+    pub fn required_args(&self) -> usize {
+        self.args.len() - self.defaults.len()
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "snapshot", derive(Serialize, Deserialize))]
 pub struct CommandLine {
     pub cmd: Command,
     pub line: usize,
@@ -188,7 +607,46 @@ pub struct CommandLine {
 #[derive(Debug, PartialEq)]
 pub struct Program {
     pub commands: Vec<CommandLine>,
-    pub functions: HashMap<String, Function>,
+    pub functions: BTreeMap<String, Function>,
+}
+
+fn index_commands<'a>(commands: &'a [CommandLine], index: &mut HashMap<usize, &'a CommandLine>) {
+    for command in commands {
+        index.entry(command.line).or_insert(command);
+        // Included lines are numbered within their own file, so would clash with ours
+        if let Command::Include { .. } = command.cmd {
+            continue;
+        }
+        for block in command.cmd.blocks() {
+            index_commands(&block.commands, index);
+        }
+        for expression in command.cmd.expressions() {
+            index_lambdas(expression, index);
+        }
+    }
+}
+
+// Lambda bodies are blocks too, but they're inside expressions rather than commands
+fn index_lambdas<'a>(expression: &'a Expression, index: &mut HashMap<usize, &'a CommandLine>) {
+    if let Expression::Lambda { block, .. } = expression {
+        index_commands(&block.commands, index);
+    }
+    for child in expression.children() {
+        index_lambdas(child, index);
+    }
+}
+
+impl Program {
+    // Every command keyed by its line, including ones nested inside blocks and functions.
+    // Where a line has several commands, the outermost one wins.
+    pub fn line_index(&self) -> HashMap<usize, &CommandLine> {
+        let mut index = HashMap::new();
+        index_commands(&self.commands, &mut index);
+        for function in self.functions.values() {
+            index_commands(&function.block.commands, &mut index);
+        }
+        index
+    }
 }
 
 #[derive(Debug, Fail)]
@@ -225,12 +683,48 @@ pub enum MaidenError {
     StackOverflow { depth: u32, line: usize },
     #[fail(display = "Hit instruction limit of 10,000,000. Infinite loop?")]
     InstructionLimit { line: usize },
+    #[fail(display = "Hit time limit of {} milliseconds", milliseconds)]
+    TimeLimit { milliseconds: u128, line: usize },
+    #[fail(display = "Hit output limit of {} bytes", limit)]
+    OutputLimit { limit: usize, line: usize },
+    #[fail(display = "Hit allocation limit of {}", limit)]
+    AllocationLimit { limit: usize, line: usize },
+    #[fail(display = "Hit memory limit")]
+    MemoryLimit { line: usize },
+    #[fail(display = "Hit iteration limit of {} for one loop", limit)]
+    IterationLimit { limit: u64, line: usize },
     #[fail(display = "Got to a pronoun, but no variable defined")]
     UndefinedPronoun { line: usize },
     #[fail(display = "Got infinity on divide between {} and {}", x, y)]
     Infinity { x: String, y: String, line: usize },
-    #[fail(display = "Expected another item, but didn't get one")]
-    Incomplete { line: usize },
+    #[fail(display = "Dividing {} by {} doesn't give an exact result", x, y)]
+    InexactDivision { x: String, y: String, line: usize },
+    #[fail(display = "Bad argument to {}: {}", name, description)]
+    BadArgument {
+        name: String,
+        description: String,
+        line: usize,
+    },
+    #[fail(display = "Index {} isn't a non-negative integer", index)]
+    BadIndex { index: String, line: usize },
+    #[fail(display = "Unreachable code")]
+    UnreachableCode { line: usize },
+    #[fail(display = "Not a number: {}", description)]
+    NotANumber { description: String, line: usize },
+    #[fail(display = "Lost precision: {}", description)]
+    LossyConversion { description: String, line: usize },
+    #[fail(display = "No enclosing loop labelled '{}'", label)]
+    UnknownLabel { label: String, line: usize },
+    #[fail(display = "{}", message)]
+    UserError { message: String, line: usize },
+    #[fail(display = "Can't compare {} with {}", left, right)]
+    TypeMismatch {
+        left: String,
+        right: String,
+        line: usize,
+    },
+    #[fail(display = "Expected {}, but didn't get one", expected)]
+    Incomplete { expected: String, line: usize },
     #[fail(display = "Bad string. Expected length at least 2 and got {}", length)]
     BadString { length: usize, line: usize },
     #[fail(display = "Expected an expression, got: {}", other)]
@@ -241,6 +735,11 @@ pub enum MaidenError {
     NotACommand { other: String, line: usize },
     #[fail(display = "Expected an block, got: {}", other)]
     NotABlock { other: String, line: usize },
+    #[fail(display = "{}: {}", file, error)]
+    InFile {
+        file: String,
+        error: Box<MaidenError>,
+    },
 }
 
 pub type Result<T> = ::core::result::Result<T, MaidenError>;
@@ -250,3 +749,228 @@ impl From<std::io::Error> for MaidenError {
         return MaidenError::Io { io_error: err };
     }
 }
+
+impl MaidenError {
+    // Stable identifiers for each kind of error, so callers don't need to match on the whole enum
+    pub fn code(&self) -> &'static str {
+        match self {
+            MaidenError::Pest { .. } => "parse",
+            MaidenError::Io { .. } => "io",
+            MaidenError::MissingVariable { .. } => "missing-variable",
+            MaidenError::MissingFunction { .. } => "missing-function",
+            MaidenError::WrongArgCount { .. } => "wrong-arg-count",
+            MaidenError::BadBooleanResolve { .. } => "bad-boolean-resolve",
+            MaidenError::ParseNumberError { .. } => "parse-number",
+            MaidenError::NoEndOfIf { .. } => "no-end-of-if",
+            MaidenError::Unimplemented { .. } => "unimplemented",
+            MaidenError::StackOverflow { .. } => "stack-overflow",
+            MaidenError::InstructionLimit { .. } => "instruction-limit",
+            MaidenError::TimeLimit { .. } => "time-limit",
+            MaidenError::OutputLimit { .. } => "output-limit",
+            MaidenError::AllocationLimit { .. } => "allocation-limit",
+            MaidenError::MemoryLimit { .. } => "memory-limit",
+            MaidenError::IterationLimit { .. } => "iteration-limit",
+            MaidenError::UndefinedPronoun { .. } => "undefined-pronoun",
+            MaidenError::Infinity { .. } => "infinity",
+            MaidenError::InexactDivision { .. } => "inexact-division",
+            MaidenError::BadArgument { .. } => "bad-argument",
+            MaidenError::BadIndex { .. } => "bad-index",
+            MaidenError::UnreachableCode { .. } => "unreachable-code",
+            MaidenError::NotANumber { .. } => "not-a-number",
+            MaidenError::LossyConversion { .. } => "lossy-conversion",
+            MaidenError::UnknownLabel { .. } => "unknown-label",
+            MaidenError::UserError { .. } => "user-error",
+            MaidenError::TypeMismatch { .. } => "type-mismatch",
+            MaidenError::Incomplete { .. } => "incomplete",
+            MaidenError::BadString { .. } => "bad-string",
+            MaidenError::NotAnExpression { .. } => "not-an-expression",
+            MaidenError::NotASymbol { .. } => "not-a-symbol",
+            MaidenError::NotACommand { .. } => "not-a-command",
+            MaidenError::NotABlock { .. } => "not-a-block",
+            MaidenError::InFile { .. } => "in-file",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Command, Expression, MaidenError};
+    use crate::parser;
+    use std::collections::HashSet;
+
+    #[test]
+    fn line_index() {
+        let program = parser::parse(
+            "Put 0 into my world
+While my world is weaker than 10
+Build my world up
+
+Say my world
+",
+        )
+        .unwrap();
+        let index = program.line_index();
+        assert_eq!(
+            index[&3].cmd,
+            Command::Increment {
+                target: Expression::Variable("my world".to_string()),
+                count: 1.0
+            }
+        );
+        assert_eq!(
+            index[&5].cmd,
+            Command::Say {
+                value: Expression::Variable("my world".to_string())
+            }
+        );
+        assert!(!index.contains_key(&4));
+    }
+
+    #[test]
+    fn line_index_lambdas() {
+        let program = parser::parse(
+            "Let the doubler be a function taking the number
+Give back the number times 2
+
+Say the doubler taking 4
+",
+        )
+        .unwrap();
+        let index = program.line_index();
+        assert_eq!(
+            index[&2].cmd,
+            Command::Return {
+                return_value: Expression::Times(
+                    Box::new(Expression::Variable("the number".to_string())),
+                    Box::new(Expression::Floating(2.0))
+                )
+            }
+        );
+    }
+
+    #[test]
+    fn error_codes() {
+        let errors = vec![
+            MaidenError::Pest {
+                kind: pest::error::Error::new_from_pos(
+                    pest::error::ErrorVariant::CustomError {
+                        message: "bad".to_string(),
+                    },
+                    pest::Position::from_start(""),
+                ),
+            },
+            MaidenError::Io {
+                io_error: std::io::Error::other("bad"),
+            },
+            MaidenError::MissingVariable {
+                name: "x".to_string(),
+                line: 1,
+            },
+            MaidenError::MissingFunction {
+                name: "x".to_string(),
+                line: 1,
+            },
+            MaidenError::WrongArgCount {
+                expected: 1,
+                got: 2,
+                line: 1,
+            },
+            MaidenError::BadBooleanResolve {
+                expression: "x".to_string(),
+                line: 1,
+            },
+            MaidenError::ParseNumberError {
+                number: "x".to_string(),
+                line: 1,
+            },
+            MaidenError::NoEndOfIf { line: 1 },
+            MaidenError::Unimplemented {
+                description: "x".to_string(),
+                line: 1,
+            },
+            MaidenError::StackOverflow { depth: 1, line: 1 },
+            MaidenError::InstructionLimit { line: 1 },
+            MaidenError::TimeLimit {
+                milliseconds: 1,
+                line: 1,
+            },
+            MaidenError::OutputLimit { limit: 1, line: 1 },
+            MaidenError::AllocationLimit { limit: 1, line: 1 },
+            MaidenError::MemoryLimit { line: 1 },
+            MaidenError::IterationLimit { limit: 1, line: 1 },
+            MaidenError::UndefinedPronoun { line: 1 },
+            MaidenError::Infinity {
+                x: "1".to_string(),
+                y: "0".to_string(),
+                line: 1,
+            },
+            MaidenError::InexactDivision {
+                x: "1".to_string(),
+                y: "3".to_string(),
+                line: 1,
+            },
+            MaidenError::BadArgument {
+                name: "powmod".to_string(),
+                description: "bad".to_string(),
+                line: 1,
+            },
+            MaidenError::BadIndex {
+                index: "0.5".to_string(),
+                line: 1,
+            },
+            MaidenError::UnreachableCode { line: 1 },
+            MaidenError::NotANumber {
+                description: "x".to_string(),
+                line: 1,
+            },
+            MaidenError::LossyConversion {
+                description: "x".to_string(),
+                line: 1,
+            },
+            MaidenError::UnknownLabel {
+                label: "x".to_string(),
+                line: 1,
+            },
+            MaidenError::UserError {
+                message: "x".to_string(),
+                line: 1,
+            },
+            MaidenError::TypeMismatch {
+                left: "\"a\"".to_string(),
+                right: "1".to_string(),
+                line: 1,
+            },
+            MaidenError::Incomplete {
+                expected: "a value".to_string(),
+                line: 1,
+            },
+            MaidenError::BadString { length: 1, line: 1 },
+            MaidenError::NotAnExpression {
+                other: "x".to_string(),
+                line: 1,
+            },
+            MaidenError::NotASymbol {
+                other: "x".to_string(),
+                line: 1,
+            },
+            MaidenError::NotACommand {
+                other: "x".to_string(),
+                line: 1,
+            },
+            MaidenError::NotABlock {
+                other: "x".to_string(),
+                line: 1,
+            },
+            MaidenError::InFile {
+                file: "x.rock".to_string(),
+                error: Box::new(MaidenError::UndefinedPronoun { line: 1 }),
+            },
+        ];
+        let codes: Vec<&str> = errors.iter().map(|e| e.code()).collect();
+        let unique: HashSet<&str> = codes.iter().cloned().collect();
+        assert_eq!(codes.len(), unique.len());
+        assert_eq!(codes[0], "parse");
+        assert_eq!(codes[2], "missing-variable");
+        assert_eq!(codes[10], "instruction-limit");
+    }
+}
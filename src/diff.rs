@@ -0,0 +1,215 @@
+use crate::common::{Block, Command, CommandLine, Program};
+use std::collections::BTreeMap;
+
+// A single difference between two programs. Lines are from the program they're found in,
+// so a changed command has the line it was at before and the line it's at now.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum ProgramDiff {
+    AddedCommand { line: usize },
+    RemovedCommand { line: usize },
+    ChangedCommand { old_line: usize, new_line: usize },
+    AddedFunction { name: String },
+    RemovedFunction { name: String },
+    ChangedFunction { name: String },
+}
+
+fn blocks_mut(command: &mut Command) -> Vec<&mut Block> {
+    match command {
+        Command::Until { block, .. }
+        | Command::While { block, .. }
+        | Command::Repeat { block, .. }
+        | Command::FunctionDeclaration { block, .. }
+        | Command::Include { block, .. } => vec![block],
+        Command::If {
+            then, otherwise, ..
+        } => then.iter_mut().chain(otherwise.iter_mut()).collect(),
+        Command::Try { body, recover, .. } => vec![body, recover],
+        _ => vec![],
+    }
+}
+
+// Line numbers are part of every nested CommandLine, so strip them out before comparing,
+// otherwise adding a blank line at the top would make everything below it "changed"
+fn without_lines(command: &Command) -> Command {
+    let mut command = command.clone();
+    for block in blocks_mut(&mut command) {
+        for command_line in block.commands.iter_mut() {
+            command_line.line = 0;
+            command_line.cmd = without_lines(&command_line.cmd);
+        }
+    }
+    command
+}
+
+fn functions(program: &Program) -> BTreeMap<String, Command> {
+    let mut functions: BTreeMap<String, Command> = program
+        .functions
+        .iter()
+        .map(|(name, function)| {
+            (
+                name.clone(),
+                without_lines(&Command::FunctionDeclaration {
+                    name: name.clone(),
+                    args: function.args.clone(),
+                    defaults: function.defaults.clone(),
+                    rest: function.rest.clone(),
+                    block: function.block.clone(),
+                }),
+            )
+        })
+        .collect();
+    for command in &program.commands {
+        if let Command::FunctionDeclaration { name, .. } = &command.cmd {
+            functions.insert(name.clone(), without_lines(&command.cmd));
+        }
+    }
+    functions
+}
+
+// Matches up the commands both programs have in the same order (a longest common subsequence),
+// and reports what's left over between matches as changed, added or removed
+fn diff_commands(a: &[&CommandLine], b: &[&CommandLine], diffs: &mut Vec<ProgramDiff>) {
+    let a_cmds: Vec<Command> = a.iter().map(|c| without_lines(&c.cmd)).collect();
+    let b_cmds: Vec<Command> = b.iter().map(|c| without_lines(&c.cmd)).collect();
+    let mut lengths = vec![vec![0usize; b.len() + 1]; a.len() + 1];
+    for i in (0..a.len()).rev() {
+        for j in (0..b.len()).rev() {
+            lengths[i][j] = if a_cmds[i] == b_cmds[j] {
+                lengths[i + 1][j + 1] + 1
+            } else {
+                lengths[i + 1][j].max(lengths[i][j + 1])
+            };
+        }
+    }
+
+    let (mut i, mut j) = (0, 0);
+    let mut removed = vec![];
+    let mut added = vec![];
+    loop {
+        let matched = i < a.len() && j < b.len() && a_cmds[i] == b_cmds[j];
+        if matched || (i == a.len() && j == b.len()) {
+            let pairs = removed.len().min(added.len());
+            for (old, new) in removed.iter().zip(added.iter()) {
+                diffs.push(ProgramDiff::ChangedCommand {
+                    old_line: *old,
+                    new_line: *new,
+                });
+            }
+            for line in &removed[pairs..] {
+                diffs.push(ProgramDiff::RemovedCommand { line: *line });
+            }
+            for line in &added[pairs..] {
+                diffs.push(ProgramDiff::AddedCommand { line: *line });
+            }
+            removed.clear();
+            added.clear();
+            if !matched {
+                break;
+            }
+            i += 1;
+            j += 1;
+        } else if j == b.len() || (i < a.len() && lengths[i + 1][j] >= lengths[i][j + 1]) {
+            removed.push(a[i].line);
+            i += 1;
+        } else {
+            added.push(b[j].line);
+            j += 1;
+        }
+    }
+}
+
+// Differences between two programs, ignoring line numbers. Top-level commands are compared in
+// order, and functions by name, so moving a function declaration doesn't count as a change.
+pub fn diff_programs(a: &Program, b: &Program) -> Vec<ProgramDiff> {
+    let not_function =
+        |command: &&CommandLine| !matches!(command.cmd, Command::FunctionDeclaration { .. });
+    let a_commands: Vec<&CommandLine> = a.commands.iter().filter(not_function).collect();
+    let b_commands: Vec<&CommandLine> = b.commands.iter().filter(not_function).collect();
+    let mut diffs = vec![];
+    diff_commands(&a_commands, &b_commands, &mut diffs);
+
+    let a_functions = functions(a);
+    let b_functions = functions(b);
+    for (name, function) in &a_functions {
+        match b_functions.get(name) {
+            None => diffs.push(ProgramDiff::RemovedFunction { name: name.clone() }),
+            Some(other) if other != function => {
+                diffs.push(ProgramDiff::ChangedFunction { name: name.clone() })
+            }
+            Some(_) => {}
+        }
+    }
+    for name in b_functions.keys() {
+        if !a_functions.contains_key(name) {
+            diffs.push(ProgramDiff::AddedFunction { name: name.clone() });
+        }
+    }
+    diffs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{diff_programs, ProgramDiff};
+    use crate::parser;
+
+    fn diff(a: &str, b: &str) -> Vec<ProgramDiff> {
+        diff_programs(&parser::parse(a).unwrap(), &parser::parse(b).unwrap())
+    }
+
+    #[test]
+    fn unchanged() {
+        assert_eq!(
+            diff(
+                "Put 1 into my heart\nIf my heart is 1\nSay my heart\n",
+                "\n\nPut 1 into my heart\nIf my heart is 1\nSay my heart\n"
+            ),
+            vec![]
+        );
+    }
+
+    #[test]
+    fn added_command() {
+        assert_eq!(
+            diff(
+                "Put 1 into my heart\nSay my heart\n",
+                "Put 1 into my heart\nBuild my heart up\nSay my heart\n"
+            ),
+            vec![ProgramDiff::AddedCommand { line: 2 }]
+        );
+    }
+
+    #[test]
+    fn changed_expression() {
+        assert_eq!(
+            diff(
+                "Put 1 into my heart\nSay my heart plus 1\n",
+                "Put 1 into my heart\nSay my heart plus 2\n"
+            ),
+            vec![ProgramDiff::ChangedCommand {
+                old_line: 2,
+                new_line: 2
+            }]
+        );
+    }
+
+    #[test]
+    fn changed_function() {
+        assert_eq!(
+            diff(
+                "Midnight takes your heart\nGive back your heart\n\nNoon takes your soul\nGive back 1\n",
+                "Midnight takes your heart\nGive back 0\n\nDawn takes your soul\nGive back 1\n"
+            ),
+            vec![
+                ProgramDiff::ChangedFunction {
+                    name: "Midnight".to_string()
+                },
+                ProgramDiff::RemovedFunction {
+                    name: "Noon".to_string()
+                },
+                ProgramDiff::AddedFunction {
+                    name: "Dawn".to_string()
+                },
+            ]
+        );
+    }
+}
@@ -1,36 +1,125 @@
-#[cfg(any(test, target_arch = "wasm32"))]
-use crate::common::{Command, CommandLine, Program};
+use crate::common::{
+    BitwiseOp, Block, Command, CommandLine, Expression, Program, Rounding, TypeName,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PrintOptions {
+    // Indent nested blocks with one tab per level, rather than indent_width spaces
+    pub use_tabs: bool,
+    pub indent_width: usize,
+}
+
+impl Default for PrintOptions {
+    fn default() -> Self {
+        PrintOptions {
+            use_tabs: false,
+            indent_width: 2,
+        }
+    }
+}
+
+impl PrintOptions {
+    fn indent(&self, level: usize) -> String {
+        if self.use_tabs {
+            "\t".repeat(level)
+        } else {
+            " ".repeat(self.indent_width * level)
+        }
+    }
+}
 
-#[cfg(any(test, target_arch = "wasm32"))]
 fn print_command(
     command: &Command,
     last_line: &mut usize,
     indent: usize,
     max_number_length: usize,
+    options: &PrintOptions,
 ) -> String {
     let new_indent = format!(
-        "{}{}",
-        "  ".repeat(indent),
-        " ".repeat(max_number_length + 2 + indent * 2) // <number> + ": " + <indent>
+        "{}{}{}",
+        options.indent(indent),
+        " ".repeat(max_number_length + 2), // <number> + ": "
+        options.indent(indent)
     );
     match command {
-        Command::FunctionDeclaration { name, args, block } => format!(
-            "FunctionDeclaration {{ name: \"{}\", args: {:?}, block: Block {{\n{}{}}}}}",
+        Command::FunctionDeclaration {
             name,
             args,
-            print_commands(&block.commands, last_line, indent + 1, max_number_length),
+            defaults,
+            rest,
+            block,
+        } => format!(
+            "FunctionDeclaration {{ name: \"{}\", args: {:?}, {}{}block: Block {{\n{}{}}}}}",
+            name,
+            args,
+            if defaults.is_empty() {
+                String::new()
+            } else {
+                let defaults: Vec<String> = defaults.iter().map(|d| format!("{:?}", d)).collect();
+                format!("defaults: [{}], ", defaults.join(", "))
+            },
+            match rest {
+                Some(rest) => format!("rest: \"{}\", ", rest),
+                None => String::new(),
+            },
+            print_commands(
+                &block.commands,
+                last_line,
+                indent + 1,
+                max_number_length,
+                options
+            ),
             new_indent
         ),
-        Command::Until { expression, block } => format!(
-            "Until {{ expression: {:?}, block: Block {{\n{}{}}}}}",
+        Command::Until {
+            expression,
+            block,
+            label,
+        } => format!(
+            "Until {{ {}expression: {:?}, block: Block {{\n{}{}}}}}",
+            print_label(label),
             expression,
-            print_commands(&block.commands, last_line, indent + 1, max_number_length),
+            print_commands(
+                &block.commands,
+                last_line,
+                indent + 1,
+                max_number_length,
+                options
+            ),
             new_indent
         ),
-        Command::While { expression, block } => format!(
-            "While {{ expression: {:?}, block: Block {{\n{}{}}}}}",
+        Command::While {
             expression,
-            print_commands(&block.commands, last_line, indent + 1, max_number_length),
+            block,
+            label,
+        } => format!(
+            "While {{ {}expression: {:?}, block: Block {{\n{}{}}}}}",
+            print_label(label),
+            expression,
+            print_commands(
+                &block.commands,
+                last_line,
+                indent + 1,
+                max_number_length,
+                options
+            ),
+            new_indent
+        ),
+        Command::Repeat {
+            count,
+            block,
+            label,
+        } => format!(
+            "Repeat {{ {}count: {:?}, block: Block {{\n{}{}}}}}",
+            print_label(label),
+            count,
+            print_commands(
+                &block.commands,
+                last_line,
+                indent + 1,
+                max_number_length,
+                options
+            ),
             new_indent
         ),
         Command::If {
@@ -47,6 +136,7 @@ fn print_command(
                         last_line,
                         indent + 1,
                         max_number_length,
+                        options,
                     )
                     + &new_indent
             } else {
@@ -59,22 +149,50 @@ fn print_command(
                         last_line,
                         indent + 1,
                         max_number_length,
+                        options,
                     )
                     + &new_indent
             } else {
                 String::new()
             },
         ),
+        Command::Try {
+            body,
+            error_var,
+            recover,
+        } => format!(
+            "Try {{ body: Block {{\n{}{}}}, {}recover: Block {{\n{}{}}}}}",
+            print_commands(
+                &body.commands,
+                last_line,
+                indent + 1,
+                max_number_length,
+                options
+            ),
+            new_indent,
+            match error_var {
+                Some(name) => format!("error_var: \"{}\", ", name),
+                None => String::new(),
+            },
+            print_commands(
+                &recover.commands,
+                last_line,
+                indent + 1,
+                max_number_length,
+                options
+            ),
+            new_indent
+        ),
         _ => format!("{:?}", command),
     }
 }
 
-#[cfg(any(test, target_arch = "wasm32"))]
 fn print_commands(
     commands: &Vec<CommandLine>,
     last_line: &mut usize,
     mut indent: usize,
     max_number_length: usize,
+    options: &PrintOptions,
 ) -> String {
     let mut res = String::new();
     for command in commands {
@@ -84,10 +202,14 @@ fn print_commands(
         }
         *last_line = command.line;
         res += &format!("{:0width$}: ", command.line, width = max_number_length);
-        res += &"  ".repeat(indent);
-        res += &(print_command(&command.cmd, last_line, indent, max_number_length) + "\n");
+        res += &options.indent(indent);
+        res += &(print_command(&command.cmd, last_line, indent, max_number_length, options) + "\n");
         match command.cmd {
-            Command::If { .. } | Command::While { .. } | Command::Until { .. } => {
+            Command::If { .. }
+            | Command::While { .. }
+            | Command::Until { .. }
+            | Command::Repeat { .. }
+            | Command::Try { .. } => {
                 indent += 1;
             }
             _ => {}
@@ -96,18 +218,513 @@ fn print_commands(
     res
 }
 
-#[cfg(any(test, target_arch = "wasm32"))]
+fn print_label(label: &Option<String>) -> String {
+    match label {
+        Some(label) => format!("label: \"{}\", ", label),
+        None => String::new(),
+    }
+}
+
+// How tightly each expression binds, loosest first, following the grammar's nor, or, and,
+// is, not, comparison, arithmetic and product levels. Everything else is a single value.
+fn precedence(expression: &Expression) -> u8 {
+    match expression {
+        Expression::Rounded(..) | Expression::Sentence(_) | Expression::List(_) => 0,
+        Expression::Nor(..) => 1,
+        Expression::Or(..) | Expression::Coalesce(..) => 2,
+        Expression::And(..) => 3,
+        Expression::Is(..) | Expression::Aint(..) => 4,
+        Expression::Not(_) => 5,
+        // The arguments run to the end of the arithmetic, so a call inside one is in parentheses
+        Expression::Call(..) => 6,
+        Expression::GreaterThan(..)
+        | Expression::GreaterThanOrEqual(..)
+        | Expression::LessThan(..)
+        | Expression::LessThanOrEqual(..)
+        | Expression::Between(..)
+        | Expression::HasKey(..)
+        | Expression::IsType(..) => 7,
+        Expression::Add(..) | Expression::Subtract(..) => 8,
+        Expression::Times(..) | Expression::Divide(..) | Expression::Modulo(..) => 9,
+        _ => 10,
+    }
+}
+
+// The expression, in parentheses if it binds more loosely than the minimum. Rockstar reads
+// parentheses as a comment, so these only show a reader the grouping.
+fn print_operand(expression: &Expression, minimum: u8) -> String {
+    if precedence(expression) < minimum {
+        format!("({})", print_expression(expression))
+    } else {
+        print_expression(expression)
+    }
+}
+
+// The expression as Rockstar source. Comparisons, is, and, or and nor group to the right and
+// arithmetic to the left, so only the other side needs parentheses at the same level.
+pub fn print_expression(expression: &Expression) -> String {
+    let level = precedence(expression);
+    let right = |a: &Expression, word: &str, b: &Expression| {
+        format!(
+            "{} {} {}",
+            print_operand(a, level + 1),
+            word,
+            print_operand(b, level)
+        )
+    };
+    let left = |a: &Expression, word: &str, b: &Expression| {
+        format!(
+            "{} {} {}",
+            print_operand(a, level),
+            word,
+            print_operand(b, level + 1)
+        )
+    };
+    let value = |x: &Expression| print_operand(x, 10);
+    let all = |items: &Vec<Expression>, minimum: u8| {
+        items
+            .iter()
+            .map(|item| print_operand(item, minimum))
+            .collect::<Vec<_>>()
+    };
+    match expression {
+        Expression::String(s) => format!("\"{}\"", s),
+        Expression::Floating(f) => crate::runner::format_number(*f),
+        Expression::Variable(name) | Expression::Object(name) => name.to_string(),
+        Expression::ArrayRef { name, index } => format!("{} at {}", value(name), value(index)),
+        Expression::Modifier(x) => format!("with {}", print_expression(x)),
+        Expression::True => "true".to_string(),
+        Expression::False => "false".to_string(),
+        Expression::Call(name, args) => format!("{} taking {}", name, all(args, 8).join(", ")),
+        Expression::Nothing => "nothing".to_string(),
+        Expression::Null => "null".to_string(),
+        Expression::Mysterious => "mysterious".to_string(),
+        Expression::Pronoun => "it".to_string(),
+        Expression::Not(x) => format!("not {}", print_operand(x, level)),
+        Expression::SquareRoot(x) => format!("the square root of {}", value(x)),
+        Expression::Sentence(items) => all(items, 8).join(" and "),
+        Expression::Concatenation(items) => {
+            format!("the concatenation of {}", all(items, 8).join(", "))
+        }
+        Expression::List(items) => all(items, 8).join(", "),
+        Expression::Text(x) => format!("the text of {}", value(x)),
+        Expression::Break => "break".to_string(),
+        Expression::Continue => "continue".to_string(),
+        Expression::Restart(label) => format!("restart {}", label),
+        Expression::Is(a, b) => right(a, "is", b),
+        Expression::Aint(a, b) => right(a, "ain't", b),
+        Expression::Add(a, b) => left(a, "plus", b),
+        Expression::Subtract(a, b) => left(a, "minus", b),
+        Expression::Times(a, b) => left(a, "times", b),
+        Expression::Divide(a, b) => left(a, "over", b),
+        Expression::Modulo(a, b) => left(a, "modulo", b),
+        Expression::And(a, b) => right(a, "and", b),
+        Expression::Or(a, b) => right(a, "or", b),
+        Expression::Nor(a, b) => right(a, "nor", b),
+        Expression::Coalesce(a, b) => right(a, "or else", b),
+        Expression::GreaterThanOrEqual(a, b) => right(a, "is as great as", b),
+        Expression::GreaterThan(a, b) => right(a, "is greater than", b),
+        Expression::LessThanOrEqual(a, b) => right(a, "is as low as", b),
+        Expression::LessThan(a, b) => right(a, "is less than", b),
+        Expression::Power(base, exponent) => match **exponent {
+            Expression::Floating(2.0) => format!("{} squared", value(base)),
+            Expression::Floating(3.0) => format!("{} cubed", value(base)),
+            _ => format!("{:?}", expression),
+        },
+        Expression::IsType(x, type_name) => format!(
+            "{} is {}",
+            print_operand(x, level + 1),
+            match type_name {
+                TypeName::Number => "a number",
+                TypeName::String => "a string",
+                TypeName::Array => "an array",
+                TypeName::Boolean => "a boolean",
+            }
+        ),
+        Expression::Rounded(x, rounding) => format!(
+            "{} {}",
+            print_operand(x, 1),
+            match rounding {
+                Rounding::Up => "rounded up",
+                Rounding::Down => "rounded down",
+                Rounding::Nearest => "rounded",
+                Rounding::Truncate => "truncated",
+            }
+        ),
+        Expression::Bitwise(op, a, b) => match op {
+            BitwiseOp::ShiftLeft | BitwiseOp::ShiftRight => {
+                format!("the {} of {} by {}", op.name(), value(a), value(b))
+            }
+            _ => format!("the {} of {} and {}", op.name(), value(a), value(b)),
+        },
+        Expression::Between(x, lower, upper) => format!(
+            "{} is between {} and {}",
+            print_operand(x, level + 1),
+            print_operand(lower, level + 1),
+            print_operand(upper, level + 1)
+        ),
+        Expression::Slice(x, start, end) => {
+            format!("{} at {} through {}", value(x), value(start), value(end))
+        }
+        Expression::HasKey(a, b) => format!(
+            "{} has key {}",
+            print_operand(a, level + 1),
+            print_operand(b, level + 1)
+        ),
+        // Arrays only come from running the program, and a function's body is a block of lines
+        Expression::Array { .. } | Expression::Lambda { .. } | Expression::Closure { .. } => {
+            format!("{:?}", expression)
+        }
+    }
+}
+
 pub fn print_program(program: &Program) -> String {
+    print_program_with(program, &PrintOptions::default())
+}
+
+pub fn print_program_with(program: &Program, options: &PrintOptions) -> String {
     let indent = 0;
     let mut last_line = 0;
     let max_line: f32 = (program.commands.iter().fold(0, |acc, x| acc.max(x.line))) as f32;
     let max_number_length: usize = (max_line + 1.0).log10().ceil() as usize;
-    print_commands(&program.commands, &mut last_line, indent, max_number_length)
+    print_commands(
+        &program.commands,
+        &mut last_line,
+        indent,
+        max_number_length,
+        options,
+    )
+}
+
+// The program as S-expressions, one top-level command per line, like (say (add x 1)) for
+// "Say x plus 1", for tools that would rather not parse Rockstar. Names with spaces in are
+// written |like this|, and a nested block is a (block ...) of its commands.
+pub fn to_sexpr(program: &Program) -> String {
+    program
+        .commands
+        .iter()
+        .map(|command| sexpr_command(&command.cmd))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn sexpr_list(head: &str, parts: Vec<String>) -> String {
+    if parts.is_empty() {
+        format!("({})", head)
+    } else {
+        format!("({} {})", head, parts.join(" "))
+    }
+}
+
+fn sexpr_symbol(name: &str) -> String {
+    if name.is_empty() || name.contains(|c: char| c.is_whitespace() || "()|\"".contains(c)) {
+        format!("|{}|", name)
+    } else {
+        name.to_string()
+    }
+}
+
+fn sexpr_block(block: &Block) -> String {
+    sexpr_list(
+        "block",
+        block
+            .commands
+            .iter()
+            .map(|command| sexpr_command(&command.cmd))
+            .collect(),
+    )
+}
+
+fn sexpr_all(expressions: &[Expression]) -> Vec<String> {
+    expressions.iter().map(sexpr_expression).collect()
+}
+
+fn sexpr_optional(expression: Option<&Expression>) -> String {
+    expression.map_or("nil".to_string(), sexpr_expression)
+}
+
+fn sexpr_loop(head: &str, condition: &Expression, block: &Block, label: &Option<String>) -> String {
+    let mut parts = vec![sexpr_expression(condition), sexpr_block(block)];
+    if let Some(label) = label {
+        parts.insert(0, sexpr_list("label", vec![sexpr_symbol(label)]));
+    }
+    sexpr_list(head, parts)
+}
+
+fn sexpr_args(args: &[String]) -> String {
+    format!(
+        "({})",
+        args.iter()
+            .map(|arg| sexpr_symbol(arg))
+            .collect::<Vec<_>>()
+            .join(" ")
+    )
+}
+
+fn sexpr_command(command: &Command) -> String {
+    let e = sexpr_expression;
+    match command {
+        Command::Assignment { target, value } => sexpr_list("set", vec![e(target), e(value)]),
+        Command::MultipleAssignment { targets, value } => sexpr_list(
+            "set-all",
+            vec![format!("({})", sexpr_all(targets).join(" ")), e(value)],
+        ),
+        Command::Until {
+            expression,
+            block,
+            label,
+        } => sexpr_loop("until", expression, block, label),
+        Command::While {
+            expression,
+            block,
+            label,
+        } => sexpr_loop("while", expression, block, label),
+        Command::Repeat {
+            count,
+            block,
+            label,
+        } => sexpr_loop("repeat", count, block, label),
+        Command::If {
+            expression,
+            then,
+            otherwise,
+        } => {
+            let mut parts = vec![
+                e(expression),
+                then.as_ref().map_or("nil".to_string(), sexpr_block),
+            ];
+            if let Some(otherwise) = otherwise {
+                parts.push(sexpr_block(otherwise));
+            }
+            sexpr_list("if", parts)
+        }
+        Command::Increment { target, count } => sexpr_list(
+            "increment",
+            vec![e(target), crate::runner::format_number(*count)],
+        ),
+        Command::Decrement { target, count } => sexpr_list(
+            "decrement",
+            vec![e(target), crate::runner::format_number(*count)],
+        ),
+        Command::Continue => "(continue)".to_string(),
+        Command::Break => "(break)".to_string(),
+        Command::Restart { label } => sexpr_list("restart", vec![sexpr_symbol(label)]),
+        Command::Say { value } => sexpr_list("say", vec![e(value)]),
+        Command::Write { value } => sexpr_list("write", vec![e(value)]),
+        Command::Listen { targets, as_type } => {
+            let mut parts: Vec<String> = targets.iter().map(|t| sexpr_symbol(t)).collect();
+            parts.push(format!("{:?}", as_type).to_lowercase());
+            sexpr_list("listen", parts)
+        }
+        Command::FunctionDeclaration {
+            name,
+            args,
+            defaults,
+            rest,
+            block,
+        } => {
+            // The defaults belong to the last args, so they're written as (arg value) pairs
+            let required = args.len() - defaults.len().min(args.len());
+            let mut params: Vec<String> = args[..required]
+                .iter()
+                .map(|arg| sexpr_symbol(arg))
+                .collect();
+            params.extend(
+                args[required..]
+                    .iter()
+                    .zip(defaults)
+                    .map(|(arg, default)| format!("({} {})", sexpr_symbol(arg), e(default))),
+            );
+            if let Some(rest) = rest {
+                params.push("&rest".to_string());
+                params.push(sexpr_symbol(rest));
+            }
+            sexpr_list(
+                "function",
+                vec![
+                    sexpr_symbol(name),
+                    format!("({})", params.join(" ")),
+                    sexpr_block(block),
+                ],
+            )
+        }
+        Command::Return { return_value } => sexpr_list("return", vec![e(return_value)]),
+        Command::Call { name, args } => {
+            let mut parts = vec![sexpr_symbol(name)];
+            parts.extend(sexpr_all(args));
+            sexpr_list("call", parts)
+        }
+        Command::Floor { target } => sexpr_list("floor", vec![e(target)]),
+        Command::Ceil { target } => sexpr_list("ceil", vec![e(target)]),
+        Command::Round { target } => sexpr_list("round", vec![e(target)]),
+        Command::Negate { target } => sexpr_list("negate", vec![e(target)]),
+        Command::Empty { target } => sexpr_list("empty", vec![e(target)]),
+        Command::Sleep { seconds } => sexpr_list("sleep", vec![e(seconds)]),
+        Command::Throw { value } => sexpr_list("throw", vec![e(value)]),
+        Command::Push { target, values } => {
+            let mut parts = vec![e(target)];
+            parts.extend(sexpr_all(values));
+            sexpr_list("push", parts)
+        }
+        Command::Dequeue { target, removed } => {
+            let mut parts = vec![e(target)];
+            parts.extend(removed.iter().map(e));
+            sexpr_list("dequeue", parts)
+        }
+        Command::Remove {
+            target,
+            index,
+            removed,
+        } => {
+            let mut parts = vec![e(target), e(index)];
+            parts.extend(removed.iter().map(e));
+            sexpr_list("remove", parts)
+        }
+        Command::Insert {
+            value,
+            target,
+            index,
+        } => sexpr_list("insert", vec![e(target), e(index), e(value)]),
+        Command::Mutation {
+            mutator,
+            source,
+            target,
+            lookup,
+            modifier,
+        } => sexpr_list(
+            &format!("{:?}", mutator).to_lowercase(),
+            [source, target, lookup, modifier]
+                .iter()
+                .map(|part| sexpr_optional(part.as_deref()))
+                .collect(),
+        ),
+        Command::ApplyToArray {
+            operation,
+            function,
+            source,
+            target,
+        } => sexpr_list(
+            &format!("{:?}", operation).to_lowercase(),
+            vec![e(function), e(source), e(target)],
+        ),
+        Command::Pad {
+            value,
+            width,
+            fill,
+            right,
+            target,
+        } => sexpr_list(
+            if *right { "pad-right" } else { "pad-left" },
+            vec![e(value), e(width), sexpr_optional(fill.as_ref()), e(target)],
+        ),
+        Command::Clamp {
+            value,
+            low,
+            high,
+            target,
+        } => sexpr_list("clamp", vec![e(value), e(low), e(high), e(target)]),
+        Command::FormatNumber {
+            value,
+            places,
+            target,
+        } => sexpr_list("format-number", vec![e(value), e(places), e(target)]),
+        Command::Try {
+            body,
+            error_var,
+            recover,
+        } => {
+            let mut recovery: Vec<String> = error_var.iter().map(|v| sexpr_symbol(v)).collect();
+            recovery.push(sexpr_block(recover));
+            sexpr_list(
+                "try",
+                vec![sexpr_block(body), sexpr_list("recover", recovery)],
+            )
+        }
+        Command::Include { file, block } => {
+            sexpr_list("include", vec![format!("{:?}", file), sexpr_block(block)])
+        }
+    }
+}
+
+fn sexpr_expression(expression: &Expression) -> String {
+    let e = |x: &Expression| sexpr_expression(x);
+    let binary = |head: &str, a: &Expression, b: &Expression| sexpr_list(head, vec![e(a), e(b)]);
+    match expression {
+        Expression::String(s) => format!("{:?}", s),
+        Expression::Floating(f) => crate::runner::format_number(*f),
+        Expression::Variable(name) | Expression::Object(name) => sexpr_symbol(name),
+        Expression::ArrayRef { name, index } => binary("at", name, index),
+        Expression::Array { numeric, strings } => sexpr_list(
+            "array",
+            numeric
+                .iter()
+                .map(|(k, v)| format!("({} {})", k, e(v)))
+                .chain(strings.iter().map(|(k, v)| format!("({:?} {})", k, e(v))))
+                .collect(),
+        ),
+        Expression::Modifier(x) => sexpr_list("modifier", vec![e(x)]),
+        Expression::True => "true".to_string(),
+        Expression::False => "false".to_string(),
+        Expression::Call(name, args) => {
+            let mut parts = vec![sexpr_symbol(name)];
+            parts.extend(sexpr_all(args));
+            sexpr_list("call", parts)
+        }
+        Expression::Nothing => "nothing".to_string(),
+        Expression::Null => "null".to_string(),
+        Expression::Mysterious => "mysterious".to_string(),
+        Expression::Pronoun => "it".to_string(),
+        Expression::Not(x) => sexpr_list("not", vec![e(x)]),
+        Expression::SquareRoot(x) => sexpr_list("sqrt", vec![e(x)]),
+        Expression::Sentence(items) => sexpr_list("sentence", sexpr_all(items)),
+        Expression::Concatenation(items) => sexpr_list("concatenation", sexpr_all(items)),
+        Expression::List(items) => sexpr_list("list", sexpr_all(items)),
+        Expression::Text(x) => sexpr_list("text", vec![e(x)]),
+        Expression::Break => "(break)".to_string(),
+        Expression::Continue => "(continue)".to_string(),
+        Expression::Restart(label) => sexpr_list("restart", vec![sexpr_symbol(label)]),
+        Expression::Is(a, b) => binary("is", a, b),
+        Expression::Aint(a, b) => binary("aint", a, b),
+        Expression::Add(a, b) => binary("add", a, b),
+        Expression::Subtract(a, b) => binary("subtract", a, b),
+        Expression::Times(a, b) => binary("times", a, b),
+        Expression::Divide(a, b) => binary("divide", a, b),
+        Expression::Modulo(a, b) => binary("modulo", a, b),
+        Expression::And(a, b) => binary("and", a, b),
+        Expression::Or(a, b) => binary("or", a, b),
+        Expression::Nor(a, b) => binary("nor", a, b),
+        Expression::GreaterThanOrEqual(a, b) => binary(">=", a, b),
+        Expression::GreaterThan(a, b) => binary(">", a, b),
+        Expression::LessThanOrEqual(a, b) => binary("<=", a, b),
+        Expression::LessThan(a, b) => binary("<", a, b),
+        Expression::Power(a, b) => binary("power", a, b),
+        Expression::IsType(x, type_name) => sexpr_list(
+            "is-a",
+            vec![e(x), format!("{:?}", type_name).to_lowercase()],
+        ),
+        Expression::Rounded(x, rounding) => sexpr_list(
+            "rounded",
+            vec![e(x), format!("{:?}", rounding).to_lowercase()],
+        ),
+        Expression::Bitwise(op, a, b) => binary(&op.name().replace(' ', "-"), a, b),
+        Expression::Between(value, lower, upper) => {
+            sexpr_list("between", vec![e(value), e(lower), e(upper)])
+        }
+        Expression::Slice(value, start, end) => {
+            sexpr_list("slice", vec![e(value), e(start), e(end)])
+        }
+        Expression::HasKey(a, b) => binary("has-key", a, b),
+        Expression::Coalesce(a, b) => binary("or-else", a, b),
+        Expression::Lambda { args, block } | Expression::Closure { args, block, .. } => {
+            sexpr_list("lambda", vec![sexpr_args(args), sexpr_block(block)])
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::print_program;
+    use super::{print_expression, print_program, print_program_with, to_sexpr, PrintOptions};
+    use crate::common::{Command, Expression};
     use crate::parser;
 
     fn test_print(code: &str, expected: &str) {
@@ -127,6 +744,35 @@ mod tests {
         test_print(code, expected);
     }
 
+    #[test]
+    fn test_print_functions_in_order() {
+        let code = "Midnight takes your heart
+Give back your heart
+
+Noon takes your soul
+Give back your soul
+
+Dawn takes your mind
+Give back your mind
+";
+        let expected =
+            "1: FunctionDeclaration { name: \"Midnight\", args: [\"your heart\"], block: Block {
+2:   Return { return_value: Variable(\"your heart\") }
+   }}
+3:
+4: FunctionDeclaration { name: \"Noon\", args: [\"your soul\"], block: Block {
+5:   Return { return_value: Variable(\"your soul\") }
+   }}
+6:
+7: FunctionDeclaration { name: \"Dawn\", args: [\"your mind\"], block: Block {
+8:   Return { return_value: Variable(\"your mind\") }
+   }}
+";
+        for _ in 0..5 {
+            test_print(code, expected);
+        }
+    }
+
     #[test]
     fn test_print_if() {
         let code = "If a thought is greater than nothing
@@ -165,4 +811,146 @@ mod tests {
 ";
         test_print(code, expected);
     }
+
+    #[test]
+    fn test_print_indentation() {
+        let code = "Midnight takes your heart
+If your heart is nothing
+Give back your heart
+";
+        let program = parser::parse(code).unwrap();
+        let tabs = print_program_with(
+            &program,
+            &PrintOptions {
+                use_tabs: true,
+                ..PrintOptions::default()
+            },
+        );
+        let four = print_program_with(
+            &program,
+            &PrintOptions {
+                indent_width: 4,
+                ..PrintOptions::default()
+            },
+        );
+        assert_eq!(
+            Some("3: \t\tReturn { return_value: Variable(\"your heart\") }"),
+            tabs.lines().nth(2)
+        );
+        assert_eq!(
+            Some("3:         Return { return_value: Variable(\"your heart\") }"),
+            four.lines().nth(2)
+        );
+
+        // Only the whitespace differs, not what's printed
+        let squashed = |text: &str| text.split_whitespace().collect::<Vec<_>>().join(" ");
+        assert_eq!(squashed(&print_program(&program)), squashed(&tabs));
+        assert_eq!(squashed(&tabs), squashed(&four));
+    }
+
+    #[test]
+    fn test_print_nested_expression() {
+        let expression = Expression::Add(
+            Box::new(Expression::Floating(1.0)),
+            Box::new(Expression::Times(
+                Box::new(Expression::Floating(2.0)),
+                Box::new(Expression::Variable("my heart".to_string())),
+            )),
+        );
+        assert_eq!("1 plus 2 times my heart", print_expression(&expression));
+
+        let expression = Expression::Times(
+            Box::new(Expression::Add(
+                Box::new(Expression::Floating(1.0)),
+                Box::new(Expression::Floating(2.0)),
+            )),
+            Box::new(Expression::Variable("my heart".to_string())),
+        );
+        assert_eq!("(1 plus 2) times my heart", print_expression(&expression));
+
+        let expression = Expression::Subtract(
+            Box::new(Expression::Subtract(
+                Box::new(Expression::Floating(10.0)),
+                Box::new(Expression::Floating(2.0)),
+            )),
+            Box::new(Expression::Subtract(
+                Box::new(Expression::Floating(3.0)),
+                Box::new(Expression::Floating(1.0)),
+            )),
+        );
+        assert_eq!(
+            "10 minus 2 minus (3 minus 1)",
+            print_expression(&expression)
+        );
+
+        let expression = Expression::Not(Box::new(Expression::Is(
+            Box::new(Expression::Variable("my heart".to_string())),
+            Box::new(Expression::True),
+        )));
+        assert_eq!("not (my heart is true)", print_expression(&expression));
+    }
+
+    #[test]
+    fn test_print_call_expression() {
+        let expression = Expression::Call(
+            "Midnight".to_string(),
+            vec![
+                Expression::Variable("your heart".to_string()),
+                Expression::String("soul".to_string()),
+            ],
+        );
+        assert_eq!(
+            "Midnight taking your heart, \"soul\"",
+            print_expression(&expression)
+        );
+    }
+
+    #[test]
+    fn test_print_expression_round_trip() {
+        for source in &[
+            "1 plus 2 times my heart",
+            "10 minus 2 minus 3 over 4",
+            "my heart is greater than 5 and your soul is as low as 2",
+            "not my heart or your soul nor the night",
+            "my heart ain't null or else the night",
+            "Midnight taking your heart plus 1, \"soul\"",
+            "the concatenation of my heart, \" and \", 2 squared",
+            "my heart at 1 through 3 has key 2",
+            "your soul is between 1 and 10",
+        ] {
+            let value = |code: &str| {
+                let program = parser::parse(&format!("Put {} into the result\n", code)).unwrap();
+                match &program.commands[0].cmd {
+                    Command::Assignment { value, .. } => (**value).clone(),
+                    other => panic!("{:?}", other),
+                }
+            };
+            let expression = value(source);
+            let printed = print_expression(&expression);
+            assert_eq!(*source, printed);
+            assert_eq!(expression, value(&printed));
+        }
+    }
+
+    #[test]
+    fn test_sexpr() {
+        let program = parser::parse(
+            "My heart is 5
+Say my heart plus 1
+While my heart is greater than 0
+Knock my heart down
+If my heart is 2
+Say \"two\"
+Else
+Break
+",
+        )
+        .unwrap();
+        assert_eq!(
+            "(set |my heart| 5)
+(say (add |my heart| 1))
+(while (> |my heart| 0) (block (decrement |my heart| 1) (if (is |my heart| 2) (block (say \"two\")) (block (break)))))",
+            to_sexpr(&program)
+        );
+    }
 }
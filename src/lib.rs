@@ -0,0 +1,15 @@
+#![deny(warnings)]
+#![allow(clippy::needless_return)]
+#![recursion_limit = "256"]
+#![allow(clippy::result_large_err)]
+#![allow(clippy::upper_case_acronyms)]
+
+pub mod analysis;
+pub mod builtins;
+pub mod common;
+pub mod diff;
+pub mod display;
+pub mod parser;
+pub mod peg;
+pub mod python;
+pub mod runner;
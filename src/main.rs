@@ -1,14 +1,9 @@
 #![deny(warnings)]
 #![allow(clippy::needless_return)]
-#![recursion_limit = "256"]
 #![allow(clippy::result_large_err)]
-#![allow(clippy::upper_case_acronyms)]
 
-mod common;
-mod display;
-mod parser;
-mod peg;
-mod runner;
+#[cfg(not(target_arch = "wasm32"))]
+use maiden::{common, display, parser, runner};
 
 #[cfg(target_arch = "wasm32")]
 use std::panic;
@@ -33,20 +28,78 @@ fn main() -> common::Result<()> {
                 .required(true)
                 .index(1),
         )
+        .arg(
+            Arg::with_name("trace")
+                .long("trace")
+                .help("Print each command to stderr as it runs"),
+        )
+        .arg(
+            Arg::with_name("number-words")
+                .long("number-words")
+                .help("Treat spelled-out numbers (\"five\", \"twenty-one\") as numeric literals"),
+        )
+        .arg(
+            Arg::with_name("line-numbers")
+                .long("line-numbers")
+                .help("Start each line printed by Say with the source line it came from"),
+        )
+        .arg(
+            Arg::with_name("report")
+                .long("report")
+                .help("Print the final variables and how much work the run took to stderr"),
+        )
+        .arg(
+            Arg::with_name("sexpr")
+                .long("sexpr")
+                .help("Print the parsed program as S-expressions instead of running it"),
+        )
+        .arg(
+            Arg::with_name("seed")
+                .long("seed")
+                .takes_value(true)
+                .help("Starting point for random numbers, so a run can be repeated"),
+        )
         .get_matches();
     let mut f = File::open(matches.value_of("INPUT").unwrap())?;
     let mut buffer = String::new();
     f.read_to_string(&mut buffer)?;
+    if matches.is_present("number-words") {
+        buffer = parser::expand_number_words(&buffer);
+    }
 
     let mut program = match parser::parse(&buffer) {
         Err(err) => {
             // This hack is in here as the standard Err printing uses Debug, not Display
-            eprintln!("Error: {}", err);
+            eprintln!("Error: {} [{}]", err, err.code());
             std::process::exit(1);
         }
         other => other?,
     };
-    runner::run(&mut program, &mut io::stdout())?;
+    if matches.is_present("sexpr") {
+        println!("{}", display::to_sexpr(&program));
+        return Ok(());
+    }
+    let random_seed = match matches.value_of("seed").map(str::parse) {
+        Some(Err(_)) => {
+            eprintln!("Error: the seed must be a whole number from 0 upwards");
+            std::process::exit(1);
+        }
+        Some(Ok(seed)) => seed,
+        None => 0,
+    };
+    let options = runner::Options {
+        trace: matches.is_present("trace"),
+        line_numbers: matches.is_present("line-numbers"),
+        report: matches.is_present("report"),
+        random_seed,
+        ..runner::Options::default()
+    };
+    let mut stdout = io::stdout();
+    let mut stderr = io::stderr();
+    let mut interpreter = runner::Interpreter::new(&mut stdout, options);
+    interpreter.diagnostics(&mut stderr);
+    interpreter.source(&buffer);
+    interpreter.run(&mut program)?;
     Ok(())
 }
 
@@ -68,9 +121,9 @@ fn main() {
 
 #[cfg(test)]
 mod tests {
-    use super::*;
-    use crate::common::Expression;
     use log::{debug, info};
+    use maiden::common::{self, Expression};
+    use maiden::{parser, runner};
     use pretty_assertions::assert_eq;
     use std::collections::HashMap;
     use std::io::Cursor;
@@ -233,6 +286,132 @@ mod tests {
         );
     }
 
+    #[test]
+    fn increase_and_decrease_by() {
+        let end_variables = hashmap! {
+            "my world" => Expression::Floating(5.5f64),
+            "the walls" => Expression::Floating(-3f64),
+        };
+        test_program(
+            "Put 0 into my world\nIncrease my world by 5.5\n\
+             Put 0 into the walls\nDecrease it by 3\n",
+            end_variables,
+            "",
+        );
+        match test_error("Put \"rock\" into my world\nIncrease my world by 1\n") {
+            common::MaidenError::Unimplemented { line, .. } => assert_eq!(line, 2),
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn try_and_recover() {
+        test_program(
+            "Try
+Say my ghost
+Recover with the problem
+Say the problem
+
+Try
+Put 1 over 0 into the answer
+Say \"not here\"
+Recover
+Say \"recovered\"
+
+Try
+Say \"fine\"
+Recover
+Say \"not here either\"
+
+",
+            hashmap! {
+                "the problem" => Expression::String("Missing variable 'my ghost'".to_string()),
+            },
+            "Missing variable 'my ghost'\nrecovered\nfine\n",
+        );
+
+        let options = runner::Options {
+            output_limit: Some(2),
+            ..runner::Options::default()
+        };
+        let mut program = parser::parse("Try\nSay \"too long\"\nRecover\nSay \"no\"\n").unwrap();
+        let mut writer = Cursor::new(Vec::new());
+        let err = runner::run_with_options(&mut program, &mut writer, options)
+            .err()
+            .unwrap();
+        assert!(
+            matches!(err, common::MaidenError::OutputLimit { .. }),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn throw_and_catch() {
+        test_program(
+            "Try
+Throw \"bad \" with 1
+Say \"not here\"
+Recover with the problem
+Say the problem
+
+",
+            hashmap! {
+                "the problem" => Expression::String("bad 1".to_string()),
+            },
+            "bad 1\n",
+        );
+        let err = test_error("Say \"before\"\nThrow \"the end\"\nSay \"after\"\n");
+        if let common::MaidenError::UserError { ref message, line } = err {
+            assert_eq!(message, "the end");
+            assert_eq!(line, 2);
+        } else {
+            panic!("{}", err);
+        }
+        assert_eq!(err.to_string(), "the end");
+    }
+
+    #[test]
+    fn inline_rounding() {
+        test_program(
+            "Let the nearest be 7 over 2 rounded
+Let the ceiling be 2.1 rounded up
+Let the floor be 2.9 rounded down
+Let the whole be 0 minus 2.9 truncated
+Let the rest be 0 minus 2.9 rounded down
+",
+            hashmap! {
+                "the nearest" => Expression::Floating(4f64),
+                "the ceiling" => Expression::Floating(3f64),
+                "the floor" => Expression::Floating(2f64),
+                "the whole" => Expression::Floating(-2f64),
+                "the rest" => Expression::Floating(-3f64),
+            },
+            "",
+        );
+    }
+
+    #[test]
+    fn rounding_modifier() {
+        test_program(
+            "Put 7 over 2 into my heart, rounding down
+Say 2.5 times 3, rounding up
+Say 0 minus 2.5, rounding to nearest
+",
+            hashmap! {
+                "my heart" => Expression::Floating(3f64),
+            },
+            "8\n-3\n",
+        );
+        let err = test_error("Put \"rock\" into my heart, rounding up\n");
+        if let common::MaidenError::BadArgument { name, line, .. } = err {
+            assert_eq!(name, "Rounding");
+            assert_eq!(line, 1);
+        } else {
+            panic!("{}", err);
+        }
+    }
+
     #[test]
     fn skip_else() {
         let end_variables = hashmap! {
@@ -261,21 +440,2963 @@ Bar says bar
         }
     }
 
-    fn test_error(input: &str) -> common::MaidenError {
-        pretty_env_logger::try_init().unwrap_or(());
-        let mut program = parser::parse(input).unwrap();
+    #[test]
+    fn sleep() {
+        let end_variables = HashMap::new();
+        test_program("Wait 0.01\nSay \"awake\"", end_variables, "awake\n");
+    }
+
+    #[test]
+    fn sleep_past_time_limit() {
+        let mut program = parser::parse("Say \"tired\"\nWait 10\nSay \"awake\"").unwrap();
+        let options = runner::Options {
+            time_limit: Some(std::time::Duration::from_millis(50)),
+            ..runner::Options::default()
+        };
+        let start = std::time::Instant::now();
+        let mut writer = Vec::new();
+        let err = runner::run_with_options(&mut program, &mut writer, options).unwrap_err();
+        assert!(start.elapsed() < std::time::Duration::from_secs(5));
+        match err {
+            common::MaidenError::TimeLimit { milliseconds, line } => {
+                assert_eq!(milliseconds, 50);
+                assert_eq!(line, 2);
+            }
+            other => panic!("{:?}", other),
+        }
+        assert_eq!(String::from_utf8(writer).unwrap(), "tired\n");
+    }
+
+    #[test]
+    fn sleep_too_long() {
+        let err = test_error("Wait 1000000 cubed cubed");
+        match err {
+            common::MaidenError::BadArgument { name, .. } => assert_eq!(name, "Wait"),
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn boolean_precedence() {
+        let end_variables = hashmap! {
+            "the first" => Expression::False,
+            "the second" => Expression::True,
+            "the third" => Expression::True,
+        };
+        test_program(
+            "Put not wrong and wrong into the first
+Put right or wrong and wrong into the second
+Put not not right into the third",
+            end_variables,
+            "",
+        );
+    }
+
+    #[test]
+    fn powers() {
+        let end_variables = hashmap! {
+            "the root" => Expression::Floating(4f64),
+            "my square" => Expression::Floating(9f64),
+            "the cube" => Expression::Floating(8f64),
+        };
+        test_program(
+            "Put the square root of 16 into the root
+Put 3 squared into my square
+Put 2 cubed into the cube",
+            end_variables,
+            "",
+        );
+    }
+
+    #[test]
+    fn mixed_precedence() {
+        let end_variables = hashmap! {
+            "the first" => Expression::Floating(50f64),
+            "the second" => Expression::Floating(2f64),
+            "the third" => Expression::Floating(2f64),
+            "the fourth" => Expression::Floating(5f64),
+            "the fifth" => Expression::Floating(3f64),
+        };
+        test_program(
+            "Let the first be 2 plus 3 times 4 squared
+Let the second be 10 modulo 3 squared plus 1
+Let the third be 2 squared times 3 modulo 5
+Let the fourth be 7 minus 2 cubed over 4
+Let the fifth be 7 minus 8 modulo 3 times 2",
+            end_variables,
+            "",
+        );
+    }
+
+    #[test]
+    fn negative_square_root() {
+        let err = test_error(
+            "Put 0 minus 4 into the debt\nPut the square root of the debt into the root",
+        );
+        if let common::MaidenError::NotANumber { line, .. } = err {
+            assert_eq!(line, 2);
+        } else {
+            panic!("{}", err);
+        }
+    }
+
+    #[test]
+    fn indexed_assignment() {
+        let mut numeric = std::collections::BTreeMap::new();
+        numeric.insert(0, Box::new(Expression::Floating(7f64)));
+        numeric.insert(3, Box::new(Expression::Floating(5f64)));
+        let end_variables = hashmap! {
+            "my array" => Expression::Array {
+                numeric,
+                strings: std::collections::BTreeMap::new(),
+            },
+            "the index" => Expression::Floating(3f64),
+            "the number" => Expression::Floating(9f64),
+        };
+        test_program(
+            "Put 3 into the index
+Put 5 into My Array at the index
+Put 7 into my array at 0
+Say my array
+Put 9 into the number",
+            end_variables,
+            "4\n",
+        );
+    }
+
+    #[test]
+    fn return_array() {
+        let mut numeric = std::collections::BTreeMap::new();
+        numeric.insert(0, Box::new(Expression::Floating(1f64)));
+        numeric.insert(1, Box::new(Expression::Floating(4f64)));
+        let end_variables = hashmap! {
+            "the result" => Expression::Array {
+                numeric,
+                strings: std::collections::BTreeMap::new(),
+            },
+            "the second" => Expression::Floating(4f64),
+        };
+        test_program(
+            "Builder takes the seed
+Put the seed into the list at 0
+Put the seed times 4 into the list at 1
+Return the list
+
+Put Builder taking 1 into the result
+Put the result at 1 into the second
+Say the result at 0
+Say the result",
+            end_variables,
+            "1\n2\n",
+        );
+    }
+
+    #[test]
+    fn disabled_builtins() {
+        let options = runner::Options {
+            disable_builtins: true,
+            ..runner::Options::default()
+        };
+        for (code, name, line) in [
+            (
+                "Midnight takes your heart\nGive back your heart\n\nSay Midnight taking 2\nSay powmod taking 4, 13, 497\n",
+                "powmod",
+                5,
+            ),
+            ("Wait 1\n", "wait", 1),
+            ("Listen to your heart\n", "listen", 1),
+        ] {
+            let mut program = parser::parse(code).unwrap();
+            let mut writer = Cursor::new(Vec::new());
+            let err = runner::run_with_options(&mut program, &mut writer, options)
+                .err()
+                .unwrap();
+            if let common::MaidenError::MissingFunction {
+                name: ref err_name,
+                line: err_line,
+            } = err
+            {
+                assert_eq!(err_name, name);
+                assert_eq!(err_line, line);
+            } else {
+                panic!("{}", err);
+            }
+            if name == "powmod" {
+                assert_eq!(std::str::from_utf8(writer.get_ref()).unwrap(), "2\n");
+            }
+        }
+    }
+
+    #[test]
+    fn say_array_output() {
+        let code = "Put 1 into the list at 0\nPut \"two\" into the list at 1\nSay the list\n";
+        for (array_output, expected) in [
+            (runner::ArrayOutput::Length, "2\n"),
+            (runner::ArrayOutput::Contents, "1, two\n"),
+            (
+                runner::ArrayOutput::Debug,
+                "Array { numeric: {0: Floating(1.0), 1: String(\"two\")}, strings: {} }\n",
+            ),
+        ] {
+            let mut program = parser::parse(code).unwrap();
+            let mut writer = Cursor::new(Vec::new());
+            let options = runner::Options {
+                array_output,
+                ..runner::Options::default()
+            };
+            runner::run_with_options(&mut program, &mut writer, options).unwrap();
+            assert_eq!(std::str::from_utf8(writer.get_ref()).unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn say_array_matches_reference() {
+        let code = "Put \"Axl\" into the band at 0\nPut \"Izzy\" into the band at 5
+Put \"Steven\" into the band at \"drums\"\nSay the band\n";
+        let mut program = parser::parse(code).unwrap();
         let mut writer = Cursor::new(Vec::new());
-        runner::run(&mut program, &mut writer).err().unwrap()
+        runner::run(&mut program, &mut writer).unwrap();
+        assert_eq!(std::str::from_utf8(writer.get_ref()).unwrap(), "6\n");
+        assert_eq!(
+            runner::Options::default().array_output,
+            runner::ArrayOutput::Length
+        );
     }
 
     #[test]
-    fn missing_variable() {
-        let err = test_error("Put Desire into my world");
-        if let common::MaidenError::MissingVariable { name, line } = err {
-            assert_eq!(name, "Desire");
+    fn inclusive_slices() {
+        let code = "Rock the list with 10, 20, 30, 40, 50
+Put \"midnight\" into the word
+Say the list at 1 through 3
+Say the list at 3 through 99
+Say the list at 3 through 1
+Say the word at 0 through 2
+Say the word at 5 through 20
+Say the word at 4 through 2
+Say the list at 1 through 100000000000000000000
+Say the word at 2 through 100000000000000000000
+";
+        let mut program = parser::parse(code).unwrap();
+        let mut writer = Cursor::new(Vec::new());
+        let options = runner::Options {
+            array_output: runner::ArrayOutput::Contents,
+            ..runner::Options::default()
+        };
+        runner::run_with_options(&mut program, &mut writer, options).unwrap();
+        assert_eq!(
+            std::str::from_utf8(writer.get_ref()).unwrap(),
+            "20, 30, 40\n40, 50\n\nmid\nght\n\n20, 30, 40, 50\ndnight\n"
+        );
+    }
+
+    #[test]
+    fn boolean_words() {
+        let code = "Say true\nSay false\nSay \"it's \" plus lies\n";
+        for (boolean_words, expected) in [
+            (runner::BooleanWords::TrueFalse, "true\nfalse\nit's false\n"),
+            (runner::BooleanWords::YesNo, "yes\nno\nit's no\n"),
+            (
+                runner::BooleanWords::RightWrong,
+                "right\nwrong\nit's wrong\n",
+            ),
+        ] {
+            let mut program = parser::parse(code).unwrap();
+            let mut writer = Cursor::new(Vec::new());
+            let options = runner::Options {
+                boolean_words,
+                ..runner::Options::default()
+            };
+            runner::run_with_options(&mut program, &mut writer, options).unwrap();
+            assert_eq!(std::str::from_utf8(writer.get_ref()).unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn scientific_numbers() {
+        let options = runner::Options {
+            number_format: runner::NumberFormat::Scientific(2),
+            ..runner::Options::default()
+        };
+        let mut program =
+            parser::parse("Say 1500\nSay 0 minus 0.25\nSay \"about \" with 42\n").unwrap();
+        let mut writer = Cursor::new(Vec::new());
+        runner::run_with_options(&mut program, &mut writer, options).unwrap();
+        assert_eq!(
+            std::str::from_utf8(writer.get_ref()).unwrap(),
+            "1.50e3\n-2.50e-1\nabout 4.20e1\n"
+        );
+    }
+
+    #[test]
+    fn map_keys_and_values() {
+        let code = "Put 10 into the map at \"b\"\nPut 20 into the map at \"a\"\n\
+                    Put 30 into the map at 0\n\
+                    Put the keys of the map into the names\n\
+                    Put the values of the map into the scores\n\
+                    Join the names with \" \"\nSay the names\n\
+                    Join the scores with \" \"\nSay the scores\n";
+        let mut program = parser::parse(code).unwrap();
+        let mut writer = Cursor::new(Vec::new());
+        runner::run(&mut program, &mut writer).unwrap();
+        assert_eq!(
+            std::str::from_utf8(writer.get_ref()).unwrap(),
+            "0 a b\n30 20 10\n"
+        );
+    }
+
+    #[test]
+    fn empty_collections() {
+        let empty = || Expression::Array {
+            numeric: std::collections::BTreeMap::new(),
+            strings: std::collections::BTreeMap::new(),
+        };
+        test_program(
+            "Rock the list with 1, 2, 3
+Empty the list
+Put 10 into the map at \"rock\"
+Put 20 into the map at 0
+Empty the map
+Put 30 into the map at \"roll\"
+Say the map has key \"rock\"
+Empty the map",
+            hashmap! {
+                "the list" => empty(),
+                "the map" => empty(),
+            },
+            "false\n",
+        );
+    }
+
+    #[test]
+    fn empty_scalar() {
+        let err = test_error("Put 5 into my heart\nEmpty my heart");
+        if let common::MaidenError::BadArgument { name, line, .. } = err {
+            assert_eq!(name, "Empty");
+            assert_eq!(line, 2);
+        } else {
+            panic!("{}", err);
+        }
+    }
+
+    #[test]
+    fn empty_without_pronoun() {
+        let err = test_error("Empty it");
+        if let common::MaidenError::UndefinedPronoun { line } = err {
             assert_eq!(line, 1);
         } else {
             panic!("{}", err);
         }
     }
+
+    #[test]
+    fn map_has_key() {
+        let code = "Put 10 into the map at \"rock\"\nPut 20 into the map at 0\n\
+                    Say the map has key \"rock\"\nSay the map has key \"roll\"\n\
+                    Say the map has key 0\nSay the map has key 1\n";
+        let mut program = parser::parse(code).unwrap();
+        let mut writer = Cursor::new(Vec::new());
+        runner::run(&mut program, &mut writer).unwrap();
+        assert_eq!(
+            std::str::from_utf8(writer.get_ref()).unwrap(),
+            "true\nfalse\ntrue\nfalse\n"
+        );
+        match test_error("Put 5 into my number\nSay my number has key 0\n") {
+            common::MaidenError::BadArgument { name, line, .. } => {
+                assert_eq!(name, "Has key");
+                assert_eq!(line, 2);
+            }
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn seeded_random() {
+        let code = "Put a random number between 1 and 100 into the dice\nSay the dice\n\
+                    Say a random number\nSay random between 1 and 6\n";
+        let outputs: Vec<Vec<u8>> = [7, 7, 8]
+            .iter()
+            .map(|&random_seed| {
+                let options = runner::Options {
+                    random_seed,
+                    ..runner::Options::default()
+                };
+                let mut program = parser::parse(code).unwrap();
+                let mut writer = Cursor::new(Vec::new());
+                runner::run_with_options(&mut program, &mut writer, options).unwrap();
+                writer.into_inner()
+            })
+            .collect();
+        assert_eq!(outputs[0], outputs[1]);
+        assert_ne!(outputs[0], outputs[2]);
+    }
+
+    #[test]
+    fn strict_mode() {
+        let strict = runner::Options {
+            strict: true,
+            ..runner::Options::default()
+        };
+        // Fine when run, but the Say could see my heart before it's set
+        let code = "If false\nPut 1 into my heart\n\nIf false\nSay my heart\n";
+        let mut program = parser::parse(code).unwrap();
+        let mut writer = Cursor::new(Vec::new());
+        runner::run(&mut program, &mut writer).unwrap();
+        match runner::run_with_options(&mut program, &mut writer, strict) {
+            Err(common::MaidenError::MissingVariable { name, line }) => {
+                assert_eq!(name, "my heart");
+                assert_eq!(line, 5);
+            }
+            other => panic!("{:?}", other),
+        }
+
+        // A warning while running stops it there, after what's already been said
+        let code = "Say \"before\"\nPut \"ab\" times 1.5 into my heart\nSay my heart\n";
+        let mut program = parser::parse(code).unwrap();
+        let mut warnings = vec![];
+        let mut writer = Cursor::new(Vec::new());
+        let mut interpreter = runner::Interpreter::new(&mut writer, runner::Options::default());
+        interpreter.warnings(&mut warnings);
+        interpreter.run(&mut program).unwrap();
+        drop(interpreter);
+        assert_eq!(warnings.len(), 1);
+        let capture = runner::run_captured(&mut program, strict);
+        match capture.result {
+            Err(common::MaidenError::LossyConversion { line, .. }) => assert_eq!(line, 2),
+            other => panic!("{:?}", other),
+        }
+        assert_eq!(capture.output, "before\n");
+    }
+
+    #[test]
+    fn strict_mode_function_values() {
+        let strict = runner::Options {
+            strict: true,
+            ..runner::Options::default()
+        };
+        let code = "Let the doubler be a function taking the number
+Give back the number times 2
+
+Say the doubler taking 4
+";
+        let mut program = parser::parse(code).unwrap();
+        let mut writer = Cursor::new(Vec::new());
+        runner::run_with_options(&mut program, &mut writer, strict).unwrap();
+        assert_eq!(std::str::from_utf8(writer.get_ref()).unwrap(), "8\n");
+
+        let mut writer = Cursor::new(Vec::new());
+        {
+            let mut interpreter = runner::Interpreter::new(&mut writer, strict);
+            interpreter.register("Twice", |args| match args.first() {
+                Some(Expression::Floating(f)) => Ok(Expression::Floating(f * 2.0)),
+                _ => Ok(Expression::Mysterious),
+            });
+            let mut program = parser::parse("Say Twice taking 21\n").unwrap();
+            interpreter.run(&mut program).unwrap();
+        }
+        assert_eq!(std::str::from_utf8(writer.get_ref()).unwrap(), "42\n");
+    }
+
+    #[test]
+    fn strict_comparison() {
+        let code = "Say \"a\" is 1\nSay 1 is \"a\"\nSay \"a\" is mysterious\n";
+        let mut program = parser::parse(code).unwrap();
+        let mut writer = Cursor::new(Vec::new());
+        runner::run(&mut program, &mut writer).unwrap();
+        assert_eq!(
+            std::str::from_utf8(writer.get_ref()).unwrap(),
+            "false\nfalse\nfalse\n"
+        );
+
+        let options = runner::Options {
+            strict_comparison: true,
+            ..runner::Options::default()
+        };
+        let mut program = parser::parse("Say \"a\" is mysterious\nSay \"a\" is 1\n").unwrap();
+        let mut writer = Cursor::new(Vec::new());
+        let err = runner::run_with_options(&mut program, &mut writer, options)
+            .err()
+            .unwrap();
+        if let common::MaidenError::TypeMismatch { left, right, line } = err {
+            assert_eq!(left, "String(\"a\")");
+            assert_eq!(right, "1");
+            assert_eq!(line, 2);
+        } else {
+            panic!("{}", err);
+        }
+        assert_eq!(std::str::from_utf8(writer.get_ref()).unwrap(), "false\n");
+    }
+
+    #[test]
+    fn exact_division() {
+        let options = runner::Options {
+            exact_division: true,
+            ..runner::Options::default()
+        };
+        let mut program = parser::parse("Say 6 over 4\nSay 1 over 3\n").unwrap();
+        let mut writer = Cursor::new(Vec::new());
+        let err = runner::run_with_options(&mut program, &mut writer, options)
+            .err()
+            .unwrap();
+        if let common::MaidenError::InexactDivision { x, y, line } = err {
+            assert_eq!(x, "1");
+            assert_eq!(y, "3");
+            assert_eq!(line, 2);
+        } else {
+            panic!("{}", err);
+        }
+        assert_eq!(std::str::from_utf8(writer.get_ref()).unwrap(), "1.5\n");
+    }
+
+    #[test]
+    fn newline_styles() {
+        for (newline, expected) in [
+            (runner::Newline::Lf, "1\ntwo\n"),
+            (runner::Newline::CrLf, "1\r\ntwo\r\n"),
+            (runner::Newline::None, "1two"),
+        ] {
+            let mut program = parser::parse("Say 1\nSay \"two\"\n").unwrap();
+            let options = runner::Options {
+                newline,
+                ..runner::Options::default()
+            };
+            let capture = runner::run_captured(&mut program, options);
+            capture.result.unwrap();
+            assert_eq!(capture.output, expected);
+        }
+    }
+
+    #[test]
+    fn numbered_output() {
+        let code = "Say \"hello\"\n\nShout 1 plus 2\nWrite \"raw\"\n";
+        for (line_numbers, expected) in [(false, "hello\n3\nraw"), (true, "[1] hello\n[3] 3\nraw")]
+        {
+            let mut program = parser::parse(code).unwrap();
+            let options = runner::Options {
+                line_numbers,
+                ..runner::Options::default()
+            };
+            let capture = runner::run_captured(&mut program, options);
+            capture.result.unwrap();
+            assert_eq!(capture.output, expected);
+        }
+    }
+
+    #[test]
+    fn blank_output() {
+        for (newline, expected) in [
+            (runner::Newline::Lf, "1\n\n2\n\nnull\n"),
+            (runner::Newline::CrLf, "1\r\n\r\n2\r\n\r\nnull\r\n"),
+        ] {
+            let mut program =
+                parser::parse("Say 1\nSay a newline\nSay 2\nSay a blank line\nSay nothing\n")
+                    .unwrap();
+            let options = runner::Options {
+                newline,
+                ..runner::Options::default()
+            };
+            let capture = runner::run_captured(&mut program, options);
+            capture.result.unwrap();
+            assert_eq!(capture.output, expected);
+        }
+    }
+
+    #[test]
+    fn interpreter_reset() {
+        let mut writer = Cursor::new(Vec::new());
+        let mut interpreter = runner::Interpreter::new(&mut writer, runner::Options::default());
+        let mut first = parser::parse(
+            "Midnight takes your heart\nGive back your heart\n\nPut Midnight taking 2 into my heart\nSay my heart\n",
+        )
+        .unwrap();
+        interpreter.run(&mut first).unwrap();
+        assert_eq!(interpreter.stats().calls.get("Midnight"), Some(&1));
+        assert!(interpreter.variables().contains_key("my heart"));
+
+        interpreter.reset();
+        assert!(interpreter.variables().is_empty());
+        assert!(interpreter.stats().calls.is_empty());
+        let mut second = parser::parse("Say 3\nSay my heart\n").unwrap();
+        let err = interpreter.run(&mut second).err().unwrap();
+        if let common::MaidenError::MissingVariable { name, line } = err {
+            assert_eq!(name, "my heart");
+            assert_eq!(line, 2);
+        } else {
+            panic!("{}", err);
+        }
+        assert_eq!(std::str::from_utf8(writer.get_ref()).unwrap(), "2\n3\n");
+    }
+
+    #[test]
+    fn factorials() {
+        test_program(
+            "Put the factorial of 0 into my start
+Put the factorial of 5 into my hand
+Say my start and my hand
+",
+            hashmap! {
+                "my start" => Expression::Floating(1f64),
+                "my hand" => Expression::Floating(120f64),
+            },
+            "1 and 120\n",
+        );
+        match test_error("Say the factorial of -3\n") {
+            common::MaidenError::BadArgument { name, line, .. } => {
+                assert_eq!(name, "factorial");
+                assert_eq!(line, 1);
+            }
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn sign_of() {
+        test_program(
+            "Put the sign of -4 into my loss
+Put the sign of 0 into my start
+Put the sign of 0.5 into my gain
+Say my loss and my start and my gain
+",
+            hashmap! {
+                "my loss" => Expression::Floating(-1f64),
+                "my start" => Expression::Floating(0f64),
+                "my gain" => Expression::Floating(1f64),
+            },
+            "-1 and 0 and 1\n",
+        );
+        test_error("Say the sign of \"up\"\n");
+    }
+
+    #[test]
+    fn concatenation() {
+        test_program(
+            "Let my answer be the concatenation of \"Tommy is \", 5, \" and \", true
+Say my answer
+",
+            hashmap! {
+                "my answer" => Expression::String("Tommy is 5 and true".to_string()),
+            },
+            "Tommy is 5 and true\n",
+        );
+    }
+
+    #[test]
+    fn digits_round_trip() {
+        test_program(
+            "Put the digits of 1984 into my list
+Put the number from my list into my year
+Say my year plus 1
+",
+            hashmap! {
+                "my list" => Expression::Array {
+                    numeric: vec![1.0, 9.0, 8.0, 4.0]
+                        .into_iter()
+                        .map(|digit| Box::new(Expression::Floating(digit)))
+                        .enumerate()
+                        .collect(),
+                    strings: std::collections::BTreeMap::new(),
+                },
+                "my year" => Expression::Floating(1984f64),
+            },
+            "1985\n",
+        );
+    }
+
+    #[test]
+    fn counting() {
+        test_program(
+            "Put the count of \"na\" in \"banana\" into my word
+Put the count of \"z\" in \"banana\" into my nothing
+Rock my list with 3, 1, 3, 3
+Put the count of 3 in my list into my threes
+",
+            hashmap! {
+                "my word" => Expression::Floating(2f64),
+                "my nothing" => Expression::Floating(0f64),
+                "my list" => Expression::Array {
+                    numeric: vec![
+                        (0, Box::new(Expression::Floating(3f64))),
+                        (1, Box::new(Expression::Floating(1f64))),
+                        (2, Box::new(Expression::Floating(3f64))),
+                        (3, Box::new(Expression::Floating(3f64))),
+                    ]
+                    .into_iter()
+                    .collect(),
+                    strings: std::collections::BTreeMap::new(),
+                },
+                "my threes" => Expression::Floating(3f64),
+            },
+            "",
+        );
+    }
+
+    #[test]
+    fn bitwise_operators() {
+        test_program(
+            "Put the bitwise and of 12 and 10 into my mask
+Put the bitwise or of 12 and 10 into my flags
+Put the bitwise xor of 12 and 10 into my toggle
+Put the left shift of 3 by 4 into my high
+Put the right shift of 0xF0 by 4 into my low
+",
+            hashmap! {
+                "my mask" => Expression::Floating(8f64),
+                "my flags" => Expression::Floating(14f64),
+                "my toggle" => Expression::Floating(6f64),
+                "my high" => Expression::Floating(48f64),
+                "my low" => Expression::Floating(15f64),
+            },
+            "",
+        );
+
+        for (count, operation, name) in [
+            ("1.5", "bitwise and of 1 and", "bitwise and"),
+            ("64", "left shift of 1 by", "left shift"),
+            ("0 minus 1", "right shift of 8 by", "right shift"),
+        ] {
+            let err = test_error(&format!(
+                "Put {} into my count\nPut the {} my count into my heart\n",
+                count, operation
+            ));
+            if let common::MaidenError::BadArgument {
+                name: got, line, ..
+            } = err
+            {
+                assert_eq!(got, name);
+                assert_eq!(line, 2);
+            } else {
+                panic!("{}", err);
+            }
+        }
+    }
+
+    #[test]
+    fn result_of_call() {
+        test_program(
+            "Midnight takes your heart
+Give back your heart with 2
+
+Let my heart be the result of Midnight taking 3
+",
+            hashmap! {
+                "my heart" => Expression::Floating(5f64),
+            },
+            "",
+        );
+    }
+
+    #[test]
+    fn return_several_values() {
+        test_program(
+            "Midnight takes your heart
+Give back your heart, your heart times 2, and \"three\"
+
+Put Midnight taking 5 into my results
+Say my results at 0
+Say my results at 1
+Say my results at 2
+",
+            hashmap! {
+                "my results" => Expression::Array {
+                    numeric: vec![
+                        (0, Box::new(Expression::Floating(5f64))),
+                        (1, Box::new(Expression::Floating(10f64))),
+                        (2, Box::new(Expression::String("three".to_string()))),
+                    ]
+                    .into_iter()
+                    .collect(),
+                    strings: std::collections::BTreeMap::new(),
+                },
+            },
+            "5\n10\nthree\n",
+        );
+    }
+
+    #[test]
+    fn pipeline() {
+        test_program(
+            "Put \"a, b, c\" into my text
+My text then split with \", \" then join with \"-\" into my result
+",
+            hashmap! {
+                "my text" => Expression::String("a, b, c".to_string()),
+                "my result" => Expression::String("a-b-c".to_string()),
+            },
+            "",
+        );
+    }
+
+    #[test]
+    fn unless_else() {
+        test_program(
+            "Put right into my heart
+Unless my heart
+Say \"unless right\"
+Else
+Say \"else right\"
+
+Unless wrong
+Say \"unless wrong\"
+Else
+Say \"else wrong\"
+",
+            hashmap! {
+                "my heart" => Expression::True,
+            },
+            "else right\nunless wrong\n",
+        );
+    }
+
+    #[test]
+    fn opposite() {
+        test_program(
+            "Put the opposite of right into my heart\nPut the opposite of 0 into my soul\nPut not 0 into my love\n",
+            hashmap! {
+                "my heart" => Expression::False,
+                "my soul" => Expression::True,
+                "my love" => Expression::True,
+            },
+            "",
+        );
+    }
+
+    #[test]
+    fn multiple_assignment() {
+        test_program(
+            "Noisy takes your heart
+Say your heart
+Give back your heart
+
+Put Noisy taking 5 into my list at 1 and into my heart and into my list at 0
+",
+            hashmap! {
+                "my heart" => Expression::Floating(5f64),
+                "my list" => Expression::Array {
+                    numeric: vec![
+                        (0, Box::new(Expression::Floating(5f64))),
+                        (1, Box::new(Expression::Floating(5f64))),
+                    ]
+                    .into_iter()
+                    .collect(),
+                    strings: std::collections::BTreeMap::new(),
+                },
+            },
+            "5\n",
+        );
+    }
+
+    #[test]
+    fn compound_loop_conditions() {
+        test_program(
+            "Put 0 into my count
+Put true into my flag
+While my count is less than 10 and my flag is true
+Build my count up
+If my count is 3
+Put false into my flag
+
+
+Say my count
+Put 0 into my count
+Until my count is 5 or my count is greater than 2
+Build my count up
+
+Say my count
+",
+            hashmap! {
+                "my count" => Expression::Floating(3f64),
+                "my flag" => Expression::False,
+            },
+            "3\n3\n",
+        );
+
+        // The function is only called once the first side doesn't settle the condition
+        test_program(
+            "Noisy takes your heart
+Say your heart
+Give back false
+
+Put 0 into my count
+While my count is less than 2 or Noisy taking my count
+Build my count up
+
+While my count is greater than 5 and Noisy taking my count
+Build my count up
+
+",
+            hashmap! {
+                "my count" => Expression::Floating(2f64),
+            },
+            "2\n",
+        );
+    }
+
+    fn snapshot_programs() -> Vec<common::Program> {
+        vec![
+            parser::parse("Put 2 into my heart\nMidnight takes your love\nGive back your love with my heart\n\nSay Midnight taking 1\n").unwrap(),
+            parser::parse("Build my heart up\nSay my heart with 10\n").unwrap(),
+        ]
+    }
+
+    #[test]
+    fn snapshot_and_restore() {
+        let options = runner::Options::default();
+        let mut whole = Cursor::new(Vec::new());
+        let mut interpreter = runner::Interpreter::new(&mut whole, options);
+        for mut program in snapshot_programs() {
+            interpreter.run(&mut program).unwrap();
+        }
+        let expected = interpreter.snapshot();
+        drop(interpreter);
+
+        let mut programs = snapshot_programs();
+        let mut first = Cursor::new(Vec::new());
+        let mut interpreter = runner::Interpreter::new(&mut first, options);
+        interpreter.run(&mut programs[0]).unwrap();
+        let state = interpreter.snapshot();
+        drop(interpreter);
+
+        let mut second = Cursor::new(Vec::new());
+        let mut interpreter = runner::Interpreter::new(&mut second, options);
+        interpreter.restore(state);
+        interpreter.run(&mut programs[1]).unwrap();
+        assert_eq!(interpreter.snapshot(), expected);
+        drop(interpreter);
+
+        let resumed = [first.into_inner(), second.into_inner()].concat();
+        assert_eq!(resumed, whole.into_inner());
+        assert_eq!(std::str::from_utf8(&resumed).unwrap(), "3\n13\n");
+    }
+
+    #[cfg(feature = "snapshot")]
+    #[test]
+    fn snapshot_round_trip() {
+        let mut writer = Cursor::new(Vec::new());
+        let mut interpreter = runner::Interpreter::new(&mut writer, runner::Options::default());
+        interpreter.run(&mut snapshot_programs()[0]).unwrap();
+        let state = interpreter.snapshot();
+        let json = serde_json::to_string(&state).unwrap();
+        let restored: runner::InterpreterState = serde_json::from_str(&json).unwrap();
+        assert_eq!(restored, state);
+    }
+
+    #[test]
+    fn host_functions() {
+        let mut writer = Cursor::new(Vec::new());
+        let mut interpreter = runner::Interpreter::new(&mut writer, runner::Options::default());
+        interpreter.register("Twice", |args| {
+            if args.len() != 1 {
+                return Err(common::MaidenError::WrongArgCount {
+                    expected: 1,
+                    got: args.len(),
+                    line: 0,
+                });
+            }
+            match args[0] {
+                Expression::Floating(f) => Ok(Expression::Floating(f * 2.0)),
+                _ => Ok(Expression::Mysterious),
+            }
+        });
+        let mut program = parser::parse(
+            "Twice takes your heart\nGive back 0\n\nSay twice taking 21\nPut Twice taking 4 into my heart\n",
+        )
+        .unwrap();
+        interpreter.run(&mut program).unwrap();
+        assert_eq!(
+            interpreter.variables()["my heart"].1,
+            Expression::Floating(8.0)
+        );
+
+        let mut program = parser::parse("Say Twice taking 1, 2\n").unwrap();
+        let err = interpreter.run(&mut program).err().unwrap();
+        if let common::MaidenError::WrongArgCount { expected, got, .. } = err {
+            assert_eq!(expected, 1);
+            assert_eq!(got, 2);
+        } else {
+            panic!("{}", err);
+        }
+        assert_eq!(std::str::from_utf8(writer.get_ref()).unwrap(), "42\n");
+    }
+
+    #[test]
+    fn output_limit() {
+        let mut program = parser::parse("While true\nSay \"hello\"\n").unwrap();
+        let mut writer = Cursor::new(Vec::new());
+        let options = runner::Options {
+            output_limit: Some(15),
+            ..runner::Options::default()
+        };
+        let err = runner::run_with_options(&mut program, &mut writer, options)
+            .err()
+            .unwrap();
+        if let common::MaidenError::OutputLimit { limit, line } = err {
+            assert_eq!(limit, 15);
+            assert_eq!(line, 2);
+        } else {
+            panic!("{}", err);
+        }
+        assert_eq!(
+            std::str::from_utf8(writer.get_ref()).unwrap(),
+            "hello\nhello\nhel"
+        );
+    }
+
+    #[test]
+    fn output_limit_mid_character() {
+        let mut program = parser::parse("Say \"☃☃\"\n").unwrap();
+        let mut writer = Cursor::new(Vec::new());
+        let options = runner::Options {
+            output_limit: Some(4),
+            ..runner::Options::default()
+        };
+        match runner::run_with_options(&mut program, &mut writer, options) {
+            Err(common::MaidenError::OutputLimit { limit, .. }) => assert_eq!(limit, 4),
+            other => panic!("{:?}", other),
+        }
+        // The second snowman would only have had 1 of its 3 bytes written
+        assert_eq!(std::str::from_utf8(writer.get_ref()).unwrap(), "☃");
+    }
+
+    #[test]
+    fn permutations() {
+        let code = "Rock the band with \"Axl\", \"Slash\", \"Duff\"
+Put the permutations of the band into the lineups
+Say the lineups
+Put the lineups at 5 into the opener
+Say the opener at 0
+Rock the band with \"Izzy\", \"Steven\", \"Matt\", \"Gilby\"
+Put the permutations of the band into the lineups
+";
+        let mut program = parser::parse(code).unwrap();
+        // 7! lineups are too many, but 3! are fine
+        let options = runner::Options {
+            allocation_limit: Some(1000),
+            ..runner::Options::default()
+        };
+        let capture = runner::run_captured(&mut program, options);
+        match capture.result {
+            Err(common::MaidenError::AllocationLimit { line, .. }) => assert_eq!(line, 7),
+            other => panic!("{:?}", other),
+        }
+        assert_eq!(capture.output, "6\nDuff\n");
+    }
+
+    #[test]
+    fn run_report() {
+        let code = "Midnight takes your heart
+Give back your heart
+
+Put Midnight taking \"rock\" into my heart
+Say my heart
+";
+        let mut program = parser::parse(code).unwrap();
+        let quiet = runner::run_captured(&mut program, runner::Options::default());
+        assert_eq!(quiet.diagnostics, "");
+        let options = runner::Options {
+            report: true,
+            ..runner::Options::default()
+        };
+        let capture = runner::run_captured(&mut program, options);
+        capture.result.unwrap();
+        // Only what the program says goes to the output
+        assert_eq!(capture.output, "rock\n");
+        // Three commands at the top level and one in the call, plus one for getting to the end of
+        // the program. The call's Return leaves before it gets to the end.
+        assert!(
+            capture.diagnostics.contains("instructions: 5\n"),
+            "{}",
+            capture.diagnostics
+        );
+        assert!(capture.diagnostics.contains("deepest call: 1\n"));
+        assert!(capture.diagnostics.contains("  my heart = \"rock\"\n"));
+    }
+
+    #[test]
+    fn iteration_limit() {
+        let options = runner::Options {
+            iteration_limit: Some(50),
+            ..runner::Options::default()
+        };
+        // Going round the first loop 40 times is fine, but the second never stops
+        let code = "My count is 0
+Until my count is 40
+Build my count up
+
+While true
+Build my count up
+";
+        let mut program = parser::parse(code).unwrap();
+        let mut writer = Cursor::new(Vec::new());
+        let err = runner::run_with_options(&mut program, &mut writer, options)
+            .err()
+            .unwrap();
+        if let common::MaidenError::IterationLimit { limit, line } = err {
+            assert_eq!(limit, 50);
+            assert_eq!(line, 5);
+        } else {
+            panic!("{}", err);
+        }
+    }
+
+    #[test]
+    fn memory_limit() {
+        let options = runner::Options {
+            memory_limit: Some(25),
+            ..runner::Options::default()
+        };
+        let code = "Rock the first with 1, 2, 3, 4, 5, 6, 7, 8, 9, 10
+Rock the second with 1, 2, 3, 4, 5, 6, 7, 8, 9, 10
+Rock the third with 1, 2, 3, 4, 5, 6, 7, 8, 9, 10
+";
+        let mut program = parser::parse(code).unwrap();
+        let mut writer = Cursor::new(Vec::new());
+        let err = runner::run_with_options(&mut program, &mut writer, options)
+            .err()
+            .unwrap();
+        if let common::MaidenError::MemoryLimit { line } = err {
+            assert_eq!(line, 3);
+        } else {
+            panic!("{}", err);
+        }
+
+        // The same arrays, one at a time, stay under it
+        let code = "Rock the first with 1, 2, 3, 4, 5, 6, 7, 8, 9, 10
+Empty the first
+Rock the first with 1, 2, 3, 4, 5, 6, 7, 8, 9, 10
+Empty the first
+Rock the first with 1, 2, 3, 4, 5, 6, 7, 8, 9, 10
+";
+        let mut program = parser::parse(code).unwrap();
+        let mut writer = Cursor::new(Vec::new());
+        runner::run_with_options(&mut program, &mut writer, options).unwrap();
+    }
+
+    #[test]
+    fn allocation_limit() {
+        let options = runner::Options {
+            allocation_limit: Some(100),
+            ..runner::Options::default()
+        };
+        for (code, line) in [
+            (
+                "Put \"ab\" times 50 into my heart\nPut my heart times 2 into my soul\n",
+                2,
+            ),
+            (
+                "Put \"a\" into my heart\nWhile true\nLet my heart be with my heart\n",
+                3,
+            ),
+        ] {
+            let mut program = parser::parse(code).unwrap();
+            let mut writer = Cursor::new(Vec::new());
+            let err = runner::run_with_options(&mut program, &mut writer, options)
+                .err()
+                .unwrap();
+            if let common::MaidenError::AllocationLimit {
+                limit,
+                line: err_line,
+            } = err
+            {
+                assert_eq!(limit, 100);
+                assert_eq!(err_line, line);
+            } else {
+                panic!("{}", err);
+            }
+        }
+    }
+
+    #[test]
+    fn powmod() {
+        let end_variables = hashmap! {
+            "the key" => Expression::Floating(445f64),
+        };
+        test_program(
+            "Put powmod taking 4, 13, 497 into the key\nSay the key",
+            end_variables,
+            "445\n",
+        );
+    }
+
+    #[test]
+    fn powmod_bad_modulus() {
+        let err = test_error("Say powmod taking 4, 13, 0");
+        if let common::MaidenError::BadArgument { name, line, .. } = err {
+            assert_eq!(name, "powmod");
+            assert_eq!(line, 1);
+        } else {
+            panic!("{}", err);
+        }
+    }
+
+    #[test]
+    fn join_mixed_array() {
+        let mut numeric = std::collections::BTreeMap::new();
+        numeric.insert(0, Box::new(Expression::Floating(1f64)));
+        numeric.insert(1, Box::new(Expression::String("two".to_string())));
+        numeric.insert(2, Box::new(Expression::Floating(3.5f64)));
+        let end_variables = hashmap! {
+            "the list" => Expression::Array {
+                numeric,
+                strings: std::collections::BTreeMap::new(),
+            },
+            "the result" => Expression::String("1two3.5".to_string()),
+            "the other" => Expression::String("1,two,3.5".to_string()),
+        };
+        test_program(
+            "Put 1 into the list at 0
+Put \"two\" into the list at 1
+Put 3.5 into the list at 2
+Unite the list into the result
+Unite the list into the other with \",\"
+Say the result
+Say the other",
+            end_variables,
+            "1two3.5\n1,two,3.5\n",
+        );
+    }
+
+    #[test]
+    fn split_transform_join() {
+        let mut numeric = std::collections::BTreeMap::new();
+        numeric.insert(0, Box::new(Expression::String("a".to_string())));
+        numeric.insert(1, Box::new(Expression::String("B".to_string())));
+        numeric.insert(2, Box::new(Expression::String("c".to_string())));
+        numeric.insert(3, Box::new(Expression::String("d".to_string())));
+        let end_variables = hashmap! {
+            "my sentence" => Expression::String("a b c".to_string()),
+            "the words" => Expression::Array {
+                numeric,
+                strings: std::collections::BTreeMap::new(),
+            },
+            "the result" => Expression::String("a, B, c, d".to_string()),
+        };
+        test_program(
+            "Put \"a b c\" into my sentence
+Cut My Sentence into the words with \" \"
+Put \"B\" into the words at 1
+Rock the words with \"d\"
+Unite the words into the result with \", \"
+Say the result",
+            end_variables,
+            "a, B, c, d\n",
+        );
+    }
+
+    #[test]
+    fn split_non_string() {
+        match test_error("Cut 12 into the words") {
+            common::MaidenError::BadArgument { name, line, .. } => {
+                assert_eq!(name, "Split");
+                assert_eq!(line, 1);
+            }
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn join_non_array() {
+        match test_error("Unite true into the result") {
+            common::MaidenError::BadArgument { name, line, .. } => {
+                assert_eq!(name, "Join");
+                assert_eq!(line, 1);
+            }
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn compound_assignment() {
+        let end_variables = hashmap! {
+            "the total" => Expression::Floating(6f64),
+        };
+        test_program(
+            "Put 10 into the total
+Let the total be with 5
+Let the total be without 3
+Let the total be of 2
+Let the total be over 4
+Say the total",
+            end_variables,
+            "6\n",
+        );
+    }
+
+    #[test]
+    fn compound_assignment_aliases() {
+        let end_variables = hashmap! {
+            "the total" => Expression::Floating(3f64),
+        };
+        test_program(
+            "Put 10 into the total
+Let the total be plus 5
+Let the total be minus 3
+Let the total be times 2
+Let the total be modulo 7",
+            end_variables,
+            "",
+        );
+    }
+
+    #[test]
+    fn guarded_assignment() {
+        let end_variables = hashmap! {
+            "the flag" => Expression::True,
+            "my heart" => Expression::Floating(2f64),
+            "my soul" => Expression::Floating(3f64),
+        };
+        test_program(
+            "The flag is right
+Put 1 into my heart
+Put 2 into my soul
+Let my heart be 2 if the flag
+Let my soul be 3 unless my heart is 5
+Let my heart be 4 unless the flag
+Put 5 into my soul if nothing
+Say my heart",
+            end_variables,
+            "2\n",
+        );
+    }
+
+    #[test]
+    fn guarded_statements() {
+        test_program(
+            "The flag is right
+Say \"if right\" if the flag
+Say \"if wrong\" if the flag is wrong
+Shout \"unless right\" unless the flag is wrong
+Whisper \"unless wrong\" unless the flag
+My count is 0
+While the flag
+Build my count up
+Break if my count is 3
+
+",
+            hashmap! {
+                "the flag" => Expression::True,
+                "my count" => Expression::Floating(3f64),
+            },
+            "if right\nunless right\n",
+        );
+    }
+
+    #[test]
+    fn unicode_variables() {
+        let end_variables = hashmap! {
+            "the café" => Expression::Floating(5f64),
+            "сердце" => Expression::String("rock".to_string()),
+            "心" => Expression::Floating(3f64),
+            "ωμέγα πόλη" => Expression::Floating(2f64),
+        };
+        test_program(
+            "Put 5 into the café
+Put \"rock\" into сердце
+Put 3 into 心
+Put 2 into Ωμέγα Πόλη
+Say the café with 心
+Say сердце
+Say Ωμέγα Πόλη",
+            end_variables,
+            "8\nrock\n2\n",
+        );
+    }
+
+    #[test]
+    fn ceiling_and_floor() {
+        let end_variables = hashmap! {
+            "my heart" => Expression::Floating(-2.5f64),
+            "the top" => Expression::Floating(-2f64),
+            "the bottom" => Expression::Floating(-3f64),
+        };
+        test_program(
+            "Put 0 minus 2.5 into my heart
+Put the ceiling of my heart into the top
+Put the floor of my heart into the bottom
+Say the ceiling of 2.5
+Say the floor of 2.5",
+            end_variables,
+            "3\n2\n",
+        );
+    }
+
+    #[test]
+    fn comment_closes_loop() {
+        let end_variables = hashmap! {
+            "my heart" => Expression::Floating(3f64),
+        };
+        test_program(
+            "Put 0 into my heart
+While my heart is smaller than 3
+Build my heart up
+(end of loop)
+Say my heart",
+            end_variables,
+            "3\n",
+        );
+    }
+
+    #[test]
+    fn bare_copula() {
+        let end_variables = hashmap! {
+            "my heart" => Expression::Floating(5f64),
+            "my soul" => Expression::String("no".to_string()),
+        };
+        test_program(
+            "My heart is 5
+My soul is \"no\"
+If my heart is 5
+Say \"yes\"
+
+If my heart is 6
+Say \"six\"
+",
+            end_variables,
+            "yes\n",
+        );
+    }
+
+    #[test]
+    fn array_concatenation() {
+        let array = |values: Vec<f64>| Expression::Array {
+            numeric: values
+                .into_iter()
+                .map(|v| Box::new(Expression::Floating(v)))
+                .enumerate()
+                .collect(),
+            strings: std::collections::BTreeMap::new(),
+        };
+        let end_variables = hashmap! {
+            "the first" => array(vec![1f64, 2f64]),
+            "the second" => array(vec![3f64]),
+            "the both" => array(vec![1f64, 2f64, 3f64]),
+            "the more" => array(vec![1f64, 2f64, 3f64, 4f64]),
+        };
+        test_program(
+            "Rock the first with 1, 2
+Rock the second with 3
+Put the first with the second into the both
+Put the both with 4 into the more
+Say the more",
+            end_variables,
+            "4\n",
+        );
+    }
+
+    #[test]
+    fn modulo() {
+        let end_variables = hashmap! {
+            "my heart" => Expression::Floating(17f64),
+            "the first" => Expression::Floating(2f64),
+            "the second" => Expression::Floating(2f64),
+        };
+        test_program(
+            "Put 17 into my heart
+Put my heart modulo 5 into the first
+Put the remainder of my heart over 5 into the second
+Say 0 minus 7 modulo 3",
+            end_variables,
+            "-1\n",
+        );
+        let err = test_error("Say 5 modulo 0");
+        if let common::MaidenError::Infinity { line, .. } = err {
+            assert_eq!(line, 1);
+        } else {
+            panic!("{}", err);
+        }
+    }
+
+    #[test]
+    fn division_by_zero() {
+        for (code, x, y, line) in [
+            (
+                "Put 5 into my heart\nPut 0 into my soul\nSay my heart over my soul\n",
+                "5",
+                "0",
+                3,
+            ),
+            (
+                "Put 0 minus 7 into my heart\nSay my heart modulo 0\n",
+                "-7",
+                "0",
+                2,
+            ),
+            (
+                "Put 2.5 into my heart\n\nSay the remainder of my heart over 0\n",
+                "2.5",
+                "0",
+                3,
+            ),
+        ] {
+            let err = test_error(code);
+            if let common::MaidenError::Infinity {
+                x: ref err_x,
+                y: ref err_y,
+                line: err_line,
+            } = err
+            {
+                assert_eq!(err_x, x);
+                assert_eq!(err_y, y);
+                assert_eq!(err_line, line);
+            } else {
+                panic!("{}", err);
+            }
+        }
+    }
+
+    #[test]
+    fn cast_with_base() {
+        let end_variables = hashmap! {
+            "the hex" => Expression::Floating(255f64),
+            "the bits" => Expression::Floating(5f64),
+            "the decimal" => Expression::Floating(42f64),
+        };
+        test_program(
+            "Cast \"ff\" into the hex with 16
+Cast \"101\" into the bits with 2
+Cast \"42\" into the decimal",
+            end_variables,
+            "",
+        );
+    }
+
+    #[test]
+    fn cast_invalid_digit_for_base() {
+        let err = test_error("Cast \"12\" into the bits with 2");
+        if let common::MaidenError::ParseNumberError { number, line } = err {
+            assert_eq!(number, "12");
+            assert_eq!(line, 1);
+        } else {
+            panic!("{}", err);
+        }
+    }
+
+    #[test]
+    fn cast_base_out_of_range() {
+        let err = test_error("Cast \"12\" into the number with 37");
+        if let common::MaidenError::BadArgument { line, .. } = err {
+            assert_eq!(line, 1);
+        } else {
+            panic!("{}", err);
+        }
+    }
+
+    #[test]
+    fn cast_errors() {
+        // Not a character code, as it's half of a UTF-16 surrogate pair
+        match test_error("Cast 55296 into my word") {
+            common::MaidenError::BadArgument { name, line, .. } => {
+                assert_eq!(name, "Cast");
+                assert_eq!(line, 1);
+            }
+            other => panic!("{:?}", other),
+        }
+        match test_error("Midnight takes your heart\nGive back your heart\n\nCast Midnight\n") {
+            common::MaidenError::MissingVariable { name, line } => {
+                assert_eq!(name, "Midnight");
+                assert_eq!(line, 4);
+            }
+            other => panic!("{:?}", other),
+        }
+        // Lenient runs read a missing variable as mysterious, but there's still nothing to cast
+        let mut program = parser::parse("Cast my ghost\n").unwrap();
+        let options = runner::Options {
+            lenient: true,
+            ..runner::Options::default()
+        };
+        let mut writer = Cursor::new(Vec::new());
+        match runner::run_with_options(&mut program, &mut writer, options) {
+            Err(common::MaidenError::MissingVariable { name, .. }) => assert_eq!(name, "my ghost"),
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn push_several_values() {
+        let mut numeric = std::collections::BTreeMap::new();
+        numeric.insert(0, Box::new(Expression::Floating(1f64)));
+        numeric.insert(1, Box::new(Expression::Floating(2f64)));
+        numeric.insert(2, Box::new(Expression::Floating(3f64)));
+        numeric.insert(3, Box::new(Expression::String("four".to_string())));
+        let end_variables = hashmap! {
+            "the list" => Expression::Array {
+                numeric,
+                strings: std::collections::BTreeMap::new(),
+            },
+        };
+        test_program(
+            "Rock the list with 1, 2, and 3
+Rock the list with \"four\"
+Say the list
+Say the list at 2",
+            end_variables,
+            "4\n3\n",
+        );
+    }
+
+    #[test]
+    fn push_pronoun() {
+        let mut numeric = std::collections::BTreeMap::new();
+        numeric.insert(0, Box::new(Expression::Floating(1f64)));
+        numeric.insert(1, Box::new(Expression::Floating(5f64)));
+        test_program(
+            "Put 1 into my heart\nRock it with 5\n",
+            hashmap! {
+                "my heart" => Expression::Array {
+                    numeric,
+                    strings: std::collections::BTreeMap::new(),
+                },
+            },
+            "",
+        );
+
+        let err = test_error("Rock it with 5\n");
+        if let common::MaidenError::UndefinedPronoun { line } = err {
+            assert_eq!(line, 1);
+        } else {
+            panic!("{}", err);
+        }
+    }
+
+    #[test]
+    fn queue_order() {
+        test_program(
+            "Enqueue 1 into the queue
+Enqueue 2 into the queue
+Enqueue 3 into the queue
+Dequeue the queue into my heart
+Roll the queue into my soul
+Dequeue the queue into my mind
+Say my heart
+Say my soul
+Say my mind
+Dequeue the queue into my hope",
+            hashmap! {
+                "the queue" => Expression::Array {
+                    numeric: std::collections::BTreeMap::new(),
+                    strings: std::collections::BTreeMap::new(),
+                },
+                "my heart" => Expression::Floating(1f64),
+                "my soul" => Expression::Floating(2f64),
+                "my mind" => Expression::Floating(3f64),
+                "my hope" => Expression::Mysterious,
+            },
+            "1\n2\n3\n",
+        );
+    }
+
+    #[test]
+    fn bad_lookups() {
+        let err = test_error("Put 5 into my heart\nSay my heart at 1\n");
+        if let common::MaidenError::BadArgument { name, line, .. } = err {
+            assert_eq!(name, "At");
+            assert_eq!(line, 2);
+        } else {
+            panic!("{}", err);
+        }
+
+        for code in &[
+            "Rock the list with 1\nSay the list at right\n",
+            "Put \"rock\" into the word\nSay the word at \"r\"\n",
+        ] {
+            let err = test_error(code);
+            if let common::MaidenError::BadIndex { line, .. } = err {
+                assert_eq!(line, 2);
+            } else {
+                panic!("{}", err);
+            }
+        }
+    }
+
+    #[test]
+    fn computed_index() {
+        let end_variables = hashmap! {
+            "the list" => Expression::Array {
+                numeric: vec![
+                    (0, Box::new(Expression::Floating(5f64))),
+                    (1, Box::new(Expression::Floating(8f64))),
+                ]
+                .into_iter()
+                .collect(),
+                strings: std::collections::BTreeMap::new(),
+            },
+            "the word" => Expression::String("rock".to_string()),
+            "the index" => Expression::Floating(0f64),
+        };
+        test_program(
+            "Put 0 into the index
+Rock the list with 5, 8
+Put \"rock\" into the word
+Say the list at the index plus 1
+Say the word at the index plus 2",
+            end_variables,
+            "8\nc\n",
+        );
+    }
+
+    #[test]
+    fn fractional_index() {
+        let err = test_error("Rock the list with 5\nSay the list at 0.5");
+        if let common::MaidenError::BadIndex { index, line } = err {
+            assert_eq!(index, "0.5");
+            assert_eq!(line, 2);
+        } else {
+            panic!("{}", err);
+        }
+    }
+
+    #[test]
+    fn return_no_value() {
+        let end_variables = hashmap! {
+            "the first" => Expression::Null,
+            "the second" => Expression::Null,
+            "the third" => Expression::Mysterious,
+            "the fourth" => Expression::Mysterious,
+        };
+        test_program(
+            "Emptiness takes your heart
+Give back nothing
+
+Silence takes your heart
+Give back null
+
+Mystery takes your heart
+Give back mysterious
+
+Absence takes your heart
+Put your heart into my soul
+
+Put Emptiness taking 1 into the first
+Put Silence taking 1 into the second
+Put Mystery taking 1 into the third
+Put Absence taking 1 into the fourth
+",
+            end_variables,
+            "",
+        );
+    }
+
+    #[test]
+    fn cut_in_place() {
+        let end_variables = hashmap! {
+            "my word" => Expression::Array {
+                numeric: "naïve☃"
+                    .chars()
+                    .enumerate()
+                    .map(|(i, c)| (i, Box::new(Expression::String(c.to_string()))))
+                    .collect(),
+                strings: std::collections::BTreeMap::new(),
+            },
+        };
+        test_program(
+            "Put \"naïve☃\" into my word\nSay my word at 2\nCut my word\nSay my word\nSay my word at 5",
+            end_variables,
+            "ï\n6\n☃\n",
+        );
+    }
+
+    #[test]
+    fn function_order() {
+        // Declared out of order, but always kept sorted by name, so anything going through them
+        // sees the same order every run
+        let code = "Noon takes your soul\nGive back your soul\n\n\
+                    Midnight takes your heart\nGive back your heart\n\n\
+                    Dawn takes your mind\nGive back your mind\n";
+        for _ in 0..5 {
+            let mut program = parser::parse(code).unwrap();
+            let mut writer = Cursor::new(Vec::new());
+            runner::run(&mut program, &mut writer).unwrap();
+            let names: Vec<&str> = program.functions.keys().map(String::as_str).collect();
+            assert_eq!(names, vec!["Dawn", "Midnight", "Noon"]);
+        }
+    }
+
+    #[test]
+    fn call_counts() {
+        let mut program = parser::parse(
+            "Midnight takes your heart
+Give back your heart
+
+Put 0 into my world
+Until my world is 5
+Build my world up
+Say Midnight taking my world
+
+Midnight taking 0
+Put powmod taking 2, 3, 5 into my soul
+",
+        )
+        .unwrap();
+        let mut writer = Cursor::new(Vec::new());
+        let mut interpreter = runner::Interpreter::new(&mut writer, runner::Options::default());
+        interpreter.run(&mut program).unwrap();
+        let expected: HashMap<String, u64> =
+            vec![("Midnight".to_string(), 6), ("powmod".to_string(), 1)]
+                .into_iter()
+                .collect();
+        assert_eq!(interpreter.stats().calls, expected);
+    }
+
+    #[test]
+    fn while_true_with_break() {
+        for word in ["true", "right", "yes", "ok"] {
+            let code = format!(
+                "Put 0 into the count
+While {}
+Build the count up
+If the count is 5
+Break
+
+Say the count
+
+",
+                word
+            );
+            let mut program = parser::parse(&code).unwrap();
+            let mut writer = Cursor::new(Vec::new());
+            runner::run(&mut program, &mut writer).unwrap();
+            assert_eq!(
+                std::str::from_utf8(writer.get_ref()).unwrap(),
+                "1\n2\n3\n4\n",
+                "While {}",
+                word
+            );
+        }
+    }
+
+    #[test]
+    fn while_true_without_break() {
+        // Each iteration runs the body separately, but the limit still adds them all up
+        let err = test_error("Put 0 into the count\nWhile true\nBuild the count up\n");
+        if let common::MaidenError::InstructionLimit { line } = err {
+            assert_eq!(line, 3);
+        } else {
+            panic!("{}", err);
+        }
+    }
+
+    #[test]
+    fn loop_iterations() {
+        use std::cell::RefCell;
+        use std::rc::Rc;
+
+        let seen = Rc::new(RefCell::new(vec![]));
+        let mut writer = Cursor::new(Vec::new());
+        let mut interpreter = runner::Interpreter::new(&mut writer, runner::Options::default());
+        let recorder = seen.clone();
+        interpreter.on_iteration(move |line, iteration| {
+            recorder.borrow_mut().push((line, iteration));
+        });
+        let mut program = parser::parse(
+            "Put 0 into my world
+While my world is less than 2
+Build my world up
+Repeat 2 times
+Say my world
+
+
+",
+        )
+        .unwrap();
+        interpreter.run(&mut program).unwrap();
+        drop(interpreter);
+        assert_eq!(
+            *seen.borrow(),
+            vec![(2, 1), (4, 1), (4, 2), (2, 2), (4, 1), (4, 2)]
+        );
+        assert_eq!(writer.into_inner(), b"1\n1\n2\n2\n".to_vec());
+    }
+
+    #[test]
+    fn peak_call_depth() {
+        let mut writer = Cursor::new(Vec::new());
+        let mut interpreter = runner::Interpreter::new(&mut writer, runner::Options::default());
+        assert_eq!(interpreter.max_depth(), 0);
+        let mut program = parser::parse(
+            "Countdown takes your heart
+If your heart is 0
+Give back 0
+
+Give back Countdown taking your heart without 1
+
+Say Countdown taking 5
+Say Countdown taking 2
+",
+        )
+        .unwrap();
+        interpreter.run(&mut program).unwrap();
+        // One call each for 5 down to 0
+        assert_eq!(interpreter.max_depth(), 6);
+        interpreter.reset();
+        assert_eq!(interpreter.max_depth(), 0);
+    }
+
+    #[test]
+    fn runtime_warnings() {
+        let mut program = parser::parse(
+            "Put \"ab\" times 2 into my word
+Put \"ab\" times 2.5 into my word
+",
+        )
+        .unwrap();
+        let mut writer = Cursor::new(Vec::new());
+        let mut warnings = vec![];
+        let mut interpreter = runner::Interpreter::new(&mut writer, runner::Options::default());
+        interpreter.warnings(&mut warnings);
+        interpreter.run(&mut program).unwrap();
+        assert_eq!(
+            interpreter.variables()["my word"].1,
+            Expression::String("abab".to_string())
+        );
+        drop(interpreter);
+        assert_eq!(warnings.len(), 1);
+        match warnings[0] {
+            common::MaidenError::LossyConversion { line, .. } => assert_eq!(line, 2),
+            ref other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn lenient_run() {
+        let code = "Say my heart\nPut my heart plus 1 into my soul\nSay \"done\"\n";
+        let mut program = parser::parse(code).unwrap();
+        let mut writer = Cursor::new(Vec::new());
+        let mut warnings = vec![];
+        let options = runner::Options {
+            lenient: true,
+            ..runner::Options::default()
+        };
+        let mut interpreter = runner::Interpreter::new(&mut writer, options);
+        interpreter.warnings(&mut warnings);
+        interpreter.run(&mut program).unwrap();
+        assert_eq!(interpreter.variables()["my soul"].1, Expression::Mysterious);
+        drop(interpreter);
+        assert_eq!(
+            std::str::from_utf8(writer.get_ref()).unwrap(),
+            "mysterious\ndone\n"
+        );
+        // One for each read of my heart, and one for adding 1 to mysterious
+        assert_eq!(warnings.len(), 3);
+        match warnings[0] {
+            common::MaidenError::MissingVariable { ref name, line } => {
+                assert_eq!(name, "my heart");
+                assert_eq!(line, 1);
+            }
+            ref other => panic!("{:?}", other),
+        }
+
+        // Without it, the first read stops the program
+        let capture = runner::run_captured(&mut program, runner::Options::default());
+        match capture.result {
+            Err(common::MaidenError::MissingVariable { line, .. }) => assert_eq!(line, 1),
+            other => panic!("{:?}", other),
+        }
+
+        // Limits aren't warnings, however lenient the run
+        let mut program = parser::parse("While true\nPut 1 into my heart\n").unwrap();
+        let capture = runner::run_captured(&mut program, options);
+        match capture.result {
+            Err(common::MaidenError::InstructionLimit { .. }) => {}
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn recorded_trace() {
+        let mut program = parser::parse(
+            "Put 1 into my heart
+Midnight takes your love
+Give back your love plus my heart
+
+Put Midnight taking 2 into my soul
+",
+        )
+        .unwrap();
+        let mut writer = Cursor::new(Vec::new());
+        let mut steps = vec![];
+        let mut interpreter = runner::Interpreter::new(&mut writer, runner::Options::default());
+        interpreter.recording(&mut steps);
+        interpreter.run(&mut program).unwrap();
+        drop(interpreter);
+        let lines: Vec<(usize, u32)> = steps.iter().map(|step| (step.line, step.depth)).collect();
+        assert_eq!(lines, vec![(1, 0), (2, 0), (5, 0), (3, 1)]);
+        assert!(steps[0].variables.is_empty());
+        let expected: std::collections::BTreeMap<String, Expression> = vec![
+            ("my heart".to_string(), Expression::Floating(1.0)),
+            ("your love".to_string(), Expression::Floating(2.0)),
+        ]
+        .into_iter()
+        .collect();
+        assert_eq!(steps[3].variables, expected);
+    }
+
+    #[test]
+    fn negative_zero() {
+        let end_variables = hashmap! {
+            "my debt" => Expression::Floating(-5f64),
+            "the sum" => Expression::Floating(-0f64),
+            "the line" => Expression::String("0 dollars".to_string()),
+        };
+        test_program(
+            "Put 0 minus 5 into my debt
+Put 0 times my debt into the sum
+Put the sum plus \" dollars\" into the line
+Say the sum
+Say the line",
+            end_variables,
+            "0\n0 dollars\n",
+        );
+    }
+
+    #[test]
+    fn between() {
+        let end_variables = hashmap! {
+            "the inside" => Expression::True,
+            "the lower edge" => Expression::True,
+            "the upper edge" => Expression::True,
+            "the outside" => Expression::False,
+            "the word" => Expression::True,
+        };
+        test_program(
+            "Put 5 is between 1 and 10 into the inside
+Put 1 is between 1 and 10 into the lower edge
+Put 10 is between 1 and 10 into the upper edge
+Put 11 is between 1 and 10 into the outside
+Put \"m\" is between \"a\" and \"z\" into the word",
+            end_variables,
+            "",
+        );
+    }
+
+    #[test]
+    fn trace_to_diagnostics() {
+        let mut program = parser::parse("Put 1 into my heart\nSay my heart").unwrap();
+        let options = runner::Options {
+            trace: true,
+            ..runner::Options::default()
+        };
+        let capture = runner::run_captured(&mut program, options);
+        capture.result.unwrap();
+        assert_eq!(capture.output, "1\n");
+        assert_eq!(
+            capture.diagnostics,
+            concat!(
+                "1: Assignment { target: Variable(\"my heart\"), value: Floating(1.0) }\n",
+                "2: Say { value: Variable(\"my heart\") }\n"
+            )
+        );
+    }
+
+    #[test]
+    fn indexed_read_in_assignment() {
+        let end_variables = hashmap! {
+            "the list" => Expression::Array {
+                numeric: vec![
+                    (0, Box::new(Expression::Floating(3f64))),
+                    (1, Box::new(Expression::Floating(6f64))),
+                    (2, Box::new(Expression::Floating(0f64))),
+                ]
+                .into_iter()
+                .collect(),
+                strings: std::collections::BTreeMap::new(),
+            },
+            "my number" => Expression::Floating(9f64),
+        };
+        test_program(
+            "Rock the list with 3, 6, 9
+Let my number be the list at 2
+Put 0 into the list at 2
+Say my number",
+            end_variables,
+            "9\n",
+        );
+    }
+
+    #[test]
+    fn trace_with_source() {
+        let source = "Put 1 into my heart\n  Say my heart";
+        let mut program = parser::parse(source).unwrap();
+        let mut writer = Cursor::new(Vec::new());
+        let mut diagnostics = Cursor::new(Vec::new());
+        let options = runner::Options {
+            trace: true,
+            ..runner::Options::default()
+        };
+        let mut interpreter = runner::Interpreter::new(&mut writer, options);
+        interpreter.diagnostics(&mut diagnostics);
+        interpreter.source(source);
+        interpreter.run(&mut program).unwrap();
+        drop(interpreter);
+        assert_eq!(
+            std::str::from_utf8(diagnostics.get_ref()).unwrap(),
+            concat!(
+                "1: Assignment { target: Variable(\"my heart\"), value: Floating(1.0) } (Put 1 into my heart)\n",
+                "2: Say { value: Variable(\"my heart\") } (Say my heart)\n"
+            )
+        );
+    }
+
+    #[test]
+    fn trace_with_source_in_include() {
+        let source = "Say 1\n\nSay 3\n";
+        let mut program = parser::parse(source).unwrap();
+        program.commands.insert(
+            1,
+            parser::parse_include("library.rock", "Say 2\n", 2).unwrap(),
+        );
+        let mut writer = Cursor::new(Vec::new());
+        let mut diagnostics = Cursor::new(Vec::new());
+        let options = runner::Options {
+            trace: true,
+            ..runner::Options::default()
+        };
+        let mut interpreter = runner::Interpreter::new(&mut writer, options);
+        interpreter.diagnostics(&mut diagnostics);
+        interpreter.source(source);
+        interpreter.run(&mut program).unwrap();
+        drop(interpreter);
+        let diagnostics = std::str::from_utf8(diagnostics.get_ref()).unwrap();
+        assert!(diagnostics.starts_with("1: Say { value: Floating(1.0) } (Say 1)\n"));
+        // Line 1 of the included file isn't line 1 of ours, so has no source text to show
+        assert!(diagnostics.contains("\n1: Say { value: Floating(2.0) }\n"));
+        assert!(diagnostics.ends_with("\n3: Say { value: Floating(3.0) } (Say 3)\n"));
+    }
+
+    #[test]
+    fn repeat_times() {
+        let end_variables = hashmap! {
+            "my count" => Expression::Floating(3f64),
+            "the never" => Expression::Floating(0f64),
+            "the limit" => Expression::Floating(0f64),
+        };
+        test_program(
+            "Put 0 into my count
+Put 0 into the never
+Put 3 into the limit
+Repeat the limit times
+Build my count up
+Put 0 into the limit
+
+Repeat 0 times
+Build the never up
+",
+            end_variables,
+            "",
+        );
+    }
+
+    #[test]
+    fn restart_outer_loop() {
+        test_program(
+            "Put 0 into my count
+Outer: While my count is weaker than 3
+Build my count up
+Put 0 into my step
+Inner: While my step is weaker than 3
+Build my step up
+If my step is 2
+Restart outer
+
+Say my step
+
+Say \"never\"
+
+Say \"done\"
+",
+            hashmap! {
+                "my count" => Expression::Floating(3f64),
+                "my step" => Expression::Floating(2f64),
+            },
+            "1\n1\n1\ndone\n",
+        );
+    }
+
+    #[test]
+    fn repeat_break() {
+        let end_variables = hashmap! {
+            "my count" => Expression::Floating(2f64),
+        };
+        test_program(
+            "Put 0 into my count
+Repeat 10 times
+Build my count up
+If my count is 2
+Break it down
+
+",
+            end_variables,
+            "",
+        );
+    }
+
+    #[test]
+    fn loop_body_errors() {
+        for code in &["While right\nSay my ghost\n", "Until wrong\nSay my ghost\n"] {
+            let err = test_error(code);
+            if let common::MaidenError::MissingVariable { name, line } = err {
+                assert_eq!(name, "my ghost");
+                assert_eq!(line, 2);
+            } else {
+                panic!("{}", err);
+            }
+        }
+    }
+
+    #[test]
+    fn loop_is_false_compares() {
+        // Mysterious isn't false, so this never loops, whereas "While not my heart" would
+        let end_variables = hashmap! {
+            "my heart" => Expression::Mysterious,
+        };
+        test_program(
+            "Put mysterious into my heart
+While my heart is false
+Say \"looped\"
+Break it down
+
+Say \"done\"",
+            end_variables,
+            "done\n",
+        );
+    }
+
+    #[test]
+    fn until_break() {
+        let end_variables = hashmap! {
+            "my count" => Expression::Floating(3f64),
+        };
+        test_program(
+            "Put 0 into my count
+Until my count is 10
+Build my count up
+If my count is 3
+Break it down
+
+",
+            end_variables,
+            "",
+        );
+    }
+
+    #[test]
+    fn repeat_negative() {
+        let err = test_error("Repeat 0 minus 1 times\nSay 1\n");
+        if let common::MaidenError::BadArgument { name, line, .. } = err {
+            assert_eq!(name, "Repeat");
+            assert_eq!(line, 1);
+        } else {
+            panic!("{}", err);
+        }
+    }
+
+    fn test_error(input: &str) -> common::MaidenError {
+        pretty_env_logger::try_init().unwrap_or(());
+        let mut program = parser::parse(input).unwrap();
+        let mut writer = Cursor::new(Vec::new());
+        runner::run(&mut program, &mut writer).err().unwrap()
+    }
+
+    #[test]
+    fn copy_variable() {
+        let end_variables = hashmap! {
+            "the source" => Expression::Floating(6f64),
+            "the copy" => Expression::Floating(5f64),
+        };
+        test_program(
+            "Put 5 into the source
+Let the copy be like the source
+Build the source up",
+            end_variables,
+            "",
+        );
+    }
+
+    #[test]
+    fn divisible_by() {
+        let code =
+            "Say 15 is divisible by 5\nSay 16 is divisible by 5\nSay 0 minus 9 is divisible by 3\n";
+        let mut program = parser::parse(code).unwrap();
+        let mut writer = Cursor::new(Vec::new());
+        runner::run(&mut program, &mut writer).unwrap();
+        assert_eq!(
+            std::str::from_utf8(writer.get_ref()).unwrap(),
+            "true\nfalse\ntrue\n"
+        );
+        let err = test_error("Put 7 into my heart\nSay my heart is divisible by 0\n");
+        if let common::MaidenError::Infinity { line, .. } = err {
+            assert_eq!(line, 2);
+        } else {
+            panic!("{}", err);
+        }
+    }
+
+    #[test]
+    fn remove_from_array() {
+        let array = |values: Vec<f64>| Expression::Array {
+            numeric: values
+                .into_iter()
+                .map(|value| Box::new(Expression::Floating(value)))
+                .enumerate()
+                .collect(),
+            strings: std::collections::BTreeMap::new(),
+        };
+        test_program(
+            "Rock the list with 1, 2, 3, 4
+Remove the list at 1 into my heart
+Remove the list at 0
+Rock the other with 5, 6
+Remove the other at 7 into my soul",
+            hashmap! {
+                "the list" => array(vec![3f64, 4f64]),
+                "my heart" => Expression::Floating(2f64),
+                "the other" => array(vec![5f64, 6f64]),
+                "my soul" => Expression::Mysterious,
+            },
+            "",
+        );
+    }
+
+    #[test]
+    fn remove_without_pronoun() {
+        let err = test_error("Remove it at 0");
+        if let common::MaidenError::UndefinedPronoun { line } = err {
+            assert_eq!(line, 1);
+        } else {
+            panic!("{}", err);
+        }
+    }
+
+    #[test]
+    fn insert_into_array() {
+        let array = |values: Vec<Expression>| Expression::Array {
+            numeric: values.into_iter().map(Box::new).enumerate().collect(),
+            strings: std::collections::BTreeMap::new(),
+        };
+        let number = Expression::Floating;
+        test_program(
+            "Rock the middle with 1, 2, 3
+Insert 9 into the middle at 1
+Rock the end with 1, 2
+Insert 9 into the end at 2
+Rock the beyond with 1
+Insert 9 into the beyond at 3",
+            hashmap! {
+                "the middle" => array(vec![number(1f64), number(9f64), number(2f64), number(3f64)]),
+                "the end" => array(vec![number(1f64), number(2f64), number(9f64)]),
+                "the beyond" => array(vec![
+                    number(1f64),
+                    Expression::Mysterious,
+                    Expression::Mysterious,
+                    number(9f64),
+                ]),
+            },
+            "",
+        );
+        let err = test_error("Rock the list with 1\nInsert 9 into the list at 0 minus 1");
+        if let common::MaidenError::BadIndex { line, .. } = err {
+            assert_eq!(line, 2);
+        } else {
+            panic!("{}", err);
+        }
+    }
+
+    #[test]
+    fn insert_without_pronoun() {
+        let err = test_error("Insert 9 into it at 0");
+        if let common::MaidenError::UndefinedPronoun { line } = err {
+            assert_eq!(line, 1);
+        } else {
+            panic!("{}", err);
+        }
+    }
+
+    #[test]
+    fn copy_nested_array() {
+        let array = |values: Vec<Expression>| Expression::Array {
+            numeric: values.into_iter().map(Box::new).enumerate().collect(),
+            strings: std::collections::BTreeMap::new(),
+        };
+        let inner = || array(vec![Expression::Floating(1f64), Expression::Floating(2f64)]);
+        test_program(
+            "Rock the inner with 1, 2
+Rock the original with the inner, 3
+Copy the original into the copy
+Rock the copy with 4
+Put 99 into the copy at 0
+Copy 7 into the number",
+            hashmap! {
+                "the inner" => inner(),
+                "the original" => array(vec![inner(), Expression::Floating(3f64)]),
+                "the copy" => array(vec![
+                    Expression::Floating(99f64),
+                    Expression::Floating(3f64),
+                    Expression::Floating(4f64),
+                ]),
+                "the number" => Expression::Floating(7f64),
+            },
+            "",
+        );
+    }
+
+    #[test]
+    fn reverse_copies() {
+        let code = "Put \"Motörhead\" into the band
+Rock the list with 1, 2, 3
+Put the reverse of the band into the backwards band
+Put the reverse of the list into the backwards list
+Say the band
+Say the backwards band
+Join the list\nSay the list
+Join the backwards list\nSay the backwards list
+";
+        let mut program = parser::parse(code).unwrap();
+        let mut writer = Cursor::new(Vec::new());
+        runner::run(&mut program, &mut writer).unwrap();
+        assert_eq!(
+            std::str::from_utf8(writer.get_ref()).unwrap(),
+            "Motörhead\ndaehrötoM\n123\n321\n"
+        );
+    }
+
+    #[test]
+    fn coalesce_to_default() {
+        test_program(
+            "Put mysterious into the missing
+Put nothing into the gap
+Put 5 into the present
+Put 0 into the zero
+Let the first be the missing or else 10
+Let the second be the gap or else \"default\"
+Let the third be the present or else 10
+Let the fourth be the zero or else 10
+Let the fifth be the missing or else the gap or else 3
+Let the sixth be the present or 0",
+            hashmap! {
+                "the missing" => Expression::Mysterious,
+                "the gap" => Expression::Null,
+                "the present" => Expression::Floating(5f64),
+                "the zero" => Expression::Floating(0f64),
+                "the first" => Expression::Floating(10f64),
+                "the second" => Expression::String("default".to_string()),
+                "the third" => Expression::Floating(5f64),
+                "the fourth" => Expression::Floating(0f64),
+                "the fifth" => Expression::Floating(3f64),
+                "the sixth" => Expression::True,
+            },
+            "",
+        );
+    }
+
+    #[test]
+    fn default_arguments() {
+        let code = "Midnight takes your heart and your soul and your mind (where your soul is 10 and your mind is 100)
+Give back your heart plus your soul plus your mind
+
+Say Midnight taking 1
+Say Midnight taking 1, 2
+Say Midnight taking 1, 2, 3
+";
+        let mut program = parser::parse(code).unwrap();
+        let mut writer = Cursor::new(Vec::new());
+        runner::run(&mut program, &mut writer).unwrap();
+        assert_eq!(
+            std::str::from_utf8(writer.get_ref()).unwrap(),
+            "111\n103\n6\n"
+        );
+
+        for (call, expected, got) in [("Dusk taking 1", 2, 1), ("Dusk taking 1, 2, 3, 4", 3, 4)] {
+            let err = test_error(&format!(
+                "Dusk takes your heart and your soul and your mind (where your mind is 100)\nGive back your heart\n\nSay {}\n",
+                call
+            ));
+            if let common::MaidenError::WrongArgCount {
+                expected: err_expected,
+                got: err_got,
+                line,
+            } = err
+            {
+                assert_eq!(err_expected, expected);
+                assert_eq!(err_got, got);
+                assert_eq!(line, 4);
+            } else {
+                panic!("{}", err);
+            }
+        }
+    }
+
+    #[test]
+    fn rest_parameter() {
+        let code = "Midnight takes your start and the rest
+Give back your start plus the sum of the rest
+
+Say Midnight taking 1, 2, 3, 4
+Say Midnight taking 5
+";
+        let mut program = parser::parse(code).unwrap();
+        let mut writer = Cursor::new(Vec::new());
+        runner::run(&mut program, &mut writer).unwrap();
+        assert_eq!(std::str::from_utf8(writer.get_ref()).unwrap(), "10\n5\n");
+
+        let err = test_error(
+            "Noon takes your heart and your soul and the rest\nGive back your heart\n\nSay Noon taking 1\n",
+        );
+        if let common::MaidenError::WrongArgCount {
+            expected,
+            got,
+            line,
+        } = err
+        {
+            assert_eq!(expected, 2);
+            assert_eq!(got, 1);
+            assert_eq!(line, 4);
+        } else {
+            panic!("{}", err);
+        }
+    }
+
+    #[test]
+    fn text_of_values() {
+        let code = "Put the text of 2.5 into my number
+Put the text of right into my truth
+Rock my list with 1, \"two\", 3
+Put the text of my list into my words
+";
+        test_program(
+            code,
+            hashmap! {
+                "my number" => Expression::String("2.5".to_string()),
+                "my truth" => Expression::String("true".to_string()),
+                "my list" => Expression::Array {
+                    numeric: vec![
+                        (0, Box::new(Expression::Floating(1f64))),
+                        (1, Box::new(Expression::String("two".to_string()))),
+                        (2, Box::new(Expression::Floating(3f64))),
+                    ]
+                    .into_iter()
+                    .collect(),
+                    strings: std::collections::BTreeMap::new(),
+                },
+                "my words" => Expression::String("3".to_string()),
+            },
+            "",
+        );
+
+        let mut program = parser::parse(code).unwrap();
+        let mut writer = Cursor::new(Vec::new());
+        let options = runner::Options {
+            array_output: runner::ArrayOutput::Contents,
+            ..runner::Options::default()
+        };
+        let variables = runner::run_with_options(&mut program, &mut writer, options).unwrap();
+        assert_eq!(
+            variables["my words"].1,
+            Expression::String("1, two, 3".to_string())
+        );
+    }
+
+    #[test]
+    fn character_codes() {
+        test_program(
+            "Put the code of \"a\" into my code
+Put my code plus 1 into my next
+Put the character of my next into my letter
+",
+            hashmap! {
+                "my code" => Expression::Floating(97f64),
+                "my next" => Expression::Floating(98f64),
+                "my letter" => Expression::String("b".to_string()),
+            },
+            "",
+        );
+    }
+
+    #[test]
+    fn clamping() {
+        test_program(
+            "Clamp 0 minus 5 between 0 and 10 into my low
+Clamp 7 between 0 and 10 into my middle
+Clamp 12.5 between 0 and 10 into my high
+",
+            hashmap! {
+                "my low" => Expression::Floating(0f64),
+                "my middle" => Expression::Floating(7f64),
+                "my high" => Expression::Floating(10f64),
+            },
+            "",
+        );
+
+        for code in [
+            "Clamp 5 between 10 and 0 into my value\n",
+            "Clamp \"five\" between 0 and 10 into my value\n",
+        ] {
+            let err = test_error(code);
+            if let common::MaidenError::BadArgument { name, line, .. } = err {
+                assert_eq!(name, "Clamp");
+                assert_eq!(line, 1);
+            } else {
+                panic!("{}", err);
+            }
+        }
+    }
+
+    #[test]
+    fn clamp_into_pronoun() {
+        test_program(
+            "Put 12 into my level\nClamp my level between 0 and 10 into it\n",
+            hashmap! {
+                "my level" => Expression::Floating(10f64),
+            },
+            "",
+        );
+
+        let err = test_error("Clamp 12 between 0 and 10 into it\n");
+        if let common::MaidenError::UndefinedPronoun { line } = err {
+            assert_eq!(line, 1);
+        } else {
+            panic!("{}", err);
+        }
+    }
+
+    #[test]
+    fn formatted_numbers() {
+        test_program(
+            "Format 3.14159 to 2 places into my pi
+Format 2.71828 to 3 places into my e
+Format 5 to 2 places into my price
+Format 2.71828 to 2 places into my guess
+Format 7.6 to 0 places into my count
+",
+            hashmap! {
+                "my pi" => Expression::String("3.14".to_string()),
+                "my e" => Expression::String("2.718".to_string()),
+                "my price" => Expression::String("5.00".to_string()),
+                "my guess" => Expression::String("2.72".to_string()),
+                "my count" => Expression::String("8".to_string()),
+            },
+            "",
+        );
+
+        for places in ["0 minus 1", "1.5"] {
+            let err = test_error(&format!(
+                "Put {} into my count\nFormat 2 to my count places into my price\n",
+                places
+            ));
+            if let common::MaidenError::BadArgument { name, line, .. } = err {
+                assert_eq!(name, "Format");
+                assert_eq!(line, 2);
+            } else {
+                panic!("{}", err);
+            }
+        }
+    }
+
+    #[test]
+    fn format_into_pronoun() {
+        test_program(
+            "Put 3.14159 into my pi\nFormat my pi to 2 places into it\n",
+            hashmap! {
+                "my pi" => Expression::String("3.14".to_string()),
+            },
+            "",
+        );
+
+        let err = test_error("Format 2 to 1 places into it\n");
+        if let common::MaidenError::UndefinedPronoun { line } = err {
+            assert_eq!(line, 1);
+        } else {
+            panic!("{}", err);
+        }
+    }
+
+    #[test]
+    fn padding() {
+        test_program(
+            "Pad 42 to 5 with \"0\" into my code
+Pad \"ab\" on the right to 4 with \"-\" into my name
+Pad \"naïve\" to 6 into my word
+Pad \"too long\" to 3 with \"x\" into my line
+",
+            hashmap! {
+                "my code" => Expression::String("00042".to_string()),
+                "my name" => Expression::String("ab--".to_string()),
+                "my word" => Expression::String(" naïve".to_string()),
+                "my line" => Expression::String("too long".to_string()),
+            },
+            "",
+        );
+    }
+
+    #[test]
+    fn pad_into_pronoun() {
+        test_program(
+            "Put 7 into my code\nPad my code to 3 with \"0\" into it\n",
+            hashmap! {
+                "my code" => Expression::String("007".to_string()),
+            },
+            "",
+        );
+
+        let err = test_error("Pad 7 to 3 into it\n");
+        if let common::MaidenError::UndefinedPronoun { line } = err {
+            assert_eq!(line, 1);
+        } else {
+            panic!("{}", err);
+        }
+    }
+
+    #[test]
+    fn map_filter_reduce() {
+        let code = "Double takes the number
+Give back the number times 2
+
+Big takes the number
+Give back the number is greater than 2
+
+Combine takes the total and the number
+Give back the total plus the number
+
+Rock the numbers with 1, 2, 3, 4
+Map Double over the numbers into the doubled
+Say the doubled at 0
+Say the doubled at 3
+Filter the numbers by Big into the winners
+Say the winners at 0
+Say the winners at 1
+Say the winners at 2
+Reduce the numbers with Combine into the sum
+Say the sum
+";
+        let mut program = parser::parse(code).unwrap();
+        let mut writer = Cursor::new(Vec::new());
+        runner::run(&mut program, &mut writer).unwrap();
+        assert_eq!(
+            std::str::from_utf8(writer.get_ref()).unwrap(),
+            "2\n8\n3\n4\nmysterious\n10\n"
+        );
+
+        let err = test_error(
+            "Double takes the number\nGive back the number times 2\n\nMap Double over 5 into the doubled\n",
+        );
+        if let common::MaidenError::BadArgument { name, line, .. } = err {
+            assert_eq!(name, "Map");
+            assert_eq!(line, 4);
+        } else {
+            panic!("{}", err);
+        }
+    }
+
+    #[test]
+    fn map_with_pronouns() {
+        let code = "Let the doubler be a function taking the number
+Give back the number times 2
+
+Rock the numbers with 1, 2, 3
+Map the doubler over the numbers into the doubled
+Say the doubled at 2
+Let the tripler be a function taking the number
+Give back the number times 3
+
+Map it over the numbers into the tripled
+Say the tripled at 2
+Put the numbers into the copy
+Map the doubler over the copy into it
+Say the copy at 0
+";
+        let mut program = parser::parse(code).unwrap();
+        let mut writer = Cursor::new(Vec::new());
+        runner::run(&mut program, &mut writer).unwrap();
+        assert_eq!(std::str::from_utf8(writer.get_ref()).unwrap(), "6\n9\n2\n");
+
+        let err = test_error("Rock the numbers with 1\nMap it over the numbers into the result\n");
+        if let common::MaidenError::UndefinedPronoun { line } = err {
+            assert_eq!(line, 2);
+        } else {
+            panic!("{}", err);
+        }
+    }
+
+    #[test]
+    fn lambdas() {
+        let code = "Let the doubler be a function taking the number
+Give back the number times 2
+
+Say the doubler taking 4
+Noon takes your fn and your heart
+Give back your fn taking your heart plus 1
+
+Say Noon taking the doubler, 10
+";
+        let mut program = parser::parse(code).unwrap();
+        let mut writer = Cursor::new(Vec::new());
+        runner::run(&mut program, &mut writer).unwrap();
+        assert_eq!(std::str::from_utf8(writer.get_ref()).unwrap(), "8\n22\n");
+    }
+
+    #[test]
+    fn closure_captures_by_value() {
+        let code = "Let the counter be 0
+Until the counter is 5
+Build the counter up
+If the counter is 2
+Let the keeper be a function taking the nonce
+Put the counter times 10 into the counter
+Give back the counter
+
+
+
+Say the keeper taking 0
+Say the keeper taking 0
+Say the counter
+";
+        let mut program = parser::parse(code).unwrap();
+        let mut writer = Cursor::new(Vec::new());
+        runner::run(&mut program, &mut writer).unwrap();
+        assert_eq!(
+            std::str::from_utf8(writer.get_ref()).unwrap(),
+            "20\n20\n5\n"
+        );
+    }
+
+    #[test]
+    fn type_tests() {
+        let mut numeric = std::collections::BTreeMap::new();
+        numeric.insert(0, Box::new(Expression::Floating(5f64)));
+        test_program(
+            "Put 5 into the number
+Put \"five\" into the string
+Rock the array with 5
+Put true into the boolean
+Say the number is a number
+Say the string is a number
+Say the string is a string
+Say the array is a string
+Say the array is an array
+Say the number is an array
+Say the boolean is a boolean
+Say the string is a boolean",
+            hashmap! {
+                "the number" => Expression::Floating(5f64),
+                "the string" => Expression::String("five".to_string()),
+                "the array" => Expression::Array {
+                    numeric,
+                    strings: std::collections::BTreeMap::new(),
+                },
+                "the boolean" => Expression::True,
+            },
+            "true\nfalse\ntrue\nfalse\ntrue\nfalse\ntrue\nfalse\n",
+        );
+    }
+
+    #[test]
+    fn raw_write() {
+        let mut program =
+            parser::parse("Write \"tab\there\r\"\nWrite \"café 100%\"\nSay 1\n").unwrap();
+        let mut writer = Cursor::new(Vec::new());
+        runner::run(&mut program, &mut writer).unwrap();
+        assert_eq!(
+            std::str::from_utf8(writer.get_ref()).unwrap(),
+            "tab\there\rcafé 100%1\n"
+        );
+
+        let err = test_error("Put 5 into my heart\nWrite my heart");
+        if let common::MaidenError::BadArgument { name, line, .. } = err {
+            assert_eq!(name, "Write");
+            assert_eq!(line, 2);
+        } else {
+            panic!("{}", err);
+        }
+    }
+
+    #[test]
+    fn joined_say() {
+        let end_variables = hashmap! {
+            "my heart" => Expression::String("rock".to_string()),
+        };
+        test_program(
+            "Put \"rock\" into my heart
+Say my heart and \"roll\"
+Shout 1 plus 1 and true",
+            end_variables,
+            "rock and roll\n2 and true\n",
+        );
+    }
+
+    #[test]
+    fn flip_sign() {
+        let end_variables = hashmap! {
+            "my heart" => Expression::Floating(-5f64),
+            "my soul" => Expression::Floating(2.5f64),
+        };
+        test_program(
+            "Put 5 into my heart
+Put 0 minus 2.5 into my soul
+Flip the sign of my heart
+Flip the sign of my soul",
+            end_variables,
+            "",
+        );
+        let err = test_error("Put \"up\" into my heart\nFlip the sign of my heart");
+        if let common::MaidenError::Unimplemented { line, .. } = err {
+            assert_eq!(line, 2);
+        } else {
+            panic!("{}", err);
+        }
+    }
+
+    #[test]
+    fn copy_missing_variable() {
+        let err = test_error("Let the copy be like the ghost");
+        if let common::MaidenError::MissingVariable { name, line } = err {
+            assert_eq!(name, "the ghost");
+            assert_eq!(line, 1);
+        } else {
+            panic!("{}", err);
+        }
+    }
+
+    #[test]
+    fn missing_variable() {
+        let err = test_error("Put Desire into my world");
+        if let common::MaidenError::MissingVariable { name, line } = err {
+            assert_eq!(name, "Desire");
+            assert_eq!(line, 1);
+        } else {
+            panic!("{}", err);
+        }
+    }
+
+    #[test]
+    fn included_file_errors() {
+        let library = "Midnight takes your heart\nGive back your heart\n\nSay Midnight taking 2\n";
+        let mut program = parser::parse("Say 1\n\nSay Midnight taking 3\n").unwrap();
+        program.commands.insert(
+            1,
+            parser::parse_include("library.rock", library, 2).unwrap(),
+        );
+        let mut writer = Cursor::new(Vec::new());
+        runner::run(&mut program, &mut writer).unwrap();
+        assert_eq!(std::str::from_utf8(writer.get_ref()).unwrap(), "1\n2\n3\n");
+
+        let broken = "Put 1 into my heart\n\nSay the ghost\n";
+        let mut program = parser::parse("Say 1\n").unwrap();
+        program
+            .commands
+            .push(parser::parse_include("broken.rock", broken, 2).unwrap());
+        let mut writer = Cursor::new(Vec::new());
+        let err = runner::run(&mut program, &mut writer).err().unwrap();
+        if let common::MaidenError::InFile {
+            ref file,
+            ref error,
+        } = err
+        {
+            assert_eq!(file, "broken.rock");
+            if let common::MaidenError::MissingVariable { name, line } = &**error {
+                assert_eq!(name, "the ghost");
+                assert_eq!(*line, 3);
+            } else {
+                panic!("{}", error);
+            }
+        } else {
+            panic!("{}", err);
+        }
+        assert_eq!(err.code(), "in-file");
+    }
 }
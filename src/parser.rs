@@ -1,11 +1,12 @@
 use crate::common::{
-    Block, Command, CommandLine, Expression, MaidenError, Program, Result, SymbolType,
+    ArrayOperation, BitwiseOp, Block, Command, CommandLine, Expression, ListenType, MaidenError,
+    Program, Result, Rounding, SymbolType, TypeName,
 };
 use crate::peg::{Rockstar, Rule};
 use log::{debug, warn};
 use pest::iterators::Pair;
 use pest::Parser;
-use std::collections::HashMap;
+use std::collections::BTreeMap;
 
 #[allow(clippy::large_enum_variant)]
 #[derive(Debug, Clone, PartialEq)]
@@ -73,7 +74,7 @@ where
         Rule::EOI => {
             return Ok(Program {
                 commands: vec![],
-                functions: HashMap::new(),
+                functions: BTreeMap::new(),
             })
         }
         rule => {
@@ -104,6 +105,9 @@ where
             Item::Command(command) => {
                 commands.push(command);
             }
+            Item::Block(block) => {
+                commands.extend(block.commands);
+            }
             Item::Symbol(SymbolType::Empty) => {}
             Item::Expression(Expression::Call(name, args)) => {
                 commands.push(CommandLine {
@@ -118,7 +122,7 @@ where
     }
     Ok(Program {
         commands,
-        functions: HashMap::new(),
+        functions: BTreeMap::new(),
     })
 }
 
@@ -154,11 +158,49 @@ fn pair_to_command_line(pair: &Pair<Rule>, command: Command) -> Item {
     .into()
 }
 
-fn remove<T>(items: &mut Vec<T>, index: usize, line: usize) -> Result<T> {
+// A last parameter of "the rest" collects any extra arguments, rather than being one itself
+fn rest_parameter(args: &mut Vec<String>) -> Option<String> {
+    if args.last()?.eq_ignore_ascii_case("the rest") {
+        return args.pop();
+    }
+    None
+}
+
+// "X stays true" is the same as "X", and "X stays false" the same as "not X". "X is true" is
+// left as a comparison, as a mysterious or array X is neither true nor false.
+fn stays_condition(condition: Expression, stays: Expression) -> Expression {
+    match stays {
+        Expression::True => condition,
+        Expression::False => Expression::Not(Box::new(condition)),
+        stays => Expression::Is(Box::new(condition), Box::new(stays)),
+    }
+}
+
+// expected describes the missing item, as "Expected {}, but didn't get one"
+fn remove<T>(items: &mut Vec<T>, index: usize, line: usize, expected: &str) -> Result<T> {
     if items.len() > index {
         Ok(items.remove(index))
     } else {
-        Err(MaidenError::Incomplete { line })
+        Err(MaidenError::Incomplete {
+            expected: expected.to_string(),
+            line,
+        })
+    }
+}
+
+// Plain decimals, or hexadecimal and binary with a "0x" or "0b" prefix
+fn parse_number(value: &str, line: usize) -> Result<f64> {
+    let radix = match value.get(..2).map(|prefix| prefix.to_ascii_lowercase()) {
+        Some(prefix) if prefix == "0x" => 16,
+        Some(prefix) if prefix == "0b" => 2,
+        _ => return Ok(value.parse::<f64>().unwrap()),
+    };
+    match u64::from_str_radix(&value[2..], radix) {
+        Ok(number) => Ok(number as f64),
+        Err(_) => Err(MaidenError::ParseNumberError {
+            number: value.to_string(),
+            line,
+        }),
     }
 }
 
@@ -184,6 +226,28 @@ fn depair_core(pair: Pair<'_, Rule>, level: usize) -> Result<Item> {
             }
             .into()
         }
+        Rule::blank_output => CommandLine {
+            cmd: Command::Say {
+                value: Expression::String(String::new()),
+            },
+            line,
+        }
+        .into(),
+        Rule::joined_output => {
+            debug!("{}Depairing joined_output", level_string);
+            let items = depair_seq(&mut pair.into_inner(), level + 1)?;
+            let parts = items
+                .into_iter()
+                .map(|item| item.expr())
+                .collect::<Result<Vec<_>>>()?;
+            CommandLine {
+                cmd: Command::Say {
+                    value: Expression::Sentence(parts),
+                },
+                line,
+            }
+            .into()
+        }
         Rule::string => {
             let mut value = pair.as_str();
             if value.len() < 2 {
@@ -195,17 +259,22 @@ fn depair_core(pair: Pair<'_, Rule>, level: usize) -> Result<Item> {
             value = &value[1..value.len() - 1];
             Expression::String(value.to_string()).into()
         }
-        Rule::number => {
-            let value = pair.as_str();
-            Expression::Floating(value.parse::<f64>().unwrap()).into()
-        }
+        Rule::number => Expression::Floating(parse_number(pair.as_str(), line)?).into(),
         Rule::conditional => {
             let mut pairs: Vec<_> = pair.into_inner().collect();
-            let expression = depair_core(remove(&mut pairs, 0, line)?, level + 1)?.expr()?;
+            let unless = pairs.first().map(|pair| pair.as_rule()) == Some(Rule::unless_kw);
+            if unless {
+                pairs.remove(0);
+            }
+            let mut expression =
+                depair_core(remove(&mut pairs, 0, line, "a condition")?, level + 1)?.expr()?;
+            if unless {
+                expression = Expression::Not(Box::new(expression));
+            }
             if pairs.is_empty() {
                 return Err(MaidenError::NoEndOfIf { line });
             }
-            let first = remove(&mut pairs, 0, line)?;
+            let first = remove(&mut pairs, 0, line, "a block")?;
             let consequent;
             let alternate;
             match first.as_rule() {
@@ -215,7 +284,8 @@ fn depair_core(pair: Pair<'_, Rule>, level: usize) -> Result<Item> {
                         None
                     } else {
                         let block =
-                            depair_core(remove(&mut pairs, 0, line)?, level + 1)?.block()?;
+                            depair_core(remove(&mut pairs, 0, line, "an else block")?, level + 1)?
+                                .block()?;
                         if !block.commands.is_empty() {
                             Some(block)
                         } else {
@@ -253,7 +323,11 @@ fn depair_core(pair: Pair<'_, Rule>, level: usize) -> Result<Item> {
             let items = depair_seq(&mut pair.into_inner(), level + 1)?;
             let mut commands = vec![];
             for item in items {
-                commands.push(item.command()?);
+                match item {
+                    // A pipeline, which is several commands on one line
+                    Item::Block(block) => commands.extend(block.commands),
+                    item => commands.push(item.command()?),
+                }
             }
             Block { commands }.into()
         }
@@ -261,11 +335,11 @@ fn depair_core(pair: Pair<'_, Rule>, level: usize) -> Result<Item> {
             debug!("{}Depairing equality_check", level_string);
             let mut items = depair_seq(&mut pair.into_inner(), level + 1)?;
             if items.len() == 1 {
-                return remove(&mut items, 0, line);
+                return remove(&mut items, 0, line, "an expression");
             }
-            let is = remove(&mut items, 1, line)?;
-            let first = Box::new(remove(&mut items, 0, line)?.expr()?);
-            let second = Box::new(remove(&mut items, 0, line)?.expr()?);
+            let is = remove(&mut items, 1, line, "a comparison")?;
+            let first = Box::new(remove(&mut items, 0, line, "a left-hand operand")?.expr()?);
+            let second = Box::new(remove(&mut items, 0, line, "a right-hand operand")?.expr()?);
             match is {
                 Item::Symbol(SymbolType::Is) => Expression::Is(first, second),
                 Item::Symbol(SymbolType::Aint) => Expression::Aint(first, second),
@@ -301,19 +375,277 @@ fn depair_core(pair: Pair<'_, Rule>, level: usize) -> Result<Item> {
         Rule::put_assignment => {
             debug!("{}Depairing put_assignment", level_string);
             let mut items = depair_seq(&mut pair.into_inner(), level + 1)?;
-            let value = Box::new(remove(&mut items, 0, line)?.expr()?);
-            let target = Box::new(remove(&mut items, 0, line)?.expr()?);
+            let value = Box::new(remove(&mut items, 0, line, "a value")?.expr()?);
+            let target = Box::new(remove(&mut items, 0, line, "a target")?.expr()?);
+            CommandLine {
+                cmd: Command::Assignment { target, value },
+                line,
+            }
+            .into()
+        }
+        // Sugar for an If around the statement, so the guard is evaluated first
+        Rule::guarded_assignment | Rule::guarded_statement => {
+            debug!("{}Depairing guarded statement", level_string);
+            let mut pairs: Vec<_> = pair.into_inner().collect();
+            let statement =
+                depair_core(remove(&mut pairs, 0, line, "a statement")?, level + 1)?.command()?;
+            let unless = remove(&mut pairs, 0, line, "unless or if")?
+                .as_str()
+                .eq_ignore_ascii_case("unless");
+            let guard =
+                depair_core(remove(&mut pairs, 0, line, "a condition")?, level + 1)?.expr()?;
+            CommandLine {
+                cmd: Command::If {
+                    expression: if unless {
+                        Expression::Not(Box::new(guard))
+                    } else {
+                        guard
+                    },
+                    then: Some(Block {
+                        commands: vec![statement],
+                    }),
+                    otherwise: None,
+                },
+                line,
+            }
+            .into()
+        }
+        Rule::multiple_assignment => {
+            debug!("{}Depairing multiple_assignment", level_string);
+            let mut items = depair_seq(&mut pair.into_inner(), level + 1)?;
+            let value = Box::new(remove(&mut items, 0, line, "a value")?.expr()?);
+            let mut targets = vec![];
+            for item in items {
+                targets.push(item.expr()?);
+            }
+            CommandLine {
+                cmd: Command::MultipleAssignment { targets, value },
+                line,
+            }
+            .into()
+        }
+        Rule::lambda_assignment => {
+            debug!("{}Depairing lambda_assignment", level_string);
+            let mut items = depair_seq(&mut pair.into_inner(), level + 1)?;
+            let target = Box::new(remove(&mut items, 0, line, "a target")?.expr()?);
+            let value = Box::new(remove(&mut items, 0, line, "a function")?.expr()?);
+            CommandLine {
+                cmd: Command::Assignment { target, value },
+                line,
+            }
+            .into()
+        }
+        Rule::lambda => {
+            debug!("{}Depairing lambda", level_string);
+            let mut items = depair_seq(&mut pair.into_inner(), level + 1)?;
+            let args = match remove(&mut items, 0, line, "a parameter list")?.symbol()? {
+                SymbolType::VariableList(variables) => variables,
+                other => panic!("Non-variable list for function: {:?}", other),
+            };
+            let block = remove(&mut items, 0, line, "a function body")?.block()?;
+            Expression::Lambda { args, block }.into()
+        }
+        // "X then split with "," then join into Y" is the same as splitting X into Y, and then
+        // joining Y in place, so it's those commands rather than one of its own
+        Rule::pipeline => {
+            debug!("{}Depairing pipeline", level_string);
+            let mut pairs: Vec<_> = pair.into_inner().collect();
+            let source = depair_core(remove(&mut pairs, 0, line, "a value")?, level + 1)?.expr()?;
+            let last = pairs.len().saturating_sub(1);
+            let target =
+                depair_core(remove(&mut pairs, last, line, "a target")?, level + 1)?.expr()?;
+            let mut commands = vec![];
+            for stage in pairs {
+                let mut items = depair_seq(&mut stage.into_inner(), level + 1)?;
+                let mutator = remove(&mut items, 0, line, "a mutation keyword")?.symbol()?;
+                let modifier = match items.pop() {
+                    Some(item) => Some(Box::new(item.expr()?)),
+                    None => None,
+                };
+                let (source, target, lookup) = if commands.is_empty() {
+                    (Some(source.clone()), Some(target.clone()), None)
+                } else {
+                    (None, None, Some(target.clone()))
+                };
+                commands.push(CommandLine {
+                    cmd: Command::Mutation {
+                        mutator,
+                        source: source.map(Box::new),
+                        target: target.map(Box::new),
+                        lookup: lookup.map(Box::new),
+                        modifier,
+                    },
+                    line,
+                });
+            }
+            Block { commands }.into()
+        }
+        Rule::clamp => {
+            debug!("{}Depairing clamp", level_string);
+            let mut items = depair_seq(&mut pair.into_inner(), level + 1)?;
+            let value = remove(&mut items, 0, line, "a number")?.expr()?;
+            let low = remove(&mut items, 0, line, "a lower bound")?.expr()?;
+            let high = remove(&mut items, 0, line, "an upper bound")?.expr()?;
+            let target = remove(&mut items, 0, line, "a target")?.expr()?;
+            CommandLine {
+                cmd: Command::Clamp {
+                    value,
+                    low,
+                    high,
+                    target,
+                },
+                line,
+            }
+            .into()
+        }
+        Rule::format_number => {
+            debug!("{}Depairing format_number", level_string);
+            let mut items = depair_seq(&mut pair.into_inner(), level + 1)?;
+            let value = remove(&mut items, 0, line, "a number")?.expr()?;
+            let places = remove(&mut items, 0, line, "a number of places")?.expr()?;
+            let target = remove(&mut items, 0, line, "a target")?.expr()?;
+            CommandLine {
+                cmd: Command::FormatNumber {
+                    value,
+                    places,
+                    target,
+                },
+                line,
+            }
+            .into()
+        }
+        Rule::pad_string => {
+            debug!("{}Depairing pad_string", level_string);
+            let mut right = false;
+            let mut items = vec![];
+            for inner in pair.into_inner() {
+                if inner.as_rule() == Rule::pad_side {
+                    right = inner.as_str().eq_ignore_ascii_case("right");
+                } else {
+                    items.push(depair_core(inner, level + 1)?);
+                }
+            }
+            let value = remove(&mut items, 0, line, "a value")?.expr()?;
+            let width = remove(&mut items, 0, line, "a width")?.expr()?;
+            // Only the fill is optional, so there's one left over when it's given
+            let fill = if items.len() > 1 {
+                Some(remove(&mut items, 0, line, "a fill")?.expr()?)
+            } else {
+                None
+            };
+            let target = remove(&mut items, 0, line, "a target")?.expr()?;
+            CommandLine {
+                cmd: Command::Pad {
+                    value,
+                    width,
+                    fill,
+                    right,
+                    target,
+                },
+                line,
+            }
+            .into()
+        }
+        Rule::map_array | Rule::filter_array | Rule::reduce_array => {
+            debug!("{}Depairing {:?}", level_string, pair.as_rule());
+            let operation = match pair.as_rule() {
+                Rule::map_array => ArrayOperation::Map,
+                Rule::filter_array => ArrayOperation::Filter,
+                _ => ArrayOperation::Reduce,
+            };
+            let mut items = depair_seq(&mut pair.into_inner(), level + 1)?;
+            let (function, source) = if operation == ArrayOperation::Map {
+                let function = remove(&mut items, 0, line, "a function")?.expr()?;
+                (function, remove(&mut items, 0, line, "an array")?.expr()?)
+            } else {
+                let source = remove(&mut items, 0, line, "an array")?.expr()?;
+                (remove(&mut items, 0, line, "a function")?.expr()?, source)
+            };
+            let target = remove(&mut items, 0, line, "a target")?.expr()?;
+            CommandLine {
+                cmd: Command::ApplyToArray {
+                    operation,
+                    function,
+                    source,
+                    target,
+                },
+                line,
+            }
+            .into()
+        }
+        Rule::copy_assignment => {
+            debug!("{}Depairing copy_assignment", level_string);
+            let mut items = depair_seq(&mut pair.into_inner(), level + 1)?;
+            let target = Box::new(remove(&mut items, 0, line, "a target")?.expr()?);
+            let value = Box::new(remove(&mut items, 0, line, "a value")?.expr()?);
+            CommandLine {
+                cmd: Command::Assignment { target, value },
+                line,
+            }
+            .into()
+        }
+        Rule::copy_into => {
+            debug!("{}Depairing copy_into", level_string);
+            let mut items = depair_seq(&mut pair.into_inner(), level + 1)?;
+            let value = Box::new(remove(&mut items, 0, line, "a value")?.expr()?);
+            let target = Box::new(remove(&mut items, 0, line, "a target")?.expr()?);
             CommandLine {
                 cmd: Command::Assignment { target, value },
                 line,
             }
             .into()
         }
+        Rule::rounded_assignment => {
+            debug!("{}Depairing rounded_assignment", level_string);
+            let mut pairs: Vec<_> = pair.into_inner().collect();
+            let suffix = pairs.pop().unwrap().as_str().to_lowercase();
+            let mut items = depair_seq(&mut pairs.into_iter(), level + 1)?;
+            let target = Box::new(remove(&mut items, 0, line, "a target")?.expr()?);
+            let value = remove(&mut items, 0, line, "a value")?.expr()?;
+            CommandLine {
+                cmd: Command::Assignment {
+                    target,
+                    value: Box::new(Expression::Rounded(Box::new(value), rounding_mode(&suffix))),
+                },
+                line,
+            }
+            .into()
+        }
+        Rule::rounding_modifier => {
+            debug!("{}Depairing rounding_modifier", level_string);
+            let mut pairs: Vec<_> = pair.into_inner().collect();
+            let direction = pairs.pop().unwrap().as_str().to_lowercase();
+            let rounding = if direction == "up" {
+                Rounding::Up
+            } else if direction == "down" {
+                Rounding::Down
+            } else {
+                Rounding::Nearest
+            };
+            let rounded = |value: Expression| Expression::Rounded(Box::new(value), rounding);
+            let mut command = depair(&mut pairs.into_iter(), level + 1)?.command()?;
+            command.cmd = match command.cmd {
+                Command::Assignment { target, value } => Command::Assignment {
+                    target,
+                    value: Box::new(rounded(*value)),
+                },
+                Command::Say { value } => Command::Say {
+                    value: rounded(value),
+                },
+                other => {
+                    return Err(MaidenError::Unimplemented {
+                        description: format!("Rounding the value of {:?}", other),
+                        line,
+                    });
+                }
+            };
+            command.into()
+        }
         Rule::assignable => {
             debug!("{}Depairing assignable", level_string);
             let mut items = depair_seq(&mut pair.into_inner(), level + 1)?;
-            let variable = remove(&mut items, 0, line)?;
-            let indexer = remove(&mut items, 0, line);
+            let variable = remove(&mut items, 0, line, "a variable")?;
+            let indexer = remove(&mut items, 0, line, "an index");
             match indexer {
                 Ok(index) => Expression::ArrayRef {
                     name: Box::new(variable.expr()?),
@@ -326,11 +658,11 @@ fn depair_core(pair: Pair<'_, Rule>, level: usize) -> Result<Item> {
         Rule::lookup => {
             debug!("{}Depairing lookup", level_string);
             let mut items = depair_seq(&mut pair.into_inner(), level + 1)?;
-            let variable = remove(&mut items, 0, line)?;
+            let variable = remove(&mut items, 0, line, "a variable")?;
             if items.is_empty() {
                 variable
             } else if items.len() == 1 {
-                let index = remove(&mut items, 0, line)?;
+                let index = remove(&mut items, 0, line, "an index")?;
                 Expression::ArrayRef {
                     name: Box::new(variable.expr()?),
                     index: Box::new(index.expr()?),
@@ -352,24 +684,24 @@ fn depair_core(pair: Pair<'_, Rule>, level: usize) -> Result<Item> {
 
             if items.len() == 1 {
                 if let Item::Command(_) = items[0] {
-                    return remove(&mut items, 0, line);
+                    return remove(&mut items, 0, line, "an assignment");
                 }
             }
 
-            let target = remove(&mut items, 0, line)?.expr()?;
+            let target = remove(&mut items, 0, line, "a target")?.expr()?;
             match items.len() {
                 1 => CommandLine {
                     cmd: Command::Assignment {
                         target: Box::new(target),
-                        value: Box::new(remove(&mut items, 0, line)?.expr()?),
+                        value: Box::new(remove(&mut items, 0, line, "a value")?.expr()?),
                     },
                     line,
                 }
                 .into(),
                 2 => {
-                    let operator = remove(&mut items, 0, line)?.symbol()?;
+                    let operator = remove(&mut items, 0, line, "an operator")?.symbol()?;
                     let first = target.clone();
-                    let mut second = match remove(&mut items, 0, line)? {
+                    let mut second = match remove(&mut items, 0, line, "a right-hand operand")? {
                         Item::Expression(expr) => vec![expr],
                         Item::Symbol(SymbolType::ExpressionList(items)) => items,
                         item => {
@@ -433,8 +765,22 @@ fn depair_core(pair: Pair<'_, Rule>, level: usize) -> Result<Item> {
                             }
                             .into()
                         }
+                        SymbolType::Modulo => {
+                            let mut expr = first;
+                            for s in second.drain(0..) {
+                                expr = Expression::Modulo(Box::new(expr), Box::new(s));
+                            }
+                            CommandLine {
+                                cmd: Command::Assignment {
+                                    target: Box::new(target),
+                                    value: Box::new(expr),
+                                },
+                                line,
+                            }
+                            .into()
+                        }
                         SymbolType::Is => {
-                            let s = remove(&mut second, 0, line)?;
+                            let s = remove(&mut second, 0, line, "a value")?;
                             CommandLine {
                                 cmd: Command::Assignment {
                                     target: Box::new(target),
@@ -458,24 +804,29 @@ fn depair_core(pair: Pair<'_, Rule>, level: usize) -> Result<Item> {
             debug!("{}Depairing arithmetic", level_string);
             let mut items = depair_seq(&mut pair.into_inner(), level + 1)?;
             if items.len() == 1 {
-                return remove(&mut items, 0, line);
+                return remove(&mut items, 0, line, "an expression");
             }
             if items.len() % 2 != 1 {
-                panic!("Weird arithmetic: {:?}", items);
+                // An operator at the end with nothing after it
+                return Err(MaidenError::Incomplete {
+                    expected: "a right-hand operand".to_string(),
+                    line,
+                });
             };
-            let mut first = remove(&mut items, 0, line)?.expr()?;
+            let mut first = remove(&mut items, 0, line, "a left-hand operand")?.expr()?;
             while !items.is_empty() {
-                let operator = remove(&mut items, 0, line)?.symbol()?;
+                let operator = remove(&mut items, 0, line, "an operator")?.symbol()?;
                 let apply_operator = move |first, other| match operator {
                     SymbolType::Add => Expression::Add(Box::new(first), Box::new(other)),
                     SymbolType::Subtract => Expression::Subtract(Box::new(first), Box::new(other)),
                     SymbolType::Times => Expression::Times(Box::new(first), Box::new(other)),
                     SymbolType::Divide => Expression::Divide(Box::new(first), Box::new(other)),
+                    SymbolType::Modulo => Expression::Modulo(Box::new(first), Box::new(other)),
                     _ => {
                         panic!("Unknown operator: {:?}", operator);
                     }
                 };
-                match remove(&mut items, 0, line)? {
+                match remove(&mut items, 0, line, "a right-hand operand")? {
                     Item::Expression(second) => {
                         first = apply_operator(first, second);
                     }
@@ -497,11 +848,11 @@ fn depair_core(pair: Pair<'_, Rule>, level: usize) -> Result<Item> {
             debug!("{}Depairing and", level_string);
             let mut items = depair_seq(&mut pair.into_inner(), level + 1)?;
             if items.len() == 1 {
-                return remove(&mut items, 0, line);
+                return remove(&mut items, 0, line, "an expression");
             }
             Expression::And(
-                Box::new(remove(&mut items, 0, line)?.expr()?),
-                Box::new(remove(&mut items, 0, line)?.expr()?),
+                Box::new(remove(&mut items, 0, line, "a left-hand operand")?.expr()?),
+                Box::new(remove(&mut items, 0, line, "a right-hand operand")?.expr()?),
             )
             .into()
         }
@@ -509,11 +860,11 @@ fn depair_core(pair: Pair<'_, Rule>, level: usize) -> Result<Item> {
             debug!("{}Depairing or", level_string);
             let mut items = depair_seq(&mut pair.into_inner(), level + 1)?;
             if items.len() == 1 {
-                return remove(&mut items, 0, line);
+                return remove(&mut items, 0, line, "an expression");
             }
             Expression::Or(
-                Box::new(remove(&mut items, 0, line)?.expr()?),
-                Box::new(remove(&mut items, 0, line)?.expr()?),
+                Box::new(remove(&mut items, 0, line, "a left-hand operand")?.expr()?),
+                Box::new(remove(&mut items, 0, line, "a right-hand operand")?.expr()?),
             )
             .into()
         }
@@ -521,26 +872,88 @@ fn depair_core(pair: Pair<'_, Rule>, level: usize) -> Result<Item> {
             debug!("{}Depairing nor", level_string);
             let mut items = depair_seq(&mut pair.into_inner(), level + 1)?;
             if items.len() == 1 {
-                return remove(&mut items, 0, line);
+                return remove(&mut items, 0, line, "an expression");
             }
             Expression::Nor(
-                Box::new(remove(&mut items, 0, line)?.expr()?),
-                Box::new(remove(&mut items, 0, line)?.expr()?),
+                Box::new(remove(&mut items, 0, line, "a left-hand operand")?.expr()?),
+                Box::new(remove(&mut items, 0, line, "a right-hand operand")?.expr()?),
             )
             .into()
         }
         Rule::add => SymbolType::Add.into(),
         Rule::subtract => SymbolType::Subtract.into(),
         Rule::multiply => SymbolType::Times.into(),
-        Rule::divide => SymbolType::Divide.into(),
+        Rule::divide => {
+            if pair.into_inner().any(|p| p.as_rule() == Rule::modulo) {
+                SymbolType::Modulo.into()
+            } else {
+                SymbolType::Divide.into()
+            }
+        }
+        Rule::remainder => {
+            debug!("{}Depairing remainder", level_string);
+            let mut items = depair_seq(&mut pair.into_inner(), level + 1)?;
+            let first = Box::new(remove(&mut items, 0, line, "a dividend")?.expr()?);
+            let second = Box::new(remove(&mut items, 0, line, "a divisor")?.expr()?);
+            Expression::Modulo(first, second).into()
+        }
+        Rule::bitwise | Rule::shift => {
+            debug!("{}Depairing {:?}", level_string, pair.as_rule());
+            let mut pairs: Vec<_> = pair.into_inner().collect();
+            let op = match remove(&mut pairs, 0, line, "an operator")?
+                .as_str()
+                .to_lowercase()
+                .as_str()
+            {
+                "and" => BitwiseOp::And,
+                "or" => BitwiseOp::Or,
+                "xor" => BitwiseOp::Xor,
+                "left" => BitwiseOp::ShiftLeft,
+                _ => BitwiseOp::ShiftRight,
+            };
+            let first = depair_core(remove(&mut pairs, 0, line, "a value")?, level + 1)?.expr()?;
+            let second =
+                depair_core(remove(&mut pairs, 0, line, "a second value")?, level + 1)?.expr()?;
+            Expression::Bitwise(op, Box::new(first), Box::new(second)).into()
+        }
+        Rule::inclusive_slice => {
+            debug!("{}Depairing inclusive_slice", level_string);
+            let mut items = depair_seq(&mut pair.into_inner(), level + 1)?;
+            let value = Box::new(remove(&mut items, 0, line, "a variable")?.expr()?);
+            let start = Box::new(remove(&mut items, 0, line, "a first index")?.expr()?);
+            let end = Box::new(remove(&mut items, 0, line, "a last index")?.expr()?);
+            Expression::Slice(value, start, end).into()
+        }
         Rule::pronoun => Expression::Pronoun.into(),
         Rule::ne => SymbolType::Aint.into(),
         Rule::return_kw => SymbolType::Return.into(),
         Rule::function_return => {
             let mut items = depair_seq(&mut pair.into_inner(), level + 1)?;
+            // A multiple_return, which is an alternative within function_return
+            if items.len() == 1 {
+                if let Item::Command(_) = items[0] {
+                    return remove(&mut items, 0, line, "a return");
+                }
+            }
+            CommandLine {
+                cmd: Command::Return {
+                    return_value: remove(&mut items, 1, line, "a return value")?.expr()?,
+                },
+                line,
+            }
+            .into()
+        }
+        // "Give back X, Y, and Z" returns an array of the three
+        Rule::multiple_return => {
+            let mut items = depair_seq(&mut pair.into_inner(), level + 1)?;
+            remove(&mut items, 0, line, "a return keyword")?;
+            let mut values = vec![];
+            for item in items {
+                values.push(item.expr()?);
+            }
             CommandLine {
                 cmd: Command::Return {
-                    return_value: remove(&mut items, 1, line)?.expr()?,
+                    return_value: Expression::List(values),
                 },
                 line,
             }
@@ -582,7 +995,10 @@ fn depair_core(pair: Pair<'_, Rule>, level: usize) -> Result<Item> {
             let mut items = depair_seq(&mut pair.into_inner(), level + 1)?;
             if items.is_empty() {
                 return Ok(CommandLine {
-                    cmd: Command::Listen { target: None },
+                    cmd: Command::Listen {
+                        targets: vec![],
+                        as_type: ListenType::String,
+                    },
                     line,
                 }
                 .into());
@@ -590,9 +1006,19 @@ fn depair_core(pair: Pair<'_, Rule>, level: usize) -> Result<Item> {
             if items.len() != 1 {
                 panic!("listen: {:?}", items);
             }
-            if let Item::Expression(Expression::Variable(name)) = remove(&mut items, 0, line)? {
+            // As is "Listen to X as a ..." or "Listen to X and Y", which are alternatives within
+            // readline
+            if let Item::Command(_) = items[0] {
+                return remove(&mut items, 0, line, "a listen");
+            }
+            if let Item::Expression(Expression::Variable(name)) =
+                remove(&mut items, 0, line, "a variable")?
+            {
                 CommandLine {
-                    cmd: Command::Listen { target: Some(name) },
+                    cmd: Command::Listen {
+                        targets: vec![name],
+                        as_type: ListenType::String,
+                    },
                     line,
                 }
                 .into()
@@ -600,6 +1026,49 @@ fn depair_core(pair: Pair<'_, Rule>, level: usize) -> Result<Item> {
                 panic!("listen: {:?}", items);
             }
         }
+        Rule::typed_readline => {
+            debug!("{}Depairing typed_readline", level_string);
+            let mut pairs: Vec<_> = pair.into_inner().collect();
+            let name =
+                match depair_core(remove(&mut pairs, 0, line, "a variable")?, level + 1)?.expr()? {
+                    Expression::Variable(name) => name,
+                    other => panic!("listen: {:?}", other),
+                };
+            let as_type = if remove(&mut pairs, 0, line, "a type")?
+                .as_str()
+                .eq_ignore_ascii_case("number")
+            {
+                ListenType::Number
+            } else {
+                ListenType::String
+            };
+            CommandLine {
+                cmd: Command::Listen {
+                    targets: vec![name],
+                    as_type,
+                },
+                line,
+            }
+            .into()
+        }
+        Rule::destructuring_readline => {
+            debug!("{}Depairing destructuring_readline", level_string);
+            let targets = depair_seq(&mut pair.into_inner(), level + 1)?
+                .into_iter()
+                .map(|item| match item.expr()? {
+                    Expression::Variable(name) => Ok(name),
+                    other => panic!("listen: {:?}", other),
+                })
+                .collect::<Result<Vec<_>>>()?;
+            CommandLine {
+                cmd: Command::Listen {
+                    targets,
+                    as_type: ListenType::String,
+                },
+                line,
+            }
+            .into()
+        }
         Rule::variable_list => {
             debug!("{}Depairing variable_list", level_string);
             let mut items = depair_seq(&mut pair.into_inner(), level + 1)?;
@@ -650,7 +1119,7 @@ fn depair_core(pair: Pair<'_, Rule>, level: usize) -> Result<Item> {
             let mut items = depair_seq(&mut pair.into_inner(), level + 1)?;
             let mut expressions = vec![];
             if items.len() == 1 {
-                return remove(&mut items, 0, line);
+                return remove(&mut items, 0, line, "an expression");
             }
             if !items.is_empty() {
                 for item in items.drain(0..) {
@@ -673,21 +1142,104 @@ fn depair_core(pair: Pair<'_, Rule>, level: usize) -> Result<Item> {
         Rule::function => {
             debug!("{}Depairing function", level_string);
             let mut items = depair_seq(&mut pair.into_inner(), level + 1)?;
-            let name = if let Expression::Variable(n) = remove(&mut items, 0, line)?.expr()? {
+            // A function_with_defaults, which is an alternative within function
+            if items.len() == 1 {
+                if let Item::Command(_) = items[0] {
+                    return remove(&mut items, 0, line, "a function");
+                }
+            }
+            let name = if let Expression::Variable(n) =
+                remove(&mut items, 0, line, "a function name")?.expr()?
+            {
                 n
             } else {
                 panic!("Non-variable name for function");
             };
-            let args = if let SymbolType::VariableList(variables) =
-                remove(&mut items, 0, line)?.symbol()?
+            let mut args = if let SymbolType::VariableList(variables) =
+                remove(&mut items, 0, line, "a parameter list")?.symbol()?
             {
                 variables
             } else {
                 panic!("Non-variable list for function");
             };
-            let block = remove(&mut items, 0, line)?.block()?;
+            let rest = rest_parameter(&mut args);
+            let block = remove(&mut items, 0, line, "a function body")?.block()?;
+            CommandLine {
+                cmd: Command::FunctionDeclaration {
+                    name,
+                    args,
+                    defaults: vec![],
+                    rest,
+                    block,
+                },
+                line,
+            }
+            .into()
+        }
+        Rule::function_with_defaults => {
+            debug!("{}Depairing function_with_defaults", level_string);
+            let mut items = vec![];
+            let mut given = vec![];
+            for inner in pair.into_inner() {
+                if inner.as_rule() == Rule::parameter_default {
+                    let mut parts = depair_seq(&mut inner.into_inner(), level + 1)?;
+                    let arg = match remove(&mut parts, 0, line, "a parameter")?.expr()? {
+                        Expression::Variable(arg) => arg.to_lowercase(),
+                        other => panic!("Non-variable parameter: {:?}", other),
+                    };
+                    let value = remove(&mut parts, 0, line, "a default value")?.expr()?;
+                    given.push((arg, value));
+                } else {
+                    items.push(depair_core(inner, level + 1)?);
+                }
+            }
+            let name = match remove(&mut items, 0, line, "a function name")?.expr()? {
+                Expression::Variable(n) => n,
+                other => panic!("Non-variable name for function: {:?}", other),
+            };
+            let mut args = match remove(&mut items, 0, line, "a parameter list")?.symbol()? {
+                SymbolType::VariableList(variables) => variables,
+                other => panic!("Non-variable list for function: {:?}", other),
+            };
+            let rest = rest_parameter(&mut args);
+            let block = remove(&mut items, 0, line, "a function body")?.block()?;
+
+            // Only the last parameters can have defaults, so a call can leave them all out
+            let first_default = args.len().saturating_sub(given.len());
+            let mut defaults = vec![];
+            for (i, arg) in args.iter().enumerate().skip(first_default) {
+                match given
+                    .iter()
+                    .position(|(name, _)| *name == arg.to_lowercase())
+                {
+                    Some(index) => defaults.push(given.remove(index).1),
+                    None => {
+                        return Err(MaidenError::BadArgument {
+                            name,
+                            description: format!(
+                                "parameter {} has no default but comes after one that does",
+                                i + 1
+                            ),
+                            line,
+                        });
+                    }
+                }
+            }
+            if let Some((arg, _)) = given.first() {
+                return Err(MaidenError::BadArgument {
+                    name,
+                    description: format!("default for '{}', which isn't a parameter", arg),
+                    line,
+                });
+            }
             CommandLine {
-                cmd: Command::FunctionDeclaration { name, args, block },
+                cmd: Command::FunctionDeclaration {
+                    name,
+                    args,
+                    defaults,
+                    rest,
+                    block,
+                },
                 line,
             }
             .into()
@@ -695,12 +1247,14 @@ fn depair_core(pair: Pair<'_, Rule>, level: usize) -> Result<Item> {
         Rule::function_call => {
             debug!("{}Depairing function_call", level_string);
             let mut items = depair_seq(&mut pair.into_inner(), level + 1)?;
-            let name = if let Expression::Variable(n) = remove(&mut items, 0, line)?.expr()? {
+            let name = if let Expression::Variable(n) =
+                remove(&mut items, 0, line, "a function name")?.expr()?
+            {
                 n
             } else {
                 panic!("Non-variable name for function_call");
             };
-            let args_list = remove(&mut items, 0, line)?.symbol()?;
+            let args_list = remove(&mut items, 0, line, "a function argument")?.symbol()?;
             if let SymbolType::ArgsList(variables) = args_list {
                 Expression::Call(name, variables).into()
             } else {
@@ -712,7 +1266,7 @@ fn depair_core(pair: Pair<'_, Rule>, level: usize) -> Result<Item> {
         Rule::increment => {
             debug!("{}Depairing increment", level_string);
             let mut items = depair_seq(&mut pair.into_inner(), level + 1)?;
-            let target = remove(&mut items, 0, line)?.expr()?;
+            let target = remove(&mut items, 0, line, "a target")?.expr()?;
             CommandLine {
                 cmd: Command::Increment {
                     target,
@@ -725,7 +1279,7 @@ fn depair_core(pair: Pair<'_, Rule>, level: usize) -> Result<Item> {
         Rule::decrement => {
             debug!("{}Depairing decrement", level_string);
             let mut items = depair_seq(&mut pair.into_inner(), level + 1)?;
-            let target = remove(&mut items, 0, line)?.expr()?;
+            let target = remove(&mut items, 0, line, "a target")?.expr()?;
             CommandLine {
                 cmd: Command::Decrement {
                     target,
@@ -735,23 +1289,85 @@ fn depair_core(pair: Pair<'_, Rule>, level: usize) -> Result<Item> {
             }
             .into()
         }
+        Rule::increase_by | Rule::decrease_by => {
+            let increase = pair.as_rule() == Rule::increase_by;
+            let mut items = depair_seq(&mut pair.into_inner(), level + 1)?;
+            let target = remove(&mut items, 0, line, "a target")?.expr()?;
+            let count = match remove(&mut items, 0, line, "an amount")?.expr()? {
+                Expression::Floating(count) => count,
+                other => panic!("Non-number amount: {:?}", other),
+            };
+            let cmd = if increase {
+                Command::Increment { target, count }
+            } else {
+                Command::Decrement { target, count }
+            };
+            CommandLine { cmd, line }.into()
+        }
         Rule::variable_list_separator | Rule::expression_list_separator => SymbolType::Empty.into(),
         Rule::greater => SymbolType::GreaterThan.into(),
         Rule::great => SymbolType::GreaterThanOrEqual.into(),
         Rule::smaller => SymbolType::LessThan.into(),
         Rule::small => SymbolType::LessThanOrEqual.into(),
+        Rule::between => {
+            debug!("{}Depairing between", level_string);
+            let mut items = depair_seq(&mut pair.into_inner(), level + 1)?;
+            let value = Box::new(remove(&mut items, 0, line, "a value")?.expr()?);
+            let lower = Box::new(remove(&mut items, 0, line, "a lower bound")?.expr()?);
+            let upper = Box::new(remove(&mut items, 0, line, "an upper bound")?.expr()?);
+            Expression::Between(value, lower, upper).into()
+        }
+        // The same as "X modulo Y is 0", so a divisor of 0 fails the same way
+        Rule::divisible_by => {
+            let mut items = depair_seq(&mut pair.into_inner(), level + 1)?;
+            let value = Box::new(remove(&mut items, 0, line, "a value")?.expr()?);
+            let divisor = Box::new(remove(&mut items, 0, line, "a divisor")?.expr()?);
+            Expression::Is(
+                Box::new(Expression::Modulo(value, divisor)),
+                Box::new(Expression::Floating(0.0)),
+            )
+            .into()
+        }
+        Rule::coalesce => {
+            let mut items = depair_seq(&mut pair.into_inner(), level + 1)?;
+            let value = Box::new(remove(&mut items, 0, line, "a value")?.expr()?);
+            let default = Box::new(remove(&mut items, 0, line, "a default")?.expr()?);
+            Expression::Coalesce(value, default).into()
+        }
+        Rule::has_key => {
+            let mut items = depair_seq(&mut pair.into_inner(), level + 1)?;
+            let map = Box::new(remove(&mut items, 0, line, "an array")?.expr()?);
+            let key = Box::new(remove(&mut items, 0, line, "a key")?.expr()?);
+            Expression::HasKey(map, key).into()
+        }
+        Rule::type_test => {
+            debug!("{}Depairing type_test", level_string);
+            let mut pairs: Vec<_> = pair.into_inner().collect();
+            let value = depair_core(remove(&mut pairs, 0, line, "a value")?, level + 1)?.expr()?;
+            let type_name = match remove(&mut pairs, 0, line, "a type")?
+                .as_str()
+                .to_lowercase()
+                .as_str()
+            {
+                "number" => TypeName::Number,
+                "string" => TypeName::String,
+                "array" => TypeName::Array,
+                _ => TypeName::Boolean,
+            };
+            Expression::IsType(Box::new(value), type_name).into()
+        }
         Rule::comparison => {
             debug!("{}Depairing comparison", level_string);
             let mut items = depair_seq(&mut pair.into_inner(), level + 1)?;
             if items.len() == 1 {
-                return remove(&mut items, 0, line);
+                return remove(&mut items, 0, line, "an expression");
             }
             if items.len() != 3 {
                 panic!("Bad comparison: {:?}", items);
             }
-            let first = Box::new(remove(&mut items, 0, line)?.expr()?);
-            let operator = remove(&mut items, 0, line)?.symbol()?;
-            let second = Box::new(remove(&mut items, 0, line)?.expr()?);
+            let first = Box::new(remove(&mut items, 0, line, "a left-hand operand")?.expr()?);
+            let operator = remove(&mut items, 0, line, "an operator")?.symbol()?;
+            let second = Box::new(remove(&mut items, 0, line, "a right-hand operand")?.expr()?);
             match operator {
                 SymbolType::GreaterThan => Expression::GreaterThan(first, second),
                 SymbolType::GreaterThanOrEqual => Expression::GreaterThanOrEqual(first, second),
@@ -767,21 +1383,34 @@ fn depair_core(pair: Pair<'_, Rule>, level: usize) -> Result<Item> {
         Rule::until_kw => SymbolType::Until.into(),
         Rule::continue_kw => pair_to_command_line(&pair, Command::Continue),
         Rule::break_kw => pair_to_command_line(&pair, Command::Break),
-        Rule::loop_kw => {
+        Rule::loop_kw | Rule::stays_loop => {
             debug!("{}Depairing loop", level_string);
             let mut items = depair_seq(&mut pair.into_inner(), level + 1)?;
-            let kind = remove(&mut items, 0, line)?.symbol()?;
-            let condition = remove(&mut items, 0, line)?.expr()?;
-            let block = remove(&mut items, 0, line)?.block()?;
+            // The extended loop forms are alternatives within loop, so arrive already depaired
+            if items.len() == 1 {
+                if let Item::Command(_) = items[0] {
+                    return remove(&mut items, 0, line, "a loop");
+                }
+            }
+            let kind = remove(&mut items, 0, line, "a loop keyword")?.symbol()?;
+            let mut condition = remove(&mut items, 0, line, "a loop condition")?.expr()?;
+            if items.len() == 2 {
+                // "stays true" or "stays false"
+                let stays = remove(&mut items, 0, line, "true or false")?.expr()?;
+                condition = stays_condition(condition, stays);
+            }
+            let block = remove(&mut items, 0, line, "a loop body")?.block()?;
             CommandLine {
                 cmd: match kind {
                     SymbolType::While => Command::While {
                         expression: condition,
                         block,
+                        label: None,
                     },
                     SymbolType::Until => Command::Until {
                         expression: condition,
                         block,
+                        label: None,
                     },
                     _ => {
                         panic!("Unrecognised block type: {:?}", kind);
@@ -791,18 +1420,83 @@ fn depair_core(pair: Pair<'_, Rule>, level: usize) -> Result<Item> {
             }
             .into()
         }
-        Rule::math_round => {
-            let pair = depair(&mut pair.into_inner(), level + 1)?;
+        Rule::try_block => {
+            debug!("{}Depairing try_block", level_string);
+            let mut items = depair_seq(&mut pair.into_inner(), level + 1)?;
+            let body = remove(&mut items, 0, line, "a block to try")?.block()?;
+            let error_var = if items.len() > 1 {
+                match remove(&mut items, 0, line, "an error variable")?.expr()? {
+                    Expression::Variable(name) => Some(name),
+                    other => panic!("Non-variable error name: {:?}", other),
+                }
+            } else {
+                None
+            };
+            let recover = remove(&mut items, 0, line, "a block to recover with")?.block()?;
             CommandLine {
-                cmd: Command::Round {
-                    target: pair.expr()?,
+                cmd: Command::Try {
+                    body,
+                    error_var,
+                    recover,
                 },
                 line,
             }
             .into()
         }
-        Rule::floor => {
-            let pair = depair(&mut pair.into_inner(), level + 1)?;
+        Rule::repeat_loop => {
+            debug!("{}Depairing repeat_loop", level_string);
+            let mut items = depair_seq(&mut pair.into_inner(), level + 1)?;
+            let count = remove(&mut items, 0, line, "a repeat count")?.expr()?;
+            let block = remove(&mut items, 0, line, "a loop body")?.block()?;
+            CommandLine {
+                cmd: Command::Repeat {
+                    count,
+                    block,
+                    label: None,
+                },
+                line,
+            }
+            .into()
+        }
+        Rule::labelled_loop => {
+            debug!("{}Depairing labelled_loop", level_string);
+            let mut items = depair_seq(&mut pair.into_inner(), level + 1)?;
+            let name = match remove(&mut items, 0, line, "a label")?.expr()? {
+                Expression::Variable(name) => name.to_lowercase(),
+                other => panic!("Non-variable label: {:?}", other),
+            };
+            let mut command = remove(&mut items, 0, line, "a loop")?.command()?;
+            match command.cmd {
+                Command::While { ref mut label, .. }
+                | Command::Until { ref mut label, .. }
+                | Command::Repeat { ref mut label, .. } => *label = Some(name),
+                ref other => panic!("Label on a non-loop: {:?}", other),
+            }
+            command.into()
+        }
+        Rule::restart => {
+            let label = match depair(&mut pair.into_inner(), level + 1)?.expr()? {
+                Expression::Variable(name) => name.to_lowercase(),
+                other => panic!("Non-variable label: {:?}", other),
+            };
+            CommandLine {
+                cmd: Command::Restart { label },
+                line,
+            }
+            .into()
+        }
+        Rule::math_round => {
+            let pair = depair(&mut pair.into_inner(), level + 1)?;
+            CommandLine {
+                cmd: Command::Round {
+                    target: pair.expr()?,
+                },
+                line,
+            }
+            .into()
+        }
+        Rule::floor => {
+            let pair = depair(&mut pair.into_inner(), level + 1)?;
             CommandLine {
                 cmd: Command::Floor {
                     target: pair.expr()?,
@@ -821,6 +1515,237 @@ fn depair_core(pair: Pair<'_, Rule>, level: usize) -> Result<Item> {
             }
             .into()
         }
+        Rule::square_root => {
+            let value = depair(&mut pair.into_inner(), level + 1)?.expr()?;
+            Expression::SquareRoot(Box::new(value)).into()
+        }
+        Rule::opposite => {
+            let value = depair(&mut pair.into_inner(), level + 1)?.expr()?;
+            Expression::Not(Box::new(value)).into()
+        }
+        // Only there for readability, so it's just the call
+        Rule::result_of => depair(&mut pair.into_inner(), level + 1)?,
+        Rule::text_of => {
+            let value = depair(&mut pair.into_inner(), level + 1)?.expr()?;
+            Expression::Text(Box::new(value)).into()
+        }
+        Rule::power => {
+            debug!("{}Depairing power", level_string);
+            let mut pairs: Vec<_> = pair.into_inner().collect();
+            let mut value =
+                depair_core(remove(&mut pairs, 0, line, "a base")?, level + 1)?.expr()?;
+            for exponent in pairs {
+                let power = if exponent.as_str().eq_ignore_ascii_case("cubed") {
+                    3.0
+                } else {
+                    2.0
+                };
+                value = Expression::Power(Box::new(value), Box::new(Expression::Floating(power)));
+            }
+            value.into()
+        }
+        Rule::builtin_phrase => {
+            debug!("{}Depairing builtin_phrase", level_string);
+            let mut pairs: Vec<_> = pair.into_inner().collect();
+            let phrase = remove(&mut pairs, 0, line, "a built-in name")?;
+            let phrase = phrase.as_str().to_lowercase();
+            let name = match phrase.split_whitespace().next() {
+                Some("absolute") => "abs",
+                Some("ceiling") => "ceil",
+                Some("floor") => "floor",
+                Some("sum") => "sum",
+                Some("product") => "product",
+                Some("index") => "argmax",
+                Some("average") | Some("mean") => "mean",
+                Some("keys") => "keys",
+                Some("values") => "values",
+                Some("code") => "code",
+                Some("character") => "character",
+                Some("digits") => "digits",
+                Some("longest") => "longest",
+                Some("shortest") => "shortest",
+                Some("reverse") => "reverse",
+                Some("sign") => "sign",
+                Some("permutations") => "permutations",
+                Some("factorial") => "factorial",
+                _ => "length",
+            };
+            let value = depair_core(remove(&mut pairs, 0, line, "a value")?, level + 1)?.expr()?;
+            Expression::Call(name.to_string(), vec![value]).into()
+        }
+        Rule::builtin_pair_phrase => {
+            debug!("{}Depairing builtin_pair_phrase", level_string);
+            let mut pairs: Vec<_> = pair.into_inner().collect();
+            let name = remove(&mut pairs, 0, line, "a built-in name")?
+                .as_str()
+                .to_lowercase();
+            let first = depair_core(remove(&mut pairs, 0, line, "a value")?, level + 1)?.expr()?;
+            let second =
+                depair_core(remove(&mut pairs, 0, line, "a second value")?, level + 1)?.expr()?;
+            Expression::Call(name, vec![first, second]).into()
+        }
+        Rule::concatenation => {
+            debug!("{}Depairing concatenation", level_string);
+            let parts = match depair(&mut pair.into_inner(), level + 1)? {
+                Item::Symbol(SymbolType::ExpressionList(parts)) => parts,
+                item => vec![item.expr()?],
+            };
+            Expression::Concatenation(parts).into()
+        }
+        Rule::number_from => {
+            let digits = depair(&mut pair.into_inner(), level + 1)?.expr()?;
+            Expression::Call("from_digits".to_string(), vec![digits]).into()
+        }
+        Rule::first_or_last => {
+            debug!("{}Depairing first_or_last", level_string);
+            let first = pair
+                .as_str()
+                .split_whitespace()
+                .nth(1)
+                .map(str::to_lowercase)
+                .as_deref()
+                == Some("first");
+            let mut items = depair_seq(&mut pair.into_inner(), level + 1)?;
+            let count = remove(&mut items, 0, line, "a count")?.expr()?;
+            let value = remove(&mut items, 0, line, "a string or array")?.expr()?;
+            let name = if first { "first" } else { "last" };
+            Expression::Call(name.to_string(), vec![value, count]).into()
+        }
+        Rule::count_of => {
+            debug!("{}Depairing count_of", level_string);
+            let mut items = depair_seq(&mut pair.into_inner(), level + 1)?;
+            let needle = remove(&mut items, 0, line, "a value to count")?.expr()?;
+            let haystack = remove(&mut items, 0, line, "a string or array")?.expr()?;
+            Expression::Call("count".to_string(), vec![haystack, needle]).into()
+        }
+        Rule::random_number => {
+            // The only thing inside is the range, if there is one
+            let mut bounds = vec![];
+            for range in pair.into_inner() {
+                for item in depair_seq(&mut range.into_inner(), level + 1)? {
+                    bounds.push(item.expr()?);
+                }
+            }
+            Expression::Call("random".to_string(), bounds).into()
+        }
+        Rule::empty_collection => {
+            let target = depair(&mut pair.into_inner(), level + 1)?.expr()?;
+            CommandLine {
+                cmd: Command::Empty { target },
+                line,
+            }
+            .into()
+        }
+        Rule::flip_sign => {
+            let target = depair(&mut pair.into_inner(), level + 1)?.expr()?;
+            CommandLine {
+                cmd: Command::Negate { target },
+                line,
+            }
+            .into()
+        }
+        Rule::raw_output => {
+            let value = depair(&mut pair.into_inner(), level + 1)?.expr()?;
+            CommandLine {
+                cmd: Command::Write { value },
+                line,
+            }
+            .into()
+        }
+        Rule::sleep => {
+            let seconds = depair(&mut pair.into_inner(), level + 1)?.expr()?;
+            CommandLine {
+                cmd: Command::Sleep { seconds },
+                line,
+            }
+            .into()
+        }
+        Rule::throw => {
+            let value = depair(&mut pair.into_inner(), level + 1)?.expr()?;
+            CommandLine {
+                cmd: Command::Throw { value },
+                line,
+            }
+            .into()
+        }
+        Rule::array_remove => {
+            debug!("{}Depairing array_remove", level_string);
+            let mut items = depair_seq(&mut pair.into_inner(), level + 1)?;
+            let target = remove(&mut items, 0, line, "an array")?.expr()?;
+            let index = remove(&mut items, 0, line, "an index")?.expr()?;
+            let removed = match items.pop() {
+                Some(item) => Some(item.expr()?),
+                None => None,
+            };
+            CommandLine {
+                cmd: Command::Remove {
+                    target,
+                    index,
+                    removed,
+                },
+                line,
+            }
+            .into()
+        }
+        Rule::array_insert => {
+            debug!("{}Depairing array_insert", level_string);
+            let mut items = depair_seq(&mut pair.into_inner(), level + 1)?;
+            let value = remove(&mut items, 0, line, "a value")?.expr()?;
+            let target = remove(&mut items, 0, line, "a target")?.expr()?;
+            let index = remove(&mut items, 0, line, "an index")?.expr()?;
+            CommandLine {
+                cmd: Command::Insert {
+                    value,
+                    target,
+                    index,
+                },
+                line,
+            }
+            .into()
+        }
+        Rule::array_push => {
+            debug!("{}Depairing array_push", level_string);
+            let mut items = depair_seq(&mut pair.into_inner(), level + 1)?;
+            let target = remove(&mut items, 0, line, "a target")?.expr()?;
+            let values = match items.pop() {
+                None => vec![],
+                Some(Item::Symbol(SymbolType::ExpressionList(values))) => values,
+                Some(item) => vec![item.expr()?],
+            };
+            CommandLine {
+                cmd: Command::Push { target, values },
+                line,
+            }
+            .into()
+        }
+        Rule::array_enqueue => {
+            debug!("{}Depairing array_enqueue", level_string);
+            let mut items = depair_seq(&mut pair.into_inner(), level + 1)?;
+            let value = remove(&mut items, 0, line, "a value")?.expr()?;
+            let target = remove(&mut items, 0, line, "a queue")?.expr()?;
+            CommandLine {
+                cmd: Command::Push {
+                    target,
+                    values: vec![value],
+                },
+                line,
+            }
+            .into()
+        }
+        Rule::array_dequeue => {
+            debug!("{}Depairing array_dequeue", level_string);
+            let mut items = depair_seq(&mut pair.into_inner(), level + 1)?;
+            let target = remove(&mut items, 0, line, "a queue")?.expr()?;
+            let removed = match items.pop() {
+                Some(item) => Some(item.expr()?),
+                None => None,
+            };
+            CommandLine {
+                cmd: Command::Dequeue { target, removed },
+                line,
+            }
+            .into()
+        }
         Rule::modifier => {
             debug!("{}Depairing modifier", level_string);
             Expression::Modifier(Box::new(depair(&mut pair.into_inner(), level + 1)?.expr()?))
@@ -829,7 +1754,7 @@ fn depair_core(pair: Pair<'_, Rule>, level: usize) -> Result<Item> {
         Rule::mutation => {
             debug!("{}Depairing mutation", level_string);
             let mut items = depair_seq(&mut pair.into_inner(), level + 1)?;
-            let mutator = remove(&mut items, 0, line)?.symbol()?;
+            let mutator = remove(&mut items, 0, line, "a mutation keyword")?.symbol()?;
             let mut count = items.len();
             let modifier =
                 if let Some(Item::Expression(Expression::Modifier(changer))) = items.last() {
@@ -845,11 +1770,11 @@ fn depair_core(pair: Pair<'_, Rule>, level: usize) -> Result<Item> {
                 1 => {
                     source = None;
                     target = None;
-                    lookup = Some(Box::new(remove(&mut items, 0, line)?.expr()?));
+                    lookup = Some(Box::new(remove(&mut items, 0, line, "a variable")?.expr()?));
                 }
                 2 => {
-                    source = Some(Box::new(remove(&mut items, 0, line)?.expr()?));
-                    target = Some(Box::new(remove(&mut items, 0, line)?.expr()?));
+                    source = Some(Box::new(remove(&mut items, 0, line, "a source")?.expr()?));
+                    target = Some(Box::new(remove(&mut items, 0, line, "a target")?.expr()?));
                     lookup = None;
                 }
                 _ => {
@@ -917,13 +1842,26 @@ where
             debug!("{}Empty", level_string);
             Ok(SymbolType::Empty.into())
         }
-        1 => Ok(remove(&mut items, 0, line)?),
+        1 => Ok(remove(&mut items, 0, line, "an item")?),
         _ => {
             panic!("{}Many! {:?}", level_string, items);
         }
     }
 }
 
+// From "rounded", "rounded up", "rounded down" or "truncated"
+fn rounding_mode(suffix: &str) -> Rounding {
+    if suffix.starts_with("truncated") {
+        Rounding::Truncate
+    } else if suffix.ends_with("up") {
+        Rounding::Up
+    } else if suffix.ends_with("down") {
+        Rounding::Down
+    } else {
+        Rounding::Nearest
+    }
+}
+
 fn depair_seq<'i, I>(pairs: &'i mut I, level: usize) -> Result<Vec<Item>>
 where
     I: Iterator<Item = pest::iterators::Pair<'i, Rule>>,
@@ -935,19 +1873,169 @@ where
     return Ok(items);
 }
 
+const NUMBER_UNITS: [&str; 20] = [
+    "zero",
+    "one",
+    "two",
+    "three",
+    "four",
+    "five",
+    "six",
+    "seven",
+    "eight",
+    "nine",
+    "ten",
+    "eleven",
+    "twelve",
+    "thirteen",
+    "fourteen",
+    "fifteen",
+    "sixteen",
+    "seventeen",
+    "eighteen",
+    "nineteen",
+];
+const NUMBER_TENS: [&str; 8] = [
+    "twenty", "thirty", "forty", "fifty", "sixty", "seventy", "eighty", "ninety",
+];
+
+fn number_word(word: &str) -> Option<u32> {
+    let lower = word.to_lowercase();
+    if let Some(units) = NUMBER_UNITS.iter().position(|u| *u == lower) {
+        return Some(units as u32);
+    }
+    let (tens, units) = match lower.split_once('-') {
+        Some((tens, units)) => (tens, Some(units)),
+        None => (lower.as_str(), None),
+    };
+    let tens = NUMBER_TENS.iter().position(|t| *t == tens)? as u32 * 10 + 20;
+    match units {
+        None => Some(tens),
+        Some(units) => {
+            let units = NUMBER_UNITS[1..10].iter().position(|u| *u == units)? as u32 + 1;
+            Some(tens + units)
+        }
+    }
+}
+
+// Rewrites spelled-out numbers ("five", "twenty-one") into digits before parsing.
+// This runs before the grammar sees anything, so with it enabled "My heart is five"
+// is the literal 5 rather than the poetic number 4, and a variable can't be called "Five".
+// Text inside strings and comments is left alone.
+pub fn expand_number_words(buffer: &str) -> String {
+    fn flush(res: &mut String, word: &mut String) {
+        match number_word(word) {
+            Some(number) => res.push_str(&number.to_string()),
+            None => res.push_str(word),
+        }
+        word.clear();
+    }
+
+    let mut res = String::with_capacity(buffer.len());
+    let mut word = String::new();
+    let mut in_string = false;
+    // Comments can nest, so this is how many are open
+    let mut comment_depth = 0;
+    for c in buffer.chars() {
+        if !in_string && comment_depth == 0 && (c.is_alphabetic() || (c == '-' && !word.is_empty()))
+        {
+            word.push(c);
+            continue;
+        }
+        flush(&mut res, &mut word);
+        match c {
+            '"' if comment_depth == 0 => in_string = !in_string,
+            '(' if !in_string => comment_depth += 1,
+            ')' if !in_string && comment_depth > 0 => comment_depth -= 1,
+            _ => {}
+        }
+        res.push(c);
+    }
+    flush(&mut res, &mut word);
+    res
+}
+
+// Every Restart has to be inside a loop with its label. Function bodies can't restart the loops
+// they're called from, so they start again with no labels.
+fn check_labels(commands: &[CommandLine], labels: &mut Vec<String>) -> Result<()> {
+    for command in commands {
+        for expression in command.cmd.expressions() {
+            check_lambda_labels(expression)?;
+        }
+        match &command.cmd {
+            Command::Restart { label } if !labels.contains(label) => {
+                return Err(MaidenError::UnknownLabel {
+                    label: label.clone(),
+                    line: command.line,
+                });
+            }
+            Command::While { block, label, .. }
+            | Command::Until { block, label, .. }
+            | Command::Repeat { block, label, .. } => {
+                labels.extend(label.iter().cloned());
+                check_labels(&block.commands, labels)?;
+                if label.is_some() {
+                    labels.pop();
+                }
+            }
+            Command::FunctionDeclaration { block, .. } => {
+                check_labels(&block.commands, &mut vec![])?;
+            }
+            other => {
+                for block in other.blocks() {
+                    check_labels(&block.commands, labels)?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+fn check_lambda_labels(expression: &Expression) -> Result<()> {
+    if let Expression::Lambda { block, .. } = expression {
+        check_labels(&block.commands, &mut vec![])?;
+    }
+    for child in expression.children() {
+        check_lambda_labels(child)?;
+    }
+    Ok(())
+}
+
 pub fn parse(buffer: &str) -> Result<Program> {
     let mut parsed =
         Rockstar::parse(Rule::program, buffer).map_err(|e| MaidenError::Pest { kind: e })?;
-    return depair_program(&mut parsed, buffer);
+    let program = depair_program(&mut parsed, buffer)?;
+    check_labels(&program.commands, &mut vec![])?;
+    return Ok(program);
+}
+
+// Parses another file's source as a command to include at line, so its commands keep their own
+// line numbers and errors in them (parsing or running) say which file they came from
+pub fn parse_include(file: &str, buffer: &str, line: usize) -> Result<CommandLine> {
+    let program = parse(buffer).map_err(|error| MaidenError::InFile {
+        file: file.to_string(),
+        error: Box::new(error),
+    })?;
+    return Ok(CommandLine {
+        cmd: Command::Include {
+            file: file.to_string(),
+            block: Block {
+                commands: program.commands,
+            },
+        },
+        line,
+    });
 }
 
 #[cfg(test)]
 mod tests {
-    use std::collections::HashMap;
+    use std::collections::BTreeMap;
 
-    use crate::common::{Command, CommandLine, Expression, Program};
+    use crate::common::{
+        BitwiseOp, Block, Command, CommandLine, Expression, ListenType, Program, Rounding, TypeName,
+    };
 
-    use super::{parse, MaidenError};
+    use super::{expand_number_words, parse, remove, MaidenError};
 
     #[test]
     fn end_of_if() {
@@ -959,6 +2047,140 @@ mod tests {
         }
     }
 
+    #[test]
+    fn multiple_return() {
+        let returned = |code: &str| {
+            let program = parse(&format!("Midnight takes your heart\n{}\n\n", code)).unwrap();
+            if let Command::FunctionDeclaration { block, .. } = &program.commands[0].cmd {
+                if let Command::Return { return_value } = &block.commands[0].cmd {
+                    return return_value.clone();
+                }
+            }
+            panic!("{:?}", program);
+        };
+        let heart = || Expression::Variable("your heart".to_string());
+        assert_eq!(
+            returned("Give back your heart, 2, and \"three\""),
+            Expression::List(vec![
+                heart(),
+                Expression::Floating(2.0),
+                Expression::String("three".to_string())
+            ])
+        );
+        assert_eq!(
+            returned("Give back your heart,2"),
+            Expression::List(vec![heart(), Expression::Floating(2.0)])
+        );
+        assert_eq!(
+            returned("Give back your heart and 2"),
+            Expression::And(Box::new(heart()), Box::new(Expression::Floating(2.0)))
+        );
+    }
+
+    #[test]
+    fn pipelines() {
+        let commands = |code| -> Vec<Command> {
+            parse(code)
+                .unwrap()
+                .commands
+                .into_iter()
+                .map(|command| command.cmd)
+                .collect()
+        };
+        assert_eq!(
+            commands("\"a,b\" then split with \",\" then join using \"-\" into my result\n"),
+            commands("Split \"a,b\" into my result with \",\"\nJoin my result with \"-\"\n")
+        );
+        let program =
+            parse("Midnight takes my text\nMy text then cut then unite into my text\nGive back my text\n\n")
+                .unwrap();
+        if let Command::FunctionDeclaration { block, .. } = &program.commands[0].cmd {
+            assert_eq!(block.commands.len(), 3);
+        } else {
+            panic!("{:?}", program);
+        }
+    }
+
+    #[test]
+    fn unless_blocks() {
+        for (unless, negated) in [
+            (
+                "Unless my heart\nSay 1\nElse\nSay 2\n",
+                "If not my heart\nSay 1\nElse\nSay 2\n",
+            ),
+            (
+                "unless my heart is 5\nSay 1\n",
+                "If not my heart is 5\nSay 1\n",
+            ),
+        ] {
+            assert_eq!(
+                parse(unless).unwrap().commands,
+                parse(negated).unwrap().commands,
+                "{}",
+                unless
+            );
+        }
+    }
+
+    #[test]
+    fn nested_comments() {
+        let program = parse("Say 1 (this (nested) note)\nSay 2 ((doubly) (nested))\n").unwrap();
+        assert_eq!(program.commands.len(), 2);
+        assert_eq!(
+            program.commands[1].cmd,
+            Command::Say {
+                value: Expression::Floating(2.0)
+            }
+        );
+        match parse("Say 1 (this (unclosed note)\n") {
+            Err(MaidenError::Pest { .. }) => {}
+            other => panic!("{:?}", other),
+        }
+        assert_eq!(
+            expand_number_words("(one (two) three) four"),
+            "(one (two) three) 4"
+        );
+    }
+
+    #[test]
+    fn comment_line_ends_block() {
+        let program = parse(
+            "Midnight takes your heart\nGive back your heart\n  (end of function)  \nSay Midnight taking 2\n",
+        )
+        .unwrap();
+        assert_eq!(program.commands.len(), 2);
+        if let Command::FunctionDeclaration { block, .. } = &program.commands[0].cmd {
+            assert_eq!(block.commands.len(), 1);
+        } else {
+            panic!("{:?}", program);
+        }
+        assert_eq!(program.commands[1].line, 4);
+    }
+
+    #[test]
+    fn incomplete_messages() {
+        // As raised at the end of "X plus" and of "Midnight taking"
+        for (expected, message) in [
+            (
+                "a right-hand operand",
+                "Expected a right-hand operand, but didn't get one",
+            ),
+            (
+                "a function argument",
+                "Expected a function argument, but didn't get one",
+            ),
+        ] {
+            let err = remove::<u8>(&mut vec![], 0, 4, expected).unwrap_err();
+            assert_eq!(err.to_string(), message);
+            if let MaidenError::Incomplete { line, .. } = err {
+                assert_eq!(line, 4);
+            } else {
+                panic!("{:?}", err);
+            }
+        }
+        assert_eq!(remove(&mut vec![1, 2], 1, 4, "a value").unwrap(), 2);
+    }
+
     #[test]
     fn hyphens_in_numbers() {
         let ok = parse("Belle was a be-our-guest intellectual");
@@ -972,8 +2194,1146 @@ mod tests {
                     },
                     line: 1,
                 },],
-                functions: HashMap::new(),
+                functions: BTreeMap::new(),
             }
         );
     }
+
+    fn assigned_value(code: &str) -> Expression {
+        let program = parse(code).unwrap();
+        if let Command::Assignment { value, .. } = &program.commands[0].cmd {
+            *value.clone()
+        } else {
+            panic!("{:?}", program);
+        }
+    }
+
+    #[test]
+    fn compound_assignment() {
+        let heart = || Box::new(Expression::Variable("my heart".to_string()));
+        let two = || Box::new(Expression::Floating(2.0));
+        assert_eq!(
+            assigned_value("Let my heart be with 2"),
+            Expression::Add(heart(), two())
+        );
+        assert_eq!(
+            assigned_value("Let my heart be without 2"),
+            Expression::Subtract(heart(), two())
+        );
+        assert_eq!(
+            assigned_value("Let my heart be of 2"),
+            Expression::Times(heart(), two())
+        );
+        assert_eq!(
+            assigned_value("Let my heart be over 2"),
+            Expression::Divide(heart(), two())
+        );
+        // The other names for each operator work the same way
+        assert_eq!(
+            assigned_value("Let my heart be plus 2"),
+            Expression::Add(heart(), two())
+        );
+        assert_eq!(
+            assigned_value("Let my heart be minus 2"),
+            Expression::Subtract(heart(), two())
+        );
+        assert_eq!(
+            assigned_value("Let my heart be times 2"),
+            Expression::Times(heart(), two())
+        );
+        assert_eq!(
+            assigned_value("Let my heart be modulo 2"),
+            Expression::Modulo(heart(), two())
+        );
+        // There's no separate "X times Y into Z" form: that's a Put of a binary expression
+        assert_eq!(
+            assigned_value("Put my heart times 2 into my soul"),
+            Expression::Times(heart(), two())
+        );
+        // Lists apply the operator to each item in turn
+        assert_eq!(
+            assigned_value("Let my heart be with 2, 3"),
+            Expression::Add(
+                Box::new(Expression::Add(heart(), two())),
+                Box::new(Expression::Floating(3.0))
+            )
+        );
+    }
+
+    #[test]
+    fn guarded_assignment() {
+        let assignment = |code| {
+            let program = parse(code).unwrap();
+            program.commands[0].cmd.clone()
+        };
+        let guarded = |expression| Command::If {
+            expression,
+            then: Some(Block {
+                commands: vec![CommandLine {
+                    cmd: Command::Assignment {
+                        target: Box::new(Expression::Variable("my heart".to_string())),
+                        value: Box::new(Expression::Floating(1.0)),
+                    },
+                    line: 1,
+                }],
+            }),
+            otherwise: None,
+        };
+        let soul = || Expression::Variable("my soul".to_string());
+        assert_eq!(
+            assignment("Let my heart be 1 unless my soul"),
+            guarded(Expression::Not(Box::new(soul())))
+        );
+        assert_eq!(
+            assignment("Put 1 into my heart if my soul"),
+            guarded(soul())
+        );
+    }
+
+    #[test]
+    fn guarded_statement() {
+        let first = |code: &str| parse(code).unwrap().commands[0].cmd.clone();
+        let soul = || Expression::Variable("my soul".to_string());
+        let guarded = |expression, cmd| Command::If {
+            expression,
+            then: Some(Block {
+                commands: vec![CommandLine { cmd, line: 1 }],
+            }),
+            otherwise: None,
+        };
+        let say = || Command::Say {
+            value: Expression::Variable("my heart".to_string()),
+        };
+        assert_eq!(first("Say my heart if my soul"), guarded(soul(), say()));
+        assert_eq!(
+            first("Say my heart unless my soul"),
+            guarded(Expression::Not(Box::new(soul())), say())
+        );
+        assert_eq!(
+            first("Build my heart up if my soul"),
+            guarded(
+                soul(),
+                Command::Increment {
+                    target: Expression::Variable("my heart".to_string()),
+                    count: 1.0,
+                }
+            )
+        );
+        assert_eq!(first("Break if my soul"), guarded(soul(), Command::Break));
+    }
+
+    #[test]
+    fn loop_condition_phrasings() {
+        let first = |code: &str| parse(code).unwrap().commands[0].cmd.clone();
+        for (phrased, bare) in [
+            (
+                "While my heart stays true\nSay 1\n",
+                "While my heart\nSay 1\n",
+            ),
+            (
+                "Until my heart stays false\nSay 1\n",
+                "Until not my heart\nSay 1\n",
+            ),
+            (
+                "While my heart stays wrong\nSay 1\n",
+                "While not my heart\nSay 1\n",
+            ),
+        ] {
+            assert_eq!(first(phrased), first(bare), "{}", phrased);
+        }
+        // "is" still compares, so mysterious or array values don't change meaning
+        assert_ne!(
+            first("While my heart is true\nSay 1\n"),
+            first("While my heart\nSay 1\n")
+        );
+        assert_ne!(
+            first("Until my heart is false\nSay 1\n"),
+            first("Until not my heart\nSay 1\n")
+        );
+        assert_eq!(
+            first("While my heart is 1\nSay 1\n"),
+            Command::While {
+                expression: Expression::Is(
+                    Box::new(Expression::Variable("my heart".to_string())),
+                    Box::new(Expression::Floating(1.0))
+                ),
+                block: Block {
+                    commands: vec![CommandLine {
+                        cmd: Command::Say {
+                            value: Expression::Floating(1.0)
+                        },
+                        line: 2
+                    }]
+                },
+                label: None
+            }
+        );
+    }
+
+    #[test]
+    fn labelled_loops() {
+        let program = parse("Outer: Repeat 2 times\nRestart Outer\n").unwrap();
+        assert_eq!(
+            program.commands[0].cmd,
+            Command::Repeat {
+                count: Expression::Floating(2.0),
+                block: Block {
+                    commands: vec![CommandLine {
+                        cmd: Command::Restart {
+                            label: "outer".to_string()
+                        },
+                        line: 2
+                    }]
+                },
+                label: Some("outer".to_string())
+            }
+        );
+
+        for code in [
+            "Restart outer\n",
+            "Outer: While right\nBreak\n\nRestart outer\n",
+            "Outer: While right\nMidnight takes my heart\nRestart outer\n",
+        ] {
+            match parse(code) {
+                Err(MaidenError::UnknownLabel { label, .. }) => assert_eq!(label, "outer"),
+                other => panic!("{}: {:?}", code, other),
+            }
+        }
+    }
+
+    #[test]
+    fn bare_copula() {
+        let heart = || Box::new(Expression::Variable("my heart".to_string()));
+        let five = || Box::new(Expression::Floating(5.0));
+        let program = parse("My heart is 5\nIf my heart is 5\nSay my heart\n").unwrap();
+        assert_eq!(
+            program.commands[0].cmd,
+            Command::Assignment {
+                target: heart(),
+                value: five(),
+            }
+        );
+        if let Command::If { expression, .. } = &program.commands[1].cmd {
+            assert_eq!(*expression, Expression::Is(heart(), five()));
+        } else {
+            panic!("{:?}", program);
+        }
+    }
+
+    #[test]
+    fn modulo_phrasings() {
+        let expected = Expression::Modulo(
+            Box::new(Expression::Variable("my heart".to_string())),
+            Box::new(Expression::Floating(3.0)),
+        );
+        assert_eq!(
+            assigned_value("Put my heart modulo 3 into my soul"),
+            expected
+        );
+        assert_eq!(
+            assigned_value("Put the remainder of my heart over 3 into my soul"),
+            expected
+        );
+    }
+
+    #[test]
+    fn power_and_modulo_precedence() {
+        let number = |n| Box::new(Expression::Floating(n));
+        let squared = |n| Box::new(Expression::Power(number(n), number(2.0)));
+        assert_eq!(
+            assigned_value("Let my heart be 2 plus 3 times 4 squared"),
+            Expression::Add(
+                number(2.0),
+                Box::new(Expression::Times(number(3.0), squared(4.0)))
+            )
+        );
+        assert_eq!(
+            assigned_value("Let my heart be 10 modulo 3 squared plus 1"),
+            Expression::Add(
+                Box::new(Expression::Modulo(number(10.0), squared(3.0))),
+                number(1.0)
+            )
+        );
+        assert_eq!(
+            assigned_value("Let my heart be 2 squared times 3 modulo 5"),
+            Expression::Modulo(
+                Box::new(Expression::Times(squared(2.0), number(3.0))),
+                number(5.0)
+            )
+        );
+        assert_eq!(
+            assigned_value("Let my heart be 7 minus 8 modulo 3 times 2"),
+            Expression::Subtract(
+                number(7.0),
+                Box::new(Expression::Times(
+                    Box::new(Expression::Modulo(number(8.0), number(3.0))),
+                    number(2.0)
+                ))
+            )
+        );
+    }
+
+    #[test]
+    fn multiple_assignment() {
+        let program = parse("Put 7 into my heart and into my list at 0").unwrap();
+        assert_eq!(
+            program.commands[0].cmd,
+            Command::MultipleAssignment {
+                targets: vec![
+                    Expression::Variable("my heart".to_string()),
+                    Expression::ArrayRef {
+                        name: Box::new(Expression::Variable("my list".to_string())),
+                        index: Box::new(Expression::Floating(0.0)),
+                    },
+                ],
+                value: Box::new(Expression::Floating(7.0)),
+            }
+        );
+    }
+
+    #[test]
+    fn inclusive_slice() {
+        assert_eq!(
+            assigned_value("Put my list at 1 through my heart into my soul"),
+            Expression::Slice(
+                Box::new(Expression::Variable("my list".to_string())),
+                Box::new(Expression::Floating(1.0)),
+                Box::new(Expression::Variable("my heart".to_string())),
+            )
+        );
+    }
+
+    #[test]
+    fn between() {
+        assert_eq!(
+            assigned_value("Put my heart is between 1 and my soul into the answer"),
+            Expression::Between(
+                Box::new(Expression::Variable("my heart".to_string())),
+                Box::new(Expression::Floating(1.0)),
+                Box::new(Expression::Variable("my soul".to_string())),
+            )
+        );
+    }
+
+    #[test]
+    fn has_key() {
+        assert_eq!(
+            assigned_value("Put my map has key \"rock\" into the answer"),
+            Expression::HasKey(
+                Box::new(Expression::Variable("my map".to_string())),
+                Box::new(Expression::String("rock".to_string())),
+            )
+        );
+    }
+
+    #[test]
+    fn default_arguments() {
+        let program = parse(
+            "Midnight takes your heart and your soul (where your soul is 1)\nGive back your soul\n",
+        )
+        .unwrap();
+        if let Command::FunctionDeclaration { args, defaults, .. } = &program.commands[0].cmd {
+            assert_eq!(
+                args,
+                &vec!["your heart".to_string(), "your soul".to_string()]
+            );
+            assert_eq!(defaults, &vec![Expression::Floating(1.0)]);
+        } else {
+            panic!("{:?}", program.commands);
+        }
+
+        let err = parse(
+            "Midnight takes your heart and your soul (where your heart is 1)\nGive back your soul\n",
+        );
+        if let Err(MaidenError::BadArgument { name, line, .. }) = err {
+            assert_eq!(name, "Midnight");
+            assert_eq!(line, 1);
+        } else {
+            panic!("{:?}", err);
+        }
+    }
+
+    #[test]
+    fn rest_parameter() {
+        let program =
+            parse("Midnight takes your heart and the rest\nGive back the rest\n").unwrap();
+        if let Command::FunctionDeclaration { args, rest, .. } = &program.commands[0].cmd {
+            assert_eq!(args, &vec!["your heart".to_string()]);
+            assert_eq!(rest, &Some("the rest".to_string()));
+        } else {
+            panic!("{:?}", program.commands);
+        }
+    }
+
+    #[test]
+    fn type_tests() {
+        for (code, type_name) in [
+            ("Put my heart is a number into my soul", TypeName::Number),
+            ("Put my heart is a string into my soul", TypeName::String),
+            ("Put my heart is an array into my soul", TypeName::Array),
+            ("Put my heart is a boolean into my soul", TypeName::Boolean),
+        ] {
+            assert_eq!(
+                assigned_value(code),
+                Expression::IsType(
+                    Box::new(Expression::Variable("my heart".to_string())),
+                    type_name
+                )
+            );
+        }
+    }
+
+    #[test]
+    fn joined_say() {
+        let program =
+            parse("Say my heart and \"soul\" and 3\nSay my heart is 1 and my soul is 2\n").unwrap();
+        let values: Vec<_> = program
+            .commands
+            .into_iter()
+            .map(|command| match command.cmd {
+                Command::Say { value } => value,
+                other => panic!("{:?}", other),
+            })
+            .collect();
+        assert_eq!(
+            values[0],
+            Expression::Sentence(vec![
+                Expression::Variable("my heart".to_string()),
+                Expression::String("soul".to_string()),
+                Expression::Floating(3.0),
+            ])
+        );
+        assert!(matches!(values[1], Expression::And(..)), "{:?}", values[1]);
+    }
+
+    #[test]
+    fn increase_and_decrease_by() {
+        let program = parse("Increase my heart by 5\nDecrease the night by 0.5\n").unwrap();
+        assert_eq!(
+            program.commands,
+            vec![
+                CommandLine {
+                    cmd: Command::Increment {
+                        target: Expression::Variable("my heart".to_string()),
+                        count: 5.0,
+                    },
+                    line: 1
+                },
+                CommandLine {
+                    cmd: Command::Decrement {
+                        target: Expression::Variable("the night".to_string()),
+                        count: 0.5,
+                    },
+                    line: 2
+                }
+            ]
+        );
+    }
+
+    #[test]
+    fn try_recover() {
+        let program = parse("Try\nSay my heart\nRecover with the pain\nSay the pain\n").unwrap();
+        assert_eq!(
+            program.commands,
+            vec![CommandLine {
+                cmd: Command::Try {
+                    body: Block {
+                        commands: vec![CommandLine {
+                            cmd: Command::Say {
+                                value: Expression::Variable("my heart".to_string())
+                            },
+                            line: 2
+                        }]
+                    },
+                    error_var: Some("the pain".to_string()),
+                    recover: Block {
+                        commands: vec![CommandLine {
+                            cmd: Command::Say {
+                                value: Expression::Variable("the pain".to_string())
+                            },
+                            line: 4
+                        }]
+                    },
+                },
+                line: 1
+            }]
+        );
+    }
+
+    #[test]
+    fn throw() {
+        let program = parse("Throw \"no\" with my heart").unwrap();
+        assert_eq!(
+            program.commands,
+            vec![CommandLine {
+                cmd: Command::Throw {
+                    value: Expression::Add(
+                        Box::new(Expression::String("no".to_string())),
+                        Box::new(Expression::Variable("my heart".to_string()))
+                    )
+                },
+                line: 1
+            }]
+        );
+    }
+
+    #[test]
+    fn rounded_assignment() {
+        for (code, rounding) in [
+            ("Let my heart be my soul rounded", Rounding::Nearest),
+            ("Let my heart be my soul rounded up", Rounding::Up),
+            ("Let my heart be my soul rounded down", Rounding::Down),
+            ("Let my heart be my soul truncated", Rounding::Truncate),
+        ] {
+            assert_eq!(
+                assigned_value(code),
+                Expression::Rounded(
+                    Box::new(Expression::Variable("my soul".to_string())),
+                    rounding
+                ),
+                "{}",
+                code
+            );
+        }
+        assert_eq!(
+            assigned_value("Let my heart be 5 over 2 rounded"),
+            Expression::Rounded(
+                Box::new(Expression::Divide(
+                    Box::new(Expression::Floating(5.0)),
+                    Box::new(Expression::Floating(2.0))
+                )),
+                Rounding::Nearest
+            )
+        );
+    }
+
+    #[test]
+    fn rounding_modifier() {
+        let soul = || Box::new(Expression::Variable("my soul".to_string()));
+        assert_eq!(
+            assigned_value("Put my soul into my heart, rounding down"),
+            Expression::Rounded(soul(), Rounding::Down)
+        );
+        assert_eq!(
+            assigned_value("Let my heart be my soul, rounding to nearest"),
+            Expression::Rounded(soul(), Rounding::Nearest)
+        );
+        let program = parse("Say my soul, rounding up").unwrap();
+        assert_eq!(
+            program.commands,
+            vec![CommandLine {
+                cmd: Command::Say {
+                    value: Expression::Rounded(soul(), Rounding::Up)
+                },
+                line: 1
+            }]
+        );
+    }
+
+    #[test]
+    fn flip_sign() {
+        let program = parse("Flip the sign of my heart").unwrap();
+        assert_eq!(
+            program.commands,
+            vec![CommandLine {
+                cmd: Command::Negate {
+                    target: Expression::Variable("my heart".to_string())
+                },
+                line: 1
+            }]
+        );
+    }
+
+    #[test]
+    fn array_remove() {
+        let program = parse("Remove the songs at 2 into my heart\nRemove it at 0").unwrap();
+        let commands: Vec<_> = program.commands.into_iter().map(|c| c.cmd).collect();
+        assert_eq!(
+            commands,
+            vec![
+                Command::Remove {
+                    target: Expression::Variable("the songs".to_string()),
+                    index: Expression::Floating(2.0),
+                    removed: Some(Expression::Variable("my heart".to_string())),
+                },
+                Command::Remove {
+                    target: Expression::Pronoun,
+                    index: Expression::Floating(0.0),
+                    removed: None,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn array_insert() {
+        let program = parse("Insert \"solo\" into the songs at 1 plus 1").unwrap();
+        assert_eq!(
+            program.commands[0].cmd,
+            Command::Insert {
+                value: Expression::String("solo".to_string()),
+                target: Expression::Variable("the songs".to_string()),
+                index: Expression::Add(
+                    Box::new(Expression::Floating(1.0)),
+                    Box::new(Expression::Floating(1.0))
+                ),
+            }
+        );
+    }
+
+    #[test]
+    fn copy_into() {
+        let program = parse("Copy the original into the copy at 2").unwrap();
+        assert_eq!(
+            program.commands[0].cmd,
+            Command::Assignment {
+                target: Box::new(Expression::ArrayRef {
+                    name: Box::new(Expression::Variable("the copy".to_string())),
+                    index: Box::new(Expression::Floating(2.0)),
+                }),
+                value: Box::new(Expression::Variable("the original".to_string())),
+            }
+        );
+    }
+
+    #[test]
+    fn blank_output() {
+        let program = parse("Say a newline\nShout a blank line\nSay a newline with 1").unwrap();
+        let values: Vec<_> = program
+            .commands
+            .into_iter()
+            .map(|command| match command.cmd {
+                Command::Say { value } => value,
+                other => panic!("{:?}", other),
+            })
+            .collect();
+        assert_eq!(values[0], Expression::String(String::new()));
+        assert_eq!(values[1], Expression::String(String::new()));
+        // Only on its own, so otherwise it's still a variable
+        assert_eq!(
+            values[2],
+            Expression::Add(
+                Box::new(Expression::Variable("a newline".to_string())),
+                Box::new(Expression::Floating(1.0))
+            )
+        );
+    }
+
+    #[test]
+    fn empty_collection() {
+        let program = parse("Empty my list\nEmpty it").unwrap();
+        let commands: Vec<_> = program.commands.into_iter().map(|c| c.cmd).collect();
+        assert_eq!(
+            commands,
+            vec![
+                Command::Empty {
+                    target: Expression::Variable("my list".to_string())
+                },
+                Command::Empty {
+                    target: Expression::Pronoun
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn destructuring_listen() {
+        let program = parse("Listen to my name and your name and the rest").unwrap();
+        assert_eq!(
+            program.commands[0].cmd,
+            Command::Listen {
+                targets: vec![
+                    "my name".to_string(),
+                    "your name".to_string(),
+                    "the rest".to_string()
+                ],
+                as_type: ListenType::String,
+            }
+        );
+    }
+
+    #[test]
+    fn typed_listen() {
+        let program = parse("Listen to your heart as a number\nListen to my soul").unwrap();
+        let types: Vec<_> = program
+            .commands
+            .iter()
+            .map(|command| command.cmd.clone())
+            .collect();
+        assert_eq!(
+            types,
+            vec![
+                Command::Listen {
+                    targets: vec!["your heart".to_string()],
+                    as_type: ListenType::Number,
+                },
+                Command::Listen {
+                    targets: vec!["my soul".to_string()],
+                    as_type: ListenType::String,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn builtin_phrases() {
+        assert_eq!(
+            assigned_value("Put the first 3 of my list into my soul"),
+            Expression::Call(
+                "first".to_string(),
+                vec![
+                    Expression::Variable("my list".to_string()),
+                    Expression::Floating(3.0)
+                ]
+            )
+        );
+        assert_eq!(
+            assigned_value("Put the LAST my heart of my list into my soul"),
+            Expression::Call(
+                "last".to_string(),
+                vec![
+                    Expression::Variable("my list".to_string()),
+                    Expression::Variable("my heart".to_string())
+                ]
+            )
+        );
+        assert_eq!(
+            assigned_value("Put the factorial of my heart into my soul"),
+            Expression::Call(
+                "factorial".to_string(),
+                vec![Expression::Variable("my heart".to_string())]
+            )
+        );
+        assert_eq!(
+            assigned_value("Put the permutations of my heart into my soul"),
+            Expression::Call(
+                "permutations".to_string(),
+                vec![Expression::Variable("my heart".to_string())]
+            )
+        );
+        assert_eq!(
+            assigned_value("Put the sign of my heart into my soul"),
+            Expression::Call(
+                "sign".to_string(),
+                vec![Expression::Variable("my heart".to_string())]
+            )
+        );
+        assert_eq!(
+            assigned_value("Put the reverse of my heart into my soul"),
+            Expression::Call(
+                "reverse".to_string(),
+                vec![Expression::Variable("my heart".to_string())]
+            )
+        );
+        assert_eq!(
+            assigned_value("Put the length of the longest of my list into my soul"),
+            Expression::Call(
+                "length".to_string(),
+                vec![Expression::Call(
+                    "longest".to_string(),
+                    vec![Expression::Variable("my list".to_string())]
+                )]
+            )
+        );
+        assert_eq!(
+            assigned_value("Put the shortest of my list into my soul"),
+            Expression::Call(
+                "shortest".to_string(),
+                vec![Expression::Variable("my list".to_string())]
+            )
+        );
+        assert_eq!(
+            assigned_value("Put the number from my heart into my soul"),
+            Expression::Call(
+                "from_digits".to_string(),
+                vec![Expression::Variable("my heart".to_string())]
+            )
+        );
+        assert_eq!(
+            assigned_value("Put the digits of my heart into my soul"),
+            Expression::Call(
+                "digits".to_string(),
+                vec![Expression::Variable("my heart".to_string())]
+            )
+        );
+        assert_eq!(
+            assigned_value("Put the index of the biggest of my list into my soul"),
+            Expression::Call(
+                "argmax".to_string(),
+                vec![Expression::Variable("my list".to_string())]
+            )
+        );
+        assert_eq!(
+            assigned_value("Put the keys of my map into my soul"),
+            Expression::Call(
+                "keys".to_string(),
+                vec![Expression::Variable("my map".to_string())]
+            )
+        );
+        assert_eq!(
+            assigned_value("Put the values of my map into my soul"),
+            Expression::Call(
+                "values".to_string(),
+                vec![Expression::Variable("my map".to_string())]
+            )
+        );
+        assert_eq!(
+            assigned_value("Put the absolute value of my heart into my soul"),
+            Expression::Call(
+                "abs".to_string(),
+                vec![Expression::Variable("my heart".to_string())]
+            )
+        );
+        assert_eq!(
+            assigned_value("Put the length of \"rock\" into my soul"),
+            Expression::Call(
+                "length".to_string(),
+                vec![Expression::String("rock".to_string())]
+            )
+        );
+        assert_eq!(
+            assigned_value("Put the ceiling of my heart into my soul"),
+            Expression::Call(
+                "ceil".to_string(),
+                vec![Expression::Variable("my heart".to_string())]
+            )
+        );
+        assert_eq!(
+            assigned_value("Put the floor of 2.5 into my soul"),
+            Expression::Call("floor".to_string(), vec![Expression::Floating(2.5)])
+        );
+        assert_eq!(
+            assigned_value("Put the sum of my numbers into my soul"),
+            Expression::Call(
+                "sum".to_string(),
+                vec![Expression::Variable("my numbers".to_string())]
+            )
+        );
+        assert_eq!(
+            assigned_value("Put the Product of my numbers into my soul"),
+            Expression::Call(
+                "product".to_string(),
+                vec![Expression::Variable("my numbers".to_string())]
+            )
+        );
+        for phrase in ["average", "Mean"] {
+            assert_eq!(
+                assigned_value(&format!("Put the {} of my numbers into my soul", phrase)),
+                Expression::Call(
+                    "mean".to_string(),
+                    vec![Expression::Variable("my numbers".to_string())]
+                )
+            );
+        }
+        assert_eq!(
+            assigned_value("Put the code of \"A\" into my soul"),
+            Expression::Call(
+                "code".to_string(),
+                vec![Expression::String("A".to_string())]
+            )
+        );
+        assert_eq!(
+            assigned_value("Put the count of \"a\" in my heart into my soul"),
+            Expression::Call(
+                "count".to_string(),
+                vec![
+                    Expression::Variable("my heart".to_string()),
+                    Expression::String("a".to_string())
+                ]
+            )
+        );
+        assert_eq!(
+            assigned_value("Put the GCD of my heart and 12 into my soul"),
+            Expression::Call(
+                "gcd".to_string(),
+                vec![
+                    Expression::Variable("my heart".to_string()),
+                    Expression::Floating(12.0)
+                ]
+            )
+        );
+        assert_eq!(
+            assigned_value("Put the character of 66 into my soul"),
+            Expression::Call("character".to_string(), vec![Expression::Floating(66.0)])
+        );
+        assert_eq!(
+            assigned_value("Put the square root of 9 into my soul"),
+            Expression::SquareRoot(Box::new(Expression::Floating(9.0)))
+        );
+        assert_eq!(
+            assigned_value("Put the text of my heart into my soul"),
+            Expression::Text(Box::new(Expression::Variable("my heart".to_string())))
+        );
+    }
+
+    #[test]
+    fn function_call_variable_args() {
+        assert_eq!(
+            assigned_value("Put Midnight taking the night, Johnny B Goode into my heart"),
+            Expression::Call(
+                "Midnight".to_string(),
+                vec![
+                    Expression::Variable("the night".to_string()),
+                    Expression::Variable("Johnny B Goode".to_string()),
+                ]
+            )
+        );
+    }
+
+    fn number_word_value(code: &str) -> Expression {
+        assigned_value(&expand_number_words(code))
+    }
+
+    #[test]
+    fn number_words() {
+        assert_eq!(
+            number_word_value("Put five into my heart"),
+            Expression::Floating(5.0)
+        );
+        assert_eq!(
+            number_word_value("Put Twenty-One into my heart"),
+            Expression::Floating(21.0)
+        );
+        assert_eq!(
+            expand_number_words("Say \"five\" (or six) seven someone"),
+            "Say \"five\" (or six) 7 someone"
+        );
+    }
+
+    #[test]
+    fn boolean_precedence() {
+        assert_eq!(
+            assigned_value("Put not wrong and wrong into the result"),
+            Expression::And(
+                Box::new(Expression::Not(Box::new(Expression::False))),
+                Box::new(Expression::False)
+            )
+        );
+        assert_eq!(
+            assigned_value("Put right or wrong and wrong into the result"),
+            Expression::Or(
+                Box::new(Expression::True),
+                Box::new(Expression::And(
+                    Box::new(Expression::False),
+                    Box::new(Expression::False)
+                ))
+            )
+        );
+        assert_eq!(
+            assigned_value("Put not not right into the result"),
+            Expression::Not(Box::new(Expression::Not(Box::new(Expression::True))))
+        );
+    }
+
+    #[test]
+    fn queue_aliases() {
+        let program =
+            parse("Enqueue \"solo\" into the band\nDequeue the band into my heart\nRoll it")
+                .unwrap();
+        let commands: Vec<_> = program.commands.into_iter().map(|c| c.cmd).collect();
+        assert_eq!(
+            commands,
+            vec![
+                Command::Push {
+                    target: Expression::Variable("the band".to_string()),
+                    values: vec![Expression::String("solo".to_string())],
+                },
+                Command::Dequeue {
+                    target: Expression::Variable("the band".to_string()),
+                    removed: Some(Expression::Variable("my heart".to_string())),
+                },
+                Command::Dequeue {
+                    target: Expression::Pronoun,
+                    removed: None,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn bitwise_phrases() {
+        let heart = || Box::new(Expression::Variable("my heart".to_string()));
+        let three = || Box::new(Expression::Floating(3.0));
+        for (code, op) in [
+            ("the bitwise and of my heart and 3", BitwiseOp::And),
+            ("the Bitwise Or of my heart and 3", BitwiseOp::Or),
+            ("the bitwise xor of my heart and 3", BitwiseOp::Xor),
+            ("the left shift of my heart by 3", BitwiseOp::ShiftLeft),
+            ("the right shift of my heart by 3", BitwiseOp::ShiftRight),
+        ] {
+            assert_eq!(
+                assigned_value(&format!("Put {} into my soul", code)),
+                Expression::Bitwise(op, heart(), three())
+            );
+        }
+    }
+
+    #[test]
+    fn divisible_by() {
+        assert_eq!(
+            parse("If my heart is divisible by 3 plus 2\nSay it\n")
+                .unwrap()
+                .commands[0]
+                .cmd,
+            parse("If my heart modulo 3 plus 2 is 0\nSay it\n")
+                .unwrap()
+                .commands[0]
+                .cmd
+        );
+    }
+
+    #[test]
+    fn concatenation() {
+        assert_eq!(
+            assigned_value("Let my heart be the concatenation of \"n\", 5, and my soul"),
+            Expression::Concatenation(vec![
+                Expression::String("n".to_string()),
+                Expression::Floating(5.0),
+                Expression::Variable("my soul".to_string()),
+            ])
+        );
+        assert_eq!(
+            assigned_value("Let my heart be the concatenation of my soul"),
+            Expression::Concatenation(vec![Expression::Variable("my soul".to_string())])
+        );
+    }
+
+    #[test]
+    fn coalesce() {
+        let variable = |name: &str| Box::new(Expression::Variable(name.to_string()));
+        assert_eq!(
+            assigned_value("Let my heart be your heart or else 0"),
+            Expression::Coalesce(variable("your heart"), Box::new(Expression::Floating(0.0)))
+        );
+        assert_eq!(
+            assigned_value("Let my heart be your heart or else my soul or else 1"),
+            Expression::Coalesce(
+                variable("your heart"),
+                Box::new(Expression::Coalesce(
+                    variable("my soul"),
+                    Box::new(Expression::Floating(1.0))
+                ))
+            )
+        );
+        assert_eq!(
+            assigned_value("Let my heart be your heart or my soul"),
+            Expression::Or(variable("your heart"), variable("my soul"))
+        );
+    }
+
+    #[test]
+    fn point_numbers() {
+        assert_eq!(
+            assigned_value("Put .5 into my heart"),
+            Expression::Floating(0.5)
+        );
+        assert_eq!(
+            assigned_value("Put 5. into my heart"),
+            Expression::Floating(5.0)
+        );
+        assert_eq!(
+            assigned_value("Put .25 times 4. into my heart"),
+            Expression::Times(
+                Box::new(Expression::Floating(0.25)),
+                Box::new(Expression::Floating(4.0))
+            )
+        );
+        assert_eq!(
+            assigned_value("Put 2.5 into my heart"),
+            Expression::Floating(2.5)
+        );
+        // Only digits make a number, so a sentence's period stays in the words
+        assert_eq!(
+            assigned_value("My heart says I'm done."),
+            Expression::String("I'm done.".to_string())
+        );
+    }
+
+    #[test]
+    fn radix_numbers() {
+        assert_eq!(
+            assigned_value("Put 0xFF into my heart"),
+            Expression::Floating(255.0)
+        );
+        assert_eq!(
+            assigned_value("Put 0b1010 into my heart"),
+            Expression::Floating(10.0)
+        );
+        assert_eq!(
+            assigned_value("Put 0x1f plus 1 into my heart"),
+            Expression::Add(
+                Box::new(Expression::Floating(31.0)),
+                Box::new(Expression::Floating(1.0))
+            )
+        );
+        for (code, number) in [
+            ("Put 0b102 into my heart", "0b102"),
+            ("Put 0xFG into my heart", "0xFG"),
+        ] {
+            match parse(code) {
+                Err(MaidenError::ParseNumberError { number: got, line }) => {
+                    assert_eq!(got, number);
+                    assert_eq!(line, 1);
+                }
+                other => panic!("{:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn result_of() {
+        assert_eq!(
+            assigned_value("Let my heart be the result of Midnight taking 3, my love"),
+            Expression::Call(
+                "Midnight".to_string(),
+                vec![
+                    Expression::Floating(3.0),
+                    Expression::Variable("my love".to_string())
+                ]
+            )
+        );
+        assert_eq!(
+            assigned_value("Put the result into my heart"),
+            Expression::Variable("the result".to_string())
+        );
+    }
+
+    #[test]
+    fn random_numbers() {
+        assert_eq!(
+            assigned_value("Put a random number into my heart"),
+            Expression::Call("random".to_string(), vec![])
+        );
+        let dice = Expression::Call(
+            "random".to_string(),
+            vec![Expression::Floating(1.0), Expression::Floating(6.0)],
+        );
+        assert_eq!(
+            assigned_value("Put a random number between 1 and 6 into my heart"),
+            dice
+        );
+        assert_eq!(
+            assigned_value("Put random between 1 and 6 into my heart"),
+            dice
+        );
+        assert_eq!(
+            assigned_value("Put a random into my heart"),
+            Expression::Variable("a random".to_string())
+        );
+    }
+
+    #[test]
+    fn opposite() {
+        assert_eq!(
+            assigned_value("Put the opposite of my flag into the result"),
+            assigned_value("Put not my flag into the result")
+        );
+        assert_eq!(
+            assigned_value("Put the opposite of right and wrong into the result"),
+            Expression::And(
+                Box::new(Expression::Not(Box::new(Expression::True))),
+                Box::new(Expression::False)
+            )
+        );
+    }
 }
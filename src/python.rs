@@ -0,0 +1,502 @@
+use crate::builtins;
+use crate::common::{Block, Command, Expression, ListenType, Program};
+
+// Python that does what the program does, for the parts of Rockstar that map straight across:
+// variables, arithmetic, comparisons, conditions, loops, functions, Say and Listen. Say becomes
+// print, functions become defs, and null and mysterious both become None.
+//
+// Known gaps, each of which becomes a call to _unsupported that fails when it's reached:
+// - arrays, pronouns, the built-in functions, and the commands that only work on arrays and
+//   strings (Split, Join, Cast, Rock, Roll, Map, Pad and the like)
+// - Restart, labelled loops, Try, Throw, Wait and Include
+// - closures and lambdas
+//
+// And some things work differently:
+// - mysterious is None, so it adds and prints as null does
+// - comparing a string with a number doesn't convert the string first
+// - a function that puts a value into a global variable makes a local one instead
+// - Listen fails at the end of the input, rather than giving mysterious
+pub fn transpile_python(program: &Program) -> String {
+    let mut output = PRELUDE.to_string();
+    for command in &program.commands {
+        write_command(program, &command.cmd, 0, &mut output);
+        if let Command::FunctionDeclaration { .. } = command.cmd {
+            output.push('\n');
+        }
+    }
+    output
+}
+
+// Everything the transpiled code needs to behave like Rockstar, so each program runs on its own
+const PRELUDE: &str = r#"# Transpiled from Rockstar by Maiden
+import math
+
+
+def _num(value):
+    return 0 if value is None else value
+
+
+def _text(value):
+    if value is None:
+        return "null"
+    if value is True:
+        return "true"
+    if value is False:
+        return "false"
+    if isinstance(value, float) and value.is_integer():
+        return str(int(value))
+    return str(value)
+
+
+def _add(a, b):
+    if isinstance(a, str) or isinstance(b, str):
+        return _text(a) + _text(b)
+    return _num(a) + _num(b)
+
+
+def _eq(a, b):
+    if a is None or b is None:
+        return _num(a) == _num(b)
+    return a == b
+
+
+def _unsupported(what):
+    raise NotImplementedError(what + " can't be transpiled to Python")
+
+
+"#;
+
+// Python's keywords, and the names the transpiled code uses itself
+const RESERVED: &[&str] = &[
+    "and", "as", "assert", "async", "await", "break", "class", "continue", "def", "del", "elif",
+    "else", "except", "finally", "float", "for", "from", "global", "if", "import", "in", "input",
+    "int", "is", "lambda", "math", "nonlocal", "not", "or", "pass", "print", "raise", "range",
+    "return", "str", "try", "while", "with", "yield",
+];
+
+// Rockstar names ignore case and can have spaces, so "My world" is my_world. None of them can
+// start with an underscore, so they never clash with the prelude.
+fn name(rockstar: &str) -> String {
+    let mut name: String = rockstar
+        .to_lowercase()
+        .chars()
+        .map(|c| if c.is_alphanumeric() { c } else { '_' })
+        .collect();
+    if RESERVED.contains(&name.as_str()) {
+        name.push('_');
+    }
+    name
+}
+
+fn string(value: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in value.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if c.is_control() => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+fn number(value: f64) -> String {
+    if value.is_nan() {
+        "math.nan".to_string()
+    } else if value.is_infinite() {
+        if value > 0.0 { "math.inf" } else { "-math.inf" }.to_string()
+    } else if value.fract() == 0.0 && value.abs() < 1e15 {
+        format!("{}", value as i64)
+    } else {
+        format!("{:?}", value)
+    }
+}
+
+fn unsupported(what: &str) -> String {
+    format!("_unsupported({})", string(what))
+}
+
+fn is_function(program: &Program, called: &str) -> bool {
+    program
+        .functions
+        .keys()
+        .any(|declared| declared.eq_ignore_ascii_case(called))
+}
+
+fn write_block(program: &Program, block: &Block, indent: usize, output: &mut String) {
+    if block.commands.is_empty() {
+        write_line("pass", indent, output);
+    }
+    for command in &block.commands {
+        write_command(program, &command.cmd, indent, output);
+    }
+}
+
+fn write_line(line: &str, indent: usize, output: &mut String) {
+    output.push_str(&"    ".repeat(indent));
+    output.push_str(line);
+    output.push('\n');
+}
+
+fn write_command(program: &Program, command: &Command, indent: usize, output: &mut String) {
+    let e = |expression: &Expression| expression_python(program, expression);
+    let line = match command {
+        Command::Assignment { target, value } => match **target {
+            Expression::Variable(ref target) => format!("{} = {}", name(target), e(value)),
+            _ => unsupported("Putting a value into an array"),
+        },
+        Command::MultipleAssignment { targets, value } => {
+            let mut names = vec![];
+            for target in targets {
+                match target {
+                    Expression::Variable(target) => names.push(name(target)),
+                    _ => {
+                        write_line(
+                            &unsupported("Putting a value into an array"),
+                            indent,
+                            output,
+                        );
+                        return;
+                    }
+                }
+            }
+            format!("{} = {}", names.join(" = "), e(value))
+        }
+        Command::While {
+            expression,
+            block,
+            label: None,
+        } => {
+            write_line(&format!("while {}:", e(expression)), indent, output);
+            write_block(program, block, indent + 1, output);
+            return;
+        }
+        Command::Until {
+            expression,
+            block,
+            label: None,
+        } => {
+            write_line(
+                &format!("while not {}:", operand(program, expression)),
+                indent,
+                output,
+            );
+            write_block(program, block, indent + 1, output);
+            return;
+        }
+        Command::Repeat {
+            count,
+            block,
+            label: None,
+        } => {
+            write_line(
+                &format!("for _ in range(int({})):", numeric(program, count)),
+                indent,
+                output,
+            );
+            write_block(program, block, indent + 1, output);
+            return;
+        }
+        Command::If {
+            expression,
+            then,
+            otherwise,
+        } => {
+            write_line(&format!("if {}:", e(expression)), indent, output);
+            match then {
+                Some(then) => write_block(program, then, indent + 1, output),
+                None => write_line("pass", indent + 1, output),
+            }
+            if let Some(otherwise) = otherwise {
+                write_line("else:", indent, output);
+                write_block(program, otherwise, indent + 1, output);
+            }
+            return;
+        }
+        Command::Increment { target, count } | Command::Decrement { target, count } => {
+            let sign = if let Command::Increment { .. } = command {
+                "+"
+            } else {
+                "-"
+            };
+            match target {
+                Expression::Variable(target) => format!(
+                    "{} = _num({}) {} {}",
+                    name(target),
+                    name(target),
+                    sign,
+                    number(*count)
+                ),
+                _ => unsupported("Building up a pronoun or array entry"),
+            }
+        }
+        Command::Continue => "continue".to_string(),
+        Command::Break => "break".to_string(),
+        Command::Say { value } => format!("print({})", text(program, value)),
+        Command::Write { value } => format!("print({}, end=\"\")", text(program, value)),
+        Command::Listen { targets, as_type } => {
+            let read = match as_type {
+                ListenType::String => "input()",
+                ListenType::Number => "float(input())",
+            };
+            match targets.as_slice() {
+                [] => read.to_string(),
+                [target] => format!("{} = {}", name(target), read),
+                // Padded out with None, so there's a word or None for each
+                targets => {
+                    let names: Vec<String> = targets.iter().map(|target| name(target)).collect();
+                    format!(
+                        "{} = (input().split() + [None] * {count})[:{count}]",
+                        names.join(", "),
+                        count = targets.len()
+                    )
+                }
+            }
+        }
+        Command::FunctionDeclaration {
+            name: function,
+            args,
+            defaults,
+            rest,
+            block,
+        } => {
+            // The defaults belong to the last args
+            let required = args.len() - defaults.len().min(args.len());
+            let mut params: Vec<String> = args[..required].iter().map(|arg| name(arg)).collect();
+            params.extend(
+                args[required..]
+                    .iter()
+                    .zip(defaults)
+                    .map(|(arg, default)| format!("{}={}", name(arg), e(default))),
+            );
+            if let Some(rest) = rest {
+                params.push(format!("*{}", name(rest)));
+            }
+            write_line(
+                &format!("def {}({}):", name(function), params.join(", ")),
+                indent,
+                output,
+            );
+            write_block(program, block, indent + 1, output);
+            return;
+        }
+        Command::Return { return_value } => format!("return {}", e(return_value)),
+        Command::Call {
+            name: function,
+            args,
+        } => e(&Expression::Call(function.clone(), args.clone())),
+        Command::Floor { target } | Command::Ceil { target } | Command::Round { target } => {
+            let rounded = match command {
+                Command::Floor { .. } => "math.floor(_num({0}))",
+                Command::Ceil { .. } => "math.ceil(_num({0}))",
+                // Away from zero on a half, where Python's round goes to even
+                _ => "math.copysign(math.floor(abs(_num({0})) + 0.5), _num({0}))",
+            };
+            match target {
+                Expression::Variable(target) => {
+                    format!(
+                        "{} = {}",
+                        name(target),
+                        rounded.replace("{0}", &name(target))
+                    )
+                }
+                _ => unsupported("Rounding a pronoun or array entry"),
+            }
+        }
+        Command::Negate { target } => match target {
+            Expression::Variable(target) => format!("{0} = -_num({0})", name(target)),
+            _ => unsupported("Negating a pronoun or array entry"),
+        },
+        Command::While { .. } | Command::Until { .. } | Command::Repeat { .. } => {
+            unsupported("A labelled loop")
+        }
+        Command::Restart { .. } => unsupported("Restart"),
+        Command::Empty { .. } => unsupported("Empty"),
+        Command::Sleep { .. } => unsupported("Wait"),
+        Command::Throw { .. } => unsupported("Throw"),
+        Command::Push { .. } => unsupported("Rock"),
+        Command::Dequeue { .. } => unsupported("Dequeue"),
+        Command::Remove { .. } => unsupported("Remove"),
+        Command::Insert { .. } => unsupported("Insert"),
+        Command::Mutation { .. } => unsupported("Split, Join and Cast"),
+        Command::ApplyToArray { .. } => unsupported("Map, Filter and Reduce"),
+        Command::Pad { .. } => unsupported("Pad"),
+        Command::Clamp { .. } => unsupported("Clamp"),
+        Command::FormatNumber { .. } => unsupported("Format"),
+        Command::Try { .. } => unsupported("Try"),
+        Command::Include { .. } => unsupported("Include"),
+    };
+    write_line(&line, indent, output);
+}
+
+// An operand of a Python operator, bracketed unless it's already a single term
+fn operand(program: &Program, expression: &Expression) -> String {
+    let python = expression_python(program, expression);
+    match expression {
+        Expression::Subtract(..)
+        | Expression::Times(..)
+        | Expression::Divide(..)
+        | Expression::Power(..)
+        | Expression::And(..)
+        | Expression::Or(..)
+        | Expression::Nor(..)
+        | Expression::Aint(..)
+        | Expression::Not(..)
+        | Expression::GreaterThanOrEqual(..)
+        | Expression::GreaterThan(..)
+        | Expression::LessThanOrEqual(..)
+        | Expression::LessThan(..) => format!("({})", python),
+        _ => python,
+    }
+}
+
+// As operand, but with null as 0, unless it's sure to be a number already
+fn numeric(program: &Program, expression: &Expression) -> String {
+    match expression {
+        Expression::Nothing | Expression::Null | Expression::Mysterious => "0".to_string(),
+        Expression::Floating(f) if *f < 0.0 => format!("({})", number(*f)),
+        Expression::Floating(_)
+        | Expression::Add(..)
+        | Expression::Modulo(..)
+        | Expression::SquareRoot(..) => operand(program, expression),
+        Expression::Subtract(..)
+        | Expression::Times(..)
+        | Expression::Divide(..)
+        | Expression::Power(..) => format!("({})", expression_python(program, expression)),
+        _ => format!("_num({})", expression_python(program, expression)),
+    }
+}
+
+// Printed as Say would, without converting what's already a string
+fn text(program: &Program, expression: &Expression) -> String {
+    match expression {
+        Expression::String(s) => string(s),
+        _ => format!("_text({})", expression_python(program, expression)),
+    }
+}
+
+fn expression_python(program: &Program, expression: &Expression) -> String {
+    let binary = |op: &str, a: &Expression, b: &Expression| {
+        format!("{} {} {}", numeric(program, a), op, numeric(program, b))
+    };
+    let logical = |op: &str, a: &Expression, b: &Expression| {
+        format!("{} {} {}", operand(program, a), op, operand(program, b))
+    };
+    match expression {
+        Expression::String(s) => string(s),
+        Expression::Floating(f) => number(*f),
+        Expression::Variable(variable) => name(variable),
+        Expression::True => "True".to_string(),
+        Expression::False => "False".to_string(),
+        Expression::Nothing | Expression::Null | Expression::Mysterious => "None".to_string(),
+        Expression::Call(function, args) => {
+            if builtins::is_builtin(function) && !is_function(program, function) {
+                return unsupported(&format!("The built-in {}", function));
+            }
+            let args: Vec<String> = args
+                .iter()
+                .map(|arg| expression_python(program, arg))
+                .collect();
+            format!("{}({})", name(function), args.join(", "))
+        }
+        Expression::Object(function) => name(function),
+        Expression::Add(a, b) => format!(
+            "_add({}, {})",
+            expression_python(program, a),
+            expression_python(program, b)
+        ),
+        Expression::Subtract(a, b) => binary("-", a, b),
+        Expression::Times(a, b) => binary("*", a, b),
+        Expression::Divide(a, b) => binary("/", a, b),
+        Expression::Power(a, b) => binary("**", a, b),
+        Expression::Modulo(a, b) => format!(
+            "math.fmod({}, {})",
+            numeric(program, a),
+            numeric(program, b)
+        ),
+        Expression::SquareRoot(a) => format!("math.sqrt({})", numeric(program, a)),
+        Expression::GreaterThanOrEqual(a, b) => binary(">=", a, b),
+        Expression::GreaterThan(a, b) => binary(">", a, b),
+        Expression::LessThanOrEqual(a, b) => binary("<=", a, b),
+        Expression::LessThan(a, b) => binary("<", a, b),
+        Expression::Is(a, b) => format!(
+            "_eq({}, {})",
+            expression_python(program, a),
+            expression_python(program, b)
+        ),
+        Expression::Aint(a, b) => format!(
+            "not _eq({}, {})",
+            expression_python(program, a),
+            expression_python(program, b)
+        ),
+        Expression::And(a, b) => logical("and", a, b),
+        Expression::Or(a, b) => logical("or", a, b),
+        Expression::Nor(a, b) => format!("not ({})", logical("or", a, b)),
+        Expression::Not(a) => format!("not {}", operand(program, a)),
+        Expression::Text(a) => text(program, a),
+        Expression::Sentence(parts) | Expression::Concatenation(parts) => {
+            let separator = if let Expression::Sentence(_) = expression {
+                " and "
+            } else {
+                ""
+            };
+            let parts: Vec<String> = parts.iter().map(|part| text(program, part)).collect();
+            format!("{}.join([{}])", string(separator), parts.join(", "))
+        }
+        Expression::Pronoun => unsupported("Pronouns"),
+        Expression::ArrayRef { .. } | Expression::Array { .. } | Expression::List(_) => {
+            unsupported("Arrays")
+        }
+        Expression::Lambda { .. } | Expression::Closure { .. } => unsupported("Lambdas"),
+        Expression::Modifier(_)
+        | Expression::Break
+        | Expression::Continue
+        | Expression::Restart(_)
+        | Expression::IsType(..)
+        | Expression::Rounded(..)
+        | Expression::Bitwise(..)
+        | Expression::Between(..)
+        | Expression::Slice(..)
+        | Expression::HasKey(..)
+        | Expression::Coalesce(..) => unsupported(&format!("{:?}", expression)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::transpile_python;
+    use crate::parser;
+    use std::fs;
+
+    fn test_golden(fixture: &str) {
+        let code = fs::read_to_string(format!("tests/local/{}.rock", fixture)).unwrap();
+        let expected = fs::read_to_string(format!("tests/local/{}.rock.py", fixture)).unwrap();
+        let program = parser::parse(&code).unwrap();
+        assert_eq!(expected, transpile_python(&program));
+    }
+
+    #[test]
+    fn fizzbuzz() {
+        test_golden("fizzbuzz");
+    }
+
+    #[test]
+    fn fizzbuzz_minimalist() {
+        test_golden("fizzbuzz_minimalist");
+    }
+
+    #[test]
+    fn modulo() {
+        test_golden("modulo");
+    }
+
+    #[test]
+    fn array_output() {
+        test_golden("array_output");
+    }
+}
@@ -1,22 +1,196 @@
+use crate::analysis;
+use crate::builtins::{self, Random};
 use crate::common::*;
 use log::debug;
+#[cfg(feature = "snapshot")]
+use serde_derive::{Deserialize, Serialize};
 use std::collections::{BTreeMap, HashMap};
 use std::io::{self, Write};
 use std::ops::Deref;
 use std::str::FromStr;
+use std::time::{Duration, Instant};
 
 #[derive(Debug, Clone, PartialEq, Eq, Copy)]
+#[cfg_attr(feature = "snapshot", derive(Serialize, Deserialize))]
 pub enum VariableType {
     Global,
     Local,
 }
 
+// How "Say" prints an array. Everywhere else an array used as a scalar is its length.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ArrayOutput {
+    // The reference behaviour: no elements at all, just the length, which counts up to the
+    // highest numeric index and ignores string keys (see tests/local/array_output.rock)
+    #[default]
+    Length,
+    // Elements printed as "Say" would, separated by ", "
+    Contents,
+    // The Debug form of the whole array, including string keys
+    Debug,
+}
+
+// The words printed for booleans, by "Say" and when they're joined onto strings
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BooleanWords {
+    // The reference behaviour
+    #[default]
+    TrueFalse,
+    YesNo,
+    RightWrong,
+}
+
+impl BooleanWords {
+    fn words(self) -> (&'static str, &'static str) {
+        match self {
+            BooleanWords::TrueFalse => ("true", "false"),
+            BooleanWords::YesNo => ("yes", "no"),
+            BooleanWords::RightWrong => ("right", "wrong"),
+        }
+    }
+}
+
+// What "Say" puts after each value it prints
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Newline {
+    #[default]
+    Lf,
+    CrLf,
+    None,
+}
+
+impl Newline {
+    fn as_str(self) -> &'static str {
+        match self {
+            Newline::Lf => "\n",
+            Newline::CrLf => "\r\n",
+            Newline::None => "",
+        }
+    }
+}
+
+// How numbers are printed, by "Say" and when they're joined onto strings
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum NumberFormat {
+    // The reference behaviour, as format_number
+    #[default]
+    Fixed,
+    // With this many digits after the point, e.g. 1500 is "1.50e3" with 2
+    Scientific(usize),
+}
+
+impl NumberFormat {
+    pub fn format(self, value: f64) -> String {
+        match self {
+            NumberFormat::Fixed => format_number(value),
+            // Infinity and NaN still print as they normally would
+            NumberFormat::Scientific(_) if !value.is_finite() => format_number(value),
+            // Adding zero turns negative zero into zero
+            NumberFormat::Scientific(precision) => format!("{:.*e}", precision, value + 0.0),
+        }
+    }
+}
+
+// Settings for a single run, including limits so untrusted programs can be sandboxed
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Options {
+    // Maximum number of bytes printed before aborting with OutputLimit
+    pub output_limit: Option<usize>,
+    // Write each command to the diagnostic stream as it's run
+    pub trace: bool,
+    // For untrusted programs: the built-in functions, Listen and Wait all fail with
+    // MissingFunction, as if they'd never been declared. The program's own functions still work.
+    pub disable_builtins: bool,
+    pub array_output: ArrayOutput,
+    pub boolean_words: BooleanWords,
+    // Dividing two numbers fails with InexactDivision unless the result is a terminating decimal
+    pub exact_division: bool,
+    pub newline: Newline,
+    // Longest string (in bytes) or array (in entries) that can be built before failing with
+    // AllocationLimit, so repetition can't use up all the memory
+    pub allocation_limit: Option<usize>,
+    // Most variables plus array entries (counting nested arrays' entries too) that can be live at
+    // once before failing with MemoryLimit. Checked after each command, against the variables the
+    // current function can see.
+    pub memory_limit: Option<usize>,
+    // Most times any one While, Until or Repeat can go round before failing with IterationLimit
+    // on that loop's line. Each time a loop starts, its count starts again from 0.
+    pub iteration_limit: Option<u64>,
+    pub number_format: NumberFormat,
+    // Where the random built-in starts, so the same seed always gives the same numbers
+    pub random_seed: u64,
+    // Comparing values that can't be converted to each other fails with TypeMismatch, rather than
+    // just being false. Comparisons with mysterious and nothing are still allowed.
+    pub strict_comparison: bool,
+    // Start each line printed by "Say" with the line it was said on, like "[12] hello"
+    pub line_numbers: bool,
+    // Anything that would be a warning fails the run instead: the problems analysis::check
+    // finds stop it before it starts, and the ones found while running stop it there
+    pub strict: bool,
+    // Reading a variable that isn't set, or doing arithmetic on values it can't be done on, gives
+    // mysterious and a warning rather than failing, so the rest of the program still runs. The
+    // warnings are collected by Interpreter::warnings. Anything else, limits included, still fails.
+    pub lenient: bool,
+    // Once the run's over, write the variables it ended with, how many instructions it took and
+    // how deep the calls went to the diagnostic stream, if there is one
+    pub report: bool,
+    // Longest a run can take, by the clock, before failing with TimeLimit. Wait counts towards
+    // it, so a Wait that would go past it only sleeps until the limit and then fails.
+    pub time_limit: Option<Duration>,
+}
+
+// Counters gathered during a run, for profiling
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "snapshot", derive(Serialize, Deserialize))]
+pub struct Stats {
+    // Number of calls to each function, keyed by name as called
+    pub calls: HashMap<String, u64>,
+    // Deepest the call stack got, with 1 for a call from the top level
+    pub max_depth: u32,
+}
+
+// A function provided by the program hosting the interpreter, given the evaluated arguments.
+// It's up to the function to check how many it got.
+pub type HostFunction = Box<dyn Fn(&[Expression]) -> Result<Expression>>;
+
+// The environment just before one command ran, from Interpreter::recording. A debugger can step
+// backwards through these, as each one has every variable the command could see.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TraceStep {
+    pub line: usize,
+    // 0 for the main program, and one more for each function call
+    pub depth: u32,
+    pub variables: BTreeMap<String, Expression>,
+}
+
 struct State<'a> {
     writer: &'a mut dyn Write,
+    // Separate from writer, so tracing never gets mixed into the program's own output
+    diagnostics: Option<&'a mut dyn Write>,
+    // The program's source lines, if we were given them and are tracing, so traces can show
+    // the original lines
+    source: Option<&'a [&'a str]>,
+    // The included file being run, if any. Its commands are numbered within that file, so
+    // they can't be looked up in source.
+    file: Option<String>,
     variables: &'a mut HashMap<String, (VariableType, Expression)>,
     current_line: usize,
     depth: u32,
     pronoun: Option<String>,
+    options: Options,
+    output_bytes: usize,
+    // Commands run so far, counted across loop bodies and function calls, so an infinite
+    // loop hits InstructionLimit however deep it is
+    instructions: usize,
+    stats: Option<&'a mut Stats>,
+    recording: Option<&'a mut Vec<TraceStep>>,
+    // Problems that didn't stop the run, from Interpreter::warnings
+    warnings: Option<&'a mut Vec<MaidenError>>,
+    host_functions: Option<&'a HashMap<String, HostFunction>>,
+    random: &'a mut Random,
+    on_iteration: Option<&'a mut dyn FnMut(usize, u64)>,
+    // When the time limit runs out. Only set if there is one, as there's no clock on wasm.
+    deadline: Option<Instant>,
 }
 
 fn run_binop(
@@ -26,7 +200,7 @@ fn run_binop(
     second: &Expression,
     f: fn(&State, &Expression, &Expression) -> Result<bool>,
 ) -> Result<Expression> {
-    let res = run_binop_shortcut(state, program, first, second, None, false, f);
+    let res = run_binop_bool(state, program, first, second, f);
     if res? {
         Ok(Expression::True)
     } else {
@@ -59,19 +233,25 @@ fn expression_to_number(inp: Expression, line: usize) -> Result<Expression> {
     };
 }
 
-fn run_binop_shortcut(
+fn run_number(state: &mut State, program: &Program, expression: &Expression) -> Result<f64> {
+    let value = run_expression(state, program, expression)?;
+    match expression_to_number(value, state.current_line)? {
+        Expression::Floating(f) => Ok(f),
+        other => Err(MaidenError::Unimplemented {
+            description: format!("Can't convert {:?} to number", other),
+            line: state.current_line,
+        }),
+    }
+}
+
+fn run_binop_bool(
     state: &mut State,
     program: &Program,
     first: &Expression,
     second: &Expression,
-    shortcut: Option<Expression>,
-    shortcut_return: bool,
     f: fn(&State, &Expression, &Expression) -> Result<bool>,
 ) -> Result<bool> {
     let res_first = run_expression(state, program, first)?;
-    if shortcut.is_some() && shortcut.unwrap() == res_first {
-        return Ok(shortcut_return);
-    }
     let res_second = run_expression(state, program, second)?;
     debug!("first: {:?} second: {:?}", res_first, res_second);
 
@@ -122,17 +302,40 @@ fn run_binop_shortcut(
         }
         _ => {}
     }
-    let converted_second = expression_to_number(res_second, state.current_line);
-    if converted_second.is_err() {
-        return Ok(false);
+    // Try numeric conversion instead
+    let second_is_mysterious = res_second == Expression::Mysterious;
+    match (
+        expression_to_number(res_first.clone(), state.current_line),
+        expression_to_number(res_second.clone(), state.current_line),
+    ) {
+        (Ok(first), Ok(second)) => f(state, &first, &second),
+        _ if state.options.strict_comparison && !second_is_mysterious => {
+            Err(MaidenError::TypeMismatch {
+                left: operand_string(&res_first),
+                right: operand_string(&res_second),
+                line: state.current_line,
+            })
+        }
+        _ => Ok(false),
     }
+}
 
-    // Try numeric conversion instead
-    return f(
-        state,
-        &expression_to_number(res_first, state.current_line)?,
-        &converted_second?,
-    );
+// "and", "or" and "nor" go by the truthiness of each side, and the second side is only evaluated
+// when the first doesn't settle it. settled_by is the first side's value that gives the result
+// straight away: false for "and", true for "or" and "nor".
+fn run_logical(
+    state: &mut State,
+    program: &Program,
+    first: &Expression,
+    second: &Expression,
+    settled_by: bool,
+) -> Result<bool> {
+    let res_first = run_expression(state, program, first)?;
+    if to_boolean(state, &res_first)? == settled_by {
+        return Ok(settled_by);
+    }
+    let res_second = run_expression(state, program, second)?;
+    return to_boolean(state, &res_second);
 }
 
 fn run_mathbinop(
@@ -145,19 +348,215 @@ fn run_mathbinop(
 ) -> Result<Expression> {
     let res_first = run_expression(state, program, first)?;
     let res_second = run_expression(state, program, second)?;
+    return apply_mathbinop(state, res_first, res_second, op, f);
+}
+
+fn write_output(state: &mut State, x: &str) -> Result<()> {
+    if let Some(limit) = state.options.output_limit {
+        if state.output_bytes + x.len() > limit {
+            // Write what still fits, so the partial output is kept, without splitting a character
+            let mut remaining = limit - state.output_bytes;
+            while !x.is_char_boundary(remaining) {
+                remaining -= 1;
+            }
+            state.output_bytes += remaining;
+            state.writer.write_all(&x.as_bytes()[..remaining])?;
+            return Err(MaidenError::OutputLimit {
+                limit,
+                line: state.current_line,
+            });
+        }
+    }
+    state.output_bytes += x.len();
+    state.writer.write_all(x.as_bytes())?;
+    Ok(())
+}
+
+fn check_length(state: &State, length: usize) -> Result<()> {
+    if let Some(limit) = state.options.allocation_limit {
+        if length > limit {
+            return Err(MaidenError::AllocationLimit {
+                limit,
+                line: state.current_line,
+            });
+        }
+    }
+    Ok(())
+}
+
+// A rough count of what a value holds: nothing for a scalar, as long strings are up to
+// allocation_limit, and one per entry for an array
+fn footprint(value: &Expression) -> usize {
+    match value {
+        Expression::Array { numeric, strings } => numeric
+            .values()
+            .chain(strings.values())
+            .map(|entry| 1 + footprint(entry))
+            .sum(),
+        _ => 0,
+    }
+}
+
+fn check_memory(state: &State) -> Result<()> {
+    if let Some(limit) = state.options.memory_limit {
+        let used: usize = state
+            .variables
+            .values()
+            .map(|(_, value)| 1 + footprint(value))
+            .sum();
+        if used > limit {
+            return Err(MaidenError::MemoryLimit {
+                line: state.current_line,
+            });
+        }
+    }
+    Ok(())
+}
+
+// There are n! permutations of n entries, so they're checked against the limits before any are
+// made, rather than once they're all there
+fn check_permutations(state: &State, args: &[Expression]) -> Result<()> {
+    if let Some(Expression::Array { numeric, .. }) = args.first() {
+        let count = (1..=numeric.len())
+            .try_fold(1usize, |total, n| total.checked_mul(n))
+            .unwrap_or(usize::MAX);
+        check_length(state, count)?;
+        if let Some(limit) = state.options.memory_limit {
+            if count.saturating_mul(numeric.len() + 1) > limit {
+                return Err(MaidenError::MemoryLimit {
+                    line: state.current_line,
+                });
+            }
+        }
+    }
+    Ok(())
+}
+
+fn warn(state: &mut State, warning: MaidenError) -> Result<()> {
+    if state.options.strict {
+        return Err(warning);
+    }
+    if let Some(warnings) = state.warnings.as_deref_mut() {
+        warnings.push(warning);
+    }
+    Ok(())
+}
+
+// The error as a warning and mysterious as the value instead, when running leniently
+fn lenient(state: &mut State, error: MaidenError) -> Result<Expression> {
+    if !state.options.lenient {
+        return Err(error);
+    }
+    warn(state, error)?;
+    Ok(Expression::Mysterious)
+}
+
+// Variables already set, by an earlier run of an Interpreter, don't count as used before
+// they're assigned, and the host's functions can be called without being declared
+fn check_strict(
+    program: &Program,
+    options: Options,
+    defined: &HashMap<String, (VariableType, Expression)>,
+    host_functions: &[&str],
+) -> Result<()> {
+    if !options.strict {
+        return Ok(());
+    }
+    for error in analysis::check_program_with_host_functions(program, host_functions) {
+        match error {
+            MaidenError::MissingVariable { ref name, .. } if defined.contains_key(name) => {}
+            error => return Err(error),
+        }
+    }
+    Ok(())
+}
+
+// Strings repeat a whole number of times, so anything else gets rounded towards zero
+fn check_repeat_count(state: &mut State, count: f64) -> Result<()> {
+    if count.fract() != 0.0 || count < 0.0 {
+        warn(
+            state,
+            MaidenError::LossyConversion {
+                description: format!(
+                    "repeating a string {} times repeats it {} times",
+                    format_number(count),
+                    count as usize
+                ),
+                line: state.current_line,
+            },
+        )?;
+    }
+    Ok(())
+}
+
+fn check_size(state: &State, value: Expression) -> Result<Expression> {
+    let length = match value {
+        Expression::String(ref s) => s.len(),
+        Expression::Array {
+            ref numeric,
+            ref strings,
+        } => numeric.len() + strings.len(),
+        _ => 0,
+    };
+    check_length(state, length)?;
+    Ok(value)
+}
+
+fn apply_mathbinop(
+    state: &mut State,
+    res_first: Expression,
+    res_second: Expression,
+    op: &Expression,
+    f: fn(f64, f64) -> f64,
+) -> Result<Expression> {
+    let res = apply_mathbinop_unchecked(state, res_first, res_second, op, f)?;
+    check_size(state, res)
+}
+
+fn apply_mathbinop_unchecked(
+    state: &mut State,
+    res_first: Expression,
+    res_second: Expression,
+    op: &Expression,
+    f: fn(f64, f64) -> f64,
+) -> Result<Expression> {
+    if let (Expression::Add(_, _), Expression::Array { .. }) = (op, &res_first) {
+        return Ok(concat_array(res_first, res_second));
+    }
     match res_first {
         Expression::Floating(ref i) => {
             let first_value = *i;
             match res_second {
                 Expression::Floating(ref i) => {
                     let second_value = *i;
-                    return Ok(Expression::Floating(f(first_value, second_value)));
+                    let result = f(first_value, second_value);
+                    if result.is_nan() && !first_value.is_nan() && !second_value.is_nan() {
+                        warn(
+                            state,
+                            MaidenError::NotANumber {
+                                description: format!(
+                                    "the result for {} and {}",
+                                    format_number(first_value),
+                                    format_number(second_value)
+                                ),
+                                line: state.current_line,
+                            },
+                        )?;
+                    }
+                    return Ok(Expression::Floating(result));
                 }
                 Expression::String(ref s_s) => match op {
                     Expression::Add(_, _) => {
-                        return Ok(Expression::String(format!("{}{}", first_value, s_s)));
+                        return Ok(Expression::String(format!(
+                            "{}{}",
+                            state.options.number_format.format(first_value),
+                            s_s
+                        )));
                     }
                     Expression::Times(_, _) => {
+                        // Checked before repeating, as the result might not fit in memory
+                        check_repeat_count(state, first_value)?;
+                        check_length(state, s_s.len().saturating_mul(first_value as usize))?;
                         return Ok(Expression::String(s_s.repeat(first_value as usize)));
                     }
                     _ => {}
@@ -183,6 +582,8 @@ fn run_mathbinop(
             Expression::Times(_, _) => match res_second {
                 Expression::Floating(ref i) => {
                     let second_value = *i;
+                    check_repeat_count(state, second_value)?;
+                    check_length(state, s_f.len().saturating_mul(second_value as usize))?;
                     return Ok(Expression::String(s_f.repeat(second_value as usize)));
                 }
                 Expression::Null => {
@@ -220,13 +621,88 @@ fn run_mathbinop(
             }
         }
     };
-    return Err(MaidenError::Unimplemented {
-        description: format!(
-            "Math op ({:?}) on values we can't apply: {:?} {:?}",
-            op, res_first, res_second
-        ),
-        line: state.current_line,
-    });
+    let line = state.current_line;
+    return lenient(
+        state,
+        MaidenError::Unimplemented {
+            description: format!(
+                "Math op ({:?}) on values we can't apply: {:?} {:?}",
+                op, res_first, res_second
+            ),
+            line,
+        },
+    );
+}
+
+// The quotient, if it can be written out as a terminating decimal. The operands are
+// taken as the decimals they print as, so 0.3 over 0.1 is exactly 3 rather than 2.9999999999999996.
+fn exact_quotient(x: f64, y: f64) -> Option<f64> {
+    fn as_fraction(value: f64) -> Option<(i128, i128)> {
+        let printed = format!("{}", value);
+        let (whole, fraction) = match printed.split_once('.') {
+            Some((whole, fraction)) => (whole, fraction),
+            None => (printed.as_str(), ""),
+        };
+        if whole.len() + fraction.len() > 36 {
+            return None;
+        }
+        let numerator = format!("{}{}", whole, fraction).parse::<i128>().ok()?;
+        Some((numerator, 10i128.pow(fraction.len() as u32)))
+    }
+    fn gcd(a: i128, b: i128) -> i128 {
+        if b == 0 {
+            a.abs()
+        } else {
+            gcd(b, a % b)
+        }
+    }
+    let (x_num, x_den) = as_fraction(x)?;
+    let (y_num, y_den) = as_fraction(y)?;
+    let mut numerator = x_num.checked_mul(y_den)?;
+    let mut denominator = x_den.checked_mul(y_num)?;
+    let divisor = gcd(numerator, denominator);
+    numerator /= divisor;
+    denominator /= divisor;
+
+    let mut remaining = denominator.abs();
+    for factor in [2, 5] {
+        while remaining % factor == 0 {
+            remaining /= factor;
+        }
+    }
+    if remaining != 1 {
+        return None;
+    }
+    Some(numerator as f64 / denominator as f64)
+}
+
+// "X with Y" where X is an array. An array Y has its elements added after X's, keeping any gaps,
+// and its string keys merged in (replacing X's). Anything else is appended as one element.
+fn concat_array(first: Expression, second: Expression) -> Expression {
+    if let Expression::Array {
+        mut numeric,
+        mut strings,
+    } = first
+    {
+        let length = numeric.keys().max().map_or(0, |x| x + 1);
+        match second {
+            Expression::Array {
+                numeric: other_numeric,
+                strings: other_strings,
+            } => {
+                for (index, value) in other_numeric {
+                    numeric.insert(length + index, value);
+                }
+                strings.extend(other_strings);
+            }
+            other => {
+                numeric.insert(length, Box::new(other));
+            }
+        }
+        Expression::Array { numeric, strings }
+    } else {
+        unreachable!("concat_array on {:?}", first);
+    }
 }
 
 fn to_boolean(state: &State, expression: &Expression) -> Result<bool> {
@@ -241,7 +717,7 @@ fn to_boolean(state: &State, expression: &Expression) -> Result<bool> {
             }
         }
         Expression::String(ref val) => Ok(!matches!(val.to_lowercase().as_str(), "")),
-        Expression::Object(_) => Ok(true),
+        Expression::Object(_) | Expression::Closure { .. } => Ok(true),
         _ => {
             return Err(MaidenError::BadBooleanResolve {
                 expression: format!("{:?}", expression),
@@ -251,23 +727,93 @@ fn to_boolean(state: &State, expression: &Expression) -> Result<bool> {
     };
 }
 
+// A variable holding a function value, as made by evaluating a Lambda
+fn closure(state: &State, name: &str) -> Option<(Function, BTreeMap<String, Expression>)> {
+    match state.variables.get(&name.to_lowercase()) {
+        Some((
+            _,
+            Expression::Closure {
+                args,
+                block,
+                captured,
+            },
+        )) => Some((
+            Function {
+                args: args.clone(),
+                defaults: vec![],
+                rest: None,
+                block: block.clone(),
+            },
+            captured.clone(),
+        )),
+        _ => None,
+    }
+}
+
 fn call_function(
     state: &mut State,
     program: &Program,
     target: &str,
     args: &[Expression],
 ) -> Result<Expression> {
-    let func_wrap = program.functions.get(target);
-    if func_wrap.is_none() {
-        return Err(MaidenError::MissingFunction {
-            name: target.to_string(),
-            line: state.current_line,
-        });
+    let host = state
+        .host_functions
+        .and_then(|functions| functions.get(&target.to_lowercase()));
+    let known =
+        host.is_some() || program.functions.contains_key(target) || builtin_enabled(state, target);
+    if let Some(stats) = state.stats.as_deref_mut() {
+        if known {
+            *stats.calls.entry(target.to_string()).or_insert(0) += 1;
+        }
+    }
+    if let Some(host) = host {
+        let mut values = vec![];
+        for arg in args {
+            values.push(run_expression(state, program, arg)?);
+        }
+        return host(&values);
     }
-    let func = func_wrap.unwrap();
-    if args.len() != func.args.len() {
+    if let Some(func) = program.functions.get(target) {
+        return invoke(state, program, func, None, args);
+    }
+    if let Some((func, captured)) = closure(state, target) {
+        return invoke(state, program, &func, Some(captured), args);
+    }
+    if builtin_enabled(state, target) {
+        let mut values = vec![];
+        for arg in args {
+            values.push(run_expression(state, program, arg)?);
+        }
+        if target.eq_ignore_ascii_case("random") {
+            return builtins::random(state.random, &values, state.current_line);
+        }
+        if target.eq_ignore_ascii_case("permutations") {
+            check_permutations(state, &values)?;
+        }
+        return builtins::call(target, &values, state.current_line);
+    }
+    return Err(MaidenError::MissingFunction {
+        name: target.to_string(),
+        line: state.current_line,
+    });
+}
+
+// Runs a function with its own copy of the variables. A function value also gets the variables
+// it captured, which hide the caller's ones with the same name.
+fn invoke(
+    state: &mut State,
+    program: &Program,
+    func: &Function,
+    captured: Option<BTreeMap<String, Expression>>,
+    args: &[Expression],
+) -> Result<Expression> {
+    if args.len() < func.required_args() || (args.len() > func.args.len() && func.rest.is_none()) {
         return Err(MaidenError::WrongArgCount {
-            expected: func.args.len(),
+            expected: if args.len() < func.required_args() {
+                func.required_args()
+            } else {
+                func.args.len()
+            },
             got: args.len(),
             line: state.current_line,
         });
@@ -280,15 +826,67 @@ fn call_function(
             line: state.current_line,
         });
     }
+    if let Some(stats) = state.stats.as_deref_mut() {
+        stats.max_depth = stats.max_depth.max(state.depth + 1);
+    }
     let mut new_state = State {
         writer: state.writer,
+        diagnostics: state
+            .diagnostics
+            .as_mut()
+            .map(|d| &mut **d as &mut dyn Write),
+        source: state.source,
+        file: state.file.clone(),
         variables: &mut new_variables,
         current_line: state.current_line,
         depth: state.depth + 1,
         pronoun: None,
+        options: state.options,
+        output_bytes: state.output_bytes,
+        instructions: state.instructions,
+        stats: state.stats.as_deref_mut(),
+        recording: state.recording.as_deref_mut(),
+        warnings: state.warnings.as_deref_mut(),
+        host_functions: state.host_functions,
+        random: &mut *state.random,
+        on_iteration: state
+            .on_iteration
+            .as_mut()
+            .map(|f| &mut **f as &mut dyn FnMut(usize, u64)),
+        deadline: state.deadline,
     };
-    for (i, arg) in args.iter().enumerate() {
-        let value = run_expression(&mut new_state, program, arg)?;
+    let mut values = vec![];
+    for arg in args {
+        values.push(run_expression(&mut new_state, program, arg)?);
+    }
+    for (name, value) in captured.into_iter().flatten() {
+        new_state
+            .variables
+            .insert(name, (VariableType::Local, value));
+    }
+    let mut extra = BTreeMap::new();
+    for (i, value) in values.into_iter().enumerate() {
+        if i < func.args.len() {
+            new_state
+                .variables
+                .insert(func.args[i].to_lowercase(), (VariableType::Local, value));
+        } else {
+            extra.insert(i - func.args.len(), Box::new(value));
+        }
+    }
+    if let Some(ref rest) = func.rest {
+        let rest_array = Expression::Array {
+            numeric: extra,
+            strings: BTreeMap::new(),
+        };
+        new_state
+            .variables
+            .insert(rest.to_lowercase(), (VariableType::Local, rest_array));
+    }
+    // Left-out args come after the given ones, so their defaults can use the earlier args
+    let first_default = func.required_args();
+    for i in args.len()..func.args.len() {
+        let value = run_expression(&mut new_state, program, &func.defaults[i - first_default])?;
         new_state
             .variables
             .insert(func.args[i].to_lowercase(), (VariableType::Local, value));
@@ -302,6 +900,8 @@ fn call_function(
         },
         0,
     );
+    state.output_bytes = new_state.output_bytes;
+    state.instructions = new_state.instructions;
     for (name, (kind, value)) in new_state.variables.iter() {
         if kind == &VariableType::Global {
             state
@@ -309,7 +909,11 @@ fn call_function(
                 .insert(name.to_string(), (VariableType::Global, value.clone()));
         }
     }
-    return result;
+    // Nothing here means we fell off the end without a Return, which gives back mysterious
+    return result.map(|value| match value {
+        Expression::Nothing => Expression::Mysterious,
+        other => other,
+    });
 }
 
 #[allow(clippy::cognitive_complexity)] // FIXME: break this up a bit
@@ -331,54 +935,27 @@ fn run_expression(
             return run_binop(state, program, first, second, |_, f, s| Ok(f != s));
         }
         Expression::And(ref first, ref second) => {
-            let res = run_binop_shortcut(
-                state,
-                program,
-                first,
-                second,
-                Some(Expression::False),
-                false,
-                |st, f, s| {
-                    return Ok(to_boolean(st, f)? && to_boolean(st, s)?);
-                },
-            )?;
-            if res {
+            if run_logical(state, program, first, second, false)? {
                 return Ok(Expression::True);
             } else {
                 return Ok(Expression::False);
             };
         }
         Expression::Or(ref first, ref second) => {
-            let res = run_binop_shortcut(
-                state,
-                program,
-                first,
-                second,
-                Some(Expression::True),
-                true,
-                |st, f, s| {
-                    return Ok(to_boolean(st, f)? || to_boolean(st, s)?);
-                },
-            )?;
-            if res {
+            if run_logical(state, program, first, second, true)? {
                 return Ok(Expression::True);
             } else {
                 return Ok(Expression::False);
             };
         }
+        Expression::Coalesce(ref first, ref second) => {
+            return match run_expression(state, program, first)? {
+                Expression::Mysterious | Expression::Null => run_expression(state, program, second),
+                value => Ok(value),
+            };
+        }
         Expression::Nor(ref first, ref second) => {
-            let res = run_binop_shortcut(
-                state,
-                program,
-                first,
-                second,
-                Some(Expression::True),
-                true,
-                |st, f, s| {
-                    return Ok(to_boolean(st, f)? || to_boolean(st, s)?);
-                },
-            )?;
-            if res {
+            if run_logical(state, program, first, second, true)? {
                 return Ok(Expression::False);
             } else {
                 return Ok(Expression::True);
@@ -393,6 +970,61 @@ fn run_expression(
         Expression::LessThanOrEqual(ref first, ref second) => {
             return run_binop(state, program, first, second, |_, f, s| Ok(f <= s));
         }
+        Expression::Between(ref value, ref lower, ref upper) => {
+            // Evaluate everything once up front, so the value's side effects only happen once
+            let value = run_expression(state, program, value)?;
+            let lower = run_expression(state, program, lower)?;
+            let upper = run_expression(state, program, upper)?;
+            if run_binop(state, program, &lower, &value, |_, f, s| Ok(f <= s))? == Expression::False
+            {
+                return Ok(Expression::False);
+            }
+            return run_binop(state, program, &value, &upper, |_, f, s| Ok(f <= s));
+        }
+        // Only numbers, so a rounding modifier on something else is a mistake rather than a
+        // conversion
+        Expression::Rounded(ref value, rounding) => match run_expression(state, program, value)? {
+            Expression::Floating(value) => Ok(Expression::Floating(rounding.apply(value))),
+            other => Err(MaidenError::BadArgument {
+                name: "Rounding".to_string(),
+                description: format!("expected a number, got {:?}", other),
+                line: state.current_line,
+            }),
+        },
+        Expression::HasKey(ref map, ref key) => {
+            let map = run_expression(state, program, map)?;
+            let key = run_expression(state, program, key)?;
+            let found = match (&map, &key) {
+                (Expression::Array { numeric, .. }, Expression::Floating(f)) => {
+                    *f >= 0.0 && f.fract() == 0.0 && numeric.contains_key(&(*f as usize))
+                }
+                (Expression::Array { strings, .. }, Expression::String(s)) => {
+                    strings.contains_key(s)
+                }
+                // Nothing else can be a key, so it's never there
+                (Expression::Array { .. }, _) => false,
+                (other, _) => {
+                    return Err(MaidenError::BadArgument {
+                        name: "Has key".to_string(),
+                        description: format!("expected an array, got {:?}", other),
+                        line: state.current_line,
+                    });
+                }
+            };
+            return Ok(if found {
+                Expression::True
+            } else {
+                Expression::False
+            });
+        }
+        Expression::Slice(ref value, ref start, ref end) => {
+            let value = run_expression(state, program, value)?;
+            let start = run_number(state, program, start)?;
+            let start = array_index(state, start)?;
+            let end = run_number(state, program, end)?;
+            let end = array_index(state, end)?;
+            return slice(state, value, start, end);
+        }
         Expression::LessThan(ref first, ref second) => {
             return run_binop(state, program, first, second, |_, f, s| Ok(f < s));
         }
@@ -406,21 +1038,130 @@ fn run_expression(
             return run_mathbinop(state, program, first, second, expression, |f, s| f * s);
         }
         Expression::Divide(ref first, ref second) => {
-            let res = run_mathbinop(state, program, first, second, expression, |f, s| f / s);
-            if let Ok(ok) = res {
-                if let Expression::Floating(val) = ok {
-                    if val == std::f64::INFINITY {
-                        return Err(MaidenError::Infinity {
-                            x: format!("{:?}", first),
-                            y: format!("{:?}", second),
+            let res_first = run_expression(state, program, first)?;
+            let res_second = run_expression(state, program, second)?;
+            check_divisor(state, &res_first, &res_second)?;
+            if state.options.exact_division {
+                if let (Expression::Floating(x), Expression::Floating(y)) =
+                    (&res_first, &res_second)
+                {
+                    return match exact_quotient(*x, *y) {
+                        Some(quotient) => Ok(Expression::Floating(quotient)),
+                        None => Err(MaidenError::InexactDivision {
+                            x: format_number(*x),
+                            y: format_number(*y),
+                            line: state.current_line,
+                        }),
+                    };
+                }
+            }
+            // A zero divisor is caught above, but big enough quotients still overflow
+            let (x, y) = (operand_string(&res_first), operand_string(&res_second));
+            let res = apply_mathbinop(state, res_first, res_second, expression, |f, s| f / s)?;
+            if let Expression::Floating(val) = res {
+                if val.is_infinite() {
+                    return Err(MaidenError::Infinity {
+                        x,
+                        y,
+                        line: state.current_line,
+                    });
+                }
+            }
+            Ok(res)
+        }
+        // Truncated like Rust's (and JavaScript's) %, so the result has the sign of the dividend
+        Expression::Modulo(ref first, ref second) => {
+            let x = run_number(state, program, first)?;
+            let y = run_number(state, program, second)?;
+            check_divisor(state, &Expression::Floating(x), &Expression::Floating(y))?;
+            Ok(Expression::Floating(x % y))
+        }
+        Expression::Bitwise(op, ref first, ref second) => {
+            let x = run_number(state, program, first)?;
+            let x = bitwise_operand(state, op, x)?;
+            let y = run_number(state, program, second)?;
+            let y = bitwise_operand(state, op, y)?;
+            let result = match op {
+                BitwiseOp::And => x & y,
+                BitwiseOp::Or => x | y,
+                BitwiseOp::Xor => x ^ y,
+                BitwiseOp::ShiftLeft | BitwiseOp::ShiftRight => {
+                    if !(0..64).contains(&y) {
+                        return Err(MaidenError::BadArgument {
+                            name: op.name().to_string(),
+                            description: format!("can't shift by {}", y),
                             line: state.current_line,
                         });
                     }
+                    if op == BitwiseOp::ShiftLeft {
+                        x << y
+                    } else {
+                        x >> y
+                    }
                 }
-                Ok(ok)
+            };
+            Ok(Expression::Floating(result as f64))
+        }
+        Expression::IsType(ref value, type_name) => {
+            let matches = matches!(
+                (run_expression(state, program, value)?, type_name),
+                (Expression::Floating(_), TypeName::Number)
+                    | (Expression::String(_), TypeName::String)
+                    | (Expression::Array { .. }, TypeName::Array)
+                    | (Expression::True | Expression::False, TypeName::Boolean)
+            );
+            Ok(if matches {
+                Expression::True
             } else {
-                res
+                Expression::False
+            })
+        }
+        Expression::Sentence(ref parts) => {
+            let mut words = vec![];
+            for part in parts {
+                let value = run_expression(state, program, part)?;
+                words.push(say_printable(&value, state)?);
+            }
+            Ok(Expression::String(words.join(" and ")))
+        }
+        Expression::Concatenation(ref parts) => {
+            let mut joined = String::new();
+            for part in parts {
+                let value = run_expression(state, program, part)?;
+                joined.push_str(&say_printable(&value, state)?);
+                check_length(state, joined.len())?;
+            }
+            Ok(Expression::String(joined))
+        }
+        Expression::List(ref values) => {
+            let mut numeric = BTreeMap::new();
+            for (index, value) in values.iter().enumerate() {
+                numeric.insert(index, Box::new(run_expression(state, program, value)?));
+            }
+            Ok(Expression::Array {
+                numeric,
+                strings: BTreeMap::new(),
+            })
+        }
+        Expression::SquareRoot(ref arg) => {
+            let value = run_number(state, program, arg)?;
+            if value < 0.0 {
+                return Err(MaidenError::NotANumber {
+                    description: format!("the square root of {}", value),
+                    line: state.current_line,
+                });
             }
+            Ok(Expression::Floating(value.sqrt()))
+        }
+        // Arrays follow the array_output option, the same as for Say
+        Expression::Text(ref value) => {
+            let value = run_expression(state, program, value)?;
+            Ok(Expression::String(say_printable(&value, state)?))
+        }
+        Expression::Power(ref base, ref exponent) => {
+            let base = run_number(state, program, base)?;
+            let exponent = run_number(state, program, exponent)?;
+            Ok(Expression::Floating(base.powf(exponent)))
         }
         Expression::Variable(ref name) => match state.variables.get(&name.to_lowercase()) {
             Some((_, exp)) => {
@@ -431,12 +1172,52 @@ fn run_expression(
                 if program.functions.get(name).is_some() {
                     return Ok(Expression::Object(name.clone()));
                 }
-                return Err(MaidenError::MissingVariable {
-                    name: name.clone(),
-                    line: state.current_line,
-                });
+                let line = state.current_line;
+                return lenient(
+                    state,
+                    MaidenError::MissingVariable {
+                        name: name.clone(),
+                        line,
+                    },
+                );
             }
         },
+        Expression::ArrayRef {
+            ref name,
+            ref index,
+        } => {
+            // Resolve the entry here rather than later, so that values like
+            // "Return the list at 1" still work once the function's locals are gone
+            let index = run_expression(state, program, index)?;
+            let array = run_expression(state, program, name)?;
+            let entry = match (array, index) {
+                (Expression::Array { numeric, .. }, Expression::Floating(f)) => {
+                    numeric.get(&array_index(state, f)?).map(|v| *v.clone())
+                }
+                (Expression::Array { strings, .. }, Expression::String(ref s)) => {
+                    strings.get(s).map(|v| *v.clone())
+                }
+                (Expression::String(ref s), Expression::Floating(f)) => {
+                    // Index by code point, to match what Cut gives for the same string
+                    let g = array_index(state, f)?;
+                    s.chars().nth(g).map(|c| Expression::String(c.to_string()))
+                }
+                (Expression::Array { .. }, index) | (Expression::String(_), index) => {
+                    return Err(MaidenError::BadIndex {
+                        index: format!("{:?}", index),
+                        line: state.current_line,
+                    });
+                }
+                (other, _) => {
+                    return Err(MaidenError::BadArgument {
+                        name: "At".to_string(),
+                        description: format!("expected an array or string, got {:?}", other),
+                        line: state.current_line,
+                    });
+                }
+            };
+            Ok(entry.unwrap_or(Expression::Mysterious))
+        }
         Expression::Call(ref target, ref args) => call_function(state, program, target, args),
         Expression::Pronoun => match state.pronoun {
             Some(ref pronoun) => match state.variables.get(&pronoun.to_lowercase()) {
@@ -445,10 +1226,11 @@ fn run_expression(
                     Ok(exp.clone())
                 }
                 None => {
-                    return Err(MaidenError::MissingVariable {
+                    let error = MaidenError::MissingVariable {
                         name: pronoun.clone(),
                         line: state.current_line,
-                    });
+                    };
+                    return lenient(state, error);
                 }
             },
             None => {
@@ -470,6 +1252,19 @@ fn run_expression(
                 return Ok(Expression::False);
             }
         }
+        // Captures by value, see Expression::Closure
+        Expression::Lambda {
+            ref args,
+            ref block,
+        } => Ok(Expression::Closure {
+            args: args.clone(),
+            block: block.clone(),
+            captured: state
+                .variables
+                .iter()
+                .map(|(name, (_, value))| (name.clone(), value.clone()))
+                .collect(),
+        }),
         _ => Ok(expression.clone()),
     };
 }
@@ -478,24 +1273,422 @@ pub fn run(
     program: &mut Program,
     writer: &mut dyn Write,
 ) -> Result<HashMap<String, (VariableType, Expression)>> {
-    let pc = 0;
-    let mut variables = HashMap::new();
-    let mut state = State {
-        variables: &mut variables,
-        writer,
-        current_line: 0,
-        depth: 0,
-        pronoun: None,
-    };
-    run_core(&mut state, program, pc)?;
-    return Ok(variables);
+    return run_with_options(program, writer, Options::default());
 }
 
-fn get_printable(value: &Expression, state: &State) -> Result<String> {
-    match *value {
-        Expression::Floating(ref x) => Ok(format!("{}", x)),
-        Expression::String(ref s) => Ok(s.to_string()),
-        Expression::Variable(ref x) => {
+pub fn run_with_options(
+    program: &mut Program,
+    writer: &mut dyn Write,
+    options: Options,
+) -> Result<HashMap<String, (VariableType, Expression)>> {
+    let mut interpreter = Interpreter::new(writer, options);
+    interpreter.run(program)?;
+    return Ok(interpreter.variables);
+}
+
+// The program's output and the diagnostic output from a run_captured
+pub struct Capture {
+    pub result: Result<HashMap<String, (VariableType, Expression)>>,
+    pub output: String,
+    pub diagnostics: String,
+}
+
+pub fn run_captured(program: &mut Program, options: Options) -> Capture {
+    let mut output = Vec::new();
+    let mut diagnostics = Vec::new();
+    let result = {
+        let mut interpreter = Interpreter::new(&mut output, options);
+        interpreter.diagnostics(&mut diagnostics);
+        interpreter.run(program).map(|_| interpreter.variables)
+    };
+    return Capture {
+        result,
+        output: String::from_utf8_lossy(&output).into_owned(),
+        diagnostics: String::from_utf8_lossy(&diagnostics).into_owned(),
+    };
+}
+
+// Everything an Interpreter keeps between runs, so it can be saved and carried on with later,
+// in another process if the "snapshot" feature is on. There's no call stack or position in a
+// program to save, as those only exist while run is going.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "snapshot", derive(Serialize, Deserialize))]
+pub struct InterpreterState {
+    pub variables: HashMap<String, (VariableType, Expression)>,
+    pub stats: Stats,
+    pub output_bytes: usize,
+    pub random: Random,
+}
+
+// Runs one program after another against the same output, for hosts that run many of them.
+// Variables, counters and the bytes counted towards the output limit carry over between runs,
+// until reset clears them.
+pub struct Interpreter<'a> {
+    writer: &'a mut dyn Write,
+    options: Options,
+    variables: HashMap<String, (VariableType, Expression)>,
+    stats: Stats,
+    output_bytes: usize,
+    host_functions: HashMap<String, HostFunction>,
+    random: Random,
+    on_iteration: Option<Box<dyn FnMut(usize, u64)>>,
+    diagnostics: Option<&'a mut dyn Write>,
+    source: Option<&'a str>,
+    recording: Option<&'a mut Vec<TraceStep>>,
+    warnings: Option<&'a mut Vec<MaidenError>>,
+}
+
+impl<'a> Interpreter<'a> {
+    pub fn new(writer: &'a mut dyn Write, options: Options) -> Interpreter<'a> {
+        return Interpreter {
+            writer,
+            options,
+            variables: HashMap::new(),
+            stats: Stats::default(),
+            output_bytes: 0,
+            host_functions: HashMap::new(),
+            random: Random::new(options.random_seed),
+            on_iteration: None,
+            diagnostics: None,
+            source: None,
+            recording: None,
+            warnings: None,
+        };
+    }
+
+    // Makes function callable by name from the programs this runs, ahead of any function the
+    // program declares with the same name. Names are matched ignoring case, as for built-ins.
+    pub fn register<F>(&mut self, name: &str, function: F)
+    where
+        F: Fn(&[Expression]) -> Result<Expression> + 'static,
+    {
+        self.host_functions
+            .insert(name.to_lowercase(), Box::new(function));
+    }
+
+    // Calls observer at the start of each iteration of every While, Until and Repeat loop, with
+    // the loop's line and which iteration it's on, counting from 1. For finding the hot loops.
+    pub fn on_iteration<F>(&mut self, observer: F)
+    where
+        F: FnMut(usize, u64) + 'static,
+    {
+        self.on_iteration = Some(Box::new(observer));
+    }
+
+    // Where trace output, and the report, go. Kept apart from the writer, so they never get mixed
+    // into the program's own output.
+    pub fn diagnostics(&mut self, diagnostics: &'a mut dyn Write) {
+        self.diagnostics = Some(diagnostics);
+    }
+
+    // The source text of the programs being run, so traces can show each command's line
+    pub fn source(&mut self, source: &'a str) {
+        self.source = Some(source);
+    }
+
+    // Adds a TraceStep to steps for every command that's run
+    pub fn recording(&mut self, steps: &'a mut Vec<TraceStep>) {
+        self.recording = Some(steps);
+    }
+
+    // Problems that don't need to stop the program, such as a string repeated a fractional
+    // number of times, are added to warnings instead of being ignored
+    pub fn warnings(&mut self, warnings: &'a mut Vec<MaidenError>) {
+        self.warnings = Some(warnings);
+    }
+
+    pub fn run(&mut self, program: &mut Program) -> Result<()> {
+        let host_functions: Vec<&str> = self.host_functions.keys().map(String::as_str).collect();
+        check_strict(program, self.options, &self.variables, &host_functions)?;
+        // Split up front, rather than for each traced command
+        let lines: Option<Vec<&str>> = match self.source {
+            Some(source) if self.options.trace => Some(source.lines().collect()),
+            _ => None,
+        };
+        let mut state = State {
+            variables: &mut self.variables,
+            writer: &mut *self.writer,
+            diagnostics: self
+                .diagnostics
+                .as_mut()
+                .map(|d| &mut **d as &mut dyn Write),
+            source: lines.as_deref(),
+            file: None,
+            current_line: 0,
+            depth: 0,
+            pronoun: None,
+            options: self.options,
+            output_bytes: self.output_bytes,
+            instructions: 0,
+            stats: Some(&mut self.stats),
+            recording: self.recording.as_deref_mut(),
+            warnings: self.warnings.as_deref_mut(),
+            host_functions: Some(&self.host_functions),
+            random: &mut self.random,
+            on_iteration: self
+                .on_iteration
+                .as_mut()
+                .map(|f| &mut **f as &mut dyn FnMut(usize, u64)),
+            deadline: deadline(self.options),
+        };
+        let result = run_core(&mut state, program, 0);
+        self.output_bytes = state.output_bytes;
+        // Written even if the run failed, as that's when it's most wanted
+        if self.options.report {
+            write_report(&mut state)?;
+        }
+        return result.map(|_| ());
+    }
+
+    pub fn variables(&self) -> &HashMap<String, (VariableType, Expression)> {
+        return &self.variables;
+    }
+
+    pub fn stats(&self) -> &Stats {
+        return &self.stats;
+    }
+
+    // Deepest the call stack got in any run since this was created or reset, for choosing a
+    // stack limit
+    pub fn max_depth(&self) -> u32 {
+        return self.stats.max_depth;
+    }
+
+    pub fn snapshot(&self) -> InterpreterState {
+        return InterpreterState {
+            variables: self.variables.clone(),
+            stats: self.stats.clone(),
+            output_bytes: self.output_bytes,
+            random: self.random.clone(),
+        };
+    }
+
+    // Carries on from a snapshot, keeping where this interpreter writes to, its options and its
+    // host functions
+    pub fn restore(&mut self, state: InterpreterState) {
+        self.variables = state.variables;
+        self.stats = state.stats;
+        self.output_bytes = state.output_bytes;
+        self.random = state.random;
+    }
+
+    // Back to how it was when created, keeping where it writes to, options and host functions.
+    // The call stack is already empty, as it only lives as long as each run.
+    pub fn reset(&mut self) {
+        self.variables.clear();
+        self.stats = Stats::default();
+        self.output_bytes = 0;
+        self.random = Random::new(self.options.random_seed);
+    }
+}
+
+fn write_report(state: &mut State) -> Result<()> {
+    let mut variables: Vec<String> = state
+        .variables
+        .iter()
+        .map(|(name, (_, value))| {
+            let printed = match value {
+                Expression::String(s) => format!("{:?}", s),
+                _ => get_printable(value, state).unwrap_or_else(|_| format!("{:?}", value)),
+            };
+            format!("  {} = {}", name, printed)
+        })
+        .collect();
+    variables.sort();
+    let max_depth = state.stats.as_deref().map_or(0, |stats| stats.max_depth);
+    if let Some(diagnostics) = state.diagnostics.as_deref_mut() {
+        writeln!(diagnostics, "instructions: {}", state.instructions)?;
+        writeln!(diagnostics, "deepest call: {}", max_depth)?;
+        writeln!(diagnostics, "variables:")?;
+        for variable in variables {
+            writeln!(diagnostics, "{}", variable)?;
+        }
+    }
+    Ok(())
+}
+
+fn say_printable(value: &Expression, state: &State) -> Result<String> {
+    if let Expression::Array { ref numeric, .. } = *value {
+        match state.options.array_output {
+            ArrayOutput::Length => {}
+            ArrayOutput::Contents => {
+                if let Expression::String(s) = join_array(state, numeric, ", ")? {
+                    return Ok(s);
+                }
+            }
+            ArrayOutput::Debug => return Ok(format!("{:?}", value)),
+        }
+    }
+    get_printable(value, state)
+}
+
+// Entries are printed the same way "Say" would, so numbers join cleanly.
+// The default separator is the empty string, matching the reference.
+fn join_array(
+    state: &State,
+    to_join: &BTreeMap<usize, Box<Expression>>,
+    join_with: &str,
+) -> Result<Expression> {
+    let mut printed = vec![];
+    for value in to_join.values() {
+        printed.push(get_printable(value, state)?);
+    }
+    return Ok(Expression::String(printed.join(join_with)));
+}
+
+// How an operand appears in an error message
+fn operand_string(value: &Expression) -> String {
+    match value {
+        Expression::Floating(f) => format_number(*f),
+        other => format!("{:?}", other),
+    }
+}
+
+// Every division-like operator gives the same error for a zero divisor, with the values that
+// were divided rather than the expressions they came from
+fn check_divisor(state: &State, x: &Expression, y: &Expression) -> Result<()> {
+    if let Expression::Floating(divisor) = y {
+        if *divisor == 0.0 {
+            return Err(MaidenError::Infinity {
+                x: operand_string(x),
+                y: operand_string(y),
+                line: state.current_line,
+            });
+        }
+    }
+    Ok(())
+}
+
+// Indexes past the end are clamped to it, and a start after the end gives an empty slice.
+// Array slices are renumbered from 0, keeping any gaps, and drop the string keys.
+fn slice(state: &State, value: Expression, start: usize, end: usize) -> Result<Expression> {
+    match value {
+        Expression::Array { numeric, .. } => Ok(Expression::Array {
+            numeric: numeric
+                .into_iter()
+                .filter(|(index, _)| start <= *index && *index <= end)
+                .map(|(index, value)| (index - start, value))
+                .collect(),
+            strings: BTreeMap::new(),
+        }),
+        // By code point, as with "X at Y" on strings
+        Expression::String(s) => Ok(Expression::String(if start > end {
+            String::new()
+        } else {
+            // end can be usize::MAX, from a huge index
+            s.chars()
+                .skip(start)
+                .take((end - start).saturating_add(1))
+                .collect()
+        })),
+        other => Err(MaidenError::BadArgument {
+            name: "Slice".to_string(),
+            description: format!("expected an array or string, got {:?}", other),
+            line: state.current_line,
+        }),
+    }
+}
+
+// Bitwise operators work on whole numbers that a float can hold exactly
+fn bitwise_operand(state: &State, op: BitwiseOp, value: f64) -> Result<i64> {
+    if value.fract() != 0.0 || value.abs() > 9_007_199_254_740_992.0 || !value.is_finite() {
+        return Err(MaidenError::BadArgument {
+            name: op.name().to_string(),
+            description: format!("expected an integer, got {}", format_number(value)),
+            line: state.current_line,
+        });
+    }
+    Ok(value as i64)
+}
+
+// Over the numeric entries in order. Map and Filter give back arrays numbered from 0, and Reduce
+// starts from the first entry, so reducing an empty array gives mysterious.
+fn apply_to_array(
+    state: &mut State,
+    program: &Program,
+    operation: ArrayOperation,
+    name: &str,
+    values: Vec<Expression>,
+) -> Result<Expression> {
+    let mut results = vec![];
+    match operation {
+        ArrayOperation::Map => {
+            for value in values {
+                results.push(call_function(state, program, name, &[value])?);
+            }
+        }
+        ArrayOperation::Filter => {
+            for value in values {
+                let keep = call_function(state, program, name, std::slice::from_ref(&value))?;
+                if to_boolean(state, &keep)? {
+                    results.push(value);
+                }
+            }
+        }
+        ArrayOperation::Reduce => {
+            let mut values = values.into_iter();
+            let mut total = match values.next() {
+                Some(first) => first,
+                None => return Ok(Expression::Mysterious),
+            };
+            for value in values {
+                total = call_function(state, program, name, &[total, value])?;
+            }
+            return Ok(total);
+        }
+    }
+    Ok(Expression::Array {
+        numeric: results.into_iter().map(Box::new).enumerate().collect(),
+        strings: BTreeMap::new(),
+    })
+}
+
+// Indexes are evaluated as expressions, so they need checking they're usable
+fn array_index(state: &State, index: f64) -> Result<usize> {
+    if index.fract() != 0.0 || index < 0.0 || !index.is_finite() {
+        return Err(MaidenError::BadIndex {
+            index: index.to_string(),
+            line: state.current_line,
+        });
+    }
+    return Ok(index as usize);
+}
+
+// "Cast X with 16" parses X as base 16. Bases run from 2 to 36, as with from_str_radix
+fn cast_base(state: &mut State, program: &Program, modifier: &Expression) -> Result<u32> {
+    let base = run_expression(state, program, modifier)?;
+    if let Expression::Floating(f) = base {
+        if f.fract() == 0.0 && (2.0..=36.0).contains(&f) {
+            return Ok(f as u32);
+        }
+    }
+    return Err(MaidenError::BadArgument {
+        name: "Cast".to_string(),
+        description: format!("base must be an integer from 2 to 36, got {:?}", base),
+        line: state.current_line,
+    });
+}
+
+// Prints numbers the way the reference does: negative zero is just "0", and the
+// non-finite values use the JavaScript names rather than Rust's "inf" and "NaN"
+pub fn format_number(value: f64) -> String {
+    if value == 0.0 {
+        return "0".to_string();
+    }
+    if value.is_nan() {
+        return "NaN".to_string();
+    }
+    if value.is_infinite() {
+        return if value > 0.0 { "Infinity" } else { "-Infinity" }.to_string();
+    }
+    return format!("{}", value);
+}
+
+fn get_printable(value: &Expression, state: &State) -> Result<String> {
+    match *value {
+        Expression::Floating(x) => Ok(state.options.number_format.format(x)),
+        Expression::String(ref s) => Ok(s.to_string()),
+        Expression::Variable(ref x) => {
             let (_, v) = {
                 let current_line = state.current_line;
                 let v = state.variables.get(&x.to_lowercase());
@@ -573,8 +1766,8 @@ fn get_printable(value: &Expression, state: &State) -> Result<String> {
             };
             get_printable(v, state)
         }
-        Expression::True => Ok("true".to_string()),
-        Expression::False => Ok("false".to_string()),
+        Expression::True => Ok(state.options.boolean_words.words().0.to_string()),
+        Expression::False => Ok(state.options.boolean_words.words().1.to_string()),
         Expression::Mysterious => Ok("mysterious".to_string()),
         Expression::Null => Ok("null".to_string()),
         _ => {
@@ -710,6 +1903,129 @@ fn round_variable(state: &mut State, target: &Expression, f: &dyn Fn(f64) -> f64
     return Ok(());
 }
 
+fn deadline(options: Options) -> Option<Instant> {
+    options.time_limit.map(|limit| Instant::now() + limit)
+}
+
+fn time_limit(state: &State) -> MaidenError {
+    MaidenError::TimeLimit {
+        milliseconds: state.options.time_limit.unwrap_or_default().as_millis(),
+        line: state.current_line,
+    }
+}
+
+fn check_time(state: &State) -> Result<()> {
+    match state.deadline {
+        Some(deadline) if Instant::now() >= deadline => Err(time_limit(state)),
+        _ => Ok(()),
+    }
+}
+
+fn sleep(state: &State, seconds: f64) -> Result<()> {
+    if seconds.is_nan() || seconds <= 0.0 {
+        return Ok(());
+    }
+    let duration = Duration::try_from_secs_f64(seconds).map_err(|_| MaidenError::BadArgument {
+        name: "Wait".to_string(),
+        description: format!("can't wait for {} seconds", seconds),
+        line: state.current_line,
+    })?;
+    if let Some(deadline) = state.deadline {
+        let left = deadline.saturating_duration_since(Instant::now());
+        if duration > left {
+            pause(left);
+            return Err(time_limit(state));
+        }
+    }
+    pause(duration);
+    Ok(())
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn pause(duration: Duration) {
+    std::thread::sleep(duration);
+}
+
+// There's no blocking sleep in the browser, and we don't want to hang the page anyway
+#[cfg(target_arch = "wasm32")]
+fn pause(_duration: Duration) {}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn read_line() -> io::Result<String> {
+    let mut input = String::new();
+    io::stdin().read_line(&mut input)?;
+    Ok(input)
+}
+
+// No stdin in the browser, so Listen always gets an empty line
+#[cfg(target_arch = "wasm32")]
+fn read_line() -> io::Result<String> {
+    Ok(String::new())
+}
+
+// Limits are there so a program can't run for ever or use up everything, so catching them with
+// Try would defeat the point
+fn recoverable(error: &MaidenError) -> bool {
+    !matches!(
+        error,
+        MaidenError::InstructionLimit { .. }
+            | MaidenError::TimeLimit { .. }
+            | MaidenError::OutputLimit { .. }
+            | MaidenError::AllocationLimit { .. }
+            | MaidenError::MemoryLimit { .. }
+            | MaidenError::IterationLimit { .. }
+            | MaidenError::StackOverflow { .. }
+            | MaidenError::Io { .. }
+    )
+}
+
+fn loop_iteration(state: &mut State, line: usize, iteration: u64) -> Result<()> {
+    if let Some(limit) = state.options.iteration_limit {
+        if iteration > limit {
+            return Err(MaidenError::IterationLimit { limit, line });
+        }
+    }
+    if let Some(on_iteration) = state.on_iteration.as_mut() {
+        on_iteration(line, iteration);
+    }
+    Ok(())
+}
+
+fn builtin_enabled(state: &State, name: &str) -> bool {
+    !state.options.disable_builtins && builtins::is_builtin(name)
+}
+
+// For the commands that reach outside the program, which are gated along with the built-ins
+fn check_builtin(state: &State, name: &str) -> Result<()> {
+    if state.options.disable_builtins {
+        return Err(MaidenError::MissingFunction {
+            name: name.to_string(),
+            line: state.current_line,
+        });
+    }
+    Ok(())
+}
+
+fn coerce_input(input: &str, as_type: ListenType, line: usize) -> Result<Expression> {
+    let input = input.trim_end_matches('\n');
+    return match as_type {
+        ListenType::String => Ok(Expression::String(input.to_string())),
+        ListenType::Number => match f64::from_str(input.trim()) {
+            Ok(number) => Ok(Expression::Floating(number)),
+            Err(_) => Err(MaidenError::ParseNumberError {
+                number: input.to_string(),
+                line,
+            }),
+        },
+    };
+}
+
+// The first count words of a line, for "Listen to X and Y", with None for each one that's missing
+fn input_words(input: &str, count: usize) -> Vec<Option<&str>> {
+    let mut words = input.split_whitespace();
+    (0..count).map(|_| words.next()).collect()
+}
+
 fn get_variable_type(state: &State) -> VariableType {
     if state.depth == 0 {
         VariableType::Global
@@ -718,123 +2034,185 @@ fn get_variable_type(state: &State) -> VariableType {
     }
 }
 
+// The variable a command works on, which may be given by a pronoun
+fn target_variable(state: &State, command: &str, target: &Expression) -> Result<String> {
+    match target {
+        Expression::Variable(name) => Ok(name.to_lowercase()),
+        Expression::Pronoun => match state.pronoun {
+            Some(ref pronoun) => Ok(pronoun.to_lowercase()),
+            None => Err(MaidenError::UndefinedPronoun {
+                line: state.current_line,
+            }),
+        },
+        other => Err(MaidenError::BadArgument {
+            name: command.to_string(),
+            description: format!("expected a variable, got {:?}", other),
+            line: state.current_line,
+        }),
+    }
+}
+
+// Same as for assignment, so a mutation into an existing global from a function keeps it global
+fn mutation_target_kind(state: &State, name: &str) -> VariableType {
+    match state.variables.get(&name.to_lowercase()) {
+        Some((kind, _)) => *kind,
+        None => get_variable_type(state),
+    }
+}
+
+fn assign(
+    state: &mut State,
+    program: &Program,
+    target: &Expression,
+    val: Expression,
+) -> Result<()> {
+    match target {
+        Expression::Variable(name) => {
+            state.pronoun = Some(name.clone());
+            let kind = if let Some((inner_kind, _var)) = state.variables.get(&name.to_lowercase()) {
+                *inner_kind
+            } else {
+                get_variable_type(state)
+            };
+            state.variables.insert(name.to_lowercase(), (kind, val));
+        }
+        Expression::Pronoun => {
+            let pronoun = state.pronoun.as_ref().unwrap();
+            state
+                .variables
+                .insert(pronoun.to_lowercase(), (get_variable_type(state), val));
+        }
+        // FIXME: improve with box patterns once stabilised https://github.com/rust-lang/rust/issues/29641
+        Expression::ArrayRef { name, index } => {
+            if let Expression::Variable(var_name) = name.deref() {
+                let var_name = var_name.to_lowercase();
+                match run_expression(state, program, index)? {
+                    Expression::Floating(ref idx) => {
+                        let idx = array_index(state, *idx)?;
+                        if let Some((_kind, array)) = state.variables.get_mut(&var_name) {
+                            if let Expression::Array {
+                                ref mut numeric, ..
+                            } = array
+                            {
+                                numeric.insert(idx, Box::new(val));
+                            } else {
+                                panic!("Array ref assignment to non-array {} {}", var_name, idx);
+                            }
+                        } else {
+                            let mut numeric = BTreeMap::new();
+                            numeric.insert(idx, Box::new(val));
+                            state.variables.insert(
+                                var_name,
+                                (
+                                    get_variable_type(state),
+                                    Expression::Array {
+                                        numeric,
+                                        strings: BTreeMap::new(),
+                                    },
+                                ),
+                            );
+                        }
+                    }
+                    Expression::String(ref idx) => {
+                        if let Some((_kind, array)) = state.variables.get_mut(&var_name) {
+                            if let Expression::Array {
+                                ref mut strings, ..
+                            } = array
+                            {
+                                strings.insert(idx.to_string(), Box::new(val));
+                            } else {
+                                panic!("Array ref assignment to non-array {} {}", var_name, idx);
+                            }
+                        } else {
+                            let mut strings = BTreeMap::new();
+                            strings.insert(idx.to_string(), Box::new(val));
+                            state.variables.insert(
+                                var_name,
+                                (
+                                    get_variable_type(state),
+                                    Expression::Array {
+                                        numeric: BTreeMap::new(),
+                                        strings,
+                                    },
+                                ),
+                            );
+                        }
+                    }
+                    _ => {
+                        panic!("Index assignment with {:?}", index);
+                    }
+                }
+            }
+        }
+        _ => {
+            panic!("Don't know how to assign to {:?}", target);
+        }
+    }
+    Ok(())
+}
+
 #[allow(clippy::cognitive_complexity)] // FIXME: break this up a bit
 fn run_core(state: &mut State, program: &mut Program, mut pc: usize) -> Result<Expression> {
-    let mut total_instr = 0;
     loop {
-        total_instr += 1;
-        if total_instr > 1_000_000 {
+        state.instructions += 1;
+        if state.instructions > 1_000_000 {
             return Err(MaidenError::InstructionLimit {
                 line: state.current_line,
             });
         }
+        check_time(state)?;
         let command_line = match program.commands.get(pc) {
             Some(c) => c,
             None => break,
         };
         state.current_line = command_line.line;
         debug!("command: {:?}", command_line);
+        if state.options.trace {
+            let text = match state.file {
+                Some(_) => None,
+                None => state
+                    .source
+                    .and_then(|lines| lines.get(command_line.line.wrapping_sub(1))),
+            };
+            if let Some(diagnostics) = state.diagnostics.as_deref_mut() {
+                match text {
+                    Some(text) => writeln!(
+                        diagnostics,
+                        "{}: {:?} ({})",
+                        command_line.line,
+                        command_line.cmd,
+                        text.trim()
+                    )?,
+                    None => writeln!(diagnostics, "{}: {:?}", command_line.line, command_line.cmd)?,
+                }
+            }
+        }
+        if let Some(recording) = state.recording.as_deref_mut() {
+            recording.push(TraceStep {
+                line: command_line.line,
+                depth: state.depth,
+                variables: state
+                    .variables
+                    .iter()
+                    .map(|(name, (_, value))| (name.clone(), value.clone()))
+                    .collect(),
+            });
+        }
         match command_line.cmd {
             Command::Assignment {
                 ref target,
                 ref value,
             } => {
                 let val = run_expression(state, program, value)?;
-                match &**target {
-                    Expression::Variable(name) => {
-                        state.pronoun = Some(name.clone());
-                        let kind = if let Some((inner_kind, _var)) =
-                            state.variables.get(&name.to_lowercase())
-                        {
-                            *inner_kind
-                        } else {
-                            get_variable_type(state)
-                        };
-                        state.variables.insert(name.to_lowercase(), (kind, val));
-                    }
-                    Expression::Pronoun => {
-                        let pronoun = state.pronoun.as_ref().unwrap();
-                        state
-                            .variables
-                            .insert(pronoun.to_lowercase(), (get_variable_type(state), val));
-                    }
-                    // FIXME: improve with box patterns once stabilised https://github.com/rust-lang/rust/issues/29641
-                    Expression::ArrayRef { name, index } => {
-                        if let Expression::Variable(var_name) = name.deref() {
-                            let mut local_index = &**index;
-                            let variable;
-                            if let Expression::Variable(ref var) = local_index {
-                                variable = state.variables.get(var).unwrap().clone().1;
-                                local_index = &variable;
-                            }
-                            match local_index {
-                                Expression::Floating(ref idx) => {
-                                    if let Some((_kind, array)) = state.variables.get_mut(var_name)
-                                    {
-                                        if let Expression::Array {
-                                            ref mut numeric, ..
-                                        } = array
-                                        {
-                                            numeric.insert(*idx as usize, Box::new(val));
-                                        } else {
-                                            panic!(
-                                                "Array ref assignment to non-array {} {}",
-                                                var_name, idx
-                                            );
-                                        }
-                                    } else {
-                                        let mut numeric = BTreeMap::new();
-                                        numeric.insert(*idx as usize, Box::new(val));
-                                        state.variables.insert(
-                                            var_name.to_string(),
-                                            (
-                                                get_variable_type(state),
-                                                Expression::Array {
-                                                    numeric,
-                                                    strings: BTreeMap::new(),
-                                                },
-                                            ),
-                                        );
-                                    }
-                                }
-                                Expression::String(ref idx) => {
-                                    if let Some((_kind, array)) = state.variables.get_mut(var_name)
-                                    {
-                                        if let Expression::Array {
-                                            ref mut strings, ..
-                                        } = array
-                                        {
-                                            strings.insert(idx.to_string(), Box::new(val));
-                                        } else {
-                                            panic!(
-                                                "Array ref assignment to non-array {} {}",
-                                                var_name, idx
-                                            );
-                                        }
-                                    } else {
-                                        let mut strings = BTreeMap::new();
-                                        strings.insert(idx.to_string(), Box::new(val));
-                                        state.variables.insert(
-                                            var_name.to_string(),
-                                            (
-                                                get_variable_type(state),
-                                                Expression::Array {
-                                                    numeric: BTreeMap::new(),
-                                                    strings,
-                                                },
-                                            ),
-                                        );
-                                    }
-                                }
-                                _ => {
-                                    panic!("Index assignment with {:?}", index);
-                                }
-                            }
-                        }
-                    }
-                    _ => {
-                        panic!("Don't know how to assign to {:?}", target);
-                    }
+                assign(state, program, target, val)?;
+            }
+            Command::MultipleAssignment {
+                ref targets,
+                ref value,
+            } => {
+                let val = run_expression(state, program, value)?;
+                for target in targets {
+                    assign(state, program, target, val.clone())?;
                 }
             }
             Command::Increment {
@@ -852,62 +2230,150 @@ fn run_core(state: &mut State, program: &mut Program, mut pc: usize) -> Result<E
             Command::Until {
                 ref expression,
                 ref block,
-            } => loop {
-                let resolve = run_expression(state, program, expression)?;
-                if to_boolean(state, &resolve)? {
-                    break;
+                ref label,
+            } => {
+                let line = state.current_line;
+                let mut iteration = 0;
+                loop {
+                    let resolve = run_expression(state, program, expression)?;
+                    if to_boolean(state, &resolve)? {
+                        break;
+                    }
+                    iteration += 1;
+                    loop_iteration(state, line, iteration)?;
+                    let res = run_core(
+                        state,
+                        &mut Program {
+                            commands: block.commands.clone(),
+                            functions: program.functions.clone(),
+                        },
+                        0,
+                    )?;
+                    match res {
+                        Expression::Break => break,
+                        // For a loop further out
+                        Expression::Restart(ref restart) if Some(restart) != label.as_ref() => {
+                            return Ok(res);
+                        }
+                        _ => {}
+                    }
                 }
-                run_core(
-                    state,
-                    &mut Program {
-                        commands: block.commands.clone(),
-                        functions: program.functions.clone(),
-                    },
-                    0,
-                )
-                .unwrap();
-            },
+            }
             Command::While {
                 ref expression,
                 ref block,
-            } => loop {
-                let resolve = run_expression(state, program, expression)?;
-                if !to_boolean(state, &resolve)? {
-                    break;
+                ref label,
+            } => {
+                let line = state.current_line;
+                let mut iteration = 0;
+                loop {
+                    let resolve = run_expression(state, program, expression)?;
+                    if !to_boolean(state, &resolve)? {
+                        break;
+                    }
+                    iteration += 1;
+                    loop_iteration(state, line, iteration)?;
+                    let res = run_core(
+                        state,
+                        &mut Program {
+                            commands: block.commands.clone(),
+                            functions: program.functions.clone(),
+                        },
+                        0,
+                    )?;
+                    match res {
+                        Expression::Break => break,
+                        // For a loop further out
+                        Expression::Restart(ref restart) if Some(restart) != label.as_ref() => {
+                            return Ok(res);
+                        }
+                        _ => {}
+                    }
                 }
-                let res = run_core(
-                    state,
-                    &mut Program {
-                        commands: block.commands.clone(),
-                        functions: program.functions.clone(),
-                    },
-                    0,
-                )
-                .unwrap();
-                if res == Expression::Break {
-                    break;
+            }
+            Command::Repeat {
+                ref count,
+                ref block,
+                ref label,
+            } => {
+                // Evaluated once, so changing the count inside the loop doesn't change the repeats
+                let times = run_number(state, program, count)?;
+                if times < 0.0 || times.fract() != 0.0 {
+                    return Err(MaidenError::BadArgument {
+                        name: "Repeat".to_string(),
+                        description: format!("count must be a non-negative integer, got {}", times),
+                        line: state.current_line,
+                    });
                 }
-            },
+                let line = state.current_line;
+                for iteration in 1..=(times as u64) {
+                    loop_iteration(state, line, iteration)?;
+                    let res = run_core(
+                        state,
+                        &mut Program {
+                            commands: block.commands.clone(),
+                            functions: program.functions.clone(),
+                        },
+                        0,
+                    )?;
+                    match res {
+                        Expression::Break => break,
+                        Expression::Continue | Expression::Nothing => {}
+                        Expression::Restart(ref restart) if Some(restart) == label.as_ref() => {}
+                        // A Return inside the loop, or a Restart for one further out
+                        other => {
+                            return Ok(other);
+                        }
+                    }
+                }
+            }
             Command::Continue => {
                 return Ok(Expression::Continue);
             }
             Command::Break => {
                 return Ok(Expression::Break);
             }
+            Command::Restart { ref label } => {
+                return Ok(Expression::Restart(label.clone()));
+            }
             Command::Say { ref value } => {
                 let resolve = run_expression(state, program, value)?;
-                let x = get_printable(&resolve, state)?;
-                writeln!(state.writer, "{}", x)?;
+                let prefix = if state.options.line_numbers {
+                    format!("[{}] ", command_line.line)
+                } else {
+                    String::new()
+                };
+                let x = format!(
+                    "{}{}{}",
+                    prefix,
+                    say_printable(&resolve, state)?,
+                    state.options.newline.as_str()
+                );
+                write_output(state, &x)?;
             }
+            Command::Write { ref value } => match run_expression(state, program, value)? {
+                Expression::String(s) => write_output(state, &s)?,
+                other => {
+                    return Err(MaidenError::BadArgument {
+                        name: "Write".to_string(),
+                        description: format!("expected a string, got {:?}", other),
+                        line: state.current_line,
+                    });
+                }
+            },
             Command::FunctionDeclaration {
                 ref name,
                 ref args,
+                ref defaults,
+                ref rest,
                 ref block,
             } => {
                 program.functions.insert(
                     name.to_string(),
                     Function {
                         args: args.to_vec(),
+                        defaults: defaults.to_vec(),
+                        rest: rest.clone(),
                         block: block.clone(),
                     },
                 );
@@ -931,8 +2397,7 @@ fn run_core(state: &mut State, program: &mut Program, mut pc: usize) -> Result<E
                                 functions: program.functions.clone(),
                             },
                             0,
-                        )
-                        .unwrap();
+                        )?;
                         if res != Expression::Nothing {
                             return Ok(res);
                         }
@@ -945,40 +2410,456 @@ fn run_core(state: &mut State, program: &mut Program, mut pc: usize) -> Result<E
                             functions: program.functions.clone(),
                         },
                         0,
-                    )
-                    .unwrap();
+                    )?;
                     if res != Expression::Nothing {
                         return Ok(res);
                     }
                 }
             }
+            Command::Try {
+                ref body,
+                ref error_var,
+                ref recover,
+            } => {
+                let tried = run_core(
+                    state,
+                    &mut Program {
+                        commands: body.commands.clone(),
+                        functions: program.functions.clone(),
+                    },
+                    0,
+                );
+                let res = match tried {
+                    Err(error) if recoverable(&error) => {
+                        if let Some(name) = error_var {
+                            let message = Expression::String(error.to_string());
+                            assign(state, program, &Expression::Variable(name.clone()), message)?;
+                        }
+                        run_core(
+                            state,
+                            &mut Program {
+                                commands: recover.commands.clone(),
+                                functions: program.functions.clone(),
+                            },
+                            0,
+                        )?
+                    }
+                    other => other?,
+                };
+                if res != Expression::Nothing {
+                    return Ok(res);
+                }
+            }
             Command::Call { ref name, ref args } => {
                 call_function(state, program, name, args)?;
             }
             Command::Listen {
-                target: ref opt_target,
+                ref targets,
+                as_type,
             } => {
-                let mut input = String::new();
-                io::stdin().read_line(&mut input)?;
-                if let Some(target) = opt_target {
-                    state.variables.insert(
-                        target.to_lowercase(),
-                        (
-                            get_variable_type(state),
-                            Expression::String(input.trim_end_matches('\n').to_string()),
-                        ),
-                    );
+                check_builtin(state, "listen")?;
+                let input = read_line()?;
+                let values = if let [_] = targets.as_slice() {
+                    vec![coerce_input(&input, as_type, state.current_line)?]
+                } else {
+                    let mut values = vec![];
+                    for word in input_words(&input, targets.len()) {
+                        values.push(match word {
+                            Some(word) => coerce_input(word, as_type, state.current_line)?,
+                            None => Expression::Mysterious,
+                        });
+                    }
+                    values
+                };
+                for (target, value) in targets.iter().zip(values) {
+                    state
+                        .variables
+                        .insert(target.to_lowercase(), (get_variable_type(state), value));
                 }
             }
             Command::Round { ref target } => {
                 round_variable(state, target, &|x| x.round())?;
             }
+            Command::ApplyToArray {
+                operation,
+                ref function,
+                ref source,
+                ref target,
+            } => {
+                // The pronoun can stand for a variable holding a function value
+                let name = match function {
+                    Expression::Variable(name) => name.clone(),
+                    Expression::Pronoun => match state.pronoun {
+                        Some(ref pronoun) => pronoun.clone(),
+                        None => {
+                            return Err(MaidenError::UndefinedPronoun {
+                                line: state.current_line,
+                            });
+                        }
+                    },
+                    other => {
+                        return Err(MaidenError::BadArgument {
+                            name: format!("{:?}", operation),
+                            description: format!("expected a function, got {:?}", other),
+                            line: state.current_line,
+                        });
+                    }
+                };
+                let values = match run_expression(state, program, source)? {
+                    Expression::Array { numeric, .. } => {
+                        numeric.into_values().map(|value| *value).collect()
+                    }
+                    other => {
+                        return Err(MaidenError::BadArgument {
+                            name: format!("{:?}", operation),
+                            description: format!("expected an array, got {:?}", other),
+                            line: state.current_line,
+                        });
+                    }
+                };
+                let result = apply_to_array(state, program, operation, &name, values)?;
+                let var_name = target_variable(state, &format!("{:?}", operation), target)?;
+                let kind = mutation_target_kind(state, &var_name);
+                state.variables.insert(var_name, (kind, result));
+            }
+            Command::FormatNumber {
+                ref value,
+                ref places,
+                ref target,
+            } => {
+                let value = run_number(state, program, value)?;
+                let places = run_number(state, program, places)?;
+                if places.fract() != 0.0 || places < 0.0 || !places.is_finite() {
+                    return Err(MaidenError::BadArgument {
+                        name: "Format".to_string(),
+                        description: format!("places should be a whole number, got {}", places),
+                        line: state.current_line,
+                    });
+                }
+                // Rounds the exact value of the float, rather than what format_number would print
+                let formatted = format!("{:.*}", places as usize, value);
+                let var_name = target_variable(state, "Format", target)?;
+                let kind = mutation_target_kind(state, &var_name);
+                state
+                    .variables
+                    .insert(var_name, (kind, Expression::String(formatted)));
+            }
+            Command::Clamp {
+                ref value,
+                ref low,
+                ref high,
+                ref target,
+            } => {
+                let mut numbers = vec![];
+                for expression in [value, low, high] {
+                    match run_expression(state, program, expression)? {
+                        Expression::Floating(f) => numbers.push(f),
+                        other => {
+                            return Err(MaidenError::BadArgument {
+                                name: "Clamp".to_string(),
+                                description: format!("expected a number, got {:?}", other),
+                                line: state.current_line,
+                            });
+                        }
+                    }
+                }
+                let (value, low, high) = (numbers[0], numbers[1], numbers[2]);
+                if low > high || low.is_nan() || high.is_nan() {
+                    return Err(MaidenError::BadArgument {
+                        name: "Clamp".to_string(),
+                        description: format!(
+                            "lower bound {} is above upper bound {}",
+                            format_number(low),
+                            format_number(high)
+                        ),
+                        line: state.current_line,
+                    });
+                }
+                let clamped = value.max(low).min(high);
+                let var_name = target_variable(state, "Clamp", target)?;
+                let kind = mutation_target_kind(state, &var_name);
+                state
+                    .variables
+                    .insert(var_name, (kind, Expression::Floating(clamped)));
+            }
+            Command::Pad {
+                ref value,
+                ref width,
+                ref fill,
+                right,
+                ref target,
+            } => {
+                let value = match run_expression(state, program, value)? {
+                    Expression::String(s) => s,
+                    Expression::Floating(f) => format_number(f),
+                    other => {
+                        return Err(MaidenError::BadArgument {
+                            name: "Pad".to_string(),
+                            description: format!("expected a string or number, got {:?}", other),
+                            line: state.current_line,
+                        });
+                    }
+                };
+                let width = run_number(state, program, width)?;
+                if width.fract() != 0.0 || width < 0.0 || !width.is_finite() {
+                    return Err(MaidenError::BadArgument {
+                        name: "Pad".to_string(),
+                        description: format!("width should be a whole number, got {}", width),
+                        line: state.current_line,
+                    });
+                }
+                let fill = match fill {
+                    Some(fill) => run_expression(state, program, fill)?,
+                    None => Expression::String(" ".to_string()),
+                };
+                let fill = match fill {
+                    Expression::String(ref s) if s.chars().count() == 1 => {
+                        s.chars().next().unwrap()
+                    }
+                    other => {
+                        return Err(MaidenError::BadArgument {
+                            name: "Pad".to_string(),
+                            description: format!("expected a single character, got {:?}", other),
+                            line: state.current_line,
+                        });
+                    }
+                };
+                // Widths are in code points, and longer strings are left as they are
+                let width = width as usize;
+                let length = value.chars().count();
+                let padded = if length >= width {
+                    value
+                } else {
+                    check_length(state, value.len() + (width - length) * fill.len_utf8())?;
+                    let padding = fill.to_string().repeat(width - length);
+                    if right {
+                        value + &padding
+                    } else {
+                        padding + &value
+                    }
+                };
+                let var_name = target_variable(state, "Pad", target)?;
+                let kind = mutation_target_kind(state, &var_name);
+                state
+                    .variables
+                    .insert(var_name, (kind, Expression::String(padded)));
+            }
+            Command::Negate { ref target } => {
+                round_variable(state, target, &|x| -x)?;
+            }
+            Command::Empty { ref target } => {
+                let line = state.current_line;
+                let name = target_variable(state, "Empty", target)?;
+                match state.variables.get_mut(&name) {
+                    Some((_, Expression::Array { numeric, strings })) => {
+                        numeric.clear();
+                        strings.clear();
+                    }
+                    Some((_, other)) => {
+                        return Err(MaidenError::BadArgument {
+                            name: "Empty".to_string(),
+                            description: format!("expected an array, got {:?}", other),
+                            line,
+                        });
+                    }
+                    None => return Err(MaidenError::MissingVariable { name, line }),
+                }
+            }
             Command::Ceil { ref target } => {
                 round_variable(state, target, &|x| x.ceil())?;
             }
             Command::Floor { ref target } => {
                 round_variable(state, target, &|x| x.floor())?;
             }
+            Command::Sleep { ref seconds } => {
+                check_builtin(state, "wait")?;
+                let resolve = run_expression(state, program, seconds)?;
+                if let Expression::Floating(seconds) =
+                    expression_to_number(resolve, state.current_line)?
+                {
+                    sleep(state, seconds)?;
+                }
+            }
+            Command::Throw { ref value } => {
+                let value = run_expression(state, program, value)?;
+                return Err(MaidenError::UserError {
+                    message: get_printable(&value, state)?,
+                    line: state.current_line,
+                });
+            }
+            Command::Include {
+                ref file,
+                ref block,
+            } => {
+                let mut included = Program {
+                    commands: block.commands.clone(),
+                    functions: program.functions.clone(),
+                };
+                // Errors from a nested include already say which file they're in
+                let outer = state.file.replace(file.clone());
+                let res = run_core(state, &mut included, 0);
+                state.file = outer;
+                let res = res.map_err(|error| match error {
+                    MaidenError::InFile { .. } => error,
+                    error => MaidenError::InFile {
+                        file: file.clone(),
+                        error: Box::new(error),
+                    },
+                })?;
+                // Functions declared in the included file can be called from after the include
+                program.functions = included.functions;
+                if res != Expression::Nothing {
+                    return Ok(res);
+                }
+            }
+            Command::Dequeue {
+                ref target,
+                ref removed,
+            } => {
+                let var_name = target_variable(state, "Dequeue", target)?;
+                let line = state.current_line;
+                // Rock adds at the back, so taking from the front makes the array a queue. An
+                // empty queue gives mysterious, as reading past the end of an array does
+                let value = match state.variables.get_mut(&var_name) {
+                    Some((_, Expression::Array { numeric, .. })) => {
+                        let value = numeric.remove(&0);
+                        *numeric = std::mem::take(numeric)
+                            .into_iter()
+                            .map(|(i, entry)| (i - 1, entry))
+                            .collect();
+                        value.map_or(Expression::Mysterious, |value| *value)
+                    }
+                    Some((_, other)) => {
+                        return Err(MaidenError::BadArgument {
+                            name: "Dequeue".to_string(),
+                            description: format!("expected an array, got {:?}", other),
+                            line,
+                        });
+                    }
+                    None => {
+                        return Err(MaidenError::MissingVariable {
+                            name: var_name,
+                            line,
+                        })
+                    }
+                };
+                if let Some(removed) = removed {
+                    let name = target_variable(state, "Dequeue", removed)?;
+                    let kind = mutation_target_kind(state, &name);
+                    state.variables.insert(name, (kind, value));
+                }
+            }
+            Command::Remove {
+                ref target,
+                ref index,
+                ref removed,
+            } => {
+                let line = state.current_line;
+                let var_name = target_variable(state, "Remove", target)?;
+                let index = run_number(state, program, index)?;
+                let index = array_index(state, index)?;
+                // Past the end there's nothing to take out, so the array is left alone and the
+                // removed value is mysterious, as reading the same index would be
+                let value = match state.variables.get_mut(&var_name) {
+                    Some((_, Expression::Array { numeric, .. })) => {
+                        let length = numeric.keys().max().map_or(0, |x| x + 1);
+                        if index < length {
+                            let value = numeric.remove(&index);
+                            let moved = numeric.split_off(&index);
+                            numeric.extend(moved.into_iter().map(|(i, entry)| (i - 1, entry)));
+                            value.map_or(Expression::Mysterious, |value| *value)
+                        } else {
+                            Expression::Mysterious
+                        }
+                    }
+                    Some((_, other)) => {
+                        return Err(MaidenError::BadArgument {
+                            name: "Remove".to_string(),
+                            description: format!("expected an array, got {:?}", other),
+                            line,
+                        });
+                    }
+                    None => {
+                        return Err(MaidenError::MissingVariable {
+                            name: var_name,
+                            line,
+                        })
+                    }
+                };
+                if let Some(removed) = removed {
+                    assign(state, program, removed, value)?;
+                }
+            }
+            Command::Insert {
+                ref value,
+                ref target,
+                ref index,
+            } => {
+                let var_name = target_variable(state, "Insert", target)?;
+                let value = run_expression(state, program, value)?;
+                let index = run_number(state, program, index)?;
+                let index = array_index(state, index)?;
+                let (kind, mut numeric, strings) = match state.variables.remove(&var_name) {
+                    Some((kind, Expression::Array { numeric, strings })) => {
+                        (kind, numeric, strings)
+                    }
+                    Some((kind, other)) => {
+                        let description = format!("expected an array, got {:?}", other);
+                        state.variables.insert(var_name, (kind, other));
+                        return Err(MaidenError::BadArgument {
+                            name: "Insert".to_string(),
+                            description,
+                            line: state.current_line,
+                        });
+                    }
+                    None => (get_variable_type(state), BTreeMap::new(), BTreeMap::new()),
+                };
+                // Past the end, the gap up to the index is filled with mysterious
+                let length = numeric.keys().max().map_or(0, |x| x + 1);
+                check_length(
+                    state,
+                    numeric.len() + strings.len() + 1 + index.saturating_sub(length),
+                )?;
+                let moved = numeric.split_off(&index);
+                numeric.extend(moved.into_iter().map(|(i, entry)| (i + 1, entry)));
+                for gap in length..index {
+                    numeric.insert(gap, Box::new(Expression::Mysterious));
+                }
+                numeric.insert(index, Box::new(value));
+                state
+                    .variables
+                    .insert(var_name, (kind, Expression::Array { numeric, strings }));
+            }
+            Command::Push {
+                ref target,
+                ref values,
+            } => {
+                let var_name = target_variable(state, "Rock", target)?;
+                let mut new_values = vec![];
+                for value in values {
+                    new_values.push(run_expression(state, program, value)?);
+                }
+                let (kind, mut numeric, strings) = match state.variables.remove(&var_name) {
+                    Some((kind, Expression::Array { numeric, strings })) => {
+                        (kind, numeric, strings)
+                    }
+                    // Pushing onto a scalar makes it the first entry of a new array
+                    Some((kind, other)) => {
+                        let mut numeric = BTreeMap::new();
+                        numeric.insert(0, Box::new(other));
+                        (kind, numeric, BTreeMap::new())
+                    }
+                    None => (get_variable_type(state), BTreeMap::new(), BTreeMap::new()),
+                };
+                check_length(state, numeric.len() + strings.len() + new_values.len())?;
+                for value in new_values {
+                    let next = numeric.keys().max().map_or(0, |x| x + 1);
+                    numeric.insert(next, Box::new(value));
+                }
+                state
+                    .variables
+                    .insert(var_name, (kind, Expression::Array { numeric, strings }));
+            }
             Command::Mutation {
                 ref mutator,
                 ref source,
@@ -987,11 +2868,40 @@ fn run_core(state: &mut State, program: &mut Program, mut pc: usize) -> Result<E
                 ref modifier,
             } => match mutator {
                 SymbolType::Cast => {
-                    if source.is_some()
-                        || target.is_some()
-                        || lookup.is_none()
-                        || modifier.is_some()
-                    {
+                    let (value, name, kind) = if let Some(lookup) = lookup {
+                        if let Expression::Variable(var_name) = lookup.deref() {
+                            let value = run_expression(state, program, lookup)?;
+                            // A function name or, when lenient, a missing variable still gives a
+                            // value, but there's no variable to cast in place
+                            let kind = match state.variables.get(&var_name.to_lowercase()) {
+                                Some((kind, _)) => *kind,
+                                None => {
+                                    return Err(MaidenError::MissingVariable {
+                                        name: var_name.to_string(),
+                                        line: state.current_line,
+                                    });
+                                }
+                            };
+                            (value, var_name, kind)
+                        } else {
+                            return Err(MaidenError::BadArgument {
+                                name: "Cast".to_string(),
+                                description: format!("expected a variable, got {:?}", lookup),
+                                line: state.current_line,
+                            });
+                        }
+                    } else if let (Some(source), Some(target)) = (source, target) {
+                        if let Expression::Variable(var_name) = target.deref() {
+                            let value = run_expression(state, program, source)?;
+                            (value, var_name, get_variable_type(state))
+                        } else {
+                            return Err(MaidenError::BadArgument {
+                                name: "Cast".to_string(),
+                                description: format!("expected a variable, got {:?}", target),
+                                line: state.current_line,
+                            });
+                        }
+                    } else {
                         unimplemented!(
                             "Cast for {:?} {:?} {:?} {:?}",
                             source,
@@ -999,32 +2909,44 @@ fn run_core(state: &mut State, program: &mut Program, mut pc: usize) -> Result<E
                             lookup,
                             modifier
                         );
-                    }
-                    if let Expression::Variable(var_name) = lookup.as_ref().unwrap().deref() {
-                        match state.variables.get(&var_name.to_lowercase()).unwrap() {
-                            (kind, Expression::String(ref s)) => {
-                                let val = f64::from_str(s).unwrap();
-                                let new_kind = *kind;
-                                state.variables.insert(
-                                    var_name.to_lowercase(),
-                                    (new_kind, Expression::Floating(val)),
-                                );
-                            }
-                            (kind, Expression::Floating(f)) => {
-                                let val = std::char::from_u32(*f as u32).unwrap().to_string();
-                                let new_kind = *kind;
-                                state.variables.insert(
-                                    var_name.to_lowercase(),
-                                    (new_kind, Expression::String(val)),
-                                );
+                    };
+                    let base = match modifier {
+                        Some(modifier) => Some(cast_base(state, program, modifier)?),
+                        None => None,
+                    };
+                    let val = match value {
+                        Expression::String(ref s) => {
+                            let parsed = match base {
+                                Some(radix) => {
+                                    i64::from_str_radix(s.trim(), radix).map(|i| i as f64).ok()
+                                }
+                                None => f64::from_str(s).ok(),
+                            };
+                            match parsed {
+                                Some(f) => Expression::Floating(f),
+                                None => {
+                                    return Err(MaidenError::ParseNumberError {
+                                        number: s.to_string(),
+                                        line: state.current_line,
+                                    });
+                                }
                             }
-                            var => {
-                                unimplemented!("Cast for {:?}", var);
+                        }
+                        Expression::Floating(f) => match std::char::from_u32(f as u32) {
+                            Some(c) => Expression::String(c.to_string()),
+                            None => {
+                                return Err(MaidenError::BadArgument {
+                                    name: "Cast".to_string(),
+                                    description: format!("{} isn't a character code", f),
+                                    line: state.current_line,
+                                });
                             }
+                        },
+                        var => {
+                            unimplemented!("Cast for {:?}", var);
                         }
-                    } else {
-                        unimplemented!("Cast for non-variable: {:?}", lookup);
-                    }
+                    };
+                    state.variables.insert(name.to_lowercase(), (kind, val));
                 }
                 SymbolType::Split => {
                     let split_by = modifier
@@ -1060,38 +2982,24 @@ fn run_core(state: &mut State, program: &mut Program, mut pc: usize) -> Result<E
                         } else {
                             unimplemented!("Split for non-variable: {:?}", lookup);
                         }
-                    } else if target.is_some() && source.is_some() {
-                        match source.as_ref().unwrap().deref() {
-                            Expression::String(src) => {
-                                if let Expression::Variable(tar) = target.as_ref().unwrap().deref()
-                                {
+                    } else if let (Some(source), Some(target)) = (source, target) {
+                        if let Expression::Variable(tar) = target.deref() {
+                            match run_expression(state, program, source)? {
+                                Expression::String(ref src) => {
                                     let val = split_array(src);
-                                    state.variables.insert(
-                                        tar.to_lowercase(),
-                                        (get_variable_type(state), val),
-                                    );
-                                } else {
-                                    unimplemented!("Split to {:?}", target);
+                                    let kind = mutation_target_kind(state, tar);
+                                    state.variables.insert(tar.to_lowercase(), (kind, val));
                                 }
-                            }
-                            Expression::Variable(src) => {
-                                if let Expression::Variable(tar) = target.as_ref().unwrap().deref()
-                                {
-                                    let var = state.variables.get(src).unwrap();
-                                    let kind = var.0;
-                                    if let Expression::String(ref var_str) = var.1 {
-                                        let val = split_array(var_str);
-                                        state.variables.insert(tar.to_lowercase(), (kind, val));
-                                    } else {
-                                        unimplemented!("Split of {:?}", var);
-                                    }
-                                } else {
-                                    unimplemented!("Split to {:?}", target);
+                                other => {
+                                    return Err(MaidenError::BadArgument {
+                                        name: "Split".to_string(),
+                                        description: format!("expected a string, got {:?}", other),
+                                        line: state.current_line,
+                                    });
                                 }
                             }
-                            _ => {
-                                unimplemented!("Split for {:?}", source);
-                            }
+                        } else {
+                            unimplemented!("Split to {:?}", target);
                         }
                     } else {
                         unimplemented!(
@@ -1111,28 +3019,11 @@ fn run_core(state: &mut State, program: &mut Program, mut pc: usize) -> Result<E
                             other => panic!("Modifier with non-string: {:?}", other),
                         })
                         .unwrap_or("");
-                    let join_array = |to_join: &BTreeMap<usize, Box<Expression>>| {
-                        Expression::String(
-                            to_join
-                                .values()
-                                .map(|b| match b.deref() {
-                                    Expression::String(s) => s.as_str(),
-                                    other => panic!("Modifier with non-string: {:?}", other),
-                                })
-                                .fold(String::new(), |acc, x| {
-                                    if acc.is_empty() {
-                                        x.to_string()
-                                    } else {
-                                        acc + join_with + x
-                                    }
-                                }),
-                        )
-                    };
                     if lookup.is_some() {
                         if let Expression::Variable(var_name) = lookup.as_ref().unwrap().deref() {
                             match state.variables.get(&var_name.to_lowercase()).unwrap() {
                                 (kind, Expression::Array { ref numeric, .. }) => {
-                                    let val = join_array(numeric);
+                                    let val = join_array(state, numeric, join_with)?;
                                     let new_kind = *kind;
                                     state
                                         .variables
@@ -1145,38 +3036,24 @@ fn run_core(state: &mut State, program: &mut Program, mut pc: usize) -> Result<E
                         } else {
                             unimplemented!("Join for non-variable: {:?}", lookup);
                         }
-                    } else if target.is_some() && source.is_some() {
-                        match source.as_ref().unwrap().deref() {
-                            Expression::Array { ref numeric, .. } => {
-                                if let Expression::Variable(tar) = target.as_ref().unwrap().deref()
-                                {
-                                    let val = join_array(numeric);
-                                    state.variables.insert(
-                                        tar.to_lowercase(),
-                                        (get_variable_type(state), val),
-                                    );
-                                } else {
-                                    unimplemented!("Join to {:?}", target);
+                    } else if let (Some(source), Some(target)) = (source, target) {
+                        if let Expression::Variable(tar) = target.deref() {
+                            match run_expression(state, program, source)? {
+                                Expression::Array { ref numeric, .. } => {
+                                    let val = join_array(state, numeric, join_with)?;
+                                    let kind = mutation_target_kind(state, tar);
+                                    state.variables.insert(tar.to_lowercase(), (kind, val));
                                 }
-                            }
-                            Expression::Variable(src) => {
-                                if let Expression::Variable(tar) = target.as_ref().unwrap().deref()
-                                {
-                                    let (kind, var) = state.variables.get(src).unwrap();
-                                    if let Expression::Array { ref numeric, .. } = var {
-                                        let val = join_array(numeric);
-                                        let new_kind = *kind;
-                                        state.variables.insert(tar.to_lowercase(), (new_kind, val));
-                                    } else {
-                                        unimplemented!("Join of {:?}", var);
-                                    }
-                                } else {
-                                    unimplemented!("Join to {:?}", target);
+                                other => {
+                                    return Err(MaidenError::BadArgument {
+                                        name: "Join".to_string(),
+                                        description: format!("expected an array, got {:?}", other),
+                                        line: state.current_line,
+                                    });
                                 }
                             }
-                            _ => {
-                                unimplemented!("Join for {:?}", source);
-                            }
+                        } else {
+                            unimplemented!("Join to {:?}", target);
                         }
                     } else {
                         unimplemented!(
@@ -1200,7 +3077,81 @@ fn run_core(state: &mut State, program: &mut Program, mut pc: usize) -> Result<E
                 }
             },
         }
+        check_memory(state)?;
         pc += 1;
     }
     return Ok(Expression::Nothing);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{coerce_input, exact_quotient, format_number, input_words, NumberFormat};
+    use crate::common::{Expression, ListenType, MaidenError};
+
+    #[test]
+    fn listen_as_number() {
+        assert_eq!(
+            coerce_input("42.5\n", ListenType::Number, 1).unwrap(),
+            Expression::Floating(42.5)
+        );
+        match coerce_input("forty\n", ListenType::Number, 3) {
+            Err(MaidenError::ParseNumberError { number, line }) => {
+                assert_eq!(number, "forty");
+                assert_eq!(line, 3);
+            }
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn exact_quotients() {
+        assert_eq!(exact_quotient(6.0, 3.0), Some(2.0));
+        assert_eq!(exact_quotient(1.0, 4.0), Some(0.25));
+        assert_eq!(exact_quotient(-7.0, 2.0), Some(-3.5));
+        assert_eq!(exact_quotient(0.3, 0.1), Some(3.0));
+        assert_eq!(exact_quotient(1.0, 3.0), None);
+        assert_eq!(exact_quotient(2.0, 0.7), None);
+    }
+
+    #[test]
+    fn number_formatting() {
+        assert_eq!(format_number(-0.0), "0");
+        assert_eq!(format_number(0.0 * -5.0), "0");
+        assert_eq!(format_number(-2.5), "-2.5");
+        assert_eq!(format_number(3.0), "3");
+        assert_eq!(format_number(f64::INFINITY), "Infinity");
+        assert_eq!(format_number(f64::NEG_INFINITY), "-Infinity");
+        assert_eq!(format_number(f64::NAN), "NaN");
+    }
+
+    #[test]
+    fn scientific_formatting() {
+        let two_places = NumberFormat::Scientific(2);
+        assert_eq!(two_places.format(1500.0), "1.50e3");
+        assert_eq!(two_places.format(-0.000123), "-1.23e-4");
+        assert_eq!(two_places.format(7.0), "7.00e0");
+        assert_eq!(two_places.format(-0.0), "0.00e0");
+        assert_eq!(NumberFormat::Scientific(0).format(6.02e23), "6e23");
+        assert_eq!(two_places.format(f64::INFINITY), "Infinity");
+        assert_eq!(two_places.format(f64::NAN), "NaN");
+        assert_eq!(NumberFormat::Fixed.format(1500.0), "1500");
+    }
+
+    #[test]
+    fn listen_as_string() {
+        assert_eq!(
+            coerce_input("42\n", ListenType::String, 1).unwrap(),
+            Expression::String("42".to_string())
+        );
+    }
+
+    #[test]
+    fn listen_to_words() {
+        assert_eq!(
+            input_words("Axl  Slash Duff Izzy\n", 3),
+            vec![Some("Axl"), Some("Slash"), Some("Duff")]
+        );
+        assert_eq!(input_words("Axl\n", 3), vec![Some("Axl"), None, None]);
+        assert_eq!(input_words("", 2), vec![None, None]);
+    }
+}
@@ -1,8 +1,8 @@
-use crate::common::MaidenError;
-use crate::display;
-use crate::parser;
-use crate::runner;
 use js_sys::Function;
+use maiden::common::MaidenError;
+use maiden::display;
+use maiden::parser;
+use maiden::runner;
 use std;
 use wasm_bindgen::closure::Closure;
 use wasm_bindgen::JsCast;
@@ -10,6 +10,9 @@ use wasm_bindgen::JsValue;
 use yew::html;
 use yew::prelude::*;
 
+// Stops runaway programs from filling the page faster than the instruction limit kicks in
+const OUTPUT_LIMIT: usize = 1_000_000;
+
 pub struct Model {
     value: String,
     program: String,
@@ -38,11 +41,12 @@ impl Model {
     fn nicer_error(&self, err: &MaidenError) -> String {
         let line = get_error_line(err);
         if line == 0 {
-            format!("{}", err)
+            format!("{} [{}]", err, err.code())
         } else {
             format!(
-                "{} at line {}: \"{}\"",
+                "{} [{}] at line {}: \"{}\"",
                 err,
+                err.code(),
                 line,
                 self.get_line(line as usize)
             )
@@ -73,7 +77,11 @@ impl Model {
                 self.program = display::print_program(&val);
                 self.parse_error = false;
                 let mut writer = std::io::Cursor::new(Vec::new());
-                let res = runner::run(&mut val, &mut writer);
+                let options = runner::Options {
+                    output_limit: Some(OUTPUT_LIMIT),
+                    ..runner::Options::default()
+                };
+                let res = runner::run_with_options(&mut val, &mut writer, options);
                 self.res = "".into();
                 if let Err(err) = res {
                     self.res += &self.nicer_error(&err);
@@ -243,14 +251,29 @@ fn get_error_line(e: &MaidenError) -> usize {
         | MaidenError::Unimplemented { ref line, .. }
         | MaidenError::StackOverflow { ref line, .. }
         | MaidenError::InstructionLimit { ref line }
+        | MaidenError::TimeLimit { ref line, .. }
+        | MaidenError::OutputLimit { ref line, .. }
+        | MaidenError::AllocationLimit { ref line, .. }
+        | MaidenError::MemoryLimit { ref line }
+        | MaidenError::IterationLimit { ref line, .. }
         | MaidenError::UndefinedPronoun { ref line }
         | MaidenError::Infinity { ref line, .. }
+        | MaidenError::InexactDivision { ref line, .. }
+        | MaidenError::BadArgument { ref line, .. }
+        | MaidenError::BadIndex { ref line, .. }
+        | MaidenError::UnreachableCode { ref line }
+        | MaidenError::NotANumber { ref line, .. }
+        | MaidenError::LossyConversion { ref line, .. }
+        | MaidenError::UnknownLabel { ref line, .. }
+        | MaidenError::UserError { ref line, .. }
+        | MaidenError::TypeMismatch { ref line, .. }
         | MaidenError::Incomplete { ref line, .. }
         | MaidenError::NotAnExpression { ref line, .. }
         | MaidenError::NotASymbol { ref line, .. }
         | MaidenError::NotACommand { ref line, .. }
         | MaidenError::NotABlock { ref line, .. }
         | MaidenError::BadString { ref line, .. } => *line,
+        MaidenError::InFile { ref error, .. } => get_error_line(error),
         MaidenError::Pest { .. } | MaidenError::Io { .. } => 0,
     }
 }
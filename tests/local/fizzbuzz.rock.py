@@ -0,0 +1,57 @@
+# Transpiled from Rockstar by Maiden
+import math
+
+
+def _num(value):
+    return 0 if value is None else value
+
+
+def _text(value):
+    if value is None:
+        return "null"
+    if value is True:
+        return "true"
+    if value is False:
+        return "false"
+    if isinstance(value, float) and value.is_integer():
+        return str(int(value))
+    return str(value)
+
+
+def _add(a, b):
+    if isinstance(a, str) or isinstance(b, str):
+        return _text(a) + _text(b)
+    return _num(a) + _num(b)
+
+
+def _eq(a, b):
+    if a is None or b is None:
+        return _num(a) == _num(b)
+    return a == b
+
+
+def _unsupported(what):
+    raise NotImplementedError(what + " can't be transpiled to Python")
+
+
+def midnight(your_heart, your_soul):
+    while _num(your_heart) >= _num(your_soul):
+        your_heart = _num(your_heart) - _num(your_soul)
+    return your_heart
+
+desire = 100
+my_world = None
+fire = 3
+hate = 5
+while not _eq(my_world, desire):
+    my_world = _num(my_world) + 1
+    if _eq(midnight(my_world, fire), None) and _eq(midnight(my_world, hate), None):
+        print("FizzBuzz!")
+        continue
+    if _eq(midnight(my_world, fire), None):
+        print("Fizz!")
+        continue
+    if _eq(midnight(my_world, hate), None):
+        print("Buzz!")
+        continue
+    print(_text(my_world))
@@ -0,0 +1,57 @@
+# Transpiled from Rockstar by Maiden
+import math
+
+
+def _num(value):
+    return 0 if value is None else value
+
+
+def _text(value):
+    if value is None:
+        return "null"
+    if value is True:
+        return "true"
+    if value is False:
+        return "false"
+    if isinstance(value, float) and value.is_integer():
+        return str(int(value))
+    return str(value)
+
+
+def _add(a, b):
+    if isinstance(a, str) or isinstance(b, str):
+        return _text(a) + _text(b)
+    return _num(a) + _num(b)
+
+
+def _eq(a, b):
+    if a is None or b is None:
+        return _num(a) == _num(b)
+    return a == b
+
+
+def _unsupported(what):
+    raise NotImplementedError(what + " can't be transpiled to Python")
+
+
+def modulus(number, divisor):
+    while _num(number) >= _num(divisor):
+        number = _num(number) - _num(divisor)
+    return number
+
+limit = 100
+counter = 0
+fizz = 3
+buzz = 5
+while not _eq(counter, limit):
+    counter = _num(counter) + 1
+    if _eq(modulus(counter, fizz), 0) and _eq(modulus(counter, buzz), 0):
+        print("FizzBuzz!")
+        continue
+    if _eq(modulus(counter, fizz), 0):
+        print("Fizz!")
+        continue
+    if _eq(modulus(counter, buzz), 0):
+        print("Buzz!")
+        continue
+    print(_text(counter))
@@ -0,0 +1,63 @@
+# Transpiled from Rockstar by Maiden
+import math
+
+
+def _num(value):
+    return 0 if value is None else value
+
+
+def _text(value):
+    if value is None:
+        return "null"
+    if value is True:
+        return "true"
+    if value is False:
+        return "false"
+    if isinstance(value, float) and value.is_integer():
+        return str(int(value))
+    return str(value)
+
+
+def _add(a, b):
+    if isinstance(a, str) or isinstance(b, str):
+        return _text(a) + _text(b)
+    return _num(a) + _num(b)
+
+
+def _eq(a, b):
+    if a is None or b is None:
+        return _num(a) == _num(b)
+    return a == b
+
+
+def _unsupported(what):
+    raise NotImplementedError(what + " can't be transpiled to Python")
+
+
+def absolute(a_thought):
+    if _num(a_thought) > 0:
+        return a_thought
+    return 0 - _num(a_thought)
+
+the_loneliest = 1
+def the_sign(your_life):
+    if _num(your_life) > 0:
+        return the_loneliest
+    if _num(your_life) < 0:
+        return 0 - _num(the_loneliest)
+    return None
+
+def what_remains(the_fighters, a_war):
+    before = absolute(the_fighters)
+    after = absolute(a_war)
+    if _num(before) < _num(after):
+        return _num(the_fighters) * _num(the_sign(a_war))
+    if _num(the_fighters) >= _num(before):
+        the_new = _num(the_fighters) - _num(after)
+        return what_remains(the_new, a_war)
+    the_battle = _add(after, the_fighters)
+    return what_remains(the_battle, a_war)
+
+fighters = 6
+war = 4
+print(_text(what_remains(fighters, war)))